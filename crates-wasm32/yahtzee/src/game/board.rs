@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+use yahtzee_rules::{Action, Category, GameState, Variant, MAX_DICE};
+
+use crate::event_loop::EventDispatcherProxy;
+use crate::game::events::{GameEvent, HintChoice, LobbyAction};
+use crate::ui::{div::Div, button::Button};
+
+//Scoreboard, dice and roll controls for one game. Built once per game since the players and rules do not change.
+pub struct Board {
+    container: Div,
+    players: Vec<u32>,
+    variant: Variant,
+    status: Div,
+    dice: Vec<Button>,
+    roll: Button,
+    hint_button: Button,
+    hint: Div,
+    score_buttons: BTreeMap<(Category, usize), Button>,
+    score_cells: BTreeMap<(u32, Category, usize), Div>,
+    bonus_cells: BTreeMap<u32, Div>,
+    total_cells: BTreeMap<u32, Div>,
+}
+impl Board {
+    pub fn new(parent: &Div, event_sender: &EventDispatcherProxy<GameEvent>, state: &GameState, user_id: u32, names: &BTreeMap<u32, String>) -> Self {
+        let container = parent.div().with_class("board");
+        let status = container.div().with_class("row");
+
+        let dice_row = container.div().with_class("row dice");
+        let dice = (0..state.dice.len()).map(|index| {
+            let event_sender = event_sender.clone();
+            dice_row.button().with_class("die").with_callback(move || {
+                event_sender.send(GameEvent::LobbyAction(LobbyAction::ToggleHold(index)));
+            })
+        }).collect();
+        let roll = {
+            let event_sender = event_sender.clone();
+            dice_row.button().with_text("Roll").with_callback(move || {
+                event_sender.send(GameEvent::LobbyAction(LobbyAction::Roll));
+            })
+        };
+        let hint_button = {
+            let event_sender = event_sender.clone();
+            dice_row.button().with_text("Hint").with_callback(move || {
+                event_sender.send(GameEvent::LobbyAction(LobbyAction::RequestHint));
+            })
+        };
+        let hint = container.div().with_class("row hint");
+
+        let players = state.players.iter().map(|player| player.id).collect::<Vec<_>>();
+        let variant = state.options.variant;
+        let columns = variant.columns();
+        let header = container.div().with_class("row score-row");
+        header.div().with_class("score-label");
+        for player_id in players.iter() {
+            let name = names.get(player_id).map(String::as_str).unwrap_or("?");
+            for column in 0..columns {
+                let text = if columns > 1 { format!("{name} x{}", variant.column_multiplier(column)) } else { name.to_string() };
+                header.div().with_class("score-cell").text(text.as_str());
+            }
+        }
+
+        let mut score_buttons = BTreeMap::new();
+        let mut score_cells = BTreeMap::new();
+        for &category in variant.categories() {
+            let row = container.div().with_class("row score-row");
+            row.div().with_class("score-label").text(variant.category_name(category));
+            for &player_id in players.iter() {
+                for column in 0..columns {
+                    if player_id == user_id {
+                        let event_sender = event_sender.clone();
+                        let button = row.button().with_class("score-cell").with_callback(move || {
+                            event_sender.send(GameEvent::LobbyAction(LobbyAction::Score(category, column)));
+                        });
+                        score_buttons.insert((category, column), button);
+                    }
+                    else {
+                        score_cells.insert((player_id, category, column), row.div().with_class("score-cell"));
+                    }
+                }
+            }
+        }
+        //Bonus and total are shown under each player's first column.
+        let summary_row = |label: &str| {
+            let row = container.div().with_class("row score-row");
+            row.div().with_class("score-label").text(label);
+            players.iter().map(|&player_id| {
+                let cell = row.div().with_class("score-cell");
+                for _ in 1..columns {
+                    row.div().with_class("score-cell");
+                }
+                (player_id, cell)
+            }).collect::<BTreeMap<_, _>>()
+        };
+        let bonus_cells = summary_row("Bonus");
+        let total_cells = summary_row("Total");
+
+        Self {
+            container,
+            players,
+            variant,
+            status,
+            dice,
+            roll,
+            hint_button,
+            hint,
+            score_buttons,
+            score_cells,
+            bonus_cells,
+            total_cells,
+        }
+    }
+    pub fn is_for(&self, state: &GameState) -> bool {
+        self.variant == state.options.variant && self.players.iter().eq(state.players.iter().map(|player| &player.id))
+    }
+    pub fn update(&self, state: &GameState, user_id: u32, held: &[bool; MAX_DICE], names: &BTreeMap<u32, String>) {
+        let name = |player_id: u32| names.get(&player_id).cloned().unwrap_or_else(|| format!("Player {player_id}"));
+        let my_turn = state.current_player() == Some(user_id);
+
+        //Turn or result summary.
+        match state.current_player() {
+            Some(_) if my_turn => self.status.set_text(format!("Your turn ({} rolls left)", state.rolls_left).as_str()),
+            Some(player_id) => self.status.set_text(format!("{}'s turn", name(player_id)).as_str()),
+            None => {
+                let winner = state.players.iter().max_by_key(|player| player.scorecard.total());
+                let text = winner.map(|player| format!("Game over! {} wins with {}", name(player.id), player.scorecard.total()));
+                self.status.set_text(text.as_deref().unwrap_or("Game over!"));
+            }
+        }
+
+        //Dice are blank until the first roll of each turn.
+        for (index, (die, face)) in self.dice.iter().zip(state.dice.iter()).enumerate() {
+            die.set_text(if state.has_rolled() { face.to_string() } else { "-".to_string() }.as_str());
+            die.set_class(if my_turn && held[index] { "die held" } else { "die" });
+        }
+        if my_turn && state.rolls_left > 0 { self.roll.show() } else { self.roll.hide() }
+        if my_turn { self.hint_button.show() } else { self.hint_button.hide() }
+        //A hint only applies to the state it was asked for.
+        self.hint.set_text("");
+
+        //Scorecards. Open categories show what the current dice would score on your turn.
+        for player in state.players.iter() {
+            for &category in self.variant.categories() {
+                for column in 0..self.variant.columns() {
+                    let text = match player.scorecard.get(column, category) {
+                        Some(score) => score.to_string(),
+                        None if player.id == user_id && my_turn && state.has_rolled() => player.scorecard.score_for(column, category, &state.dice)
+                            .map(|score| format!("({score})"))
+                            .unwrap_or_default(),
+                        None => String::new(),
+                    };
+                    if player.id == user_id {
+                        if let Some(button) = self.score_buttons.get(&(category, column)) {
+                            button.set_text(text.as_str());
+                        }
+                    }
+                    else if let Some(cell) = self.score_cells.get(&(player.id, category, column)) {
+                        cell.set_text(text.as_str());
+                    }
+                }
+            }
+            if let Some(cell) = self.bonus_cells.get(&player.id) {
+                cell.set_text(player.scorecard.bonus().to_string().as_str());
+            }
+            if let Some(cell) = self.total_cells.get(&player.id) {
+                cell.set_text(player.scorecard.total().to_string().as_str());
+            }
+        }
+    }
+}
+impl Board {
+    pub fn show_hint(&self, choices: &[HintChoice]) {
+        let text = choices.iter().map(|choice| {
+            let action = match choice.action {
+                Action::Roll { held } => {
+                    let kept = held.iter().enumerate().filter(|(_, held)| **held).map(|(index, _)| (index + 1).to_string()).collect::<Vec<_>>();
+                    if kept.is_empty() { "Reroll all".to_string() } else { format!("Keep dice {}", kept.join(", ")) }
+                }
+                Action::Score(category, _) => format!("Score {}", self.variant.category_name(category)),
+            };
+            format!("{action} ({:.1})", choice.expected_total)
+        }).collect::<Vec<_>>();
+        self.hint.set_text(format!("Best moves: {}", text.join(" / ")).as_str());
+    }
+}
+impl Drop for Board {
+    fn drop(&mut self) {
+        self.container.remove();
+    }
+}
@@ -1,58 +1,98 @@
 use serde::{Serialize, Deserialize};
+use yahtzee_rules::Category;
 
-use crate::network::peer_network::PeerHandshake;
+use yahtzee_net::{DiceMessage, PeerGame, SyncMessage};
 use super::scene::GameScene;
 
-#[derive(Serialize, Deserialize)]
-pub enum WebSocketMessage {
-    ConnectSuccess {
-        lobby_id: u64,
-        user_id: u32,
-        peers_id: Vec<u32>,
-    },
-    PeerHandshake {
-        source_id: u32,
-        target_id: u32,
-        sdp_description: String,
-        ice_candidates: Vec<(String, Option<String>, Option<u16>)>,
-    }
+pub use signaling_protocol::lobby::{AiDifficulty, HintChoice, LobbyPhase, LobbySettings, LobbyUser, SocketMessage};
+
+//Tournament standings as served by the JSON endpoint. Only the fields the client shows are mirrored.
+#[derive(Deserialize, Clone)]
+pub struct TournamentEntrant {
+    pub player_id: u64,
+    pub display_name: String,
 }
-impl From<PeerHandshake> for WebSocketMessage {
-    fn from(value: PeerHandshake) -> Self {
-        Self::PeerHandshake {
-            source_id: value.source_id,
-            target_id: value.target_id,
-            sdp_description: value.sdp_description,
-            ice_candidates: value.ice_candidates,
-        }
-    }
+
+#[derive(Deserialize, Clone)]
+pub struct TournamentSeries {
+    pub lobby_id: u64,
+    pub players: [u64; 2],
+    pub wins: [u8; 2],
+    pub totals: [u32; 2],
+    pub winner: Option<u64>,
 }
-impl From<WebSocketMessage> for PeerHandshake {
-    fn from(value: WebSocketMessage) -> Self {
-        if let WebSocketMessage::PeerHandshake { source_id, target_id, sdp_description, ice_candidates } = value {
-            PeerHandshake {
-                source_id,
-                target_id,
-                sdp_description,
-                ice_candidates,
-            }
-        }
-        else {
-            PeerHandshake::default()
-        }
-    }
+
+#[derive(Deserialize, Clone)]
+pub struct TournamentRound {
+    pub series: Vec<TournamentSeries>,
+    pub byes: Vec<u64>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct TournamentStanding {
+    pub display_name: String,
+    pub series_played: u32,
+    pub series_won: u32,
+    pub games_won: u32,
+    pub total_score: u32,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct TournamentInfo {
+    pub name: String,
+    pub format: String,
+    pub best_of: u8,
+    pub entrants: Vec<TournamentEntrant>,
+    pub rounds: Vec<TournamentRound>,
+    pub winner: Option<u64>,
+    pub standings: Vec<TournamentStanding>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub enum PeerMessage {
     Ping,
     Pong(String),
+    Sync(SyncMessage<PeerGame>),
+    Dice(DiceMessage),
+}
+impl From<SyncMessage<PeerGame>> for PeerMessage {
+    fn from(value: SyncMessage<PeerGame>) -> Self {
+        Self::Sync(value)
+    }
+}
+impl From<DiceMessage> for PeerMessage {
+    fn from(value: DiceMessage) -> Self {
+        Self::Dice(value)
+    }
 }
 
-pub type WebSocketEvent = crate::network::web_socket::WebSocketEvent<WebSocketMessage>;
+pub type WebSocketEvent = crate::network::web_socket::WebSocketEvent<SocketMessage>;
 pub type PeerNetworkEvent = crate::network::peer_network::PeerNetworkEvent<PeerMessage>;
+//Actions triggered by lobby and game board UI elements.
+pub enum LobbyAction {
+    ToggleReady,
+    Kick(u32),
+    MakeHost(u32),
+    ToggleSpectator(u32),
+    SendChat(String),
+    ChangeMaxPlayers(i8),
+    ToggleYahtzeeBonus,
+    NextVariant,
+    ToggleJokerRules,
+    ChangeBonusThreshold(i16),
+    AddAi(AiDifficulty),
+    RequestHint,
+    Start,
+    StartPeerGame,
+    ToggleHold(usize),
+    Roll,
+    Score(Category, usize),
+}
+
 pub enum GameEvent {
     ChangeGameScene(Box<dyn GameScene>),
+    LobbyAction(LobbyAction),
+    TournamentLoaded(TournamentInfo),
     WebSocketEvent(WebSocketEvent),
     PeerNetworkEvent(PeerNetworkEvent),
 }
\ No newline at end of file
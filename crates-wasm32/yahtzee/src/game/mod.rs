@@ -4,6 +4,7 @@ use alloc::boxed::Box;
 mod events;
 pub use events::GameEvent;
 
+mod board;
 mod scene;
 use scene::{GameScene, main::Main};
 use crate::render::Renderer;
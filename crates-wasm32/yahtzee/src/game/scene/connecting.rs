@@ -1,16 +1,38 @@
 use wasm_bindgen::prelude::*;
 use crate::network::{web_socket::WebSocket};
 use crate::event_loop::EventDispatcherProxy;
-use crate::game::events::{GameEvent, WebSocketEvent, WebSocketMessage};
-use super::{GameScene, lobby::Lobby};
+use crate::ui::{Ui, div::Div};
+use crate::game::events::{GameEvent, WebSocketEvent, SocketMessage};
+use super::{GameScene, lobby::Lobby, main::Main};
+
+const TOKEN_STORAGE_KEY: &str = "yahtzee_token";
+const REJOIN_DELAY: f64 = 3000.0;
+const MAX_REJOIN_ATTEMPTS: u32 = 40;
+
+#[derive(Clone, Copy)]
+pub enum JoinMode {
+    Play,
+    Spectate,
+    QuickPlay { players: u8 },
+    //Reattach to a lobby after losing the connection, retrying while the server restarts.
+    //Spectators come back as spectators rather than asking for a seat.
+    Rejoin { lobby_id: u64, spectate: bool },
+}
 
 pub struct Connecting {
+    _ui: Ui,
+    display_status: Div,
     event_sender: EventDispatcherProxy<GameEvent>,
-    web_socket: Option<WebSocket<WebSocketMessage>>,
+    web_socket: Option<WebSocket<SocketMessage>>,
+    ws_address: String,
     name: String,
+    mode: JoinMode,
+    rejoin_attempts: u32,
+    rejoin_at: Option<f64>,
+    time: f64,
 }
 impl Connecting {
-    pub fn new(event_sender: EventDispatcherProxy<GameEvent>, name: String) -> Self {
+    pub fn new(event_sender: EventDispatcherProxy<GameEvent>, name: String, mode: JoinMode) -> Self {
         let window = web_sys::window().unwrap_throw();
         let location = window.location();
         let protocol = location.protocol().unwrap_throw();
@@ -18,39 +40,118 @@ impl Connecting {
         let path = location.pathname().unwrap_throw();
         let search = location.search().unwrap_throw();
         let ws_protocol = if protocol.contains("https:") { "wss:" } else { "ws:" };
-        let ws_address = format!("{ws_protocol}//{host}{path}ws{search}");
 
-        let event_sender_clone = event_sender.clone();
-        let web_socket = WebSocket::new(ws_address.as_str(), move |message| {
-            event_sender_clone.send(GameEvent::WebSocketEvent(message));
-        });
+        //Forward the page's query (lobby id) and identify as the guest stored in local storage, if any.
+        //Quick play ignores the lobby id and asks the matchmaker for a game instead.
+        let mut query = Vec::new();
+        let endpoint = match mode {
+            JoinMode::Play | JoinMode::Spectate => {
+                query.extend(search.trim_start_matches('?').split('&').filter(|param| !param.is_empty()).map(String::from));
+                "ws"
+            }
+            JoinMode::QuickPlay { players } => {
+                query.push(format!("players={players}"));
+                "quickplay"
+            }
+            JoinMode::Rejoin { lobby_id, .. } => {
+                query.push(format!("lobby_id={lobby_id}"));
+                "ws"
+            }
+        };
+        query.push(format!("name={}", js_sys::encode_uri_component(name.as_str())));
+        if let JoinMode::Spectate | JoinMode::Rejoin { spectate: true, .. } = mode {
+            query.push("spectate=true".to_string());
+        }
+        if let Some(token) = window.local_storage().ok().flatten().and_then(|storage| storage.get_item(TOKEN_STORAGE_KEY).ok().flatten()) {
+            query.push(format!("token={}", js_sys::encode_uri_component(token.as_str())));
+        }
+        let ws_address = format!("{ws_protocol}//{host}{path}{endpoint}?{}", query.join("&"));
+
+        let ui = Ui::new();
+        let display_status = ui.div().with_class("row");
+        display_status.set_text(if let JoinMode::Rejoin { .. } = mode { "Connection lost, reconnecting..." } else { "Connecting..." });
+        let web_socket = Self::open(&event_sender, ws_address.as_str());
+        if web_socket.is_none() {
+            display_status.set_text("Could not connect to the server.");
+        }
 
         Self {
+            _ui: ui,
+            display_status,
             event_sender,
-            web_socket: Some(web_socket),
-            name
+            web_socket,
+            ws_address,
+            name,
+            mode,
+            rejoin_attempts: 0,
+            rejoin_at: None,
+            time: 0.0,
         }
     }
+    //Keep trying to reattach while the server may be restarting.
+    fn retry_rejoin(&mut self) {
+        if let JoinMode::Rejoin { .. } = self.mode {
+            self.rejoin_attempts += 1;
+            if self.rejoin_attempts < MAX_REJOIN_ATTEMPTS {
+                self.rejoin_at = Some(self.time + REJOIN_DELAY);
+            }
+            else {
+                log::warn!("Could not reconnect to lobby");
+                self.event_sender.send(GameEvent::ChangeGameScene(Box::new(Main::new(self.event_sender.clone()))));
+            }
+        }
+    }
+    fn open(event_sender: &EventDispatcherProxy<GameEvent>, ws_address: &str) -> Option<WebSocket<SocketMessage>> {
+        let event_sender = event_sender.clone();
+        WebSocket::new(ws_address, move |message| {
+            event_sender.send(GameEvent::WebSocketEvent(message));
+        }).map_err(|error| log::warn!("Could not open web socket: {error}")).ok()
+    }
 }
 impl GameScene for Connecting {
-    fn update(&mut self, _time: f64) {}
+    fn update(&mut self, time: f64) {
+        self.time = time;
+        if self.rejoin_at.is_some_and(|rejoin_at| time >= rejoin_at) {
+            self.rejoin_at = None;
+            self.web_socket = Self::open(&self.event_sender, self.ws_address.as_str());
+            //Count a socket that could not even be opened as a failed attempt.
+            if self.web_socket.is_none() {
+                self.retry_rejoin();
+            }
+        }
+    }
 
     fn handle_event(&mut self, event: GameEvent) {
         if let GameEvent::WebSocketEvent(event) = event {
             match event {
                 WebSocketEvent::Connect => {}
-                WebSocketEvent::Disconnect => {}
-                WebSocketEvent::Message(WebSocketMessage::ConnectSuccess { lobby_id, user_id, peers_id }) => {
+                WebSocketEvent::Error(error) => log::warn!("Server connection error: {error}"),
+                WebSocketEvent::Disconnect { code, reason } => {
+                    log::info!("Server connection closed ({code}) {reason}");
+                    self.retry_rejoin();
+                }
+                WebSocketEvent::Message(SocketMessage::ConnectSuccess { lobby_id, user_id, player_id, token, peers_id }) => {
+                    if let Some(storage) = web_sys::window().unwrap_throw().local_storage().ok().flatten() {
+                        let _ = storage.set_item(TOKEN_STORAGE_KEY, token.as_str());
+                    }
                     self.event_sender.send(GameEvent::ChangeGameScene(Box::new(
                         Lobby::new(self.event_sender.clone(),
-                                   self.web_socket.take().unwrap(),
+                                   Box::new(self.web_socket.take().unwrap()),
                                    lobby_id,
                                    std::mem::take(&mut self.name),
                                    user_id,
+                                   player_id,
                                    peers_id
                         )
                     )));
                 }
+                WebSocketEvent::Message(SocketMessage::QueueStatus { waiting, players }) => {
+                    self.display_status.set_text(format!("Looking for players... ({waiting}/{players})").as_str());
+                }
+                WebSocketEvent::Message(SocketMessage::JoinRejected { reason }) => {
+                    log::warn!("Could not join lobby: {reason}");
+                    self.event_sender.send(GameEvent::ChangeGameScene(Box::new(Main::new(self.event_sender.clone()))));
+                }
                 _ => {}
             }
         }
@@ -1,35 +1,86 @@
 use wasm_bindgen::prelude::*;
 use std::collections::BTreeMap;
+use yahtzee_rules::{Action, GameState, Variant, MAX_DICE};
 
-use crate::network::{web_socket::WebSocket, peer_network::PeerNetwork};
+use yahtzee_net::{Delivery, DiceEvent, FairDice, HostSync, PeerGame, PeerIntent, PeerStats, Seed, SignalingChannel, SyncEvent, Transport};
+use crate::network::peer_network::PeerNetwork;
 use crate::event_loop::EventDispatcherProxy;
-use crate::game::events::{GameEvent, PeerMessage, PeerNetworkEvent, WebSocketEvent, WebSocketMessage};
-use crate::game::scene::GameScene;
-use crate::ui::{Ui, div::Div};
+use crate::game::board::Board;
+use crate::game::events::{AiDifficulty, GameEvent, LobbyAction, LobbyPhase, LobbySettings, LobbyUser, PeerMessage, PeerNetworkEvent, WebSocketEvent, SocketMessage};
+use crate::game::scene::{GameScene, connecting::{Connecting, JoinMode}};
+use crate::ui::{Ui, div::Div, button::Button};
 
 struct UserData {
     display_container: Div,
     display_name: Div,
+    display_status: Div,
     display_ping: Div,
-    ping_timestamp: Option<f64>,
+    kick_button: Button,
+    host_button: Button,
+    seat_button: Button,
 }
 impl UserData {
-    fn new() -> Self {
+    fn new(event_sender: &EventDispatcherProxy<GameEvent>, user_id: u32) -> Self {
         let document = web_sys::window().unwrap_throw().document().unwrap_throw();
         let display_container = Div::new(document).with_class("user-display");
         let display_name = display_container.div();
+        let display_status = display_container.div();
         let display_ping = display_container.div();
+        let kick_button = {
+            let event_sender = event_sender.clone();
+            display_container.button().with_text("Kick").with_callback(move || {
+                event_sender.send(GameEvent::LobbyAction(LobbyAction::Kick(user_id)));
+            })
+        };
+        let host_button = {
+            let event_sender = event_sender.clone();
+            display_container.button().with_text("Make host").with_callback(move || {
+                event_sender.send(GameEvent::LobbyAction(LobbyAction::MakeHost(user_id)));
+            })
+        };
+        let seat_button = {
+            let event_sender = event_sender.clone();
+            display_container.button().with_callback(move || {
+                event_sender.send(GameEvent::LobbyAction(LobbyAction::ToggleSpectator(user_id)));
+            })
+        };
+        kick_button.hide();
+        host_button.hide();
+        seat_button.hide();
         Self {
             display_container,
             display_name,
+            display_status,
             display_ping,
-            ping_timestamp: None,
+            kick_button,
+            host_button,
+            seat_button,
         }
     }
     fn set_name(&self, name: &str) {
         self.display_name.clear();
         self.display_name.text(name);
     }
+    //Ping and connection type, with the rest of the stats on hover.
+    fn show_stats(&self, stats: &PeerStats) {
+        let ping = stats.timing.rtt.map_or("? ms".to_string(), |rtt| format!("{rtt:.0} ms"));
+        match stats.connection.candidate_type.as_deref() {
+            Some(candidate_type) => self.display_ping.set_text(format!("{ping} ({candidate_type})").as_str()),
+            None => self.display_ping.set_text(ping.as_str()),
+        }
+        let mut details = Vec::new();
+        if let Some(clock_offset) = stats.timing.clock_offset {
+            details.push(format!("Clock offset {clock_offset:+.1} ms"));
+        }
+        if let Some(packet_loss) = stats.timing.packet_loss {
+            details.push(format!("Packet loss {:.0}%", packet_loss * 100.0));
+        }
+        if let Some(ice_rtt) = stats.connection.ice_rtt {
+            details.push(format!("ICE round trip {ice_rtt:.0} ms"));
+        }
+        details.push(format!("{} kB sent, {} kB received", stats.connection.bytes_sent / 1000, stats.connection.bytes_received / 1000));
+        self.display_ping.set_title(details.join("\n").as_str());
+    }
 }
 impl Drop for UserData {
     fn drop(&mut self) {
@@ -39,15 +90,45 @@ impl Drop for UserData {
 
 pub struct Lobby {
     _ui: Ui,
+    event_sender: EventDispatcherProxy<GameEvent>,
     display_users: Div,
+    display_spectators: Div,
+    display_chat: Div,
+    display_phase: Div,
+    display_settings: Div,
+    display_game: Div,
+    display_notices: Div,
+    ready_button: Button,
+    spectate_button: Button,
+    start_button: Button,
+    host_controls: Div,
     username: String,
-    web_socket: WebSocket<WebSocketMessage>,
-    peer_network: PeerNetwork<PeerMessage>,
+    lobby_id: u64,
+    user_id: u32,
+    removed: bool,
+    web_socket: Box<dyn SignalingChannel<SocketMessage>>,
+    peer_network: Box<dyn Transport<PeerMessage>>,
+    //Games started between peers, hosted by the peer with the lowest user id.
+    sync: HostSync<PeerGame>,
+    dice: FairDice,
+    //Last peer game roll whose faces were checked against the revealed seeds.
+    checked_round: u32,
     users_list: BTreeMap<u32, UserData>,
+    names: BTreeMap<u32, String>,
+    spectators: Vec<u32>,
+    bots: Vec<u32>,
+    host_id: Option<u32>,
+    phase: LobbyPhase,
+    settings: Option<LobbySettings>,
+    ready: bool,
+    spectator: bool,
+    game: Option<GameState>,
+    board: Option<Board>,
+    held: [bool; MAX_DICE],
 }
 impl Lobby {
-    pub fn new(event_sender: EventDispatcherProxy<GameEvent>, web_socket: WebSocket<WebSocketMessage>, lobby_id: u64,
-               username: String, user_id: u32, peers_id: Vec<u32>) -> Self {
+    pub fn new(event_sender: EventDispatcherProxy<GameEvent>, web_socket: Box<dyn SignalingChannel<SocketMessage>>, lobby_id: u64,
+               username: String, user_id: u32, player_id: u64, peers_id: Vec<u32>) -> Self {
         let window = web_sys::window().unwrap_throw();
         let location = window.location();
         let protocol = location.protocol().unwrap_throw();
@@ -63,22 +144,136 @@ impl Lobby {
             ui.div().with_class("row").text("Users in this lobby:");
 
         let display_users = ui.div().with_class("user-display-list");
-        log::info!("Assigned id {} in lobby {} with {} users", user_id, lobby_id, peers_id.len());
+            ui.div().with_class("row").text("Spectators:");
+        let display_spectators = ui.div().with_class("user-display-list");
+        let display_phase = ui.div().with_class("row");
+        let display_settings = ui.div().with_class("row");
 
-        let peer_network = PeerNetwork::new(user_id, move |message| {
-            event_sender.send(GameEvent::PeerNetworkEvent(message));
-        });
+        //Lobby controls. Host controls are only shown to the host.
+        let controls = ui.div().with_class("row");
+        let ready_button = {
+            let event_sender = event_sender.clone();
+            controls.button().with_text("Ready").with_callback(move || {
+                event_sender.send(GameEvent::LobbyAction(LobbyAction::ToggleReady));
+            })
+        };
+        let spectate_button = {
+            let event_sender = event_sender.clone();
+            controls.button().with_text("Spectate").with_callback(move || {
+                event_sender.send(GameEvent::LobbyAction(LobbyAction::ToggleSpectator(user_id)));
+            })
+        };
+        let host_controls = controls.div();
+        let start_button = {
+            let event_sender = event_sender.clone();
+            host_controls.button().with_text("Start game").with_callback(move || {
+                event_sender.send(GameEvent::LobbyAction(LobbyAction::Start));
+            })
+        };
+        {
+            let event_sender = event_sender.clone();
+            host_controls.button().with_text("Start peer game").with_callback(move || {
+                event_sender.send(GameEvent::LobbyAction(LobbyAction::StartPeerGame));
+            });
+        }
+        for (text, action) in [("Fewer players", -1), ("More players", 1)] {
+            let event_sender = event_sender.clone();
+            host_controls.button().with_text(text).with_callback(move || {
+                event_sender.send(GameEvent::LobbyAction(LobbyAction::ChangeMaxPlayers(action)));
+            });
+        }
+        {
+            let event_sender = event_sender.clone();
+            host_controls.button().with_text("Toggle Yahtzee bonus").with_callback(move || {
+                event_sender.send(GameEvent::LobbyAction(LobbyAction::ToggleYahtzeeBonus));
+            });
+        }
+        {
+            let event_sender = event_sender.clone();
+            host_controls.button().with_text("Change variant").with_callback(move || {
+                event_sender.send(GameEvent::LobbyAction(LobbyAction::NextVariant));
+            });
+        }
+        {
+            let event_sender = event_sender.clone();
+            host_controls.button().with_text("Toggle joker rules").with_callback(move || {
+                event_sender.send(GameEvent::LobbyAction(LobbyAction::ToggleJokerRules));
+            });
+        }
+        for (text, change) in [("Lower bonus threshold", -1), ("Raise bonus threshold", 1)] {
+            let event_sender = event_sender.clone();
+            host_controls.button().with_text(text).with_callback(move || {
+                event_sender.send(GameEvent::LobbyAction(LobbyAction::ChangeBonusThreshold(change)));
+            });
+        }
+        for (text, difficulty) in [("Add easy bot", AiDifficulty::Easy), ("Add normal bot", AiDifficulty::Normal), ("Add hard bot", AiDifficulty::Hard), ("Add expert bot", AiDifficulty::Expert)] {
+            let event_sender = event_sender.clone();
+            host_controls.button().with_text(text).with_callback(move || {
+                event_sender.send(GameEvent::LobbyAction(LobbyAction::AddAi(difficulty)));
+            });
+        }
+        host_controls.hide();
+
+        let display_game = ui.div().with_class("game");
+        let display_notices = ui.div().with_class("notice-list");
+
+        //Chat is shared by players and spectators.
+        let display_chat = ui.div().with_class("chat-log");
+        {
+            let event_sender = event_sender.clone();
+            let chat_input = ui.div().with_class("row").text_input().with_max_length(200);
+            let chat_input_clone = chat_input.clone();
+            chat_input.with_callback(move |text| {
+                chat_input_clone.set_value("");
+                event_sender.send(GameEvent::LobbyAction(LobbyAction::SendChat(text)));
+            });
+        }
+        log::info!("Assigned id {} (player {}) in lobby {} with {} users", user_id, player_id, lobby_id, peers_id.len());
+
+        let event_sender_clone = event_sender.clone();
+        let peer_network: Box<dyn Transport<PeerMessage>> = Box::new(PeerNetwork::new(user_id, move |message| {
+            event_sender_clone.send(GameEvent::PeerNetworkEvent(message));
+        }));
         for &peer_id in peers_id.iter() {
-            peer_network.initiate_handshake(peer_id);
+            peer_network.connect(peer_id);
         }
+        let sync = HostSync::new(user_id, peers_id.iter().copied(), PeerGame::default());
 
         let mut lobby_state = Self {
             _ui: ui,
+            event_sender,
             display_users,
+            display_spectators,
+            display_chat,
+            display_phase,
+            display_settings,
+            display_game,
+            display_notices,
+            ready_button,
+            spectate_button,
+            start_button,
+            host_controls,
             username: username.clone(),
+            lobby_id,
+            removed: false,
+            user_id,
             web_socket,
             peer_network,
+            sync,
+            dice: FairDice::new(user_id),
+            checked_round: 0,
             users_list: BTreeMap::new(),
+            names: BTreeMap::new(),
+            spectators: Vec::new(),
+            bots: Vec::new(),
+            host_id: None,
+            phase: LobbyPhase::Waiting,
+            settings: None,
+            ready: false,
+            spectator: false,
+            game: None,
+            board: None,
+            held: [false; MAX_DICE],
         };
         for peer_id in peers_id {
             lobby_state.add_user(peer_id)
@@ -89,7 +284,10 @@ impl Lobby {
     }
 
     fn add_user(&mut self, user_id: u32) {
-        let user = UserData::new();
+        if self.users_list.contains_key(&user_id) {
+            return
+        }
+        let user = UserData::new(&self.event_sender, user_id);
         user.set_name("Connecting...");
         self.users_list.insert(user_id, user);
         for peer in self.users_list.values() {
@@ -103,50 +301,379 @@ impl Lobby {
         }
     }
 
+    fn show_notice(&self, text: &str) {
+        self.display_notices.div().with_class("row notice").text(text);
+    }
+
     fn update_user(&self, user_id: u32, name: &str) {
         if let Some(user) = self.users_list.get(&user_id) {
             user.display_name.clear();
             user.display_name.text(name);
         }
     }
+
+    //Bring the user list and controls in line with the lobby state broadcast by the server.
+    fn update_lobby(&mut self, host_id: u32, phase: LobbyPhase, settings: LobbySettings, users: Vec<LobbyUser>) {
+        let user_ids = users.iter().map(|user| user.user_id).collect::<Vec<_>>();
+        let departed = self.users_list.keys().filter(|user_id| !user_ids.contains(user_id)).copied().collect::<Vec<_>>();
+        for user_id in departed {
+            self.remove_user(user_id);
+        }
+        let is_host = host_id == self.user_id;
+        let can_configure = matches!(phase, LobbyPhase::Waiting | LobbyPhase::Finished);
+        let all_ready = users.iter().filter(|user| !user.spectator).all(|user| user.ready);
+        self.names.clear();
+        self.spectators = users.iter().filter(|user| user.spectator).map(|user| user.user_id).collect();
+        self.bots = users.iter().filter(|user| user.ai).map(|user| user.user_id).collect();
+        for user in users {
+            self.add_user(user.user_id);
+            self.update_user(user.user_id, user.display_name.as_str());
+            if let Some(user_data) = self.users_list.get(&user.user_id) {
+                let mut status = Vec::new();
+                if user.user_id == host_id {
+                    status.push("Host");
+                }
+                if user.ai {
+                    status.push("Bot");
+                }
+                if user.ready {
+                    status.push("Ready");
+                }
+                user_data.display_status.set_text(status.join(", ").as_str());
+                //Bots can only be removed, not made host or moved to the spectators.
+                let is_other = is_host && user.user_id != self.user_id;
+                if is_other { user_data.kick_button.show() } else { user_data.kick_button.hide() }
+                if is_other && !user.ai { user_data.host_button.show() } else { user_data.host_button.hide() }
+                user_data.seat_button.set_text(if user.spectator { "Give seat" } else { "Move to spectators" });
+                if is_other && can_configure && !user.ai { user_data.seat_button.show() } else { user_data.seat_button.hide() }
+
+                //Players and spectators are listed separately.
+                if user.spectator {
+                    self.display_spectators.append_child(&user_data.display_container);
+                }
+                else {
+                    self.display_users.append_child(&user_data.display_container);
+                }
+            }
+            if user.user_id == self.user_id {
+                self.ready = user.ready;
+                self.spectator = user.spectator;
+            }
+            self.names.insert(user.user_id, user.display_name);
+        }
+
+        self.display_phase.set_text(match phase {
+            LobbyPhase::Waiting => "Waiting for players to get ready",
+            LobbyPhase::Starting => "Game starting...",
+            LobbyPhase::InGame => "Game in progress",
+            LobbyPhase::Finished => "Game finished. Get ready for a rematch!",
+        });
+        let game = settings.game;
+        let mut text = format!("Max players: {}, {}, upper bonus at {}", settings.max_players, game.variant.name(), game.upper_bonus_threshold());
+        if game.variant.has_jokers() {
+            let on_off = |on: bool| if on { "on" } else { "off" };
+            text += format!(", Yahtzee bonus: {}, joker rules: {}", on_off(game.yahtzee_bonus), on_off(game.joker_rules)).as_str();
+        }
+        self.display_settings.set_text(text.as_str());
+        self.ready_button.set_text(if self.ready { "Not ready" } else { "Ready" });
+        self.spectate_button.set_text(if self.spectator { "Take a seat" } else { "Spectate" });
+        if can_configure && !self.spectator { self.ready_button.show() } else { self.ready_button.hide() }
+        if can_configure { self.spectate_button.show() } else { self.spectate_button.hide() }
+        if is_host && can_configure { self.host_controls.show() } else { self.host_controls.hide() }
+        if is_host && can_configure && all_ready { self.start_button.show() } else { self.start_button.hide() }
+
+        self.host_id = Some(host_id);
+        self.phase = phase;
+        self.settings = Some(settings);
+        self.update_board();
+    }
+
+    fn is_spectator(&self, user_id: u32) -> bool {
+        self.spectators.contains(&user_id)
+    }
+
+    fn update_game(&mut self, state: GameState) {
+        if !state.has_rolled() || state.current_player() != Some(self.user_id) {
+            self.held = [false; MAX_DICE];
+        }
+        if !self.board.as_ref().is_some_and(|board| board.is_for(&state)) {
+            self.board = Some(Board::new(&self.display_game, &self.event_sender, &state, self.user_id, &self.names));
+        }
+        self.game = Some(state);
+        self.update_board();
+    }
+
+    fn update_board(&self) {
+        if let (Some(board), Some(state)) = (self.board.as_ref(), self.game.as_ref()) {
+            board.update(state, self.user_id, &self.held, &self.names);
+        }
+    }
+
+    fn handle_action(&mut self, action: LobbyAction) {
+        let is_host = self.host_id == Some(self.user_id);
+        let message = match action {
+            LobbyAction::ToggleReady => SocketMessage::SetReady { ready: !self.ready },
+            LobbyAction::Kick(user_id) if is_host => SocketMessage::KickUser { user_id },
+            LobbyAction::MakeHost(user_id) if is_host => SocketMessage::TransferHost { user_id },
+            LobbyAction::ToggleSpectator(user_id) if is_host || user_id == self.user_id => {
+                let spectator = if user_id == self.user_id { !self.spectator } else { !self.is_spectator(user_id) };
+                SocketMessage::SetSpectator { user_id, spectator }
+            }
+            LobbyAction::SendChat(text) => SocketMessage::SendChat { text },
+            LobbyAction::ChangeMaxPlayers(change) if is_host => {
+                let Some(mut settings) = self.settings else { return };
+                settings.max_players = settings.max_players.saturating_add_signed(change).max(1);
+                SocketMessage::SetSettings { settings }
+            }
+            LobbyAction::ToggleYahtzeeBonus if is_host => {
+                let Some(mut settings) = self.settings else { return };
+                settings.game.yahtzee_bonus = !settings.game.yahtzee_bonus;
+                SocketMessage::SetSettings { settings }
+            }
+            LobbyAction::NextVariant if is_host => {
+                let Some(mut settings) = self.settings else { return };
+                let index = Variant::ALL.iter().position(|&variant| variant == settings.game.variant).unwrap_or(0);
+                settings.game.variant = Variant::ALL[(index + 1) % Variant::ALL.len()];
+                //A custom threshold made for one variant rarely suits another.
+                settings.game.upper_bonus_threshold = None;
+                SocketMessage::SetSettings { settings }
+            }
+            LobbyAction::ToggleJokerRules if is_host => {
+                let Some(mut settings) = self.settings else { return };
+                settings.game.joker_rules = !settings.game.joker_rules;
+                SocketMessage::SetSettings { settings }
+            }
+            LobbyAction::ChangeBonusThreshold(change) if is_host => {
+                let Some(mut settings) = self.settings else { return };
+                settings.game.upper_bonus_threshold = Some(settings.game.upper_bonus_threshold().saturating_add_signed(change));
+                SocketMessage::SetSettings { settings }
+            }
+            LobbyAction::AddAi(difficulty) if is_host => SocketMessage::AddAi { difficulty },
+            LobbyAction::RequestHint => SocketMessage::RequestHint,
+            LobbyAction::Start if is_host => SocketMessage::StartGame,
+            //Bots are run by the server, so a peer game is played by the people in the lobby.
+            LobbyAction::StartPeerGame if is_host => {
+                let Some(settings) = self.settings else { return };
+                let players = self.users_list.keys().copied().filter(|user_id| !self.is_spectator(*user_id) && !self.bots.contains(user_id)).collect();
+                return self.send_intent(PeerIntent::Start { players, options: settings.game })
+            }
+            LobbyAction::ToggleHold(index) => {
+                if self.game.as_ref().is_some_and(|state| state.has_rolled()) {
+                    self.held[index] = !self.held[index];
+                    self.update_board();
+                }
+                return
+            }
+            LobbyAction::Roll => return self.send_game_action(Action::Roll { held: self.held }),
+            LobbyAction::Score(category, column) => return self.send_game_action(Action::Score(category, column)),
+            _ => return,
+        };
+        self.send_to_server(message);
+    }
+    //Actions go to whoever runs the game: the peer hosting a peer game, or else the server.
+    fn send_game_action(&mut self, action: Action) {
+        if self.sync.state().is_running() {
+            self.send_intent(PeerIntent::Action(action));
+        }
+        else {
+            self.send_to_server(SocketMessage::GameAction { action });
+        }
+    }
+    fn send_intent(&mut self, intent: PeerIntent) {
+        let events = self.sync.send_intent(&*self.peer_network, intent);
+        self.handle_sync_events(events);
+    }
+    fn handle_sync_events(&mut self, events: Vec<SyncEvent<PeerGame>>) {
+        for event in events {
+            match event {
+                SyncEvent::State => {
+                    if let Some(state) = self.sync.state().state.clone() {
+                        self.update_game(state);
+                    }
+                    self.check_dice();
+                    self.update_dice();
+                }
+                SyncEvent::Rejected(rejection) => log::warn!("Peer game action rejected: {:?}", rejection),
+                SyncEvent::HostChanged(host_id) => {
+                    log::info!("{} is now hosting the peer game", host_id);
+                    self.update_dice();
+                }
+                SyncEvent::Error(error) => log::warn!("Could not sync peer game: {error}"),
+            }
+        }
+    }
+    //Roll the dice of a pending peer game roll together with the other peers. Whoever hosts the
+    //game resolves the roll once every seed is revealed.
+    fn update_dice(&mut self) {
+        let Some(round) = self.sync.state().pending_round() else { return };
+        let players = self.sync.state().players();
+        let events = self.dice.begin(&*self.peer_network, round, players, random_seed);
+        self.handle_dice_events(events);
+        if self.sync.is_host() {
+            if let Some(rolls) = self.dice.rolls(round) {
+                let events = self.sync.modify(&*self.peer_network, |game| game.resolve_roll(round, rolls));
+                self.handle_sync_events(events);
+            }
+        }
+    }
+    //Make sure the host used the dice everyone derived from the revealed seeds. A host that did not
+    //is dropped from the game, and the next peer takes over hosting.
+    fn check_dice(&mut self) {
+        let game = self.sync.state();
+        let round = game.rolls;
+        if round <= self.checked_round || self.sync.is_host() {
+            return
+        }
+        if let Some(rolls) = self.dice.rolls(round) {
+            self.checked_round = round;
+            if !rolls.take(game.last_faces.len()).eq(game.last_faces.iter().copied()) {
+                let name = self.names.get(&self.sync.host_id()).cloned().unwrap_or_else(|| "The host".to_string());
+                self.show_notice(format!("{name} rolled dice that do not match the revealed seeds and was removed from the game").as_str());
+                let events = self.sync.depose(&*self.peer_network);
+                self.handle_sync_events(events);
+            }
+        }
+    }
+    fn handle_dice_events(&mut self, events: Vec<DiceEvent>) {
+        for event in events {
+            match event {
+                DiceEvent::Ready(_) => {
+                    self.update_dice();
+                    self.check_dice();
+                }
+                DiceEvent::Flagged { round, user_id } => {
+                    let name = self.names.get(&user_id).map(String::as_str).unwrap_or("?");
+                    self.show_notice(format!("{name} broke the dice protocol in roll {round}, by revealing a seed that does not match its commitment or leaving before revealing it").as_str());
+                }
+                DiceEvent::Error(error) => log::warn!("Could not roll peer game dice: {error}"),
+            }
+        }
+    }
+    fn send_to_server(&self, message: SocketMessage) {
+        if let Err(error) = self.web_socket.send(message) {
+            log::warn!("Could not send message to server: {error}");
+        }
+    }
 }
 impl GameScene for Lobby {
-    fn update(&mut self, _time: f64) {}
+    fn update(&mut self, time: f64) {
+        self.peer_network.update(time);
+        let events = self.sync.update(&*self.peer_network, time);
+        self.handle_sync_events(events);
+    }
 
     fn handle_event(&mut self, event: GameEvent) {
         match event {
-            GameEvent::ChangeGameScene(_) => {}
+            GameEvent::ChangeGameScene(_) | GameEvent::TournamentLoaded(_) => {}
+            GameEvent::LobbyAction(action) => self.handle_action(action),
             GameEvent::WebSocketEvent(event) => match event {
                 WebSocketEvent::Connect => {}
-                WebSocketEvent::Disconnect => {}
-                WebSocketEvent::Message(message) => if let WebSocketMessage::PeerHandshake { .. } = message {
-                    self.peer_network.receive_handshake(message.into());
+                WebSocketEvent::Error(error) => log::warn!("Server connection error: {error}"),
+                //Unless the server sent us away, try to get our seat back.
+                WebSocketEvent::Disconnect { code, reason } => {
+                    log::info!("Server connection closed ({code}) {reason}");
+                    if !self.removed {
+                        let connecting = Connecting::new(self.event_sender.clone(), self.username.clone(), JoinMode::Rejoin { lobby_id: self.lobby_id, spectate: self.spectator });
+                        self.event_sender.send(GameEvent::ChangeGameScene(Box::new(connecting)));
+                    }
+                }
+                WebSocketEvent::Message(message) => match message {
+                    SocketMessage::WebRtcHandshake { .. } => self.peer_network.receive_handshake(message.into()),
+                    SocketMessage::Kicked { reason } => {
+                        self.removed = true;
+                        self.show_notice(format!("You were removed from the lobby: {reason}").as_str());
+                    }
+                    SocketMessage::LobbyClosed { reason } => {
+                        self.removed = true;
+                        self.show_notice(format!("The lobby was closed: {reason}").as_str());
+                    }
+                    SocketMessage::ServerNotice { message } => self.show_notice(message.as_str()),
+                    SocketMessage::LobbyState { host_id, phase, settings, users } => self.update_lobby(host_id, phase, settings, users),
+                    SocketMessage::GameState { state } => self.update_game(state),
+                    SocketMessage::Hint { choices } => {
+                        if let Some(board) = self.board.as_ref() {
+                            board.show_hint(&choices);
+                        }
+                    }
+                    SocketMessage::GameActionRejected { error } => log::warn!("Game action rejected: {:?}", error),
+                    SocketMessage::Chat { user_id, text } => {
+                        let name = self.names.get(&user_id).map(String::as_str).unwrap_or("?");
+                        self.display_chat.div().with_class("row chat").text(format!("{name}: {text}").as_str());
+                    }
+                    _ => {}
                 }
             },
             GameEvent::PeerNetworkEvent(event) => match event {
                 PeerNetworkEvent::Connect(peer_id) => {
                     self.add_user(peer_id);
-                    self.peer_network.send(peer_id, &PeerMessage::Ping);
+                    if let Some(user) = self.users_list.get(&peer_id) {
+                        user.display_ping.clear();
+                    }
+                    if let Err(error) = self.peer_network.send(peer_id, &PeerMessage::Ping, Delivery::Unreliable) {
+                        log::warn!("Could not ping {peer_id}: {error}");
+                    }
+                    let events = self.sync.connect(&*self.peer_network, peer_id);
+                    self.handle_sync_events(events);
+                },
+                PeerNetworkEvent::Reconnecting(peer_id) => {
+                    if let Some(user) = self.users_list.get(&peer_id) {
+                        user.display_ping.set_text("Reconnecting...");
+                    }
+                },
+                PeerNetworkEvent::Stats(peer_id, stats) => {
+                    if let Some(user) = self.users_list.get(&peer_id) {
+                        user.show_stats(&stats);
+                    }
                 },
                 PeerNetworkEvent::Disconnect(peer_id) => {
-                    self.remove_user(peer_id)
+                    //The server's lobby state decides who is in the lobby; only drop peers it no longer lists.
+                    if !self.names.contains_key(&peer_id) {
+                        self.remove_user(peer_id)
+                    }
+                    let events = self.dice.disconnect(&*self.peer_network, peer_id);
+                    self.handle_dice_events(events);
+                    let events = self.sync.disconnect(&*self.peer_network, peer_id);
+                    self.handle_sync_events(events);
                 },
                 PeerNetworkEvent::Message(peer_id, message) => {
                     match message {
                         PeerMessage::Ping => {
                             log::info!("Received ping");
-                            self.peer_network.send(peer_id, &PeerMessage::Pong(self.username.clone()));
+                            if let Err(error) = self.peer_network.send(peer_id, &PeerMessage::Pong(self.username.clone()), Delivery::Reliable) {
+                                log::warn!("Could not answer ping from {peer_id}: {error}");
+                            }
                         }
                         PeerMessage::Pong(name) => {
                             log::info!("Received pong");
-                            self.update_user(peer_id, name.as_str());
+                            if !self.names.contains_key(&peer_id) {
+                                self.update_user(peer_id, name.as_str());
+                            }
+                        }
+                        PeerMessage::Sync(message) => {
+                            let events = self.sync.receive(&*self.peer_network, peer_id, message);
+                            self.handle_sync_events(events);
+                        }
+                        PeerMessage::Dice(message) => {
+                            let events = self.dice.receive(&*self.peer_network, peer_id, message);
+                            self.handle_dice_events(events);
                         }
                     }
                 },
-                PeerNetworkEvent::Handshake(handshake) => {
-                    self.web_socket.send(handshake.into());
-                }
+                PeerNetworkEvent::Handshake(handshake) => self.send_to_server(handshake.into()),
+                PeerNetworkEvent::Error(peer_id, error) => log::warn!("Peer {peer_id}: {error}"),
             },
         }
     }
-}
\ No newline at end of file
+}
+
+//Our part of the seed for peer game dice. Falls back to Math.random if the browser has no crypto.
+fn random_seed() -> Seed {
+    let mut seed = [0; 32];
+    let crypto = web_sys::window().and_then(|window| window.crypto().ok());
+    if !crypto.is_some_and(|crypto| crypto.get_random_values_with_u8_array(&mut seed).is_ok()) {
+        for byte in seed.iter_mut() {
+            *byte = (js_sys::Math::random() * 256.0) as u8;
+        }
+    }
+    seed
+}
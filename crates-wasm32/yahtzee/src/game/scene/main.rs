@@ -1,10 +1,16 @@
 extern crate alloc;
 use alloc::boxed::Box;
+use std::{cell::Cell, rc::Rc};
 
 use crate::ui::Ui;
 use crate::event_loop::EventDispatcherProxy;
 use crate::game::events::GameEvent;
-use super::{GameScene, connecting::Connecting};
+use wasm_bindgen::prelude::*;
+
+use super::{GameScene, connecting::{Connecting, JoinMode}, tournament::Tournament};
+
+const MIN_QUICK_PLAY_PLAYERS: u8 = 2;
+const MAX_QUICK_PLAY_PLAYERS: u8 = 4;
 
 pub struct Main {
     _ui: Ui,
@@ -19,14 +25,54 @@ impl Main {
             let event_sender_clone = event_sender.clone();
             let name_input = ui.text_input().with_max_length(16).with_callback(move |name| {
                 event_sender_clone.send(GameEvent::ChangeGameScene(Box::new(
-                    Connecting::new(event_sender_clone.clone(), name)
+                    Connecting::new(event_sender_clone.clone(), name, JoinMode::Play)
                 )));
             });
             name_input.clone().focus();
 
+            let event_sender_clone = event_sender.clone();
+            let name_input_clone = name_input.clone();
             ui.button().with_text("Join Lobby").with_callback(move || {
+                event_sender_clone.send(GameEvent::ChangeGameScene(Box::new(
+                    Connecting::new(event_sender_clone.clone(), name_input_clone.value(), JoinMode::Play)
+                )));
+            });
+
+            let event_sender_clone = event_sender.clone();
+            let name_input_clone = name_input.clone();
+            ui.button().with_text("Watch Lobby").with_callback(move || {
+                event_sender_clone.send(GameEvent::ChangeGameScene(Box::new(
+                    Connecting::new(event_sender_clone.clone(), name_input_clone.value(), JoinMode::Spectate)
+                )));
+            });
+
+            //Quick play matches strangers by preferred game size.
+            let players = Rc::new(Cell::new(MIN_QUICK_PLAY_PLAYERS));
+            let players_clone = players.clone();
+            let players_button = ui.button().with_text(format!("Players: {}", players.get()).as_str());
+            let players_button_clone = players_button.clone();
+            players_button.with_callback(move || {
+                let next = players_clone.get() + 1;
+                players_clone.set(if next > MAX_QUICK_PLAY_PLAYERS { MIN_QUICK_PLAY_PLAYERS } else { next });
+                players_button_clone.set_text(format!("Players: {}", players_clone.get()).as_str());
+            });
+            let event_sender_clone = event_sender.clone();
+            ui.button().with_text("Quick Play").with_callback(move || {
+                event_sender_clone.send(GameEvent::ChangeGameScene(Box::new(
+                    Connecting::new(event_sender_clone.clone(), name_input.value(), JoinMode::QuickPlay { players: players.get() })
+                )));
+            });
+        }
+
+        //Tournament links open the standings instead of a lobby.
+        let search = web_sys::window().unwrap_throw().location().search().unwrap_throw();
+        let tournament_id = search.trim_start_matches('?').split('&')
+            .find_map(|param| param.strip_prefix("tournament_id="))
+            .and_then(|tournament_id| tournament_id.parse::<u64>().ok());
+        if let Some(tournament_id) = tournament_id {
+            ui.div().with_class("row").button().with_text("View Tournament").with_callback(move || {
                 event_sender.send(GameEvent::ChangeGameScene(Box::new(
-                    Connecting::new(event_sender.clone(), name_input.value())
+                    Tournament::new(event_sender.clone(), tournament_id)
                 )));
             });
         }
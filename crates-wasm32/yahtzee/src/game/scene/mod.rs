@@ -3,6 +3,7 @@ use super::events::GameEvent;
 pub mod main;
 pub mod connecting;
 pub mod lobby;
+pub mod tournament;
 
 
 pub trait GameScene {
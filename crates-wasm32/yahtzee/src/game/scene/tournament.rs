@@ -0,0 +1,124 @@
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::Response;
+use std::collections::BTreeMap;
+
+use crate::event_loop::EventDispatcherProxy;
+use crate::game::events::{GameEvent, TournamentInfo};
+use crate::ui::{Ui, div::Div};
+use super::{GameScene, main::Main};
+
+const REFRESH_INTERVAL: f64 = 5000.0;
+
+pub struct Tournament {
+    _ui: Ui,
+    event_sender: EventDispatcherProxy<GameEvent>,
+    display_tournament: Div,
+    url: String,
+    page_path: String,
+    last_refresh: Option<f64>,
+}
+impl Tournament {
+    pub fn new(event_sender: EventDispatcherProxy<GameEvent>, tournament_id: u64) -> Self {
+        let location = web_sys::window().unwrap_throw().location();
+        let protocol = location.protocol().unwrap_throw();
+        let host = location.host().unwrap_throw();
+        let path = location.pathname().unwrap_throw();
+        let page_path = format!("{protocol}//{host}{path}");
+        let url = format!("{page_path}api/tournaments/{tournament_id}");
+
+        let ui = Ui::new();
+            ui.div().with_class("row heading").text("Yahtzee!");
+        let display_tournament = ui.div().with_class("tournament");
+            display_tournament.set_text("Loading tournament...");
+        {
+            let ui = ui.div().with_class("row");
+            let event_sender_clone = event_sender.clone();
+            ui.button().with_text("Back").with_callback(move || {
+                event_sender_clone.send(GameEvent::ChangeGameScene(Box::new(Main::new(event_sender_clone.clone()))));
+            });
+        }
+
+        Self {
+            _ui: ui,
+            event_sender,
+            display_tournament,
+            url,
+            page_path,
+            last_refresh: None,
+        }
+    }
+    fn refresh(&self) {
+        let event_sender = self.event_sender.clone();
+        let url = self.url.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            match fetch_tournament(url.as_str()).await {
+                Some(tournament) => event_sender.send(GameEvent::TournamentLoaded(tournament)),
+                None => log::warn!("Failed to load tournament from {url}"),
+            }
+        });
+    }
+    fn show(&self, tournament: TournamentInfo) {
+        let names = tournament.entrants.iter().map(|entrant| (entrant.player_id, entrant.display_name.as_str())).collect::<BTreeMap<_, _>>();
+        let name = |player_id: &u64| names.get(player_id).copied().unwrap_or("?");
+
+        self.display_tournament.clear();
+            self.display_tournament.div().with_class("row heading").text(tournament.name.as_str());
+            self.display_tournament.div().with_class("row").text(format!("{}, best of {}", tournament.format.replace('_', " "), tournament.best_of).as_str());
+        if let Some(winner) = tournament.winner.as_ref() {
+            self.display_tournament.div().with_class("row").text(format!("Winner: {}", name(winner)).as_str());
+        }
+
+        //Standings, best first.
+            self.display_tournament.div().with_class("row").text("Standings:");
+        for (place, standing) in tournament.standings.iter().enumerate() {
+            self.display_tournament.div().with_class("row standing").text(format!(
+                "{}. {} - series {}/{}, games {}, score {}",
+                place + 1, standing.display_name, standing.series_won, standing.series_played, standing.games_won, standing.total_score
+            ).as_str());
+        }
+
+        //Every round with a link into each series lobby that is still being played.
+        for (index, round) in tournament.rounds.iter().enumerate() {
+            self.display_tournament.div().with_class("row").text(format!("Round {}:", index + 1).as_str());
+            for series in round.series.iter() {
+                let row = self.display_tournament.div().with_class("row series");
+                row.div().text(format!(
+                    "{} {} - {} {} ({} - {})",
+                    name(&series.players[0]), series.wins[0], series.wins[1], name(&series.players[1]), series.totals[0], series.totals[1]
+                ).as_str());
+                if series.winner.is_none() {
+                    let link = format!("{}?lobby_id={}", self.page_path, series.lobby_id);
+                    row.anchor().with_text("Join").with_link(link.as_str());
+                }
+            }
+            for player_id in round.byes.iter() {
+                self.display_tournament.div().with_class("row series").text(format!("{} has a bye", name(player_id)).as_str());
+            }
+        }
+    }
+}
+impl GameScene for Tournament {
+    fn update(&mut self, time: f64) {
+        if self.last_refresh.is_none_or(|last_refresh| time - last_refresh >= REFRESH_INTERVAL) {
+            self.last_refresh = Some(time);
+            self.refresh();
+        }
+    }
+
+    fn handle_event(&mut self, event: GameEvent) {
+        if let GameEvent::TournamentLoaded(tournament) = event {
+            self.show(tournament);
+        }
+    }
+}
+
+async fn fetch_tournament(url: &str) -> Option<TournamentInfo> {
+    let window = web_sys::window()?;
+    let response = JsFuture::from(window.fetch_with_str(url)).await.ok()?.dyn_into::<Response>().ok()?;
+    if !response.ok() {
+        return None
+    }
+    let text = JsFuture::from(response.text().ok()?).await.ok()?.as_string()?;
+    serde_json::from_str(text.as_str()).ok()
+}
@@ -1,48 +1,84 @@
 use wasm_bindgen::prelude::*;
-use web_sys::{MessageEvent, RtcPeerConnectionIceEvent};
-use std::{rc::Rc, cell::RefCell, collections::BTreeMap};
+use web_sys::{MessageEvent, RtcDataChannelState, RtcPeerConnectionIceEvent};
+use std::{rc::Rc, cell::{Cell, RefCell}, collections::BTreeMap};
 use js_sys::{ArrayBuffer, Uint8Array};
-use serde::{Serialize, Deserialize, de::DeserializeOwned};
-use crate::network::webrtc::{ConfigurationBuilder, Configuration, PeerConnection, PeerConnectionState, DataChannel};
+use serde::{Serialize, de::DeserializeOwned};
+use yahtzee_net::{Delivery, Result, SdpType, Timing, Transport};
+use yahtzee_net::link::{Frame, Link};
+use crate::network::webrtc::{ConfigurationBuilder, Configuration, PeerConnection, PeerConnectionState, DataChannel, SignalingState};
 
-#[derive(Default)]
-pub struct PeerHandshake {
-    pub source_id: u32,
-    pub target_id: u32,
-    pub sdp_description: String,
-    pub ice_candidates: Vec<(String, Option<String>, Option<u16>)>,
-}
-
-#[derive(Serialize, Deserialize)]
-struct MessageWrapper<T>(u32, T);
+pub use yahtzee_net::{PeerHandshake, PeerNetworkEvent, PeerStats};
 
-pub enum PeerNetworkEvent<T> {
-    Handshake(PeerHandshake),
-    Connect(u32),
-    Disconnect(u32),
-    Message(u32, T),
+//Settings of the data channel each delivery class travels on, indexed by `Delivery as usize`.
+struct Lane {
+    label: &'static str,
+    ordered: bool,
+    max_retransmits: Option<u16>,
 }
+const LANES: [Lane; 2] = [
+    Lane { label: "Reliable", ordered: true, max_retransmits: None },
+    Lane { label: "Unreliable", ordered: false, max_retransmits: Some(0) },
+];
+
+//Milliseconds an interrupted connection gets to come back on its own before ICE is restarted, and between restarts.
+const ICE_RESTART_DELAY: f64 = 3000.0;
+//Milliseconds after which an interrupted connection is given up on.
+const RECOVERY_TIMEOUT: f64 = 15000.0;
+//Milliseconds between connection stats reports.
+const STATS_INTERVAL: f64 = 2000.0;
 
 enum PeerStatus {
-    Connecting(PeerHandshake),
+    Connecting,
     Connected,
+    //The connection dropped after being established. Data channels stay open while ICE recovers.
+    Recovering { since: f64, next_restart: f64 },
 }
 
-struct PeerData {
+struct PeerData<T> {
     status: PeerStatus,
+    //Our offer or answer to this peer while its ICE candidates are gathered.
+    handshake: Option<PeerHandshake>,
+    //Session of the remote peer network this connection was negotiated with, once known.
+    remote_session_id: Option<u32>,
+    //Set while our own offer is being created, so an offer crossing it is seen as a collision.
+    making_offer: bool,
     peer_connection: PeerConnection,
-    data_channel: DataChannel,
+    data_channels: Vec<DataChannel>, //One per lane.
+    link: Link<T>,
     _onconnectionstatechange_callback: Closure<dyn FnMut()>,
     _onicecandidate_callback: Closure<dyn FnMut(RtcPeerConnectionIceEvent)>,
-    _onopen_callback: Closure<dyn FnMut()>,
-    _onmessage_callback: Closure<dyn FnMut(MessageEvent)>,
+    _onopen_callbacks: Vec<Closure<dyn FnMut()>>,
+    _onmessage_callbacks: Vec<Closure<dyn FnMut(MessageEvent)>>,
+}
+impl<T> PeerData<T> {
+    fn is_connected(&self) -> bool {
+        matches!(self.status, PeerStatus::Connected | PeerStatus::Recovering { .. })
+    }
+    //Channels still opening or already closing are skipped. Reliable messages are resent by the link.
+    fn send(&self, delivery: Delivery, data: &[u8]) -> Result<()> {
+        let data_channel = &self.data_channels[delivery as usize];
+        if data_channel.ready_state() == RtcDataChannelState::Open {
+            data_channel.send_u8_array(data)?;
+        }
+        Ok(())
+    }
+    fn close(&self) {
+        for data_channel in self.data_channels.iter() {
+            data_channel.close();
+        }
+        self.peer_connection.close();
+    }
 }
 
 pub struct PeerNetwork<T> {
     user_id: u32,
+    session_id: u32,
     configuration: Configuration,
-    peer_map: Rc<RefCell<BTreeMap<u32, PeerData>>>,
+    peer_map: Rc<RefCell<BTreeMap<u32, PeerData<T>>>>,
     event_callback: Rc<RefCell<dyn FnMut(PeerNetworkEvent<T>)>>,
+    //Frame time of the last update, used to time retransmits and recovery.
+    time: Rc<Cell<f64>>,
+    next_stats: Cell<f64>,
 }
 
 impl<T: Serialize + DeserializeOwned + 'static> PeerNetwork<T> {
@@ -53,140 +89,402 @@ impl<T: Serialize + DeserializeOwned + 'static> PeerNetwork<T> {
             .build();
         Self {
             user_id,
+            session_id: (js_sys::Math::random() * u32::MAX as f64) as u32,
             configuration,
             peer_map: Rc::new(RefCell::new(BTreeMap::new())),
             event_callback: Rc::new(RefCell::new(event_handler)),
+            time: Rc::new(Cell::new(0.0)),
+            next_stats: Cell::new(0.0),
         }
     }
-    pub fn user_id(&self) -> u32 {
-        self.user_id
-    }
-    pub fn broadcast(&self, message: &T) {
-        let serialized = bincode::serialize(&message).unwrap();
-        for peer in self.peer_map.borrow().values().filter(|peer| matches!(peer.status, PeerStatus::Connected)) {
-            peer.data_channel.send_u8_array(serialized.as_slice());
-        }
-    }
-    pub fn send(&self, peer_id: u32, message: &T) {
-        let serialized = bincode::serialize(&MessageWrapper(self.user_id, message)).unwrap();
-        if let Some(peer) = self.peer_map.borrow().get(&peer_id) {
-            peer.data_channel.send_u8_array(serialized.as_slice());
+    //Send this peer an offer on its current connection.
+    fn make_offer(&self, peer_id: u32, ice_restart: bool) {
+        let peer_connection = {
+            let mut peer_map = self.peer_map.borrow_mut();
+            let Some(peer_data) = peer_map.get_mut(&peer_id) else {
+                return
+            };
+            peer_data.making_offer = true;
+            peer_data.handshake = Some(self.handshake(peer_id, SdpType::Offer));
+            peer_data.peer_connection.clone()
+        };
+        if ice_restart {
+            log::info!("Restarting ICE with {}", peer_id);
         }
-    }
-    pub fn initiate_handshake(&self, peer_id: u32) {
-        let mut peer_data = self.create_peer_data(peer_id);
-        let peer_network_clone = self.peer_map.clone();
+        let peer_map = self.peer_map.clone();
+        let event_callback = self.event_callback.clone();
         wasm_bindgen_futures::spawn_local(async move {
-            log::info!("Initiating handshake to {}", peer_id);
-            if let PeerStatus::Connecting(ref mut handshake_data) = peer_data.status {
-                handshake_data.sdp_description = peer_data.peer_connection.create_offer_sdp().await;
+            let result = peer_connection.create_offer_sdp(ice_restart).await;
+            if let Some(peer_data) = peer_map.borrow_mut().get_mut(&peer_id).filter(|peer_data| peer_data.peer_connection == peer_connection) {
+                peer_data.making_offer = false;
+            }
+            match result {
+                Ok(sdp_description) => set_handshake_sdp(&peer_map, peer_id, &peer_connection, SdpType::Offer, sdp_description),
+                Err(error) => event_callback.borrow_mut()(PeerNetworkEvent::Error(peer_id, error)),
             }
-            peer_network_clone.borrow_mut().insert(peer_id, peer_data);
         });
     }
-    pub fn receive_handshake(&self, mut handshake: PeerHandshake) {
-        if let Some(peer_connection) = self.peer_map.borrow().get(&handshake.source_id).map(|v| v.peer_connection.clone()) {
-            log::info!("Received handshake answer from {}", handshake.source_id);
-            wasm_bindgen_futures::spawn_local(async move {
-                peer_connection.receive_answer_sdp(handshake.sdp_description.as_str()).await;
-                for ice_candidate in std::mem::take(&mut handshake.ice_candidates) {
-                    peer_connection.add_ice_candidate(ice_candidate.into()).await;
-                }
-            });
+    fn receive_offer(&self, mut handshake: PeerHandshake) {
+        let peer_id = handshake.source_id;
+        let collision = self.peer_map.borrow().get(&peer_id).map(|peer_data| {
+            peer_data.making_offer || peer_data.peer_connection.signaling_state() != SignalingState::Stable
+        });
+        if collision == Some(true) && !self.is_polite(peer_id) {
+            log::info!("Ignoring handshake offer from {} that crossed ours", peer_id);
+            return
+        }
+        log::info!("Received handshake offer from {}", peer_id);
+        let peer_connection = if collision.is_some() {
+            //Answer on the existing connection. Candidates gathered for our own offer do not apply to the answer.
+            let mut peer_map = self.peer_map.borrow_mut();
+            let Some(peer_data) = peer_map.get_mut(&peer_id) else {
+                return
+            };
+            peer_data.making_offer = false;
+            peer_data.remote_session_id = Some(handshake.session_id);
+            peer_data.handshake = Some(self.handshake(peer_id, SdpType::Answer));
+            peer_data.peer_connection.clone()
         }
         else {
-            log::info!("Received handshake offer from {}", handshake.source_id);
-            let peer_map = self.peer_map.clone();
-            let mut peer_data = self.create_peer_data(handshake.source_id);
-            wasm_bindgen_futures::spawn_local(async move {
-                peer_data.peer_connection.receive_offer_sdp(handshake.sdp_description.as_str()).await;
-                for ice_candidate in std::mem::take(&mut handshake.ice_candidates) {
-                    peer_data.peer_connection.add_ice_candidate(ice_candidate.into()).await;
+            let mut peer_data = match self.create_peer_data(peer_id, SdpType::Answer) {
+                Ok(peer_data) => peer_data,
+                Err(error) => return self.event_callback.borrow_mut()(PeerNetworkEvent::Error(peer_id, error)),
+            };
+            peer_data.remote_session_id = Some(handshake.session_id);
+            let peer_connection = peer_data.peer_connection.clone();
+            self.peer_map.borrow_mut().insert(peer_id, peer_data);
+            peer_connection
+        };
+        let peer_map = self.peer_map.clone();
+        let event_callback = self.event_callback.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let result = async {
+                if peer_connection.signaling_state() == SignalingState::HaveLocalOffer {
+                    peer_connection.rollback().await?;
                 }
-                if let PeerStatus::Connecting(ref mut handshake_data) = peer_data.status {
-                    handshake_data.sdp_description = peer_data.peer_connection.create_answer_sdp().await;
+                peer_connection.receive_offer_sdp(handshake.sdp_description.as_str()).await?;
+                for ice_candidate in std::mem::take(&mut handshake.ice_candidates) {
+                    peer_connection.add_ice_candidate(ice_candidate.into()).await?;
                 }
-                peer_map.borrow_mut().insert(handshake.source_id, peer_data);
+                peer_connection.create_answer_sdp().await
+            }.await;
+            match result {
+                Ok(sdp_description) => set_handshake_sdp(&peer_map, peer_id, &peer_connection, SdpType::Answer, sdp_description),
+                Err(error) => event_callback.borrow_mut()(PeerNetworkEvent::Error(peer_id, error)),
+            }
+        });
+    }
+    fn receive_answer(&self, mut handshake: PeerHandshake) {
+        let peer_id = handshake.source_id;
+        //Only an outstanding offer can be answered. Anything else answers an offer that was rolled back or replaced.
+        let peer_connection = self.peer_map.borrow_mut().get_mut(&peer_id)
+            .filter(|peer_data| peer_data.peer_connection.signaling_state() == SignalingState::HaveLocalOffer)
+            .map(|peer_data| {
+                peer_data.remote_session_id = Some(handshake.session_id);
+                peer_data.peer_connection.clone()
             });
+        let Some(peer_connection) = peer_connection else {
+            log::info!("Ignoring unexpected handshake answer from {}", peer_id);
+            return
+        };
+        log::info!("Received handshake answer from {}", peer_id);
+        let event_callback = self.event_callback.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let result = async {
+                peer_connection.receive_answer_sdp(handshake.sdp_description.as_str()).await?;
+                for ice_candidate in std::mem::take(&mut handshake.ice_candidates) {
+                    peer_connection.add_ice_candidate(ice_candidate.into()).await?;
+                }
+                Ok(())
+            }.await;
+            if let Err(error) = result {
+                event_callback.borrow_mut()(PeerNetworkEvent::Error(peer_id, error));
+            }
+        });
+    }
+    //Collect the browser's stats for this peer and report them with the link timing.
+    fn report_stats(&self, peer_id: u32, peer_connection: PeerConnection) {
+        let peer_map = self.peer_map.clone();
+        let event_callback = self.event_callback.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let connection = match peer_connection.stats().await {
+                Ok(connection) => connection,
+                Err(error) => return event_callback.borrow_mut()(PeerNetworkEvent::Error(peer_id, error)),
+            };
+            let timing = peer_map.borrow().get(&peer_id).map(|peer_data| peer_data.link.timing());
+            if let Some(timing) = timing {
+                event_callback.borrow_mut()(PeerNetworkEvent::Stats(peer_id, PeerStats { timing, connection }));
+            }
+        });
+    }
+    //The peer with the higher user id yields when offers cross.
+    fn is_polite(&self, peer_id: u32) -> bool {
+        self.user_id > peer_id
+    }
+    fn handshake(&self, peer_id: u32, sdp_type: SdpType) -> PeerHandshake {
+        PeerHandshake {
+            source_id: self.user_id,
+            target_id: peer_id,
+            session_id: self.session_id,
+            sdp_type,
+            ..Default::default()
         }
     }
-    fn create_peer_data(&self, peer_id: u32) -> PeerData {
-        //Create peer connection and data channel.
-        let peer_connection = PeerConnection::new_with_configuration(&self.configuration);
-        let data_channel = peer_connection.create_data_channel_negotiated("Data Channel", 0);
+    fn create_peer_data(&self, peer_id: u32, sdp_type: SdpType) -> Result<PeerData<T>> {
+        //Create peer connection and a data channel for each lane.
+        let peer_connection = PeerConnection::new_with_configuration(&self.configuration)?;
+        let data_channels = LANES.iter().enumerate().map(|(id, lane)| {
+            peer_connection.create_data_channel_negotiated(lane.label, id as u16, lane.ordered, lane.max_retransmits)
+        }).collect::<Vec<_>>();
 
         //Initialize peer connection connectionstatechange event handler.
         let peer_map = self.peer_map.clone();
         let event_callback = self.event_callback.clone();
         let peer_connection_clone = peer_connection.clone();
+        let time = self.time.clone();
         let _onconnectionstatechange_callback = peer_connection.set_onconnectionstatechange(move || {
-            if let PeerConnectionState::Closed | PeerConnectionState::Failed | PeerConnectionState::Disconnected = peer_connection_clone.connection_state() {
-                if let Some(peer_data) = peer_map.borrow_mut().remove(&peer_id) {
-                    log::info!("Connection to {peer_id} closed.");
-                    event_callback.borrow_mut()(PeerNetworkEvent::Disconnect(peer_id));
-                    peer_data.data_channel.close();
-                    peer_data.peer_connection.close();
+            let event = {
+                let mut peer_map = peer_map.borrow_mut();
+                //The peer may already be on a newer connection.
+                let Some(peer_data) = peer_map.get_mut(&peer_id).filter(|peer_data| peer_data.peer_connection == peer_connection_clone) else {
+                    return
+                };
+                match (peer_connection_clone.connection_state(), &peer_data.status) {
+                    //Data channels survive an ICE restart, so the peer is simply back.
+                    (PeerConnectionState::Connected, PeerStatus::Recovering { .. }) => {
+                        log::info!("Connection to {peer_id} recovered.");
+                        peer_data.status = PeerStatus::Connected;
+                        PeerNetworkEvent::Connect(peer_id)
+                    }
+                    //Often transient. Give the connection a chance to come back before reporting it lost.
+                    (PeerConnectionState::Disconnected | PeerConnectionState::Failed, PeerStatus::Connected) => {
+                        log::info!("Connection to {peer_id} interrupted.");
+                        peer_data.status = PeerStatus::Recovering { since: time.get(), next_restart: time.get() + ICE_RESTART_DELAY };
+                        PeerNetworkEvent::Reconnecting(peer_id)
+                    }
+                    (PeerConnectionState::Closed, _) | (PeerConnectionState::Disconnected | PeerConnectionState::Failed, PeerStatus::Connecting) => {
+                        if let Some(peer_data) = peer_map.remove(&peer_id) {
+                            log::info!("Connection to {peer_id} closed.");
+                            peer_data.close();
+                        }
+                        PeerNetworkEvent::Disconnect(peer_id)
+                    }
+                    _ => return,
                 }
-            }
+            };
+            event_callback.borrow_mut()(event);
         });
 
         //Initialize peer connection icecandidate event handler.
         let peer_map = self.peer_map.clone();
         let event_callback = self.event_callback.clone();
         let _onicecandidate_callback = peer_connection.set_onicecandidate(move |event| {
-            let mut handshake = if let Some(PeerData { status: PeerStatus::Connecting(handshake_data), .. }) = peer_map.borrow_mut().get_mut(&peer_id) {
+            let handshake = {
+                let mut peer_map = peer_map.borrow_mut();
+                let Some(peer_data) = peer_map.get_mut(&peer_id) else {
+                    return
+                };
+                let Some(handshake_data) = peer_data.handshake.as_mut() else {
+                    return
+                };
                 if let Some(candidate) = event.candidate() {
                     //ICE candidate discovered, push into peer's candidate list.
                     handshake_data.ice_candidates.push((candidate.candidate(), candidate.sdp_mid(), candidate.sdp_m_line_index()));
                     return;
-                } else {
-                    //No more ICE candidates to discover, send handshake.
-                    log::info!("{} ICE candidates gathered, sending handshake.", handshake_data.ice_candidates.len());
-                    std::mem::take(handshake_data)
                 }
-            }
-            else {
-                PeerHandshake::default()
+                //No more ICE candidates to discover, send handshake.
+                log::info!("{} ICE candidates gathered, sending handshake.", handshake_data.ice_candidates.len());
+                peer_data.handshake.take()
             };
-            event_callback.borrow_mut()(PeerNetworkEvent::Handshake(std::mem::take(&mut handshake)));
-        });
-
-        //Initialize data channel open event handler.
-        let peer_map = self.peer_map.clone();
-        let event_callback = self.event_callback.clone();
-        let _onopen_callback = data_channel.set_onopen(move || {
-            if let Some(peer_data) = peer_map.borrow_mut().get_mut(&peer_id) {
-                log::info!("Data Channel to {} opened!", peer_id);
-                peer_data.status = PeerStatus::Connected;
+            if let Some(handshake) = handshake {
+                event_callback.borrow_mut()(PeerNetworkEvent::Handshake(handshake));
             }
-            event_callback.borrow_mut()(PeerNetworkEvent::Connect(peer_id));
         });
 
-        //Initialize data channel message event handler.
-        let event_callback = self.event_callback.clone();
-        let _onmessage_callback = data_channel.set_onmessage(move |event| {
-            if let Ok(data) = event.data().dyn_into::<ArrayBuffer>() {
-                let data = Uint8Array::new(&data).to_vec();
-                let message: MessageWrapper<T> = bincode::deserialize(data.as_slice()).unwrap();
-                event_callback.borrow_mut()(PeerNetworkEvent::Message(message.0, message.1));
-            }
-            else {
-                log::warn!("Unhandled DataChannel message type.");
-            }
-        });
+        //Initialize data channel open event handlers. The peer counts as connected once every lane is open.
+        let _onopen_callbacks = data_channels.iter().map(|data_channel| {
+            let peer_map = self.peer_map.clone();
+            let event_callback = self.event_callback.clone();
+            let data_channels = data_channels.clone();
+            data_channel.set_onopen(move || {
+                if !data_channels.iter().all(|data_channel| data_channel.ready_state() == RtcDataChannelState::Open) {
+                    return
+                }
+                if let Some(peer_data) = peer_map.borrow_mut().get_mut(&peer_id) {
+                    log::info!("Data Channels to {} opened!", peer_id);
+                    peer_data.status = PeerStatus::Connected;
+                }
+                event_callback.borrow_mut()(PeerNetworkEvent::Connect(peer_id));
+            })
+        }).collect();
+
+        //Initialize data channel message event handlers. Every lane carries the same frames.
+        let _onmessage_callbacks = data_channels.iter().map(|data_channel| {
+            let peer_map = self.peer_map.clone();
+            let event_callback = self.event_callback.clone();
+            data_channel.set_onmessage(move |event| {
+                let Ok(data) = event.data().dyn_into::<ArrayBuffer>() else {
+                    log::warn!("Unhandled DataChannel message type.");
+                    return
+                };
+                //Release the peer map before handing messages to the game, which may send replies.
+                let (messages, error) = {
+                    let mut peer_map = peer_map.borrow_mut();
+                    let Some(peer_data) = peer_map.get_mut(&peer_id) else {
+                        return
+                    };
+                    let received_at = event.time_stamp();
+                    match Frame::decode(peer_id, Uint8Array::new(&data).to_vec().as_slice()).and_then(|frame| peer_data.link.receive(frame, received_at)) {
+                        //A lost ack only delays the peer's retransmit, so the messages are still delivered.
+                        Ok((messages, reply)) => (messages, reply.and_then(|(delivery, reply)| peer_data.send(delivery, reply.as_slice()).err())),
+                        Err(error) => (Vec::new(), Some(error)),
+                    }
+                };
+                for message in messages {
+                    event_callback.borrow_mut()(PeerNetworkEvent::Message(peer_id, message));
+                }
+                if let Some(error) = error {
+                    event_callback.borrow_mut()(PeerNetworkEvent::Error(peer_id, error));
+                }
+            })
+        }).collect();
 
-        PeerData {
-            status: PeerStatus::Connecting(PeerHandshake {
-                source_id: self.user_id,
-                target_id: peer_id,
-                ..Default::default()
-            }),
+        Ok(PeerData {
+            status: PeerStatus::Connecting,
+            handshake: Some(self.handshake(peer_id, sdp_type)),
+            remote_session_id: None,
+            making_offer: false,
             peer_connection,
-            data_channel,
+            data_channels,
+            link: Link::new(self.user_id),
             _onconnectionstatechange_callback,
             _onicecandidate_callback,
-            _onopen_callback,
-            _onmessage_callback,
+            _onopen_callbacks,
+            _onmessage_callbacks,
+        })
+    }
+}
+impl<T: Serialize + DeserializeOwned + 'static> Transport<T> for PeerNetwork<T> {
+    fn user_id(&self) -> u32 {
+        self.user_id
+    }
+    fn timing(&self, peer_id: u32) -> Option<Timing> {
+        self.peer_map.borrow().get(&peer_id).map(|peer_data| peer_data.link.timing())
+    }
+    //Sends to every connected peer, even if sending to some of them fails. Returns the first failure.
+    fn broadcast(&self, message: &T, delivery: Delivery) -> Result<()> {
+        let mut result = Ok(());
+        for peer in self.peer_map.borrow_mut().values_mut().filter(|peer| peer.is_connected()) {
+            let sent = peer.link.encode(message, delivery, self.time.get()).and_then(|encoded| peer.send(delivery, encoded.as_slice()));
+            if result.is_ok() {
+                result = sent;
+            }
+        }
+        result
+    }
+    fn send(&self, peer_id: u32, message: &T, delivery: Delivery) -> Result<()> {
+        if let Some(peer) = self.peer_map.borrow_mut().get_mut(&peer_id).filter(|peer| peer.is_connected()) {
+            let encoded = peer.link.encode(message, delivery, self.time.get())?;
+            peer.send(delivery, encoded.as_slice())?;
         }
+        Ok(())
     }
-}
\ No newline at end of file
+    //Resend reliable messages that have not been acknowledged in time, ping peers and look after
+    //interrupted connections. Call every frame.
+    fn update(&self, time: f64) {
+        self.time.set(time);
+        let report_stats = time >= self.next_stats.get();
+        if report_stats {
+            self.next_stats.set(time + STATS_INTERVAL);
+        }
+        let mut errors = Vec::new();
+        let mut restarts = Vec::new();
+        let mut lost = Vec::new();
+        for (&peer_id, peer) in self.peer_map.borrow_mut().iter_mut().filter(|(_, peer)| peer.is_connected()) {
+            for encoded in peer.link.due(time) {
+                if let Err(error) = peer.send(Delivery::Reliable, encoded.as_slice()) {
+                    errors.push((peer_id, error));
+                }
+            }
+            match peer.link.ping(time) {
+                Ok(Some(ping)) => if let Err(error) = peer.send(Delivery::Unreliable, ping.as_slice()) {
+                    errors.push((peer_id, error));
+                }
+                Ok(None) => {}
+                Err(error) => errors.push((peer_id, error)),
+            }
+            if report_stats && matches!(peer.status, PeerStatus::Connected) {
+                self.report_stats(peer_id, peer.peer_connection.clone());
+            }
+            //Only the impolite peer restarts ICE, so both ends do not send restart offers at once.
+            if let PeerStatus::Recovering { since, ref mut next_restart } = peer.status {
+                if time - since >= RECOVERY_TIMEOUT {
+                    lost.push(peer_id);
+                }
+                else if time >= *next_restart && !self.is_polite(peer_id) && peer.peer_connection.signaling_state() == SignalingState::Stable {
+                    *next_restart = time + ICE_RESTART_DELAY;
+                    restarts.push(peer_id);
+                }
+            }
+        }
+        for (peer_id, error) in errors {
+            self.event_callback.borrow_mut()(PeerNetworkEvent::Error(peer_id, error));
+        }
+        for peer_id in restarts {
+            self.make_offer(peer_id, true);
+        }
+        for peer_id in lost {
+            if let Some(peer_data) = self.peer_map.borrow_mut().remove(&peer_id) {
+                log::info!("Connection to {} could not be recovered.", peer_id);
+                peer_data.close();
+            }
+            self.event_callback.borrow_mut()(PeerNetworkEvent::Disconnect(peer_id));
+        }
+    }
+    fn connect(&self, peer_id: u32) {
+        let peer_data = match self.create_peer_data(peer_id, SdpType::Offer) {
+            Ok(peer_data) => peer_data,
+            Err(error) => return self.event_callback.borrow_mut()(PeerNetworkEvent::Error(peer_id, error)),
+        };
+        if let Some(previous) = self.peer_map.borrow_mut().insert(peer_id, peer_data) {
+            previous.close();
+        }
+        log::info!("Initiating handshake to {}", peer_id);
+        self.make_offer(peer_id, false);
+    }
+    //Offers are answered following the W3C perfect negotiation pattern. When two offers cross,
+    //the polite peer drops its own offer and answers, while the impolite peer ignores the other offer.
+    fn receive_handshake(&self, handshake: PeerHandshake) {
+        let peer_id = handshake.source_id;
+        //A peer that reloaded starts a new session. Whatever we had with its previous one is gone.
+        let stale = self.peer_map.borrow().get(&peer_id).is_some_and(|peer_data| {
+            peer_data.remote_session_id.is_some_and(|session_id| session_id != handshake.session_id)
+        });
+        if stale {
+            if let Some(peer_data) = self.peer_map.borrow_mut().remove(&peer_id) {
+                log::info!("{} started a new session.", peer_id);
+                peer_data.close();
+                if peer_data.is_connected() {
+                    self.event_callback.borrow_mut()(PeerNetworkEvent::Disconnect(peer_id));
+                }
+            }
+        }
+        match handshake.sdp_type {
+            SdpType::Offer => self.receive_offer(handshake),
+            SdpType::Answer => self.receive_answer(handshake),
+        }
+    }
+}
+
+//Fill in the description of the handshake being gathered, unless the connection was replaced or
+//the handshake changed to the other type meanwhile.
+fn set_handshake_sdp<T>(peer_map: &RefCell<BTreeMap<u32, PeerData<T>>>, peer_id: u32, peer_connection: &PeerConnection, sdp_type: SdpType, sdp_description: String) {
+    let mut peer_map = peer_map.borrow_mut();
+    let handshake = peer_map.get_mut(&peer_id)
+        .filter(|peer_data| peer_data.peer_connection == *peer_connection)
+        .and_then(|peer_data| peer_data.handshake.as_mut())
+        .filter(|handshake| handshake.sdp_type == sdp_type);
+    if let Some(handshake) = handshake {
+        handshake.sdp_description = sdp_description;
+    }
+}
@@ -1,62 +1,95 @@
 use wasm_bindgen::prelude::*;
-use web_sys::{BinaryType, MessageEvent};
+use web_sys::{BinaryType, CloseEvent, MessageEvent};
 use js_sys::{ArrayBuffer, Uint8Array};
 use serde::{Serialize, de::DeserializeOwned};
 use std::{rc::Rc, cell::RefCell, marker::PhantomData};
+use yahtzee_net::{NetworkError, Result, SignalingChannel};
+pub use yahtzee_net::{ReadyState, WebSocketEvent};
 
-pub enum WebSocketEvent<T> {
-    Connect,
-    Disconnect,
-    Message(T),
-}
 pub struct WebSocket<T: Serialize + DeserializeOwned + 'static> {
     websocket: web_sys::WebSocket,
+    //Messages sent before the connection opened, flushed once it does.
+    queue: Rc<RefCell<Vec<Vec<u8>>>>,
     _onmessage_callback: Closure<dyn FnMut(MessageEvent)>,
     _onopen_callback: Closure<dyn FnMut()>,
-    _onclose_callback: Closure<dyn FnMut()>,
+    _onclose_callback: Closure<dyn FnMut(CloseEvent)>,
     _phantom_data: PhantomData<T>,
 }
 impl<T: Serialize + DeserializeOwned + 'static> WebSocket<T> {
-    pub fn new<F: FnMut(WebSocketEvent<T>) + 'static>(url: &str, message_callback: F) -> Self {
+    pub fn new<F: FnMut(WebSocketEvent<T>) + 'static>(url: &str, message_callback: F) -> Result<Self> {
+        let websocket = web_sys::WebSocket::new(url)?;
+            websocket.set_binary_type(BinaryType::Arraybuffer);
+        let queue = Rc::new(RefCell::new(Vec::<Vec<u8>>::new()));
         let message_callback = Rc::new(RefCell::new(message_callback));
         let onmessage_callback: Closure<dyn FnMut(MessageEvent)> = {
             let message_callback = message_callback.clone();
             Closure::new(move |event: MessageEvent| {
                 if let Ok(data) = event.data().dyn_into::<ArrayBuffer>() {
                     let data = Uint8Array::new(&data).to_vec();
-                    let message: T = bincode::deserialize(data.as_slice()).unwrap();
-                    message_callback.borrow_mut()(WebSocketEvent::Message(message));
+                    let event = match bincode::deserialize(data.as_slice()) {
+                        Ok(message) => WebSocketEvent::Message(message),
+                        Err(error) => WebSocketEvent::Error(NetworkError::Deserialize(error.to_string())),
+                    };
+                    message_callback.borrow_mut()(event);
                 }
             })
         };
         let onopen_callback: Closure<dyn FnMut()> = {
             let message_callback = message_callback.clone();
+            let websocket = websocket.clone();
+            let queue = queue.clone();
             Closure::new(move || {
                 message_callback.borrow_mut()(WebSocketEvent::Connect);
+                for data in std::mem::take(&mut *queue.borrow_mut()) {
+                    if let Err(error) = websocket.send_with_u8_array(data.as_slice()) {
+                        message_callback.borrow_mut()(WebSocketEvent::Error(error.into()));
+                    }
+                }
             })
         };
-        let onclose_callback: Closure<dyn FnMut()> = {
+        let onclose_callback: Closure<dyn FnMut(CloseEvent)> = {
             let message_callback = message_callback;
-            Closure::new(move || {
-                message_callback.borrow_mut()(WebSocketEvent::Disconnect);
+            let queue = queue.clone();
+            Closure::new(move |event: CloseEvent| {
+                let unsent = std::mem::take(&mut *queue.borrow_mut()).len();
+                if unsent > 0 {
+                    message_callback.borrow_mut()(WebSocketEvent::Error(NetworkError::Unsent(unsent)));
+                }
+                message_callback.borrow_mut()(WebSocketEvent::Disconnect { code: event.code(), reason: event.reason() });
             })
         };
 
-        let websocket = web_sys::WebSocket::new(url).unwrap_throw();
-            websocket.set_binary_type(BinaryType::Arraybuffer);
             websocket.set_onmessage(Some(onmessage_callback.as_ref().unchecked_ref()));
             websocket.set_onopen(Some(onopen_callback.as_ref().unchecked_ref()));
             websocket.set_onclose(Some(onclose_callback.as_ref().unchecked_ref()));
-        Self {
+        Ok(Self {
             websocket,
+            queue,
             _onmessage_callback: onmessage_callback,
             _onopen_callback: onopen_callback,
             _onclose_callback: onclose_callback,
             _phantom_data: PhantomData,
+        })
+    }
+}
+impl<T: Serialize + DeserializeOwned + 'static> SignalingChannel<T> for WebSocket<T> {
+    fn ready_state(&self) -> ReadyState {
+        match self.websocket.ready_state() {
+            web_sys::WebSocket::CONNECTING => ReadyState::Connecting,
+            web_sys::WebSocket::OPEN => ReadyState::Open,
+            web_sys::WebSocket::CLOSING => ReadyState::Closing,
+            _ => ReadyState::Closed,
         }
     }
-    pub fn send(&self, message: T) {
-        let serialized = bincode::serialize(&message).unwrap();
-        self.websocket.send_with_u8_array(serialized.as_slice()).unwrap();
+    fn send(&self, message: T) -> Result<()> {
+        let serialized = bincode::serialize(&message).map_err(|error| NetworkError::Serialize(error.to_string()))?;
+        match self.ready_state() {
+            ReadyState::Connecting => {
+                self.queue.borrow_mut().push(serialized);
+                Ok(())
+            }
+            ReadyState::Open => Ok(self.websocket.send_with_u8_array(serialized.as_slice())?),
+            ReadyState::Closing | ReadyState::Closed => Err(NetworkError::NotOpen),
+        }
     }
-}
\ No newline at end of file
+}
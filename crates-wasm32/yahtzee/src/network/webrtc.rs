@@ -1,10 +1,13 @@
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::JsFuture;
-use web_sys::{Event, RtcPeerConnection, RtcSessionDescriptionInit, RtcSdpType, RtcDataChannel, RtcDataChannelInit, RtcPeerConnectionIceEvent, RtcIceCandidateInit, MessageEvent, RtcIceCandidate, RtcIceServer, RtcConfiguration, RtcPeerConnectionState, RtcDataChannelState, RtcDataChannelEvent, RtcDataChannelType};
+use web_sys::{Event, RtcPeerConnection, RtcSessionDescriptionInit, RtcSdpType, RtcDataChannel, RtcDataChannelInit, RtcPeerConnectionIceEvent, RtcIceCandidateInit, MessageEvent, RtcIceCandidate, RtcIceServer, RtcConfiguration, RtcPeerConnectionState, RtcDataChannelState, RtcDataChannelEvent, RtcDataChannelType, RtcSignalingState, RtcOfferOptions};
 use serde::{Deserialize, Serialize};
-use js_sys::{Object, Array, Reflect};
+use js_sys::{Object, Array, Reflect, Map};
+use yahtzee_net::{NetworkError, Result};
 
+pub use yahtzee_net::ConnectionStats;
 pub use web_sys::RtcPeerConnectionState as PeerConnectionState;
+pub use web_sys::RtcSignalingState as SignalingState;
 
 #[wasm_bindgen]
 extern "C" {
@@ -57,12 +60,13 @@ impl From<RtcIceCandidate> for IceCandidate {
         Self(value.candidate(), value.sdp_mid(), value.sdp_m_line_index())
     }
 }
-impl From<IceCandidate> for RtcIceCandidate {
-    fn from(value: IceCandidate) -> Self {
+impl TryFrom<IceCandidate> for RtcIceCandidate {
+    type Error = NetworkError;
+    fn try_from(value: IceCandidate) -> Result<Self> {
         let mut candidate_dict = RtcIceCandidateInit::new(value.0.as_str());
             candidate_dict.sdp_mid(value.1.as_deref());
             candidate_dict.sdp_m_line_index(value.2);
-        RtcIceCandidate::new(&candidate_dict).unwrap()
+        Ok(RtcIceCandidate::new(&candidate_dict)?)
     }
 }
 impl From<(String, Option<String>, Option<u16>)> for IceCandidate {
@@ -79,11 +83,11 @@ impl From<IceCandidate> for (String, Option<String>, Option<u16>) {
 #[derive(Clone)]
 pub struct DataChannel(RtcDataChannel);
 impl DataChannel {
-    pub fn send_str(&self, data: &str) {
-        self.0.send_with_str(data).unwrap();
+    pub fn send_str(&self, data: &str) -> Result<()> {
+        Ok(self.0.send_with_str(data)?)
     }
-    pub fn send_u8_array(&self, data: &[u8]) {
-        self.0.send_with_u8_array(data).unwrap();
+    pub fn send_u8_array(&self, data: &[u8]) -> Result<()> {
+        Ok(self.0.send_with_u8_array(data)?)
     }
     pub fn close(&self) {
         self.0.close();
@@ -101,10 +105,10 @@ impl DataChannel {
         self.0.set_onclose(Some(callback.as_ref().unchecked_ref()));
         callback
     }
-    pub fn set_onclosing<F: FnMut() + 'static>(&self, f: F) -> Closure<dyn FnMut()> {
+    pub fn set_onclosing<F: FnMut() + 'static>(&self, f: F) -> Result<Closure<dyn FnMut()>> {
         let callback = Closure::new(f);
-        self.0.add_event_listener_with_callback("closing", callback.as_ref().unchecked_ref()).unwrap();
-        callback
+        self.0.add_event_listener_with_callback("closing", callback.as_ref().unchecked_ref())?;
+        Ok(callback)
     }
     pub fn set_onerror<F: FnMut() + 'static>(&self, f: F) -> Closure<dyn FnMut()> {
         let callback = Closure::new(f);
@@ -118,14 +122,14 @@ impl DataChannel {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct PeerConnection(RtcPeerConnection);
 impl PeerConnection {
-    pub fn new() -> Self {
-        Self(RtcPeerConnection::new().unwrap())
+    pub fn new() -> Result<Self> {
+        Ok(Self(RtcPeerConnection::new()?))
     }
-    pub fn new_with_configuration(configuration: &Configuration) -> Self {
-        Self(RtcPeerConnection::new_with_configuration(&configuration.0).unwrap())
+    pub fn new_with_configuration(configuration: &Configuration) -> Result<Self> {
+        Ok(Self(RtcPeerConnection::new_with_configuration(&configuration.0)?))
     }
     pub fn close(&self) {
         self.0.close();
@@ -133,6 +137,9 @@ impl PeerConnection {
     pub fn connection_state(&self) -> RtcPeerConnectionState {
         self.0.connection_state()
     }
+    pub fn signaling_state(&self) -> RtcSignalingState {
+        self.0.signaling_state()
+    }
     pub fn set_ondatachannel<F: FnMut(RtcDataChannelEvent) + 'static>(&self, f: F) -> Closure<dyn FnMut(RtcDataChannelEvent)> {
         let callback = Closure::new(f);
         self.0.set_ondatachannel(Some(callback.as_ref().unchecked_ref()));
@@ -148,41 +155,83 @@ impl PeerConnection {
         self.0.set_onicecandidate(Some(callback.as_ref().unchecked_ref()));
         callback
     }
-    pub async fn add_ice_candidate(&self, candidate: IceCandidate) {
-        JsFuture::from(self.0.add_ice_candidate_with_opt_rtc_ice_candidate(Some(&candidate.into()))).await.unwrap();
+    pub async fn add_ice_candidate(&self, candidate: IceCandidate) -> Result<()> {
+        JsFuture::from(self.0.add_ice_candidate_with_opt_rtc_ice_candidate(Some(&candidate.try_into()?))).await?;
+        Ok(())
     }
-    pub fn create_data_channel_negotiated(&self, label: &str, id: u16) -> DataChannel {
+    //Both peers must create the channel with the same id and settings. Unordered channels with a
+    //retransmit limit drop messages instead of holding up later ones.
+    pub fn create_data_channel_negotiated(&self, label: &str, id: u16, ordered: bool, max_retransmits: Option<u16>) -> DataChannel {
         let mut data_channel_dict = RtcDataChannelInit::new();
             data_channel_dict.negotiated(true);
             data_channel_dict.id(id);
+            data_channel_dict.set_ordered(ordered);
+        if let Some(max_retransmits) = max_retransmits {
+            data_channel_dict.set_max_retransmits(max_retransmits);
+        }
         let mut data_channel = self.0.create_data_channel_with_data_channel_dict(label, &data_channel_dict);
             data_channel.set_binary_type(RtcDataChannelType::Arraybuffer);
         DataChannel(data_channel)
     }
-    pub async fn create_offer_sdp(&self) -> String {
-        let offer_obj = JsFuture::from(self.0.create_offer()).await.unwrap();
-        let offer_sdp = Reflect::get(&offer_obj, &"sdp".into())
-            .unwrap().as_string().unwrap();
+    //An ICE restart offer gathers new candidates for a connection that stopped working, keeping its data channels.
+    pub async fn create_offer_sdp(&self, ice_restart: bool) -> Result<String> {
+        let offer_options = RtcOfferOptions::new();
+            offer_options.set_ice_restart(ice_restart);
+        let offer_obj = JsFuture::from(self.0.create_offer_with_rtc_offer_options(&offer_options)).await?;
+        let offer_sdp = Reflect::get(&offer_obj, &"sdp".into())?
+            .as_string().ok_or(NetworkError::MissingSdp)?;
         let offer_description = RtcSessionDescriptionInit::from(offer_obj);
-        JsFuture::from(self.0.set_local_description(&offer_description)).await.unwrap();
-        offer_sdp
+        JsFuture::from(self.0.set_local_description(&offer_description)).await?;
+        Ok(offer_sdp)
     }
-    pub async fn receive_offer_sdp(&self, offer_sdp: &str) {
+    pub async fn receive_offer_sdp(&self, offer_sdp: &str) -> Result<()> {
         let mut offer_description = RtcSessionDescriptionInit::new(RtcSdpType::Offer);
             offer_description.sdp(offer_sdp);
-        JsFuture::from(self.0.set_remote_description(&offer_description)).await.unwrap();
+        JsFuture::from(self.0.set_remote_description(&offer_description)).await?;
+        Ok(())
     }
-    pub async fn create_answer_sdp(&self) -> String {
-        let answer_obj = JsFuture::from(self.0.create_answer()).await.unwrap();
-        let answer_sdp = Reflect::get(&answer_obj, &"sdp".into())
-            .unwrap().as_string().unwrap();
+    pub async fn create_answer_sdp(&self) -> Result<String> {
+        let answer_obj = JsFuture::from(self.0.create_answer()).await?;
+        let answer_sdp = Reflect::get(&answer_obj, &"sdp".into())?
+            .as_string().ok_or(NetworkError::MissingSdp)?;
         let answer_description = RtcSessionDescriptionInit::from(answer_obj);
-        JsFuture::from(self.0.set_local_description(&answer_description)).await.unwrap();
-        answer_sdp
-    }
-    pub async fn receive_answer_sdp(&self, answer_sdp: &str) {
+        JsFuture::from(self.0.set_local_description(&answer_description)).await?;
+        Ok(answer_sdp)
+    }
+    //Stats of the selected candidate pair. Default stats if no pair has been selected yet.
+    pub async fn stats(&self) -> Result<ConnectionStats> {
+        let report = JsFuture::from(self.0.get_stats()).await?.unchecked_into::<Map>();
+        let field = |stat: &JsValue, key: &str| Reflect::get(stat, &key.into()).ok().filter(|value| !value.is_undefined());
+        let stats = report.values().into_iter().filter_map(|stat| stat.ok()).collect::<Vec<_>>();
+        let of_type = |kind: &'static str| stats.iter().filter(move |stat| field(stat, "type").and_then(|value| value.as_string()).as_deref() == Some(kind));
+        //The transport names the pair in use. Firefox marks the pair itself as selected instead.
+        let pair = of_type("transport")
+            .find_map(|transport| field(transport, "selectedCandidatePairId"))
+            .map(|pair_id| report.get(&pair_id))
+            .filter(|pair| !pair.is_undefined())
+            .or_else(|| of_type("candidate-pair").find(|pair| field(pair, "selected").and_then(|value| value.as_bool()) == Some(true)).cloned());
+        let Some(pair) = pair else {
+            return Ok(ConnectionStats::default())
+        };
+        let local_candidate = field(&pair, "localCandidateId").map(|candidate_id| report.get(&candidate_id));
+        let count = |key: &str| field(&pair, key).and_then(|value| value.as_f64()).unwrap_or(0.0) as u64;
+        Ok(ConnectionStats {
+            candidate_type: local_candidate.and_then(|candidate| field(&candidate, "candidateType")).and_then(|value| value.as_string()),
+            bytes_sent: count("bytesSent"),
+            bytes_received: count("bytesReceived"),
+            ice_rtt: field(&pair, "currentRoundTripTime").and_then(|value| value.as_f64()).map(|seconds| seconds * 1000.0),
+        })
+    }
+    //Abandon a local offer that was not answered, returning to the stable state.
+    pub async fn rollback(&self) -> Result<()> {
+        let rollback_description = RtcSessionDescriptionInit::new(RtcSdpType::Rollback);
+        JsFuture::from(self.0.set_local_description(&rollback_description)).await?;
+        Ok(())
+    }
+    pub async fn receive_answer_sdp(&self, answer_sdp: &str) -> Result<()> {
         let mut answer_description = RtcSessionDescriptionInit::new(RtcSdpType::Answer);
             answer_description.sdp(answer_sdp);
-        JsFuture::from(self.0.set_remote_description(&answer_description)).await.unwrap();
+        JsFuture::from(self.0.set_remote_description(&answer_description)).await?;
+        Ok(())
     }
 }
\ No newline at end of file
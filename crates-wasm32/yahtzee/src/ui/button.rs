@@ -34,6 +34,12 @@ impl Button {
         self.button.set_class_name(class);
         self
     }
+    pub fn set_text(&self, text: &str) {
+        self.button.set_text_content(Some(text));
+    }
+    pub fn set_class(&self, class: &str) {
+        self.button.set_class_name(class);
+    }
     pub fn hide(&self) {
         self.button.set_hidden(true);
     }
@@ -32,6 +32,12 @@ impl Div {
     pub fn text(&self, text: &str) {
         self.div.append_with_str_1(text).unwrap_throw();
     }
+    pub fn set_text(&self, text: &str) {
+        self.div.set_text_content(Some(text));
+    }
+    pub fn set_title(&self, title: &str) {
+        self.div.set_title(title);
+    }
     pub fn anchor(&self) -> Anchor {
         let anchor = Anchor::new(self.document.clone());
         self.div.append_child(anchor.as_ref()).unwrap_throw();
@@ -0,0 +1,203 @@
+use futures::{SinkExt, StreamExt};
+use std::{fmt, time::Duration};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, tungstenite::Message};
+use yahtzee_rules::{Action, GameState, MAX_DICE, strategy::{Strategy, best_category}};
+
+use crate::protocol::{LegacyClientEvent, LobbyPhase, LobbyUser, SocketMessage};
+
+pub struct BotConfig {
+    //Lobby websocket endpoint, e.g. wss://example.com/yahtzee/ws.
+    pub url: String,
+    //Lobby to join. A new lobby is created when missing.
+    pub lobby_id: Option<u64>,
+    pub name: String,
+    //Guest token from an earlier session, to play as the same profile.
+    pub token: Option<String>,
+    //Stop after this many finished games. Zero plays until the lobby goes away.
+    pub games: u32,
+    //When hosting, wait for this many ready players before starting.
+    pub min_players: usize,
+    //Pause before each game action so humans can follow along.
+    pub delay: Duration,
+}
+impl Default for BotConfig {
+    fn default() -> Self {
+        Self {
+            url: "ws://127.0.0.1:8000/yahtzee/ws".to_string(),
+            lobby_id: None,
+            name: "Bot".to_string(),
+            token: None,
+            games: 1,
+            min_players: 2,
+            delay: Duration::from_millis(300),
+        }
+    }
+}
+
+pub struct GameResult {
+    pub total: u16,
+    //1 for the winner. Tied players share a place.
+    pub place: usize,
+    pub players: usize,
+}
+
+#[derive(Debug)]
+pub enum BotError {
+    Connect(String),
+    JoinRejected(String),
+    Kicked(String),
+    LobbyClosed(String),
+    Disconnected,
+}
+impl fmt::Display for BotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connect(error) => write!(f, "Failed to connect: {error}"),
+            Self::JoinRejected(reason) => write!(f, "Join rejected: {reason}"),
+            Self::Kicked(reason) => write!(f, "Kicked: {reason}"),
+            Self::LobbyClosed(reason) => write!(f, "Lobby closed: {reason}"),
+            Self::Disconnected => write!(f, "Disconnected"),
+        }
+    }
+}
+impl std::error::Error for BotError {}
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn connect(url: String) -> Result<Socket, BotError> {
+    let (socket, _) = tokio_tungstenite::connect_async(url).await.map_err(|error| BotError::Connect(error.to_string()))?;
+    Ok(socket)
+}
+async fn send(socket: &mut Socket, message: &SocketMessage) -> Result<(), BotError> {
+    let message_serialized = bincode::serialize(message).map_err(|_| BotError::Disconnected)?;
+    socket.send(Message::Binary(message_serialized.into())).await.map_err(|_| BotError::Disconnected)
+}
+async fn receive(socket: &mut Socket) -> Result<SocketMessage, BotError> {
+    loop {
+        match socket.next().await {
+            Some(Ok(Message::Binary(message_serialized))) => match bincode::deserialize(&message_serialized) {
+                Ok(message) => return Ok(message),
+                Err(_) => println!("->> Ignoring unknown message"),
+            },
+            Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return Err(BotError::Disconnected),
+            Some(Ok(_)) => {}
+        }
+    }
+}
+
+//Join a lobby and play until the configured number of games is done.
+//Bots take part through the server only; peer handshakes from other users are ignored.
+pub async fn play(config: &BotConfig, strategy: &mut dyn Strategy) -> Result<Vec<GameResult>, BotError> {
+    let query = {
+        let mut query = url::form_urlencoded::Serializer::new(String::new());
+        query.append_pair("name", &config.name);
+        if let Some(lobby_id) = config.lobby_id {
+            query.append_pair("lobby_id", &lobby_id.to_string());
+        }
+        if let Some(token) = &config.token {
+            query.append_pair("token", token);
+        }
+        query.finish()
+    };
+    let mut socket = connect(format!("{}?{query}", config.url)).await?;
+
+    let user_id = loop {
+        match receive(&mut socket).await? {
+            SocketMessage::ConnectSuccess { lobby_id, user_id, player_id, token, .. } => {
+                println!("->> Joined lobby {lobby_id} as user {user_id} (player {player_id}, token {token})");
+                break user_id
+            }
+            SocketMessage::JoinRejected { reason } => return Err(BotError::JoinRejected(reason)),
+            _ => {}
+        }
+    };
+
+    let mut results = Vec::new();
+    let mut game: Option<GameState> = None;
+    let mut phase = LobbyPhase::Waiting;
+    loop {
+        match receive(&mut socket).await? {
+            SocketMessage::LobbyState { host_id, phase: new_phase, users, .. } => {
+                //A game just ended.
+                if phase == LobbyPhase::InGame && new_phase == LobbyPhase::Finished {
+                    if let Some(result) = game.take().and_then(|game| result_of(&game, user_id)) {
+                        println!("->> Game over: scored {} and placed {} of {}", result.total, result.place, result.players);
+                        results.push(result);
+                    }
+                    if config.games != 0 && results.len() >= config.games as usize {
+                        let _ = socket.close(None).await;
+                        return Ok(results)
+                    }
+                }
+                phase = new_phase;
+                if matches!(phase, LobbyPhase::Waiting | LobbyPhase::Finished) {
+                    prepare(&mut socket, config, user_id, host_id, &users).await?;
+                }
+            }
+            SocketMessage::GameState { state } => {
+                if state.current_player() == Some(user_id) {
+                    tokio::time::sleep(config.delay).await;
+                    let action = strategy.choose(&state, user_id);
+                    send(&mut socket, &SocketMessage::GameAction { action }).await?;
+                }
+                game = Some(state);
+            }
+            //Fall back to a move the rules always allow so the game keeps going.
+            SocketMessage::GameActionRejected { error } => {
+                println!("->> Action rejected: {error:?}");
+                if let Some(state) = game.as_ref().filter(|state| state.current_player() == Some(user_id)) {
+                    let scorecard = state.player(user_id).map(|player| player.scorecard.clone()).unwrap_or_default();
+                    let action = match best_category(&scorecard, &state.dice) {
+                        Some((category, column, _)) if state.has_rolled() => Action::Score(category, column),
+                        _ => Action::Roll { held: [false; MAX_DICE] },
+                    };
+                    send(&mut socket, &SocketMessage::GameAction { action }).await?;
+                }
+            }
+            SocketMessage::Kicked { reason } => return Err(BotError::Kicked(reason)),
+            SocketMessage::LobbyClosed { reason } => return Err(BotError::LobbyClosed(reason)),
+            SocketMessage::ServerNotice { message } => println!("->> Notice: {message}"),
+            _ => {}
+        }
+    }
+}
+
+//Ready up between games, and start the next one when hosting and enough players are ready.
+async fn prepare(socket: &mut Socket, config: &BotConfig, user_id: u32, host_id: u32, users: &[LobbyUser]) -> Result<(), BotError> {
+    let players = users.iter().filter(|user| !user.spectator).collect::<Vec<_>>();
+    if players.iter().any(|user| user.user_id == user_id && !user.ready) {
+        return send(socket, &SocketMessage::SetReady { ready: true }).await
+    }
+    if host_id == user_id && players.len() >= config.min_players && players.iter().all(|user| user.ready) {
+        send(socket, &SocketMessage::StartGame).await?;
+    }
+    Ok(())
+}
+
+fn result_of(game: &GameState, user_id: u32) -> Option<GameResult> {
+    let total = game.player(user_id)?.scorecard.total();
+    Some(GameResult {
+        total,
+        place: 1 + game.players.iter().filter(|player| player.scorecard.total() > total).count(),
+        players: game.players.len(),
+    })
+}
+
+//Join a room on the older /yahtzee1 endpoint and return the server's reply.
+//That endpoint does not run games yet, so this only checks it is up and answering.
+pub async fn join_legacy(url: &str, room: Option<String>, name: &str) -> Result<String, BotError> {
+    let mut socket = connect(url.to_string()).await?;
+    let event = serde_json::to_string(&LegacyClientEvent::Join { room, name: name.to_string() }).map_err(|_| BotError::Disconnected)?;
+    socket.send(Message::text(event)).await.map_err(|_| BotError::Disconnected)?;
+    loop {
+        match socket.next().await {
+            Some(Ok(Message::Text(reply))) => {
+                let _ = socket.close(None).await;
+                return Ok(reply.to_string())
+            }
+            Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return Err(BotError::Disconnected),
+            Some(Ok(_)) => {}
+        }
+    }
+}
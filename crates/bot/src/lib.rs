@@ -0,0 +1,6 @@
+//Headless Yahtzee client that joins lobbies and plays with a pluggable strategy.
+pub mod protocol;
+pub mod strategy;
+mod client;
+
+pub use client::{BotConfig, BotError, GameResult, join_legacy, play};
@@ -0,0 +1,83 @@
+use std::time::Duration;
+use yahtzee_bot::{BotConfig, join_legacy, play, strategy::Random};
+use yahtzee_rules::strategy::{Greedy, Strategy};
+
+const USAGE: &str = "\
+Usage: yahtzee_bot [options]
+    --url <url>             Websocket endpoint, /yahtzee/ws or /yahtzee1/ws (default ws://127.0.0.1:8000/yahtzee/ws)
+    --lobby-id <id>         Lobby to join, or room name on /yahtzee1 (default: create a new lobby)
+    --name <name>           Display name (default Bot)
+    --token <token>         Guest token to play as an existing profile
+    --games <n>             Games to play before leaving, 0 for no limit (default 1)
+    --min-players <n>       Ready players needed before a hosting bot starts (default 2)
+    --strategy <name>       greedy or random (default greedy)
+    --delay <ms>            Pause before each move (default 300)";
+
+struct Options {
+    config: BotConfig,
+    strategy: String,
+    //The lobby id as given, used as the room name on /yahtzee1.
+    room: Option<String>,
+}
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Self { config: BotConfig::default(), strategy: "greedy".to_string(), room: None };
+        let config = &mut options.config;
+        while let Some(arg) = args.next() {
+            let value = args.next().ok_or_else(|| format!("Missing value for {arg}"))?;
+            let invalid = || format!("Invalid value for {arg}: {value}");
+            match arg.as_str() {
+                "--url" => config.url = value,
+                "--lobby-id" => {
+                    config.lobby_id = value.parse().ok();
+                    options.room = Some(value);
+                }
+                "--name" => config.name = value,
+                "--token" => config.token = Some(value),
+                "--games" => config.games = value.parse().map_err(|_| invalid())?,
+                "--min-players" => config.min_players = value.parse().map_err(|_| invalid())?,
+                "--strategy" => options.strategy = value,
+                "--delay" => config.delay = Duration::from_millis(value.parse().map_err(|_| invalid())?),
+                _ => return Err(format!("Unknown option {arg}")),
+            }
+        }
+        Ok(options)
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let Options { config, strategy, room } = Options::parse(std::env::args().skip(1)).unwrap_or_else(|error| exit_with_usage(&error));
+
+    if config.url.contains("/yahtzee1/") {
+        match join_legacy(&config.url, room, &config.name).await {
+            Ok(reply) => println!("->> Server replied: {reply}"),
+            Err(error) => fail(error),
+        }
+        return
+    }
+
+    let mut strategy: Box<dyn Strategy> = match strategy.as_str() {
+        "greedy" => Box::new(Greedy),
+        "random" => Box::new(Random),
+        _ => exit_with_usage(&format!("Unknown strategy {strategy}")),
+    };
+    match play(&config, strategy.as_mut()).await {
+        Ok(results) => {
+            let wins = results.iter().filter(|result| result.place == 1).count();
+            let average = results.iter().map(|result| result.total as f64).sum::<f64>() / results.len().max(1) as f64;
+            println!("->> Played {} games, won {wins}, average score {average:.1}", results.len());
+        }
+        Err(error) => fail(error),
+    }
+}
+
+fn exit_with_usage(error: &str) -> ! {
+    eprintln!("{error}");
+    eprintln!("{USAGE}");
+    std::process::exit(2);
+}
+fn fail(error: impl std::fmt::Display) -> ! {
+    eprintln!("{error}");
+    std::process::exit(1);
+}
@@ -0,0 +1,14 @@
+use serde::Serialize;
+
+//The lobby websocket speaks the wire types shared with the server and the browser client.
+pub use signaling_protocol::lobby::{AiDifficulty, HintChoice, LobbyPhase, LobbySettings, LobbyUser, SdpType, SocketMessage};
+
+//Messages of the older /yahtzee1 endpoint, sent as tagged JSON text.
+#[derive(Serialize)]
+#[serde(tag = "type")]
+pub enum LegacyClientEvent {
+    Join {
+        room: Option<String>,
+        name: String,
+    },
+}
@@ -0,0 +1,18 @@
+use yahtzee_rules::{Action, GameState, MAX_DICE, strategy::Strategy};
+
+//Rerolls random dice a random number of times, then scores any open category. Useful for
+//exercising odd paths through the rules rather than for winning.
+#[derive(Default)]
+pub struct Random;
+impl Strategy for Random {
+    fn choose(&mut self, state: &GameState, player_id: u32) -> Action {
+        let open = state.player(player_id).map(|player| {
+            player.scorecard.choices(&state.dice).map(|(category, column, _)| (category, column)).collect::<Vec<_>>()
+        }).unwrap_or_default();
+        let reroll = state.rolls_left > 0 && (!state.has_rolled() || rand::random_bool(0.5));
+        match open.get(rand::random_range(0..open.len().max(1))) {
+            Some(&(category, column)) if !reroll => Action::Score(category, column),
+            _ => Action::Roll { held: [(); MAX_DICE].map(|_| rand::random()) },
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use server::yahtzee::{self, history::HistoryFilter, registry::InMemoryRegistry};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use yahtzee_bot::{BotConfig, play};
+use yahtzee_rules::strategy::Greedy;
+
+//The yahtzee routes on an ephemeral port, like the server tests run them.
+async fn start_server() -> (SocketAddr, yahtzee::YahtzeeState) {
+    let config = yahtzee::Config {
+        data_dir: std::env::temp_dir().join(format!("yahtzee-bot-test-{:016x}", rand::random::<u64>())),
+        admin_token: None,
+        redis_url: None,
+        instance_id: None,
+        solver: false,
+    };
+    let state = yahtzee::state_with_registry(&config, Arc::new(InMemoryRegistry::new())).expect("Failed to create server state");
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("Failed to bind listener");
+    let addr = listener.local_addr().unwrap();
+    let app = server::app(state.clone());
+    tokio::spawn(async move {
+        let _ = axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await;
+    });
+    (addr, state)
+}
+
+#[tokio::test]
+async fn bot_plays_a_full_game() {
+    let (addr, state) = start_server().await;
+    //The name needs encoding to reach the server intact.
+    let config = BotConfig {
+        url: format!("ws://{addr}/yahtzee/ws"),
+        name: "Bot & Co=1".to_string(),
+        min_players: 1,
+        delay: Duration::ZERO,
+        ..Default::default()
+    };
+    let results = tokio::time::timeout(Duration::from_secs(30), play(&config, &mut Greedy))
+        .await
+        .expect("Game did not finish in time")
+        .expect("Bot failed to play");
+
+    assert_eq!(results.len(), 1);
+    assert_eq!((results[0].place, results[0].players), (1, 1));
+    let leaderboard = state.history.leaderboard(HistoryFilter::AllTime, 10);
+    assert_eq!(leaderboard.len(), 1);
+    assert_eq!(leaderboard[0].display_name, "Bot & Co=1");
+    assert_eq!(leaderboard[0].high_score, results[0].total);
+}
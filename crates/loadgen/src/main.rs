@@ -0,0 +1,41 @@
+mod metrics;
+mod options;
+mod user;
+
+use std::{sync::Arc, time::Instant};
+use tokio::task::JoinSet;
+
+use metrics::Metrics;
+use options::{Options, USAGE};
+
+#[tokio::main]
+async fn main() {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => Arc::new(options),
+        Err(error) => {
+            if !error.is_empty() {
+                eprintln!("{error}");
+            }
+            eprintln!("{USAGE}");
+            std::process::exit(1);
+        }
+    };
+    let lobbies = options.lobbies();
+    println!("->> Simulating {} users in {lobbies} lobbies against {}", options.users, options.url);
+
+    let metrics = Arc::new(Metrics::default());
+    let start = Instant::now();
+    let mut tasks = JoinSet::new();
+    for index in 0..lobbies {
+        //The last lobby takes whatever users are left over.
+        let size = (options.users - index * options.lobby_size as usize).min(options.lobby_size as usize) as u8;
+        let delay = options.ramp_up.mul_f64(index as f64 / lobbies as f64);
+        let (options, metrics) = (options.clone(), metrics.clone());
+        tasks.spawn(async move {
+            tokio::time::sleep(delay).await;
+            user::run_lobby(options, metrics, index, size).await
+        });
+    }
+    while tasks.join_next().await.is_some() {}
+    metrics.report(start.elapsed());
+}
@@ -0,0 +1,105 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, atomic::{AtomicU64, Ordering}},
+    time::Duration,
+};
+
+//Counters shared by every simulated user.
+#[derive(Default)]
+pub struct Metrics {
+    connect_latencies: Mutex<Vec<Duration>>,
+    handshake_latencies: Mutex<Vec<Duration>>,
+    errors: Mutex<BTreeMap<&'static str, u64>>,
+    messages_sent: AtomicU64,
+    messages_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    lobbies_formed: AtomicU64,
+}
+impl Metrics {
+    pub fn connected(&self, latency: Duration) {
+        self.connect_latencies.lock().unwrap().push(latency);
+    }
+    pub fn handshake(&self, latency: Duration) {
+        self.handshake_latencies.lock().unwrap().push(latency);
+    }
+    pub fn error(&self, kind: &'static str) {
+        *self.errors.lock().unwrap().entry(kind).or_default() += 1;
+    }
+    pub fn sent(&self, bytes: usize) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+    pub fn received(&self, bytes: usize) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+    pub fn lobby_formed(&self) {
+        self.lobbies_formed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn report(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+        let messages_sent = self.messages_sent.load(Ordering::Relaxed);
+        let messages_received = self.messages_received.load(Ordering::Relaxed);
+        let bytes = self.bytes_sent.load(Ordering::Relaxed) + self.bytes_received.load(Ordering::Relaxed);
+        println!("Finished in {seconds:.1}s");
+        println!("Lobbies formed: {}", self.lobbies_formed.load(Ordering::Relaxed));
+        print_latencies("Connect", &mut self.connect_latencies.lock().unwrap());
+        print_latencies("Handshake", &mut self.handshake_latencies.lock().unwrap());
+        println!(
+            "Throughput: {:.1} msg/s sent, {:.1} msg/s received, {:.1} KiB/s",
+            messages_sent as f64 / seconds, messages_received as f64 / seconds, bytes as f64 / 1024.0 / seconds
+        );
+        let errors = self.errors.lock().unwrap();
+        println!("Errors: {}", errors.values().sum::<u64>());
+        for (kind, count) in errors.iter() {
+            println!("    {kind}: {count}");
+        }
+    }
+}
+
+fn print_latencies(name: &str, latencies: &mut [Duration]) {
+    if latencies.is_empty() {
+        println!("{name} latency: no samples");
+        return
+    }
+    latencies.sort();
+    let percentile = |p: usize| percentile(latencies, p);
+    println!(
+        "{name} latency ({} samples): p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+        latencies.len(), percentile(50), percentile(90), percentile(99), latencies[latencies.len() - 1]
+    );
+}
+
+//Nearest-rank percentile of sorted, non-empty samples.
+fn percentile(sorted: &[Duration], p: usize) -> Duration {
+    sorted[(sorted.len() * p).div_ceil(100).saturating_sub(1)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(values: &[u64]) -> Vec<Duration> {
+        values.iter().copied().map(Duration::from_millis).collect()
+    }
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let samples = millis(&(1..=100).collect::<Vec<_>>());
+        assert_eq!(percentile(&samples, 50), Duration::from_millis(50));
+        assert_eq!(percentile(&samples, 99), Duration::from_millis(99));
+        assert_eq!(percentile(&samples, 100), Duration::from_millis(100));
+
+        //With few samples the rank rounds up to the next one.
+        let samples = millis(&[10, 20, 30, 40, 50]);
+        assert_eq!(percentile(&samples, 50), Duration::from_millis(30));
+        assert_eq!(percentile(&samples, 90), Duration::from_millis(50));
+        assert_eq!(percentile(&samples, 1), Duration::from_millis(10));
+
+        let samples = millis(&[7]);
+        assert_eq!(percentile(&samples, 0), Duration::from_millis(7));
+        assert_eq!(percentile(&samples, 99), Duration::from_millis(7));
+    }
+}
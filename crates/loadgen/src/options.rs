@@ -0,0 +1,98 @@
+use std::time::Duration;
+
+pub const USAGE: &str = "\
+Usage: loadgen [options]
+    --url <url>             Lobby websocket endpoint (default ws://127.0.0.1:8000/yahtzee/ws)
+    --users <n>             Total number of simulated users (default 100)
+    --lobby-size <n>        Users per lobby, 1 to 8 (default 4)
+    --renegotiations <n>    Extra handshakes per peer pair after the first, like an ICE restart (default 1)
+    --ramp-up <seconds>     Spread lobby creation over this long (default 10)
+    --hold <seconds>        How long each user stays connected after joining (default 30)
+    --timeout <seconds>     Give up on a connection or handshake after this long (default 10)";
+
+pub struct Options {
+    pub url: String,
+    pub users: usize,
+    pub lobby_size: u8,
+    pub renegotiations: u32,
+    pub ramp_up: Duration,
+    pub hold: Duration,
+    pub timeout: Duration,
+}
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            url: "ws://127.0.0.1:8000/yahtzee/ws".to_string(),
+            users: 100,
+            lobby_size: 4,
+            renegotiations: 1,
+            ramp_up: Duration::from_secs(10),
+            hold: Duration::from_secs(30),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+impl Options {
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Self::default();
+        while let Some(arg) = args.next() {
+            if arg == "--help" || arg == "-h" {
+                return Err(String::new())
+            }
+            let value = args.next().ok_or_else(|| format!("Missing value for {arg}"))?;
+            let number = |value: &str| value.parse::<u64>().map_err(|_| format!("Invalid value for {arg}: {value}"));
+            match arg.as_str() {
+                "--url" => options.url = value,
+                "--users" => options.users = number(&value)? as usize,
+                "--lobby-size" => options.lobby_size = number(&value)?.clamp(1, server::yahtzee::lobby::MAX_PLAYERS as u64) as u8,
+                "--renegotiations" => options.renegotiations = number(&value)? as u32,
+                "--ramp-up" => options.ramp_up = Duration::from_secs(number(&value)?),
+                "--hold" => options.hold = Duration::from_secs(number(&value)?),
+                "--timeout" => options.timeout = Duration::from_secs(number(&value)?),
+                _ => return Err(format!("Unknown option {arg}")),
+            }
+        }
+        Ok(options)
+    }
+    pub fn lobbies(&self) -> usize {
+        self.users.div_ceil(self.lobby_size as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parses_every_option() {
+        let options = parse(&[
+            "--url", "ws://example.com/yahtzee/ws", "--users", "12", "--lobby-size", "3", "--renegotiations", "0",
+            "--ramp-up", "5", "--hold", "60", "--timeout", "2",
+        ]).unwrap();
+        assert_eq!(options.url, "ws://example.com/yahtzee/ws");
+        assert_eq!((options.users, options.lobby_size, options.renegotiations), (12, 3, 0));
+        assert_eq!((options.ramp_up, options.hold, options.timeout), (Duration::from_secs(5), Duration::from_secs(60), Duration::from_secs(2)));
+        assert_eq!(options.lobbies(), 4);
+    }
+
+    #[test]
+    fn defaults_and_limits() {
+        let options = parse(&[]).unwrap();
+        assert_eq!((options.users, options.lobby_size), (100, 4));
+        assert_eq!(parse(&["--lobby-size", "0"]).unwrap().lobby_size, 1);
+        assert_eq!(parse(&["--lobby-size", "50"]).unwrap().lobby_size, server::yahtzee::lobby::MAX_PLAYERS);
+        assert_eq!(parse(&["--users", "10", "--lobby-size", "4"]).unwrap().lobbies(), 3);
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert_eq!(parse(&["--help"]).err(), Some(String::new()));
+        assert_eq!(parse(&["--users"]).err(), Some("Missing value for --users".to_string()));
+        assert_eq!(parse(&["--users", "many"]).err(), Some("Invalid value for --users: many".to_string()));
+        assert_eq!(parse(&["--speed", "1"]).err(), Some("Unknown option --speed".to_string()));
+    }
+}
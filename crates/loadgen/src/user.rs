@@ -0,0 +1,208 @@
+use futures::{SinkExt, StreamExt};
+use server::yahtzee::lobby::{LobbyID, LobbySettings, SdpType, SocketMessage, UserID};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::{net::TcpStream, sync::oneshot, task::JoinSet, time::Instant};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, tungstenite::Message};
+
+use crate::{metrics::Metrics, options::Options};
+
+//Longest random pause before a guest joins, so guests of one lobby do not arrive in lockstep.
+const MAX_JOIN_JITTER: Duration = Duration::from_millis(500);
+const ICE_CANDIDATES: usize = 4;
+
+//One host creates the lobby, then the guests join it once the host has set the lobby size.
+pub async fn run_lobby(options: Arc<Options>, metrics: Arc<Metrics>, index: usize, size: u8) {
+    let (lobby_sender, lobby_receiver) = oneshot::channel();
+    let mut users = JoinSet::new();
+    users.spawn(run_user(options.clone(), metrics.clone(), format!("host{index}"), None, size, Some(lobby_sender)));
+    if let Ok(lobby_id) = lobby_receiver.await {
+        for guest in 1..size {
+            let jitter = MAX_JOIN_JITTER.mul_f64(rand::random::<f64>());
+            let (options, metrics) = (options.clone(), metrics.clone());
+            users.spawn(async move {
+                tokio::time::sleep(jitter).await;
+                run_user(options, metrics, format!("guest{index}-{guest}"), Some(lobby_id), size, None).await
+            });
+        }
+    }
+    while users.join_next().await.is_some() {}
+}
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+struct User {
+    socket: Socket,
+    metrics: Arc<Metrics>,
+    user_id: UserID,
+    session_id: u32,
+    //Peers this user sent an offer to, with when it was sent and how many handshakes were completed with them.
+    offers: HashMap<UserID, (Option<Instant>, u32)>,
+}
+impl User {
+    async fn send(&mut self, socket_message: &SocketMessage) -> bool {
+        let Ok(socket_message_serialized) = bincode::serialize(socket_message) else {
+            return false
+        };
+        self.metrics.sent(socket_message_serialized.len());
+        self.socket.send(Message::Binary(socket_message_serialized.into())).await.is_ok()
+    }
+    async fn receive(&mut self) -> Option<SocketMessage> {
+        loop {
+            match self.socket.next().await? {
+                Ok(Message::Binary(socket_message_serialized)) => {
+                    self.metrics.received(socket_message_serialized.len());
+                    match bincode::deserialize(&socket_message_serialized) {
+                        Ok(socket_message) => return Some(socket_message),
+                        Err(_) => self.metrics.error("malformed message"),
+                    }
+                }
+                Ok(Message::Close(_)) | Err(_) => return None,
+                Ok(_) => {}
+            }
+        }
+    }
+    async fn offer(&mut self, peer_id: UserID) -> bool {
+        let round = self.offers.get(&peer_id).map_or(0, |(_, round)| *round);
+        self.offers.insert(peer_id, (Some(Instant::now()), round));
+        self.handshake(peer_id, SdpType::Offer).await
+    }
+    //The same shape of handshake the browser client sends: a full SDP with every gathered ICE candidate.
+    async fn handshake(&mut self, peer_id: UserID, sdp_type: SdpType) -> bool {
+        let handshake = SocketMessage::WebRtcHandshake {
+            source_id: self.user_id,
+            target_id: peer_id,
+            session_id: self.session_id,
+            sdp_type,
+            sdp_description: fake_sdp(),
+            ice_candidates: (0..ICE_CANDIDATES).map(|index| (fake_candidate(index), Some("0".to_string()), Some(0))).collect(),
+        };
+        self.send(&handshake).await
+    }
+}
+
+async fn run_user(options: Arc<Options>, metrics: Arc<Metrics>, name: String, lobby_id: Option<LobbyID>, size: u8, mut created: Option<oneshot::Sender<LobbyID>>) {
+    let query = {
+        let mut query = url::form_urlencoded::Serializer::new(String::new());
+        query.append_pair("name", &name);
+        if let Some(lobby_id) = lobby_id {
+            query.append_pair("lobby_id", &lobby_id.to_string());
+        }
+        query.finish()
+    };
+    let url = format!("{}?{query}", options.url);
+    let creator = created.is_some();
+    let start = Instant::now();
+    let socket = match tokio::time::timeout(options.timeout, tokio_tungstenite::connect_async(url)).await {
+        Ok(Ok((socket, _))) => socket,
+        Ok(Err(error)) => {
+            println!("->> Failed to connect {name}: {error}");
+            return metrics.error("connect failed")
+        }
+        Err(_) => return metrics.error("connect timeout"),
+    };
+    let mut user = User { socket, metrics: metrics.clone(), user_id: 0, session_id: rand::random(), offers: HashMap::new() };
+
+    //Wait to be let into the lobby.
+    let joined = tokio::time::timeout(options.timeout, async {
+        loop {
+            match user.receive().await {
+                Some(SocketMessage::ConnectSuccess { lobby_id, user_id, peers_id, .. }) => return Ok((lobby_id, user_id, peers_id)),
+                Some(SocketMessage::JoinRejected { .. }) => return Err("join rejected"),
+                Some(_) => {}
+                None => return Err("closed before joining"),
+            }
+        }
+    }).await;
+    let (lobby_id, peers_id) = match joined {
+        Ok(Ok((lobby_id, user_id, peers_id))) => {
+            user.user_id = user_id;
+            (lobby_id, peers_id)
+        }
+        Ok(Err(kind)) => return metrics.error(kind),
+        Err(_) => return metrics.error("join timeout"),
+    };
+    metrics.connected(start.elapsed());
+
+    //The host sizes the lobby before letting guests in.
+    let settings = LobbySettings { max_players: size, ..LobbySettings::default() };
+    if creator && !user.send(&SocketMessage::SetSettings { settings }).await {
+        return metrics.error("send failed")
+    }
+    //Newcomers offer a connection to everyone already in the lobby.
+    for peer_id in peers_id {
+        if !user.offer(peer_id).await {
+            return metrics.error("send failed")
+        }
+    }
+
+    let hold = tokio::time::sleep(options.hold);
+    tokio::pin!(hold);
+    let mut formed = false;
+    loop {
+        let socket_message = tokio::select! {
+            _ = &mut hold => break,
+            socket_message = user.receive() => socket_message,
+        };
+        let sent = match socket_message {
+            Some(SocketMessage::WebRtcHandshake { source_id, .. }) => match user.offers.get_mut(&source_id) {
+                //An answer to an offer from this user. Renegotiate until enough rounds are done.
+                Some((Some(sent_at), round)) => {
+                    metrics.handshake(sent_at.elapsed());
+                    *round += 1;
+                    if *round <= options.renegotiations {
+                        user.offer(source_id).await
+                    } else {
+                        user.offers.insert(source_id, (None, options.renegotiations));
+                        true
+                    }
+                }
+                //An offer from a newer peer, answered right away.
+                _ => user.handshake(source_id, SdpType::Answer).await,
+            },
+            Some(SocketMessage::LobbyState { settings, users, .. }) => {
+                if settings.max_players == size && let Some(created) = created.take() {
+                    let _ = created.send(lobby_id);
+                }
+                if creator && !formed && users.len() == size as usize {
+                    formed = true;
+                    metrics.lobby_formed();
+                }
+                true
+            }
+            Some(SocketMessage::Kicked { .. }) => return metrics.error("kicked"),
+            Some(SocketMessage::LobbyClosed { .. }) => return metrics.error("lobby closed"),
+            Some(_) => true,
+            None => return metrics.error("unexpected close"),
+        };
+        if !sent {
+            return metrics.error("send failed")
+        }
+    }
+    for (_, (sent_at, _)) in user.offers.iter() {
+        if sent_at.is_some_and(|sent_at| sent_at.elapsed() > options.timeout) {
+            metrics.error("handshake timeout");
+        }
+    }
+    let _ = user.socket.close(None).await;
+}
+
+fn random_text(length: usize) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    (0..length).map(|_| ALPHABET[rand::random_range(0..ALPHABET.len())] as char).collect()
+}
+//A data channel session description about the size of the ones browsers produce.
+fn fake_sdp() -> String {
+    let fingerprint = (0..32).map(|_| format!("{:02X}", rand::random::<u8>())).collect::<Vec<_>>().join(":");
+    format!(
+        "v=0\r\no=- {} 2 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\na=group:BUNDLE 0\r\na=extmap-allow-mixed\r\na=msid-semantic: WMS\r\n\
+        m=application 9 UDP/DTLS/SCTP webrtc-datachannel\r\nc=IN IP4 0.0.0.0\r\na=ice-ufrag:{}\r\na=ice-pwd:{}\r\n\
+        a=ice-options:trickle\r\na=fingerprint:sha-256 {fingerprint}\r\na=setup:actpass\r\na=mid:0\r\na=sctp-port:5000\r\na=max-message-size:262144\r\n",
+        rand::random::<u64>() >> 1, random_text(4), random_text(24)
+    )
+}
+fn fake_candidate(index: usize) -> String {
+    format!(
+        "candidate:{} 1 udp {} 192.0.2.{} {} typ host generation 0 ufrag {} network-id {}",
+        rand::random::<u32>(), 2122260223 - index as u32 * 256, rand::random_range(1..255), rand::random_range(49152..65535), random_text(4), index + 1
+    )
+}
@@ -1,6 +1,8 @@
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
+use axum::Json;
 use serde::Serialize;
+use serde_json::json;
 
 pub type Result<T> = core::result::Result<T, Error>;
 
@@ -10,6 +12,14 @@ pub enum Error {
     YahtzeeLobbyNotFound,
     YahtzeeLobbyError,
     YahtzeeMessageSerializationError,
+    YahtzeeStorageError,
+    YahtzeeInvalidToken,
+    YahtzeeProfileNotFound,
+    YahtzeeAdminUnauthorized,
+    YahtzeeUserNotFound,
+    YahtzeeTournamentNotFound,
+    YahtzeeInvalidTournament,
+    YahtzeeRegistryError,
 }
 
 impl core::fmt::Display for Error {
@@ -22,7 +32,8 @@ impl std::error::Error for Error {}
 
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
-        let mut response = StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        let (status, client_error) = self.client_status_and_error();
+        let mut response = (status, Json(json!({ "error": client_error }))).into_response();
         response.extensions_mut().insert(self);
         response
     }
@@ -32,6 +43,12 @@ impl Error {
     pub fn client_status_and_error(&self) -> (StatusCode, &'static str) {
         match self {
             Self::YahtzeeLobbyNotFound => (StatusCode::BAD_REQUEST, "INVALID_LOBBY"),
+            Self::YahtzeeInvalidToken => (StatusCode::UNAUTHORIZED, "INVALID_TOKEN"),
+            Self::YahtzeeProfileNotFound => (StatusCode::NOT_FOUND, "INVALID_PROFILE"),
+            Self::YahtzeeAdminUnauthorized => (StatusCode::UNAUTHORIZED, "UNAUTHORIZED"),
+            Self::YahtzeeUserNotFound => (StatusCode::NOT_FOUND, "INVALID_USER"),
+            Self::YahtzeeTournamentNotFound => (StatusCode::NOT_FOUND, "INVALID_TOURNAMENT"),
+            Self::YahtzeeInvalidTournament => (StatusCode::BAD_REQUEST, "INVALID_TOURNAMENT"),
             _ => (StatusCode::INTERNAL_SERVER_ERROR, "SERVICE_ERROR"),
         }
     }
@@ -0,0 +1,16 @@
+pub mod error;
+pub mod yahtzee;
+mod yahtzee1;
+
+use axum::Router;
+use tower_http::services::{ServeDir, ServeFile};
+
+pub use crate::error::Result;
+
+//Everything served over https. Tests mount this on a plain listener instead.
+pub fn app(yahtzee_state: yahtzee::YahtzeeState) -> Router {
+    Router::new()
+        .fallback_service(ServeDir::new("assets").precompressed_gzip().not_found_service(ServeFile::new("assets/not_found.html")))
+        .nest("/yahtzee", yahtzee::routes(yahtzee_state))
+        .nest("/yahtzee1", yahtzee1::routes())
+}
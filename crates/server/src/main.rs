@@ -1,35 +1,42 @@
-pub mod error;
-pub mod yahtzee;
-mod yahtzee1;
-
 use axum::Router;
-use axum_server::tls_rustls::RustlsConfig;
-use std::net::SocketAddr;
-use tower_http::services::{ServeDir, ServeFile};
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use std::{net::SocketAddr, time::Duration};
+use tower_http::services::ServeDir;
 
-pub use crate::error::Result;
+use server::{yahtzee, Result};
 
 const IP_ADDR: [u8; 4] = [0, 0, 0, 0];
 const HTTP_PORT: u16 = 8000;
 const HTTPS_PORT: u16 = 8001;
 const CERT_FILE: &str = "certs/cert.pem";
 const KEY_FILE: &str = "certs/key.pem";
+const DATA_DIR: &str = "data";
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(5);
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let https_routes = Router::new()
-        .fallback_service(ServeDir::new("assets").precompressed_gzip().not_found_service(ServeFile::new("assets/not_found.html")))
-        .nest("/yahtzee", yahtzee::routes())
-        .nest("/yahtzee1", yahtzee1::routes());
+    let yahtzee_state = yahtzee::state(&yahtzee::Config::from_env(DATA_DIR))?;
+    let https_routes = server::app(yahtzee_state.clone());
     let http_routes = Router::new()
         .nest_service("/.well-known/acme-challenge", ServeDir::new("assets/.well-known/acme-challenge"));
     match RustlsConfig::from_pem_file(CERT_FILE, KEY_FILE).await {
         Ok(config) => {
             println!("->> Found certificates!, Running in encrypted mode.");
+            let handle = Handle::new();
             let https_addr = SocketAddr::from((IP_ADDR, HTTPS_PORT));
-            let https = tokio::task::spawn(axum_server::bind_rustls(https_addr, config).serve(https_routes.into_make_service_with_connect_info::<SocketAddr>()));
+            let https = tokio::task::spawn(axum_server::bind_rustls(https_addr, config).handle(handle.clone()).serve(https_routes.into_make_service_with_connect_info::<SocketAddr>()));
             let http_addr = SocketAddr::from((IP_ADDR, HTTP_PORT));
-            let http = tokio::task::spawn(axum_server::bind(http_addr).serve(http_routes.into_make_service_with_connect_info::<SocketAddr>()));
+            let http = tokio::task::spawn(axum_server::bind(http_addr).handle(handle.clone()).serve(http_routes.into_make_service_with_connect_info::<SocketAddr>()));
+
+            //Save lobbies before stopping so they survive a redeploy.
+            tokio::task::spawn(async move {
+                shutdown_signal().await;
+                println!("->> Shutting down");
+                if yahtzee_state.shutdown().await.is_err() {
+                    println!("->> Failed to save lobbies");
+                }
+                handle.graceful_shutdown(Some(SHUTDOWN_GRACE_PERIOD));
+            });
             let _ = tokio::join!(https, http);
         }
         Err(error) => {
@@ -38,4 +45,17 @@ async fn main() -> Result<()> {
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+async fn shutdown_signal() {
+    let ctrl_c = tokio::signal::ctrl_c();
+    #[cfg(unix)]
+    {
+        let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+        tokio::select! {
+            _ = ctrl_c => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = ctrl_c.await;
+}
@@ -0,0 +1,65 @@
+use axum::{
+    extract::{Path, State},
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    routing::{delete, get, post},
+    Json, Router
+};
+use serde::Deserialize;
+
+use crate::{error::Error, Result};
+use super::{YahtzeeState, lobby::{LobbyID, LobbyInfo, UserID}};
+
+pub const ADMIN_TOKEN_VAR: &str = "YAHTZEE_ADMIN_TOKEN";
+
+pub fn routes() -> Router<YahtzeeState> {
+    Router::new()
+        .route("/lobbies", get(list_lobbies))
+        .route("/lobbies/{lobby_id}", delete(close_lobby))
+        .route("/lobbies/{lobby_id}/users/{user_id}", delete(kick_user))
+        .route("/notice", post(broadcast_notice))
+}
+
+//Check the "Authorization: Bearer <token>" header against the configured admin token.
+//Admin endpoints are disabled entirely when no admin token is configured.
+pub(super) fn authorize(state: &YahtzeeState, headers: &HeaderMap) -> Result<()> {
+    let expected = state.admin_token.as_deref().ok_or(Error::YahtzeeAdminUnauthorized)?;
+    let provided = headers.get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(Error::YahtzeeAdminUnauthorized)?;
+    //Compare in constant time to avoid leaking the token through response timing.
+    let matches = provided.len() == expected.len() &&
+        provided.bytes().zip(expected.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0;
+    if matches { Ok(()) } else { Err(Error::YahtzeeAdminUnauthorized) }
+}
+
+async fn list_lobbies(State(state): State<YahtzeeState>, headers: HeaderMap) -> Result<Json<Vec<LobbyInfo>>> {
+    authorize(&state, &headers)?;
+    let mut lobbies = state.lobbies.list().await;
+    lobbies.sort_by_key(|lobby| lobby.created_at);
+    Ok(Json(lobbies))
+}
+
+#[derive(Deserialize)]
+struct Reason {
+    reason: String,
+}
+async fn close_lobby(State(state): State<YahtzeeState>, headers: HeaderMap, Path(lobby_id): Path<LobbyID>, Json(Reason { reason }): Json<Reason>) -> Result<StatusCode> {
+    authorize(&state, &headers)?;
+    if state.lobbies.close(lobby_id, reason) { Ok(StatusCode::NO_CONTENT) } else { Err(Error::YahtzeeLobbyNotFound) }
+}
+
+async fn kick_user(State(state): State<YahtzeeState>, headers: HeaderMap, Path((lobby_id, user_id)): Path<(LobbyID, UserID)>, Json(Reason { reason }): Json<Reason>) -> Result<StatusCode> {
+    authorize(&state, &headers)?;
+    if state.lobbies.kick(lobby_id, user_id, reason).await { Ok(StatusCode::NO_CONTENT) } else { Err(Error::YahtzeeUserNotFound) }
+}
+
+#[derive(Deserialize)]
+struct Notice {
+    message: String,
+}
+async fn broadcast_notice(State(state): State<YahtzeeState>, headers: HeaderMap, Json(Notice { message }): Json<Notice>) -> Result<StatusCode> {
+    authorize(&state, &headers)?;
+    state.lobbies.broadcast_notice(&message);
+    Ok(StatusCode::NO_CONTENT)
+}
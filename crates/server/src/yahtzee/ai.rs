@@ -0,0 +1,43 @@
+use std::time::Duration;
+use yahtzee_rules::{Action, GameState, MAX_DICE, strategy::{Greedy, Lookahead, Strategy}};
+
+use super::solvers::Solvers;
+
+pub use signaling_protocol::lobby::AiDifficulty;
+
+//How computer players of each difficulty play. They are driven by the lobby task itself.
+pub trait AiPlayer {
+    fn choose(self, state: &GameState, user_id: u32, solvers: &Solvers) -> Action;
+    //How long to pause before an action so games move at a human pace.
+    fn think_time(self, action: &Action) -> Duration;
+}
+impl AiPlayer for AiDifficulty {
+    fn choose(self, state: &GameState, user_id: u32, solvers: &Solvers) -> Action {
+        match self {
+            //Plays like Normal but often keeps the wrong dice or scores in a random box.
+            Self::Easy if rand::random_bool(0.3) => blunder(state, user_id),
+            Self::Easy | Self::Normal => Greedy.choose(state, user_id),
+            //Plays optimally once the solver is ready, and like Hard until then.
+            Self::Expert if let Some(mut solver) = solvers.get(state.options) => solver.choose(state, user_id),
+            Self::Hard | Self::Expert => Lookahead.choose(state, user_id),
+        }
+    }
+    fn think_time(self, action: &Action) -> Duration {
+        let millis = match action {
+            Action::Roll { .. } => rand::random_range(600..1500),
+            Action::Score(..) => rand::random_range(1200..2500),
+        };
+        Duration::from_millis(millis)
+    }
+}
+
+fn blunder(state: &GameState, user_id: u32) -> Action {
+    let open = state.player(user_id).map(|player| {
+        player.scorecard.choices(&state.dice).map(|(category, column, _)| (category, column)).collect::<Vec<_>>()
+    }).unwrap_or_default();
+    if state.has_rolled() && (state.rolls_left == 0 || rand::random_bool(0.5)) && !open.is_empty() {
+        let (category, column) = open[rand::random_range(0..open.len())];
+        return Action::Score(category, column)
+    }
+    Action::Roll { held: [(); MAX_DICE].map(|_| rand::random()) }
+}
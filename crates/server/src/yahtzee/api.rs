@@ -0,0 +1,143 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::{header::AUTHORIZATION, HeaderMap},
+    routing::{get, post},
+    Json, Router
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Error, Result};
+use super::{
+    YahtzeeState,
+    admin,
+    identity::PlayerID,
+    profile::{self, Profile},
+    history::{GameRecord, HistoryFilter, LeaderboardEntry},
+    tournament::{self, Entrant, Standing, Tournament, TournamentFormat, TournamentID, TournamentSummary},
+};
+
+const DEFAULT_LIMIT: usize = 20;
+const MAX_LIMIT: usize = 100;
+
+pub fn routes() -> Router<YahtzeeState> {
+    Router::new()
+        .route("/profile", get(get_own_profile).put(update_own_profile))
+        .route("/profiles/{player_id}", get(get_profile))
+        .route("/players/{player_id}/history", get(get_player_history))
+        .route("/leaderboard", get(get_leaderboard))
+        .route("/tournaments", post(create_tournament).get(list_tournaments))
+        .route("/tournaments/{tournament_id}", get(get_tournament))
+}
+
+//Extract the player ID from an "Authorization: Bearer <token>" header.
+pub fn authenticate(state: &YahtzeeState, headers: &HeaderMap) -> Result<PlayerID> {
+    headers.get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .and_then(|token| state.token_signer.verify(token.trim()))
+        .ok_or(Error::YahtzeeInvalidToken)
+}
+
+async fn get_own_profile(State(state): State<YahtzeeState>, headers: HeaderMap) -> Result<Json<Profile>> {
+    let player_id = authenticate(&state, &headers)?;
+    state.profiles.get(player_id).map(Json).ok_or(Error::YahtzeeProfileNotFound)
+}
+
+#[derive(Deserialize)]
+struct ProfileUpdate {
+    display_name: Option<String>,
+    avatar_color: Option<u32>,
+}
+async fn update_own_profile(State(state): State<YahtzeeState>, headers: HeaderMap, Json(update): Json<ProfileUpdate>) -> Result<Json<Profile>> {
+    let player_id = authenticate(&state, &headers)?;
+    let display_name = update.display_name.map(|name| profile::sanitize_display_name(&name)).filter(|name| !name.is_empty());
+    //A guest that never finished a game has nothing stored yet, so this may be the first change to keep.
+    let default_name = display_name.clone().unwrap_or_else(|| "Guest".to_string());
+    let profile = state.profiles.upsert(player_id, &default_name, |profile| {
+        if let Some(display_name) = display_name {
+            profile.display_name = display_name;
+        }
+        if let Some(avatar_color) = update.avatar_color {
+            profile.avatar_color = avatar_color & 0xFFFFFF;
+        }
+    });
+    Ok(Json(profile))
+}
+
+async fn get_profile(State(state): State<YahtzeeState>, Path(player_id): Path<PlayerID>) -> Result<Json<Profile>> {
+    state.profiles.get(player_id).map(Json).ok_or(Error::YahtzeeProfileNotFound)
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    #[serde(default)]
+    filter: HistoryFilter,
+    limit: Option<usize>,
+}
+impl HistoryQuery {
+    fn limit(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT)
+    }
+}
+
+async fn get_leaderboard(State(state): State<YahtzeeState>, Query(query): Query<HistoryQuery>) -> Json<Vec<LeaderboardEntry>> {
+    Json(state.history.leaderboard(query.filter, query.limit()))
+}
+
+async fn get_player_history(State(state): State<YahtzeeState>, Path(player_id): Path<PlayerID>, Query(query): Query<HistoryQuery>) -> Result<Json<Vec<GameRecord>>> {
+    if state.profiles.get(player_id).is_none() {
+        return Err(Error::YahtzeeProfileNotFound)
+    }
+    Ok(Json(state.history.player_history(player_id, query.filter, query.limit())))
+}
+
+#[derive(Deserialize)]
+struct NewTournament {
+    name: String,
+    format: TournamentFormat,
+    best_of: Option<u8>,
+    players: Vec<PlayerID>,
+}
+//Every series gets its own lobby task, so only administrators may create tournaments, and only up to a size.
+async fn create_tournament(State(state): State<YahtzeeState>, headers: HeaderMap, Json(new_tournament): Json<NewTournament>) -> Result<Json<TournamentView>> {
+    admin::authorize(&state, &headers)?;
+    let best_of = new_tournament.best_of.unwrap_or(1);
+    if best_of % 2 == 0 || best_of > tournament::MAX_BEST_OF || new_tournament.players.len() > tournament::MAX_ENTRANTS {
+        return Err(Error::YahtzeeInvalidTournament)
+    }
+    let mut entrants = Vec::<Entrant>::new();
+    for player_id in new_tournament.players {
+        if entrants.iter().any(|entrant| entrant.player_id == player_id) {
+            return Err(Error::YahtzeeInvalidTournament)
+        }
+        let profile = state.profiles.get(player_id).ok_or(Error::YahtzeeProfileNotFound)?;
+        entrants.push(Entrant { player_id, display_name: profile.display_name });
+    }
+    if entrants.len() < 2 {
+        return Err(Error::YahtzeeInvalidTournament)
+    }
+    let tournament = state.tournaments.create(new_tournament.name, new_tournament.format, best_of, entrants);
+    Ok(Json(TournamentView::new(tournament)))
+}
+
+async fn list_tournaments(State(state): State<YahtzeeState>) -> Json<Vec<TournamentSummary>> {
+    Json(state.tournaments.list())
+}
+
+#[derive(Serialize)]
+struct TournamentView {
+    #[serde(flatten)]
+    tournament: Tournament,
+    standings: Vec<Standing>,
+}
+impl TournamentView {
+    fn new(tournament: Tournament) -> Self {
+        Self {
+            standings: tournament.standings(),
+            tournament,
+        }
+    }
+}
+async fn get_tournament(State(state): State<YahtzeeState>, Path(tournament_id): Path<TournamentID>) -> Result<Json<TournamentView>> {
+    state.tournaments.get(tournament_id).map(TournamentView::new).map(Json).ok_or(Error::YahtzeeTournamentNotFound)
+}
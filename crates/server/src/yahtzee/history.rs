@@ -0,0 +1,263 @@
+use serde::{Deserialize, Serialize};
+use std::{cmp::Reverse, collections::BTreeMap, path::PathBuf, sync::{Arc, Mutex}, time::{SystemTime, UNIX_EPOCH}};
+use yahtzee_rules::Scorecard;
+
+use crate::error::Result;
+use super::{identity::{AI_PLAYER_ID, PlayerID}, lobby::LobbyID, profile::ProfileStore, storage::{self, Writer}};
+
+pub type GameID = u64;
+
+const WEEK_SECONDS: u64 = 7 * 24 * 60 * 60;
+
+pub fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or_default()
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PlayerResult {
+    pub player_id: PlayerID,
+    pub display_name: String,
+    pub scorecard: Scorecard,
+    pub total: u16,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GameRecord {
+    pub game_id: GameID,
+    pub lobby_id: LobbyID,
+    pub started_at: u64,
+    pub finished_at: u64,
+    pub players: Vec<PlayerResult>,
+}
+impl GameRecord {
+    pub fn winning_total(&self) -> Option<u16> {
+        self.players.iter().map(|player| player.total).max()
+    }
+    pub fn is_winner(&self, player_id: PlayerID) -> bool {
+        let winning_total = self.winning_total();
+        self.players.iter().any(|player| player.player_id == player_id && Some(player.total) == winning_total)
+    }
+    pub fn result_of(&self, player_id: PlayerID) -> Option<&PlayerResult> {
+        self.players.iter().find(|player| player.player_id == player_id)
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryFilter {
+    #[default]
+    AllTime,
+    Weekly,
+    HighScore,
+}
+
+#[derive(Serialize, Clone)]
+pub struct LeaderboardEntry {
+    pub player_id: PlayerID,
+    pub display_name: String,
+    pub games_played: u32,
+    pub games_won: u32,
+    pub total_score: u64,
+    pub high_score: u16,
+}
+
+//Completed games kept in memory and persisted as an append-only JSON lines log.
+#[derive(Clone)]
+pub struct GameHistory {
+    path: Arc<PathBuf>,
+    games: Arc<Mutex<Vec<GameRecord>>>,
+    profiles: ProfileStore,
+    writer: Writer,
+}
+impl GameHistory {
+    pub fn open(path: PathBuf, profiles: ProfileStore, writer: Writer) -> Result<Self> {
+        let games = storage::load_lines(&path)?;
+        Ok(Self {
+            path: Arc::new(path),
+            games: Arc::new(Mutex::new(games)),
+            profiles,
+            writer,
+        })
+    }
+    //Store a finished game and fold its results into each player's profile stats.
+    //Called from lobby tasks, so the log and the profiles are written by the writer thread.
+    pub fn record(&self, mut record: GameRecord) -> Result<()> {
+        //Never trust submitted totals; recompute them from the scorecards.
+        for player in record.players.iter_mut() {
+            player.total = player.scorecard.total();
+        }
+        self.writer.append_line(&self.path, &record)?;
+        for player in record.players.iter().filter(|player| player.player_id != AI_PLAYER_ID) {
+            let won = record.is_winner(player.player_id);
+            self.profiles.upsert(player.player_id, &player.display_name, |profile| {
+                profile.stats.games_played += 1;
+                profile.stats.games_won += won as u32;
+                profile.stats.total_score += player.total as u64;
+                profile.stats.high_score = profile.stats.high_score.max(player.total as u32);
+            });
+        }
+        println!("->> Recorded game {} from lobby {}", record.game_id, record.lobby_id);
+        self.games.lock().unwrap().push(record);
+        Ok(())
+    }
+    pub fn leaderboard(&self, filter: HistoryFilter, limit: usize) -> Vec<LeaderboardEntry> {
+        let since = if filter == HistoryFilter::Weekly { unix_time().saturating_sub(WEEK_SECONDS) } else { 0 };
+        let mut entries = BTreeMap::<PlayerID, LeaderboardEntry>::new();
+        for game in self.games.lock().unwrap().iter().filter(|game| game.finished_at >= since) {
+            for player in game.players.iter().filter(|player| player.player_id != AI_PLAYER_ID) {
+                let entry = entries.entry(player.player_id).or_insert_with(|| LeaderboardEntry {
+                    player_id: player.player_id,
+                    display_name: player.display_name.clone(),
+                    games_played: 0,
+                    games_won: 0,
+                    total_score: 0,
+                    high_score: 0,
+                });
+                entry.games_played += 1;
+                entry.games_won += game.is_winner(player.player_id) as u32;
+                entry.total_score += player.total as u64;
+                entry.high_score = entry.high_score.max(player.total);
+            }
+        }
+        let mut entries = entries.into_values().collect::<Vec<_>>();
+        match filter {
+            HistoryFilter::HighScore => entries.sort_by_key(|entry| Reverse(entry.high_score)),
+            _ => entries.sort_by_key(|entry| Reverse((entry.games_won, entry.total_score))),
+        }
+        entries.truncate(limit);
+        //Show current display names rather than the ones players had at the time.
+        for entry in entries.iter_mut() {
+            if let Some(profile) = self.profiles.get(entry.player_id) {
+                entry.display_name = profile.display_name;
+            }
+        }
+        entries
+    }
+    pub fn player_history(&self, player_id: PlayerID, filter: HistoryFilter, limit: usize) -> Vec<GameRecord> {
+        let since = if filter == HistoryFilter::Weekly { unix_time().saturating_sub(WEEK_SECONDS) } else { 0 };
+        let mut games = self.games.lock().unwrap().iter()
+            .filter(|game| game.finished_at >= since && game.result_of(player_id).is_some())
+            .cloned()
+            .collect::<Vec<_>>();
+        match filter {
+            HistoryFilter::HighScore => games.sort_by_key(|game| Reverse(game.result_of(player_id).map(|player| player.total))),
+            _ => games.sort_by_key(|game| Reverse(game.finished_at)),
+        }
+        games.truncate(limit);
+        games
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yahtzee_rules::Category;
+
+    const ALICE: PlayerID = 1;
+    const BOB: PlayerID = 2;
+    const CAROL: PlayerID = 3;
+    const DAVE: PlayerID = 4;
+
+    struct TestHistory {
+        history: GameHistory,
+        data_dir: PathBuf,
+    }
+    impl TestHistory {
+        fn open() -> Self {
+            let data_dir = std::env::temp_dir().join(format!("yahtzee-history-{:016x}", rand::random::<u64>()));
+            std::fs::create_dir_all(&data_dir).unwrap();
+            let writer = Writer::spawn();
+            let profiles = ProfileStore::open(data_dir.join("profiles.json"), writer.clone()).unwrap();
+            let history = GameHistory::open(data_dir.join("history.jsonl"), profiles, writer).unwrap();
+            Self { history, data_dir }
+        }
+        //Add a game without going through `record`, so totals can be picked freely.
+        fn add(&self, finished_at: u64, totals: &[(PlayerID, u16)]) {
+            let players = totals.iter().map(|&(player_id, total)| PlayerResult {
+                player_id,
+                display_name: format!("Player {player_id}"),
+                scorecard: Scorecard::default(),
+                total,
+            }).collect();
+            let game = GameRecord { game_id: rand::random(), lobby_id: 1, started_at: finished_at, finished_at, players };
+            self.history.games.lock().unwrap().push(game);
+        }
+        fn ranking(&self, filter: HistoryFilter, limit: usize) -> Vec<PlayerID> {
+            self.history.leaderboard(filter, limit).iter().map(|entry| entry.player_id).collect()
+        }
+    }
+    impl Drop for TestHistory {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.data_dir);
+        }
+    }
+
+    fn with_games() -> TestHistory {
+        let test = TestHistory::open();
+        let now = unix_time();
+        test.add(now, &[(ALICE, 200), (BOB, 150)]);
+        test.add(now, &[(BOB, 250), (CAROL, 100)]);
+        test.add(now, &[(ALICE, 180), (CAROL, 170)]);
+        test.add(now, &[(DAVE, 300), (AI_PLAYER_ID, 100)]);
+        test.add(now, &[(DAVE, 120), (AI_PLAYER_ID, 400)]);
+        //Carol's best game is more than a week old.
+        test.add(now - WEEK_SECONDS - 60, &[(CAROL, 350), (ALICE, 90), (BOB, 80)]);
+        test
+    }
+
+    #[test]
+    fn leaderboard_ranks_by_wins_then_total_score() {
+        let test = with_games();
+        //Alice won twice. Carol, Bob and Dave won once and are ordered by their total score.
+        assert_eq!(test.ranking(HistoryFilter::AllTime, 10), vec![ALICE, CAROL, BOB, DAVE]);
+        let alice = &test.history.leaderboard(HistoryFilter::AllTime, 10)[0];
+        assert_eq!((alice.games_played, alice.games_won, alice.total_score, alice.high_score), (3, 2, 470, 200));
+    }
+
+    #[test]
+    fn weekly_leaderboard_skips_older_games() {
+        let test = with_games();
+        assert_eq!(test.ranking(HistoryFilter::Weekly, 10), vec![ALICE, DAVE, BOB, CAROL]);
+        let carol = test.history.leaderboard(HistoryFilter::Weekly, 10).into_iter().find(|entry| entry.player_id == CAROL).unwrap();
+        assert_eq!((carol.games_played, carol.games_won, carol.high_score), (2, 0, 170));
+    }
+
+    #[test]
+    fn high_score_leaderboard_ranks_by_best_game() {
+        let test = with_games();
+        assert_eq!(test.ranking(HistoryFilter::HighScore, 10), vec![CAROL, DAVE, BOB, ALICE]);
+    }
+
+    #[test]
+    fn leaderboard_is_limited_and_leaves_out_ai_players() {
+        let test = with_games();
+        assert_eq!(test.ranking(HistoryFilter::AllTime, 2), vec![ALICE, CAROL]);
+        assert!(test.history.leaderboard(HistoryFilter::AllTime, 10).iter().all(|entry| entry.player_id != AI_PLAYER_ID));
+        assert!(test.ranking(HistoryFilter::AllTime, 0).is_empty());
+    }
+
+    #[test]
+    fn recorded_games_update_profiles() {
+        let test = TestHistory::open();
+        let mut scorecard = Scorecard::default();
+        scorecard.scores.insert(Category::Chance, 20);
+        let players = [ALICE, AI_PLAYER_ID].into_iter().map(|player_id| PlayerResult {
+            player_id,
+            display_name: "Alice".to_string(),
+            scorecard: scorecard.clone(),
+            total: 999,
+        }).collect();
+        let game = GameRecord { game_id: 1, lobby_id: 1, started_at: 0, finished_at: unix_time(), players };
+        test.history.record(game).unwrap();
+
+        //The submitted total is replaced, the guest gets a profile and the AI does not.
+        let profile = test.history.profiles.get(ALICE).unwrap();
+        assert_eq!((profile.display_name.as_str(), profile.stats.games_played, profile.stats.high_score), ("Alice", 1, 20));
+        assert!(test.history.profiles.get(AI_PLAYER_ID).is_none());
+        assert_eq!(test.history.player_history(ALICE, HistoryFilter::AllTime, 10)[0].players[0].total, 20);
+
+        //The leaderboard shows the name players have now.
+        test.history.profiles.update(ALICE, |profile| profile.display_name = "Renamed".to_string());
+        assert_eq!(test.history.leaderboard(HistoryFilter::AllTime, 10)[0].display_name, "Renamed");
+    }
+}
@@ -0,0 +1,97 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::{fs, io::ErrorKind, path::Path, sync::Arc};
+
+use crate::error::{Error, Result};
+
+pub type PlayerID = u64;
+
+//Stands in for computer players in game records. Never handed out to a profile.
+pub const AI_PLAYER_ID: PlayerID = 0;
+
+const SECRET_LENGTH: usize = 32;
+
+//Issues and verifies guest tokens of the form "<player id as hex>.<HMAC-SHA256 of player id as hex>".
+//Tokens never expire; they only prove that this server handed out the player id.
+#[derive(Clone)]
+pub struct TokenSigner {
+    secret: Arc<[u8]>,
+}
+impl TokenSigner {
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            secret: secret.into(),
+        }
+    }
+    //Read the signing secret from disk, generating and persisting a new one on first run.
+    pub fn load_or_generate(path: &Path) -> Result<Self> {
+        match fs::read(path) {
+            Ok(secret) if secret.len() >= SECRET_LENGTH => Ok(Self::new(&secret)),
+            Ok(_) => {
+                println!("->> Token secret at {} is too short", path.display());
+                Err(Error::YahtzeeStorageError)
+            }
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                let secret = rand::random::<[u8; SECRET_LENGTH]>();
+                fs::write(path, secret).map_err(|error| {
+                    println!("->> Failed to write token secret: {error}");
+                    Error::YahtzeeStorageError
+                })?;
+                println!("->> Generated new token secret");
+                Ok(Self::new(&secret))
+            }
+            Err(error) => {
+                println!("->> Failed to read token secret: {error}");
+                Err(Error::YahtzeeStorageError)
+            }
+        }
+    }
+    pub fn issue(&self, player_id: PlayerID) -> String {
+        let signature = self.mac(player_id).finalize().into_bytes();
+        format!("{player_id:016x}.{}", hex::encode(signature))
+    }
+    pub fn verify(&self, token: &str) -> Option<PlayerID> {
+        let (player_id, signature) = token.split_once('.')?;
+        let player_id = PlayerID::from_str_radix(player_id, 16).ok()?;
+        let signature = hex::decode(signature).ok()?;
+        self.mac(player_id).verify_slice(&signature).ok()?;
+        Some(player_id)
+    }
+    fn mac(&self, player_id: PlayerID) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        mac.update(&player_id.to_be_bytes());
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn issued_tokens_verify() {
+        let signer = TokenSigner::new(&[7; SECRET_LENGTH]);
+        for player_id in [1, 42, PlayerID::MAX] {
+            assert_eq!(signer.verify(&signer.issue(player_id)), Some(player_id));
+        }
+    }
+
+    #[test]
+    fn tampered_tokens_are_rejected() {
+        let signer = TokenSigner::new(&[7; SECRET_LENGTH]);
+        let token = signer.issue(42);
+        let (_, signature) = token.split_once('.').unwrap();
+        //Another player id with the original signature.
+        assert_eq!(signer.verify(&format!("{:016x}.{signature}", 43)), None);
+        //A flipped signature digit.
+        let mut flipped = token.clone().into_bytes();
+        let last = flipped.len() - 1;
+        flipped[last] = if flipped[last] == b'0' { b'1' } else { b'0' };
+        assert_eq!(signer.verify(std::str::from_utf8(&flipped).unwrap()), None);
+        //Truncated, malformed or signed with another secret.
+        assert_eq!(signer.verify(&token[..token.len() - 2]), None);
+        assert_eq!(signer.verify("not a token"), None);
+        assert_eq!(signer.verify(&format!("{:016x}.", 42)), None);
+        assert_eq!(TokenSigner::new(&[8; SECRET_LENGTH]).verify(&token), None);
+    }
+}
@@ -1,32 +1,161 @@
 use dashmap::{DashMap, mapref::entry::Entry};
 use serde::{Deserialize, Serialize};
-use std::{sync::Arc, collections::BTreeMap};
-use futures::{sink::SinkExt, stream::{StreamExt, SplitSink}};
-use tokio::sync::mpsc::UnboundedSender;
+use std::{ops::ControlFlow, sync::Arc, collections::BTreeMap, time::{Duration, Instant}};
+use futures::{sink::SinkExt, stream::{BoxStream, StreamExt}};
+use tokio::sync::{mpsc::{UnboundedReceiver, UnboundedSender}, oneshot};
 use axum::extract::ws::{Message, WebSocket};
 use bytes::Bytes;
+use yahtzee_rules::{Action, GameOptions, GameState};
 
-pub type LobbyID = u64;
-type UserID = u32;
+use super::{
+    ai::{AiDifficulty, AiPlayer},
+    identity::{AI_PLAYER_ID, PlayerID},
+    history::{unix_time, GameHistory, GameRecord, PlayerResult},
+    solvers::Solvers,
+    registry::{CLAIM_REFRESH, CLAIM_TTL, InstanceID, LobbyRegistry},
+};
 
-#[derive(Serialize, Deserialize, Clone)]
-enum SocketMessage {
-    ConnectSuccess {
+pub use signaling_protocol::lobby::{HintChoice, LobbyID, LobbyPhase, LobbySettings, LobbyUser, SdpType, SocketMessage, UserID};
+
+type ConnectionID = u64;
+
+pub const MAX_PLAYERS: u8 = 8;
+const STARTING_COUNTDOWN: Duration = Duration::from_secs(3);
+const MAX_CHAT_LENGTH: usize = 200;
+//Each user may send this many chat messages per window. Anything more is dropped.
+const CHAT_BURST: usize = 5;
+const CHAT_WINDOW: Duration = Duration::from_secs(10);
+const MAX_HINT_CHOICES: usize = 5;
+//Highest upper subtotal any variant can reach, six sixes in every upper box of Maxi Yatzy.
+const MAX_UPPER_BONUS_THRESHOLD: u16 = 126;
+//How long users of a restored lobby have to reconnect before their seats are given up.
+const RESUME_WINDOW: Duration = Duration::from_secs(120);
+const INTERRUPTED_REASON: &str = "The server restarted and this match could not be resumed";
+
+fn encode(socket_message: &SocketMessage) -> Option<Message> {
+    bincode::serialize(socket_message).ok().map(|socket_message_serialized| Message::Binary(socket_message_serialized.into()))
+}
+pub(super) async fn send(socket_message: &SocketMessage, socket_sender: &mut (impl SinkExt<Message> + Unpin)) {
+    if let Some(message) = encode(socket_message) {
+        let _ = socket_sender.send(message).await;
+    }
+}
+
+//A client connection as seen by a lobby. The websocket itself may be held by another server instance.
+pub struct Connection {
+    outgoing: UnboundedSender<Message>,
+    incoming: BoxStream<'static, Message>,
+}
+impl Connection {
+    fn local(websocket: WebSocket) -> Self {
+        let (mut socket_sender, socket_receiver) = websocket.split();
+
+        //Spawn a task that writes queued messages to the websocket. Closes it after a close message or once the queue is dropped.
+        let (outgoing, mut outgoing_receiver) = tokio::sync::mpsc::unbounded_channel::<Message>();
+        tokio::spawn(async move {
+            while let Some(message) = outgoing_receiver.recv().await {
+                let close = matches!(message, Message::Close(_));
+                if socket_sender.send(message).await.is_err() || close {
+                    break
+                }
+            }
+            let _ = socket_sender.close().await;
+        }); //End of websocket writer task.
+
+        //Incoming messages end with the first error.
+        let incoming = futures::stream::unfold(socket_receiver, |mut socket_receiver| async move {
+            match socket_receiver.next().await {
+                Some(Ok(message)) => Some((message, socket_receiver)),
+                _ => None,
+            }
+        }).boxed();
+        Self { outgoing, incoming }
+    }
+    fn from_channels(outgoing: UnboundedSender<Message>, incoming_receiver: UnboundedReceiver<Message>) -> Self {
+        let incoming = futures::stream::unfold(incoming_receiver, |mut incoming_receiver| async move {
+            incoming_receiver.recv().await.map(|message| (message, incoming_receiver))
+        }).boxed();
+        Self { outgoing, incoming }
+    }
+}
+
+//Messages exchanged between server instances through the lobby registry.
+//The instance holding a websocket relays it to the instance that owns the lobby.
+#[derive(Serialize, Deserialize)]
+enum Envelope {
+    //To the lobby owner:
+    Connect {
         lobby_id: LobbyID,
-        user_id: UserID,
-        peers_id: Vec<UserID>,
+        connection_id: ConnectionID,
+        reply_to: InstanceID,
+        player_id: PlayerID,
+        display_name: String,
+        token: String,
+        spectator: bool,
+    },
+    FromClient {
+        connection_id: ConnectionID,
+        message: Vec<u8>,
+    },
+    ClientClosed {
+        connection_id: ConnectionID,
+    },
+    //To the instance holding the websocket:
+    ToClient {
+        connection_id: ConnectionID,
+        message: Vec<u8>,
+    },
+    CloseClient {
+        connection_id: ConnectionID,
     },
-    WebRtcHandshake {
-        source_id: UserID,
-        target_id: UserID,
-        sdp_description: String,
-        ice_candidates: Vec<(String, Option<String>, Option<u16>)>,
-    }
+}
+
+//Set on lobbies the server creates for a fixed group of players, such as tournament matches.
+//Only the listed players may take a seat, and finished games are reported back.
+#[derive(Clone)]
+pub struct LobbyOrganizer {
+    pub players: Vec<PlayerID>,
+    pub results: UnboundedSender<OrganizerReport>,
+    //How long the listed players have to get ready for each game, counted from when the lobby opens or the last game
+    //ends. Whoever is not ready by then is reported absent.
+    pub no_show_window: Duration,
+}
+
+pub enum OrganizerReport {
+    Finished(GameRecord),
+    NoShow {
+        lobby_id: LobbyID,
+        absent: Vec<PlayerID>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UserInfo {
+    pub user_id: UserID,
+    pub player_id: PlayerID,
+    pub display_name: String,
+    pub connected_at: u64,
+    pub spectator: bool,
+    #[serde(default)]
+    pub ai: Option<AiDifficulty>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct LobbyInfo {
+    pub lobby_id: LobbyID,
+    pub created_at: u64,
+    pub phase: LobbyPhase,
+    pub host_id: Option<UserID>,
+    pub users: Vec<UserInfo>,
 }
 
 enum LobbyMessage {
     Connect{
-        websocket: WebSocket,
+        connection: Connection,
+        player_id: PlayerID,
+        display_name: String,
+        token: String,
+        spectator: bool,
     },
     Disconnect{
         user_id: UserID,
@@ -34,6 +163,610 @@ enum LobbyMessage {
     Message{
         target: UserID,
         socket_message_serialized: Bytes,
+    },
+    Request{
+        user_id: UserID,
+        socket_message: SocketMessage,
+    },
+    CountdownElapsed{
+        countdown: u32,
+    },
+    NoShowExpired{
+        no_show: u32,
+    },
+    AiTurn{
+        turn: u32,
+        action: Action,
+    },
+    Inspect{
+        reply: oneshot::Sender<LobbyInfo>,
+    },
+    Kick{
+        user_id: UserID,
+        reason: String,
+        reply: oneshot::Sender<bool>,
+    },
+    Close{
+        reason: String,
+    },
+    Notice{
+        message: String,
+    },
+    Release,
+    Snapshot{
+        reply: oneshot::Sender<LobbySnapshot>,
+    },
+    ResumeExpired,
+}
+
+struct User {
+    socket_sender: UnboundedSender<Message>,
+    info: UserInfo,
+    ready: bool,
+    recent_chats: Vec<Instant>,
+}
+impl User {
+    fn new(socket_sender: UnboundedSender<Message>, info: UserInfo) -> Self {
+        Self { socket_sender, info, ready: false, recent_chats: Vec::new() }
+    }
+    //Computer players have no connection. Messages to them go nowhere.
+    fn ai(info: UserInfo) -> Self {
+        let (socket_sender, _) = tokio::sync::mpsc::unbounded_channel();
+        Self { ready: true, ..Self::new(socket_sender, info) }
+    }
+    //Count a chat message against the user's allowance. False if they already used it up.
+    fn may_chat(&mut self, now: Instant) -> bool {
+        self.recent_chats.retain(|sent_at| now.duration_since(*sent_at) < CHAT_WINDOW);
+        if self.recent_chats.len() >= CHAT_BURST {
+            return false
+        }
+        self.recent_chats.push(now);
+        true
+    }
+    async fn send(&mut self, socket_message: &SocketMessage) {
+        if let Some(message) = encode(socket_message) {
+            let _ = self.socket_sender.send(message);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Game {
+    state: GameState,
+    started_at: u64,
+}
+
+//Everything needed to bring a lobby back after a restart. Connections are not kept, so every user
+//becomes pending until they reconnect with their guest token. Organizers are not kept either, so organized lobbies
+//only come back to tell their players the match is off.
+#[derive(Serialize, Deserialize)]
+pub struct LobbySnapshot {
+    #[serde(default)]
+    organized: bool,
+    lobby_id: LobbyID,
+    created_at: u64,
+    user_id_counter: UserID,
+    host_id: Option<UserID>,
+    phase: LobbyPhase,
+    settings: LobbySettings,
+    users: Vec<UserInfo>,
+    game: Option<Game>,
+}
+
+//State owned by a single lobby's task. Only ever touched from that task.
+struct LobbyTask {
+    lobby_id: LobbyID,
+    created_at: u64,
+    lobby_sender: UnboundedSender<LobbyMessage>,
+    history: GameHistory,
+    solvers: Solvers,
+    user_id_counter: UserID,
+    users: BTreeMap<UserID, User>,
+    host_id: Option<UserID>,
+    phase: LobbyPhase,
+    settings: LobbySettings,
+    countdown: u32,
+    no_show: u32,
+    ai_turn: u32,
+    game: Option<Game>,
+    organizer: Option<LobbyOrganizer>,
+    pending: BTreeMap<UserID, UserInfo>, //Users restored from a snapshot who have not reconnected yet.
+    interrupted: bool, //Restored without the organizer it was created for. Only turns users away.
+}
+impl LobbyTask {
+    async fn handle(&mut self, lobby_message: LobbyMessage) -> ControlFlow<()> {
+        match lobby_message {
+            //On client joining this lobby:
+            LobbyMessage::Connect { connection, player_id, display_name, token, spectator } => {
+                if self.interrupted {
+                    return self.turn_away(connection, player_id)
+                }
+                self.connect(connection, player_id, display_name, token, spectator).await
+            },
+            //On client disconnect from this lobby:
+            LobbyMessage::Disconnect { user_id } => {
+                if self.users.remove(&user_id).is_some() {
+                    self.user_left(user_id).await;
+                }
+                if self.is_abandoned() {
+                    return ControlFlow::Break(()) //Break out of lobby message loop when no users are connected to this lobby.
+                }
+            },
+            //Relay websocket message to target user:
+            LobbyMessage::Message { target, socket_message_serialized } => {
+                if let Some(user) = self.users.get_mut(&target) {
+                    let _ = user.socket_sender.send(Message::Binary(socket_message_serialized));
+                }
+            },
+            //Handle a request sent by a client:
+            LobbyMessage::Request { user_id, socket_message } => self.request(user_id, socket_message).await,
+            //Start the game once the countdown runs out, unless it was cancelled in the meantime:
+            LobbyMessage::CountdownElapsed { countdown } => {
+                if self.phase == LobbyPhase::Starting && countdown == self.countdown {
+                    self.start_game().await;
+                }
+            },
+            //Report the organizer's players who did not get ready in time, unless a game got going in the meantime:
+            LobbyMessage::NoShowExpired { no_show } => {
+                if no_show == self.no_show && self.phase != LobbyPhase::InGame {
+                    self.report_no_show();
+                }
+            },
+            //Play a computer player's move, unless the game moved on while it was thinking:
+            LobbyMessage::AiTurn { turn, action } => {
+                let current_player = self.game.as_ref().and_then(|game| game.state.current_player());
+                if let Some(user_id) = current_player.filter(|_| turn == self.ai_turn) {
+                    self.request(user_id, SocketMessage::GameAction { action }).await;
+                }
+            },
+            //Report lobby state to an administrator:
+            LobbyMessage::Inspect { reply } => {
+                let users = self.users.values().map(|user| user.info.clone()).collect();
+                let _ = reply.send(LobbyInfo { lobby_id: self.lobby_id, created_at: self.created_at, phase: self.phase, host_id: self.host_id, users });
+            },
+            //Notify and disconnect a single user:
+            LobbyMessage::Kick { user_id, reason, reply } => {
+                let _ = reply.send(self.kick(user_id, reason).await);
+                if self.is_abandoned() {
+                    return ControlFlow::Break(()) //Break out of lobby message loop when no users are connected to this lobby.
+                }
+            },
+            //Notify and disconnect every user:
+            LobbyMessage::Close { reason } => {
+                println!("->> Lobby {} closed: {reason}", self.lobby_id);
+                let socket_message = SocketMessage::LobbyClosed { reason };
+                for user in self.users.values_mut() {
+                    user.send(&socket_message).await;
+                    let _ = user.socket_sender.send(Message::Close(None));
+                }
+                return ControlFlow::Break(()) //Break out of lobby message loop once everyone is notified.
+            },
+            //Relay a server notice to every user:
+            LobbyMessage::Notice { message } => self.broadcast(&SocketMessage::ServerNotice { message }).await,
+            //The organizer no longer needs this lobby, so it may close once everyone has left:
+            LobbyMessage::Release => {
+                self.organizer = None;
+                if self.is_abandoned() {
+                    return ControlFlow::Break(())
+                }
+            },
+            //Capture lobby state before the server shuts down:
+            LobbyMessage::Snapshot { reply } => {
+                let users = self.users.values().map(|user| user.info.clone()).chain(self.pending.values().cloned()).collect();
+                let phase = if self.phase == LobbyPhase::Starting { LobbyPhase::Waiting } else { self.phase };
+                let _ = reply.send(LobbySnapshot {
+                    organized: self.organizer.is_some() || self.interrupted,
+                    lobby_id: self.lobby_id,
+                    created_at: self.created_at,
+                    user_id_counter: self.user_id_counter,
+                    host_id: self.host_id,
+                    phase,
+                    settings: self.settings,
+                    users,
+                    game: self.game.as_ref().map(|game| Game { state: game.state.clone(), started_at: game.started_at }),
+                });
+            },
+            //Give up the seats of restored users who did not come back in time:
+            LobbyMessage::ResumeExpired => {
+                for user_id in std::mem::take(&mut self.pending).into_keys() {
+                    self.user_left(user_id).await;
+                }
+                if self.is_abandoned() {
+                    return ControlFlow::Break(())
+                }
+            },
+        }
+        ControlFlow::Continue(())
+    }
+
+    //Tell a player of an interrupted lobby why it is gone. The lobby ends once all of them were told or the resume
+    //window is over.
+    fn turn_away(&mut self, connection: Connection, player_id: PlayerID) -> ControlFlow<()> {
+        if let Some(message) = encode(&SocketMessage::LobbyClosed { reason: INTERRUPTED_REASON.to_string() }) {
+            let _ = connection.outgoing.send(message);
+        }
+        let _ = connection.outgoing.send(Message::Close(None));
+        self.pending.retain(|_, info| info.player_id != player_id);
+        if self.is_abandoned() { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+    }
+
+    async fn connect(&mut self, connection: Connection, player_id: PlayerID, display_name: String, token: String, spectator: bool) {
+        let lobby_id = self.lobby_id;
+        let Connection { outgoing: socket_sender, incoming: mut socket_receiver } = connection;
+        let spectator = spectator || !self.may_play(player_id);
+
+        //A user restored from a snapshot takes back their old user ID, and with it their seat and scorecard.
+        let resumed = self.pending.iter().find(|(_, info)| info.player_id == player_id).map(|(&user_id, _)| user_id);
+        let resumed = resumed.and_then(|user_id| self.pending.remove(&user_id));
+
+        //Turn the client away if the lobby is already full. Spectators do not take up seats.
+        if resumed.is_none() && !spectator && !self.has_free_seat() {
+            if let Some(message) = encode(&SocketMessage::JoinRejected { reason: "Lobby is full".to_string() }) {
+                let _ = socket_sender.send(message);
+            }
+            let _ = socket_sender.send(Message::Close(None));
+            return
+        }
+
+        //Generate user ID.
+        let info = match resumed {
+            Some(info) => {
+                println!("->> Player {player_id} resumed lobby {lobby_id}");
+                UserInfo { display_name, connected_at: unix_time(), ..info }
+            }
+            None => {
+                let user_id = self.user_id_counter;
+                self.user_id_counter += 1;
+                UserInfo { user_id, player_id, display_name, connected_at: unix_time(), spectator, ai: None }
+            }
+        };
+        let user_id = info.user_id;
+        let spectator = info.spectator;
+
+        //Send message to client notifying connection to this lobby.
+        let peers_id = self.users.keys().cloned().collect::<Vec<_>>();
+        let mut user = User::new(socket_sender, info);
+        user.send(&SocketMessage::ConnectSuccess { lobby_id, user_id, player_id, token, peers_id }).await;
+
+        //Add client to users list. The first user to join hosts the lobby.
+        self.users.insert(user_id, user);
+        self.host_id.get_or_insert(user_id);
+        if self.phase == LobbyPhase::Starting && !spectator {
+            self.cancel_countdown();
+        }
+        self.broadcast_lobby_state().await;
+        if let Some(game) = self.game.as_ref() {
+            let socket_message = SocketMessage::GameState { state: game.state.clone() };
+            self.send_to(user_id, &socket_message).await;
+            self.schedule_ai_turn(); //A restored game may be waiting on a computer player.
+        }
+
+        //Spawn a task that receives websocket messages from the client and relay them to the lobby task.
+        let lobby_sender = self.lobby_sender.clone();
+        tokio::spawn(async move {
+            println!("->> User {user_id} (player {player_id}) joined lobby {lobby_id}");
+            //Read incoming messages from the client. Breaks if the connection closes.
+            while let Some(Message::Binary(socket_message_serialized)) = socket_receiver.next().await {
+                let socket_message = match bincode::deserialize::<SocketMessage>(&socket_message_serialized) {
+                    Ok(socket_message) => socket_message,
+                    Err(_) => break, //Break out of websocket message loop on deserialization failure.
+                };
+                let lobby_message = match socket_message {
+                    SocketMessage::WebRtcHandshake { target_id: target, .. } => LobbyMessage::Message { target, socket_message_serialized },
+                    socket_message => LobbyMessage::Request { user_id, socket_message },
+                };
+                let _ = lobby_sender.send(lobby_message);
+            }
+            //Remove this user from lobby.
+            println!("->> User {user_id} left lobby {lobby_id}");
+            let _ = lobby_sender.send(LobbyMessage::Disconnect { user_id });
+        }); //End of websocket task.
+    }
+
+    //Clean up after a user who is no longer in the users list.
+    async fn user_left(&mut self, user_id: UserID) {
+        if self.host_id == Some(user_id) {
+            self.host_id = self.users.values().find(|user| user.info.ai.is_none()).map(|user| user.info.user_id);
+        }
+        if self.phase == LobbyPhase::Starting {
+            self.cancel_countdown();
+        }
+        if let Some(game) = self.game.as_mut() {
+            game.state.remove_player(user_id);
+            if game.state.players.is_empty() {
+                self.game = None;
+                self.phase = LobbyPhase::Waiting;
+                self.start_no_show_deadline();
+            }
+            else {
+                self.broadcast_game_state().await;
+            }
+        }
+        self.broadcast_lobby_state().await;
+    }
+
+    async fn kick(&mut self, user_id: UserID, reason: String) -> bool {
+        match self.users.remove(&user_id) {
+            Some(mut user) => {
+                println!("->> User {user_id} kicked from lobby {}: {reason}", self.lobby_id);
+                user.send(&SocketMessage::Kicked { reason }).await;
+                let _ = user.socket_sender.send(Message::Close(None));
+                self.user_left(user_id).await;
+                true
+            }
+            None => false,
+        }
+    }
+
+    async fn request(&mut self, user_id: UserID, socket_message: SocketMessage) {
+        let is_host = self.host_id == Some(user_id);
+        let can_configure = matches!(self.phase, LobbyPhase::Waiting | LobbyPhase::Finished);
+        match socket_message {
+            SocketMessage::SetReady { ready } => {
+                if let Some(user) = self.users.get_mut(&user_id).filter(|user| !user.info.spectator) {
+                    user.ready = ready;
+                }
+                if self.phase == LobbyPhase::Starting && !ready {
+                    self.cancel_countdown();
+                }
+                self.broadcast_lobby_state().await;
+            }
+            SocketMessage::KickUser { user_id: target } if is_host && target != user_id => {
+                self.kick(target, "Removed by the host".to_string()).await;
+            }
+            SocketMessage::TransferHost { user_id: target } if is_host && self.users.get(&target).is_some_and(|user| user.info.ai.is_none()) => {
+                self.host_id = Some(target);
+                self.broadcast_lobby_state().await;
+            }
+            SocketMessage::SetSettings { settings } if is_host && can_configure => {
+                self.settings = LobbySettings {
+                    max_players: settings.max_players.clamp(1, MAX_PLAYERS),
+                    game: GameOptions {
+                        upper_bonus_threshold: settings.game.upper_bonus_threshold.map(|threshold| threshold.min(MAX_UPPER_BONUS_THRESHOLD)),
+                        ..settings.game
+                    },
+                };
+                self.broadcast_lobby_state().await;
+            }
+            SocketMessage::StartGame if is_host && can_configure && self.players_ready() => {
+                self.phase = LobbyPhase::Starting;
+                self.countdown += 1;
+                let countdown = self.countdown;
+                let lobby_sender = self.lobby_sender.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(STARTING_COUNTDOWN).await;
+                    let _ = lobby_sender.send(LobbyMessage::CountdownElapsed { countdown });
+                });
+                self.broadcast_lobby_state().await;
+            }
+            SocketMessage::GameAction { action } if self.phase == LobbyPhase::InGame => {
+                let Some(game) = self.game.as_mut() else {
+                    return
+                };
+                if let Err(error) = game.state.apply(user_id, action, || rand::random_range(1..=6)) {
+                    self.send_to(user_id, &SocketMessage::GameActionRejected { error }).await;
+                    return
+                }
+                self.broadcast_game_state().await;
+                if self.game.as_ref().is_some_and(|game| game.state.is_finished()) {
+                    self.finish_game().await;
+                }
+            }
+            //Players may step back to spectate and spectators may take a free seat between games. The host may move anyone.
+            SocketMessage::SetSpectator { user_id: target, spectator } if (is_host || target == user_id) && can_configure => {
+                let may_play = self.users.get(&target).is_some_and(|user| self.may_play(user.info.player_id));
+                let is_ai = self.users.get(&target).is_some_and(|user| user.info.ai.is_some());
+                if is_ai || (!spectator && (!self.has_free_seat() || !may_play)) {
+                    return
+                }
+                if let Some(user) = self.users.get_mut(&target) {
+                    user.info.spectator = spectator;
+                    user.ready = false;
+                }
+                self.broadcast_lobby_state().await;
+            }
+            //Computer players only fill open lobbies, never organized ones.
+            SocketMessage::AddAi { difficulty } if is_host && can_configure && self.organizer.is_none() && self.has_free_seat() => {
+                let user_id = self.user_id_counter;
+                self.user_id_counter += 1;
+                let number = 1 + self.users.values().filter(|user| user.info.ai.is_some()).count();
+                let display_name = format!("{} Bot {number}", difficulty.name());
+                println!("->> Lobby {} seated {display_name} as user {user_id}", self.lobby_id);
+                let info = UserInfo { user_id, player_id: AI_PLAYER_ID, display_name, connected_at: unix_time(), spectator: false, ai: Some(difficulty) };
+                self.users.insert(user_id, User::ai(info));
+                self.broadcast_lobby_state().await;
+            }
+            //Hints are off in organized games so tournament results stay fair.
+            SocketMessage::RequestHint if self.phase == LobbyPhase::InGame && self.organizer.is_none() => {
+                let Some(state) = self.game.as_ref().map(|game| &game.state) else {
+                    return
+                };
+                let socket_message = match self.solvers.get(state.options) {
+                    Some(solver) => {
+                        let choices = solver.evaluate(state, user_id).into_iter().take(MAX_HINT_CHOICES);
+                        SocketMessage::Hint { choices: choices.map(|(action, expected_total)| HintChoice { action, expected_total }).collect() }
+                    }
+                    None => SocketMessage::ServerNotice { message: "Hints are not available yet. Try again in a minute.".to_string() },
+                };
+                self.send_to(user_id, &socket_message).await;
+            }
+            SocketMessage::SendChat { text } => {
+                let text = text.trim().chars().take(MAX_CHAT_LENGTH).collect::<String>();
+                let may_chat = self.users.get_mut(&user_id).is_some_and(|user| user.may_chat(Instant::now()));
+                if !text.is_empty() && may_chat {
+                    self.broadcast(&SocketMessage::Chat { user_id, text }).await;
+                }
+            }
+            _ => {} //Ignore requests the user is not allowed to make in the current phase.
+        }
+    }
+
+    //Organized lobbies stay open while empty so their players can come back, until the organizer releases them once their
+    //match is decided or forfeited. Restored lobbies stay open until the resume window ends.
+    //Computer players do not keep a lobby open on their own.
+    fn is_abandoned(&self) -> bool {
+        self.users.values().all(|user| user.info.ai.is_some()) && self.organizer.is_none() && self.pending.is_empty()
+    }
+
+    fn may_play(&self, player_id: PlayerID) -> bool {
+        self.organizer.as_ref().is_none_or(|organizer| organizer.players.contains(&player_id))
+    }
+
+    fn has_free_seat(&self) -> bool {
+        let players = self.users.values().map(|user| &user.info).chain(self.pending.values()).filter(|info| !info.spectator).count();
+        players < self.settings.max_players as usize
+    }
+
+    //Spectators never need to ready up, but a game needs at least one player. Organized games need every listed player.
+    fn players_ready(&self) -> bool {
+        let players = self.users.values().filter(|user| !user.info.spectator).collect::<Vec<_>>();
+        let seated = self.organizer.as_ref().map_or(1, |organizer| organizer.players.len());
+        players.len() >= seated && players.iter().all(|user| user.ready)
+    }
+
+    fn cancel_countdown(&mut self) {
+        self.countdown += 1;
+        self.phase = LobbyPhase::Waiting;
+    }
+
+    //Give the organizer's players a while to show up for the next game. Any earlier deadline is dropped.
+    fn start_no_show_deadline(&mut self) {
+        self.no_show += 1;
+        let Some(no_show_window) = self.organizer.as_ref().map(|organizer| organizer.no_show_window) else {
+            return
+        };
+        let no_show = self.no_show;
+        let lobby_sender = self.lobby_sender.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(no_show_window).await;
+            let _ = lobby_sender.send(LobbyMessage::NoShowExpired { no_show });
+        });
+    }
+
+    //Players who connected but never got ready hold up the game as much as those who never came.
+    fn report_no_show(&mut self) {
+        let Some(organizer) = self.organizer.as_ref() else {
+            return
+        };
+        let absent = organizer.players.iter()
+            .filter(|&&player_id| !self.users.values().any(|user| user.info.player_id == player_id && !user.info.spectator && user.ready))
+            .copied()
+            .collect::<Vec<_>>();
+        if !absent.is_empty() {
+            println!("->> Lobby {} reported {} absent players", self.lobby_id, absent.len());
+            let _ = organizer.results.send(OrganizerReport::NoShow { lobby_id: self.lobby_id, absent });
+        }
+    }
+
+    async fn start_game(&mut self) {
+        if !self.players_ready() {
+            self.cancel_countdown();
+            self.broadcast_lobby_state().await;
+            return
+        }
+        let players = self.users.values().filter(|user| !user.info.spectator).map(|user| user.info.user_id).collect::<Vec<_>>();
+        println!("->> Lobby {} started a game with {} players", self.lobby_id, players.len());
+        let state = GameState::new(players, self.settings.game);
+        self.game = Some(Game { state, started_at: unix_time() });
+        self.phase = LobbyPhase::InGame;
+        self.no_show += 1;
+        for user in self.users.values_mut() {
+            user.ready = user.info.ai.is_some();
+        }
+        self.broadcast_lobby_state().await;
+        self.broadcast_game_state().await;
+    }
+
+    async fn finish_game(&mut self) {
+        self.phase = LobbyPhase::Finished;
+        if let Some(game) = self.game.as_ref() {
+            let players = game.state.players.iter().filter_map(|player| {
+                let user = self.users.get(&player.id)?;
+                Some(PlayerResult {
+                    player_id: user.info.player_id,
+                    display_name: user.info.display_name.clone(),
+                    scorecard: player.scorecard.clone(),
+                    total: player.scorecard.total(),
+                })
+            }).collect();
+            let record = GameRecord {
+                game_id: rand::random(),
+                lobby_id: self.lobby_id,
+                started_at: game.started_at,
+                finished_at: unix_time(),
+                players,
+            };
+            if let Some(organizer) = self.organizer.as_ref() {
+                let _ = organizer.results.send(OrganizerReport::Finished(record.clone()));
+            }
+            if self.history.record(record).is_err() {
+                println!("->> Failed to record game in lobby {}", self.lobby_id);
+            }
+        }
+        self.start_no_show_deadline();
+        self.broadcast_lobby_state().await;
+    }
+
+    async fn send_to(&mut self, user_id: UserID, socket_message: &SocketMessage) {
+        if let Some(user) = self.users.get_mut(&user_id) {
+            user.send(socket_message).await;
+        }
+    }
+
+    async fn broadcast(&mut self, socket_message: &SocketMessage) {
+        for user in self.users.values_mut() {
+            user.send(socket_message).await;
+        }
+    }
+
+    async fn broadcast_lobby_state(&mut self) {
+        let Some(host_id) = self.host_id else {
+            return
+        };
+        let users = self.users.values().map(|user| LobbyUser {
+            user_id: user.info.user_id,
+            display_name: user.info.display_name.clone(),
+            ready: user.ready,
+            spectator: user.info.spectator,
+            ai: user.info.ai.is_some(),
+        }).collect();
+        self.broadcast(&SocketMessage::LobbyState { host_id, phase: self.phase, settings: self.settings, users }).await;
+    }
+
+    async fn broadcast_game_state(&mut self) {
+        if let Some(game) = self.game.as_ref() {
+            let socket_message = SocketMessage::GameState { state: game.state.clone() };
+            self.broadcast(&socket_message).await;
+        }
+        self.schedule_ai_turn();
+    }
+
+    //If a computer player is up, decide its move off the lobby task and play it after a human-like pause.
+    //Any earlier scheduled move is dropped since the game state it was decided on has changed.
+    fn schedule_ai_turn(&mut self) {
+        self.ai_turn += 1;
+        let Some(state) = self.game.as_ref().map(|game| game.state.clone()) else {
+            return
+        };
+        let Some((user_id, difficulty)) = state.current_player().and_then(|user_id| Some((user_id, self.users.get(&user_id)?.info.ai?))) else {
+            return
+        };
+        let (turn, solvers, lobby_sender) = (self.ai_turn, self.solvers.clone(), self.lobby_sender.clone());
+        tokio::spawn(async move {
+            //Searching the strategy can take a while, so it runs on the blocking pool instead of stalling other lobbies.
+            let choice = tokio::task::spawn_blocking(move || {
+                let action = difficulty.choose(&state, user_id, &solvers);
+                match state.clone().apply(user_id, action, || 1) {
+                    Ok(_) => action,
+                    Err(_) => AiDifficulty::Normal.choose(&state, user_id, &solvers),
+                }
+            }).await;
+            let Ok(action) = choice else {
+                return
+            };
+            tokio::time::sleep(difficulty.think_time(&action)).await;
+            let _ = lobby_sender.send(LobbyMessage::AiTurn { turn, action });
+        }); //End of AI turn task.
     }
 }
 
@@ -41,21 +774,56 @@ struct Lobby {
     channel: UnboundedSender<LobbyMessage>,
 }
 
+//Lobbies owned by this server instance. Websockets for lobbies owned by other instances are relayed through the registry.
 #[derive(Clone)]
 pub struct LobbyCollection {
     lobbies: Arc<DashMap<LobbyID, Lobby>>,
+    history: GameHistory,
+    solvers: Solvers,
+    instance_id: InstanceID,
+    registry: Arc<dyn LobbyRegistry>,
+    forwarded: Arc<DashMap<ConnectionID, UnboundedSender<Message>>>, //Websockets held here for lobbies owned elsewhere.
+    relayed: Arc<DashMap<ConnectionID, UnboundedSender<Message>>>, //Websockets held elsewhere for lobbies owned here.
 }
-impl Default for LobbyCollection {
-    fn default() -> Self {
-        Self {
+impl LobbyCollection {
+    //Give every instance its own `instance_id`. An instance that keeps its id across restarts can take its restored
+    //lobbies back right away, otherwise they wait for the claims of the previous run to lapse.
+    pub fn new(history: GameHistory, solvers: Solvers, registry: Arc<dyn LobbyRegistry>, instance_id: InstanceID) -> Self {
+        let lobby_collection = Self {
             lobbies: Arc::new(DashMap::new()),
-        }
+            history,
+            solvers,
+            instance_id,
+            registry,
+            forwarded: Arc::new(DashMap::new()),
+            relayed: Arc::new(DashMap::new()),
+        };
+
+        //Spawn a task that handles envelopes sent to this instance by other instances.
+        let lobby_collection_clone = lobby_collection.clone();
+        tokio::spawn(async move {
+            let mut envelopes = lobby_collection_clone.registry.subscribe(lobby_collection_clone.instance_id).await;
+            while let Some(envelope_serialized) = envelopes.next().await {
+                if let Ok(envelope) = bincode::deserialize::<Envelope>(&envelope_serialized) {
+                    lobby_collection_clone.receive(envelope).await;
+                }
+            }
+            println!("->> Instance {} stopped receiving envelopes", lobby_collection_clone.instance_id);
+        }); //End of envelope task.
+
+        println!("->> Instance {} started", lobby_collection.instance_id);
+
+        lobby_collection
     }
-}
-impl LobbyCollection {
-    pub fn create(&self) -> LobbyID {
+    pub fn create(&self, settings: LobbySettings) -> LobbyID {
+        self.spawn(settings, None)
+    }
+    pub fn create_organized(&self, settings: LobbySettings, organizer: LobbyOrganizer) -> LobbyID {
+        self.spawn(settings, Some(organizer))
+    }
+    fn spawn(&self, settings: LobbySettings, organizer: Option<LobbyOrganizer>) -> LobbyID {
         //Create lobby message channel.
-        let (lobby_sender, mut lobby_receiver) = tokio::sync::mpsc::unbounded_channel::<LobbyMessage>();
+        let (lobby_sender, lobby_receiver) = tokio::sync::mpsc::unbounded_channel::<LobbyMessage>();
 
         //Loop until randomly generated lobby ID does not collide with existing lobbies (unlikely to loop more than once).
         let lobby_id = loop {
@@ -66,82 +834,367 @@ impl LobbyCollection {
             }
         };
 
+        let mut lobby_task = LobbyTask {
+            lobby_id,
+            created_at: unix_time(),
+            lobby_sender,
+            history: self.history.clone(),
+            solvers: self.solvers.clone(),
+            user_id_counter: 0,
+            users: BTreeMap::new(),
+            host_id: None,
+            phase: LobbyPhase::Waiting,
+            settings,
+            countdown: 0,
+            no_show: 0,
+            ai_turn: 0,
+            game: None,
+            organizer,
+            pending: BTreeMap::new(),
+            interrupted: false,
+        };
+        lobby_task.start_no_show_deadline();
+        self.run(lobby_task, lobby_receiver);
+
+        println!("->> Lobby {lobby_id} created");
+
+        lobby_id
+    }
+    //Bring back lobbies saved before the last shutdown. Their users have a while to reconnect.
+    pub fn restore(&self, snapshots: Vec<LobbySnapshot>) {
+        for snapshot in snapshots {
+            let lobby_id = snapshot.lobby_id;
+            let (lobby_sender, lobby_receiver) = tokio::sync::mpsc::unbounded_channel::<LobbyMessage>();
+            match self.lobbies.entry(lobby_id) {
+                Entry::Vacant(v) => v.insert(Lobby { channel: lobby_sender.clone() }),
+                Entry::Occupied(_) => continue,
+            };
+            //Computer players are back right away. Everyone else has to reconnect.
+            let (ai_users, users) = snapshot.users.into_iter().partition::<Vec<_>, _>(|info| info.ai.is_some());
+            let lobby_task = LobbyTask {
+                lobby_id,
+                created_at: snapshot.created_at,
+                lobby_sender: lobby_sender.clone(),
+                history: self.history.clone(),
+                solvers: self.solvers.clone(),
+                user_id_counter: snapshot.user_id_counter,
+                users: ai_users.into_iter().map(|info| (info.user_id, User::ai(info))).collect(),
+                host_id: snapshot.host_id,
+                phase: snapshot.phase,
+                settings: snapshot.settings,
+                countdown: 0,
+                no_show: 0,
+                ai_turn: 0,
+                game: snapshot.game,
+                organizer: None,
+                pending: users.into_iter().map(|info| (info.user_id, info)).collect(),
+                interrupted: snapshot.organized,
+            };
+            self.run(lobby_task, lobby_receiver);
+            tokio::spawn(async move {
+                tokio::time::sleep(RESUME_WINDOW).await;
+                let _ = lobby_sender.send(LobbyMessage::ResumeExpired);
+            });
+            println!("->> Lobby {lobby_id} restored");
+        }
+    }
+    //Capture every lobby owned by this instance, notifying their users that the server is going away.
+    pub async fn snapshot(&self, notice: &str) -> Vec<LobbySnapshot> {
+        self.broadcast_notice(notice);
+        let channels = self.lobbies.iter().map(|lobby| lobby.channel.clone()).collect::<Vec<_>>();
+        let replies = channels.into_iter().filter_map(|channel| {
+            let (reply, receiver) = oneshot::channel();
+            channel.send(LobbyMessage::Snapshot { reply }).ok().map(|_| receiver)
+        });
+        futures::future::join_all(replies).await.into_iter().filter_map(|snapshot| snapshot.ok()).collect()
+    }
+    fn run(&self, mut lobby_task: LobbyTask, mut lobby_receiver: UnboundedReceiver<LobbyMessage>) {
         //Spawn a task that handles lobby logic.
+        let lobby_id = lobby_task.lobby_id;
         let lobbies = self.lobbies.clone();
+        let registry = self.registry.clone();
+        let instance_id = self.instance_id;
         tokio::spawn(async move {
-            let mut user_id_counter = 0;
-            let mut users = BTreeMap::<UserID, SplitSink<WebSocket, Message>>::new();
-            //Read incoming messages for this lobby.
-            while let Some(lobby_message) = lobby_receiver.recv().await {
-                match lobby_message {
-                    //On client joining this lobby:
-                    LobbyMessage::Connect { websocket } => {
-                        let (mut socket_sender, mut socket_receiver) = websocket.split();
-
-                        //Generate user ID.
-                        let user_id = user_id_counter;
-                        user_id_counter += 1;
-
-                        //Send message to client notifying connection to this lobby.
-                        let peers_id = users.keys().cloned().collect::<Vec<_>>();
-                        let socket_message = SocketMessage::ConnectSuccess { lobby_id, user_id: user_id, peers_id };
-                        let socket_message_serialized = match bincode::serialize(&socket_message) {
-                            Ok(socket_message_serialized) => socket_message_serialized,
-                            Err(_) => break, //Break out of lobby message loop on serialization failure.
-                        };
-                        let _ = socket_sender.send(Message::Binary(socket_message_serialized.into())).await;
-
-                        //Add client to users list.
-                        users.insert(user_id, socket_sender);
-
-                        //Spawn a task that receives websocket messages from the client and relay them to the lobby task.
-                        let lobby_sender = lobby_sender.clone();
-                        tokio::spawn(async move {
-                            println!("->> User {user_id} joined lobby {lobby_id}");
-                            //Read incoming messages from the client. Breaks if the connection closes.
-                            while let Some(Ok(Message::Binary(socket_message_serialized))) = socket_receiver.next().await {
-                                let socket_message = match bincode::deserialize::<SocketMessage>(&socket_message_serialized) {
-                                    Ok(socket_message) => socket_message,
-                                    Err(_) => break, //Break out of websocket message loop on deserialization failure.
-                                };
-                                if let SocketMessage::WebRtcHandshake { target_id: target, .. } = socket_message {
-                                    let _ = lobby_sender.send(LobbyMessage::Message { target, socket_message_serialized });
-                                }
-                            }
-                            //Remove this user from lobby.
-                            println!("->> User {user_id} left lobby {lobby_id}");
-                            let _ = lobby_sender.send(LobbyMessage::Disconnect { user_id });
-                        }); //End of websocket task.
-                    },
-                    //On client disconnect from this lobby:
-                    LobbyMessage::Disconnect { user_id } => {
-                        users.remove(&user_id);
-                        if users.is_empty() {
-                            break; //Break out of lobby message loop when no users are connected to this lobby.
+            //Let other instances know where to find this lobby, and keep the claim alive while the lobby runs.
+            //A failed claim is retried until a claim left behind by a crashed instance would have lapsed.
+            let mut claimed_at = Instant::now();
+            if !registry.claim(lobby_id, instance_id, CLAIM_TTL).await {
+                println!("->> Lobby {lobby_id} is claimed by another instance, retrying");
+            }
+            let mut heartbeat = tokio::time::interval_at((claimed_at + CLAIM_REFRESH).into(), CLAIM_REFRESH);
+
+            loop {
+                tokio::select! {
+                    _ = heartbeat.tick() => {
+                        if registry.claim(lobby_id, instance_id, CLAIM_TTL).await {
+                            claimed_at = Instant::now();
                         }
-                    },
-                    //Relay websocket message to target user:
-                    LobbyMessage::Message { target, socket_message_serialized } => {
-                        if let Some(user) = users.get_mut(&target) {
-                            let _ = user.send(Message::Binary(socket_message_serialized)).await;
+                        else if claimed_at.elapsed() >= CLAIM_TTL {
+                            println!("->> Lobby {lobby_id} could not be registered");
+                            let _ = lobby_task.handle(LobbyMessage::Close { reason: "The lobby could not be registered".to_string() }).await;
+                            break
                         }
-                    },
+                    }
+                    //Read incoming messages for this lobby.
+                    lobby_message = lobby_receiver.recv() => {
+                        let Some(lobby_message) = lobby_message else {
+                            break
+                        };
+                        if lobby_task.handle(lobby_message).await.is_break() {
+                            break
+                        }
+                    }
                 }
             }
 
             //Remove this lobby from registry.
             println!("->> Lobby {lobby_id} removed");
             let _ = lobbies.remove(&lobby_id);
+            registry.release(lobby_id, instance_id).await;
         }); //End of lobby task.
+    }
+    pub async fn join(&self, lobby_id: LobbyID, websocket: WebSocket, player_id: PlayerID, display_name: String, token: String, spectator: bool) {
+        //Send websocket to lobby if found.
+        let connection = Connection::local(websocket);
+        if let Some(lobby) = self.lobbies.get(&lobby_id) {
+            let _ = lobby.channel.send(LobbyMessage::Connect { connection, player_id, display_name, token, spectator });
+            return
+        }
 
-        println!("->> Lobby {lobby_id} created");
+        //Otherwise relay it to the instance that owns the lobby. The websocket closes if there is none.
+        let Some(owner) = self.registry.owner(lobby_id).await.filter(|&owner| owner != self.instance_id) else {
+            return
+        };
+        let connection_id = rand::random::<ConnectionID>();
+        let Connection { outgoing, mut incoming } = connection;
+        self.forwarded.insert(connection_id, outgoing);
+        let envelope = Envelope::Connect { lobby_id, connection_id, reply_to: self.instance_id, player_id, display_name, token, spectator };
+        self.publish(owner, &envelope).await;
 
-        lobby_id
+        //Spawn a task that relays websocket messages from the client to the owner.
+        let lobby_collection = self.clone();
+        tokio::spawn(async move {
+            while let Some(message) = incoming.next().await {
+                match message {
+                    Message::Binary(message) => lobby_collection.publish(owner, &Envelope::FromClient { connection_id, message: message.to_vec() }).await,
+                    Message::Close(_) => break,
+                    _ => {}
+                }
+            }
+            lobby_collection.forwarded.remove(&connection_id);
+            lobby_collection.publish(owner, &Envelope::ClientClosed { connection_id }).await;
+        }); //End of relay task.
     }
-    pub fn join(&self, lobby_id: LobbyID, websocket: WebSocket) {
-        //Send websocket to lobby if found.
+    async fn publish(&self, instance_id: InstanceID, envelope: &Envelope) {
+        if let Ok(envelope_serialized) = bincode::serialize(envelope) {
+            self.registry.publish(instance_id, envelope_serialized).await;
+        }
+    }
+    async fn receive(&self, envelope: Envelope) {
+        match envelope {
+            //A websocket held by another instance joins a lobby owned here:
+            Envelope::Connect { lobby_id, connection_id, reply_to, player_id, display_name, token, spectator } => {
+                let (incoming_sender, incoming_receiver) = tokio::sync::mpsc::unbounded_channel::<Message>();
+                let (outgoing_sender, mut outgoing_receiver) = tokio::sync::mpsc::unbounded_channel::<Message>();
+                self.relayed.insert(connection_id, incoming_sender);
+
+                //Spawn a task that relays messages for the client back to the instance holding its websocket.
+                let lobby_collection = self.clone();
+                tokio::spawn(async move {
+                    while let Some(message) = outgoing_receiver.recv().await {
+                        match message {
+                            Message::Binary(message) => lobby_collection.publish(reply_to, &Envelope::ToClient { connection_id, message: message.to_vec() }).await,
+                            Message::Close(_) => break,
+                            _ => {}
+                        }
+                    }
+                    lobby_collection.relayed.remove(&connection_id);
+                    lobby_collection.publish(reply_to, &Envelope::CloseClient { connection_id }).await;
+                }); //End of relay task.
+
+                let connection = Connection::from_channels(outgoing_sender, incoming_receiver);
+                if let Some(lobby) = self.lobbies.get(&lobby_id) {
+                    let _ = lobby.channel.send(LobbyMessage::Connect { connection, player_id, display_name, token, spectator });
+                }
+            }
+            Envelope::FromClient { connection_id, message } => {
+                if let Some(incoming) = self.relayed.get(&connection_id) {
+                    let _ = incoming.send(Message::Binary(message.into()));
+                }
+            }
+            Envelope::ClientClosed { connection_id } => {
+                self.relayed.remove(&connection_id);
+            }
+            //A lobby owned elsewhere talks to a websocket held here:
+            Envelope::ToClient { connection_id, message } => {
+                if let Some(outgoing) = self.forwarded.get(&connection_id) {
+                    let _ = outgoing.send(Message::Binary(message.into()));
+                }
+            }
+            Envelope::CloseClient { connection_id } => {
+                if let Some((_, outgoing)) = self.forwarded.remove(&connection_id) {
+                    let _ = outgoing.send(Message::Close(None));
+                }
+            }
+        }
+    }
+    pub async fn list(&self) -> Vec<LobbyInfo> {
+        //Collect channels first so no map shard stays locked while awaiting replies.
+        let channels = self.lobbies.iter().map(|lobby| lobby.channel.clone()).collect::<Vec<_>>();
+        let replies = channels.into_iter().filter_map(|channel| {
+            let (reply, receiver) = oneshot::channel();
+            channel.send(LobbyMessage::Inspect { reply }).ok().map(|_| receiver)
+        });
+        futures::future::join_all(replies).await.into_iter().filter_map(|info| info.ok()).collect()
+    }
+    pub async fn kick(&self, lobby_id: LobbyID, user_id: UserID, reason: String) -> bool {
+        let (reply, receiver) = oneshot::channel();
+        let sent = self.lobbies.get(&lobby_id)
+            .is_some_and(|lobby| lobby.channel.send(LobbyMessage::Kick { user_id, reason, reply }).is_ok());
+        sent && receiver.await.unwrap_or(false)
+    }
+    pub fn close(&self, lobby_id: LobbyID, reason: String) -> bool {
+        self.lobbies.get(&lobby_id)
+            .is_some_and(|lobby| lobby.channel.send(LobbyMessage::Close { reason }).is_ok())
+    }
+    pub fn release(&self, lobby_id: LobbyID) {
         if let Some(lobby) = self.lobbies.get(&lobby_id) {
-            let _ = lobby.channel.send(LobbyMessage::Connect { websocket });
+            let _ = lobby.channel.send(LobbyMessage::Release);
         }
     }
-}
\ No newline at end of file
+    pub fn broadcast_notice(&self, message: &str) {
+        for lobby in self.lobbies.iter() {
+            let _ = lobby.channel.send(LobbyMessage::Notice { message: message.to_string() });
+        }
+    }
+}
+
+#[cfg(test)]
+impl LobbyCollection {
+    //A collection for unit tests. Its lobbies never record games, so nothing is written to disk.
+    pub(super) fn for_tests() -> Self {
+        use super::{profile::ProfileStore, registry::InMemoryRegistry, storage::Writer};
+        let unused = std::env::temp_dir().join(format!("yahtzee-lobbies-{:016x}", rand::random::<u64>()));
+        let writer = Writer::spawn();
+        let profiles = ProfileStore::open(unused.join("profiles.json"), writer.clone()).unwrap();
+        let history = GameHistory::open(unused.join("history.jsonl"), profiles, writer).unwrap();
+        Self::new(history, Solvers::default(), Arc::new(InMemoryRegistry::new()), 0)
+    }
+    //Connect to a lobby of this instance over channels rather than a websocket. Returns the client's two ends.
+    pub(super) fn connect_over_channels(&self, lobby_id: LobbyID, player_id: PlayerID, display_name: &str) -> (UnboundedSender<Message>, UnboundedReceiver<Message>) {
+        let (client_sender, incoming_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (outgoing, client_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let connection = Connection::from_channels(outgoing, incoming_receiver);
+        if let Some(lobby) = self.lobbies.get(&lobby_id) {
+            let _ = lobby.channel.send(LobbyMessage::Connect { connection, player_id, display_name: display_name.to_string(), token: String::new(), spectator: false });
+        }
+        (client_sender, client_receiver)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestClient {
+        sender: UnboundedSender<Message>,
+        receiver: UnboundedReceiver<Message>,
+    }
+    impl TestClient {
+        fn connect(lobbies: &LobbyCollection, lobby_id: LobbyID, player_id: PlayerID) -> Self {
+            let (sender, receiver) = lobbies.connect_over_channels(lobby_id, player_id, &format!("Player {player_id}"));
+            Self { sender, receiver }
+        }
+        fn send(&self, socket_message: &SocketMessage) {
+            let _ = self.sender.send(encode(socket_message).unwrap());
+        }
+        //Skip messages until one is picked out. Panics if the connection closes first.
+        async fn receive_until<T>(&mut self, mut pick: impl FnMut(SocketMessage) -> Option<T>) -> T {
+            loop {
+                match self.receiver.recv().await.expect("Connection closed while waiting for a message") {
+                    Message::Binary(serialized) => if let Some(picked) = pick(bincode::deserialize(&serialized).unwrap()) {
+                        return picked
+                    },
+                    Message::Close(_) => panic!("Connection closed while waiting for a message"),
+                    _ => {}
+                }
+            }
+        }
+        async fn user_id(&mut self) -> UserID {
+            self.receive_until(|socket_message| match socket_message {
+                SocketMessage::ConnectSuccess { user_id, .. } => Some(user_id),
+                _ => None,
+            }).await
+        }
+    }
+
+    //Snapshots go through JSON on their way to the next start, like `YahtzeeState::shutdown` saves them.
+    async fn restart(lobbies: &LobbyCollection) -> LobbyCollection {
+        let snapshots = serde_json::to_vec(&lobbies.snapshot("Restarting").await).unwrap();
+        let restored = LobbyCollection::for_tests();
+        restored.restore(serde_json::from_slice(&snapshots).unwrap());
+        restored
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn restored_lobbies_wait_for_their_users() {
+        let lobbies = LobbyCollection::for_tests();
+        let lobby_id = lobbies.create(LobbySettings::default());
+        let mut host = TestClient::connect(&lobbies, lobby_id, 1);
+        let host_id = host.user_id().await;
+        let mut guest = TestClient::connect(&lobbies, lobby_id, 2);
+        let guest_id = guest.user_id().await;
+        host.send(&SocketMessage::SetReady { ready: true });
+        guest.send(&SocketMessage::SetReady { ready: true });
+        host.receive_until(|socket_message| match socket_message {
+            SocketMessage::LobbyState { users, .. } if users.iter().all(|user| user.ready) => Some(()),
+            _ => None,
+        }).await;
+        host.send(&SocketMessage::StartGame);
+        let state = host.receive_until(|socket_message| match socket_message {
+            SocketMessage::GameState { state } => Some(state),
+            _ => None,
+        }).await;
+
+        let restored = restart(&lobbies).await;
+        //The host comes back to their seat and the game where it was.
+        let mut host = TestClient::connect(&restored, lobby_id, 1);
+        assert_eq!(host.user_id().await, host_id);
+        let restored_state = host.receive_until(|socket_message| match socket_message {
+            SocketMessage::GameState { state } => Some(state),
+            _ => None,
+        }).await;
+        assert_eq!(restored_state, state);
+
+        //The guest's seat is kept until the resume window is over.
+        let users = host.receive_until(|socket_message| match socket_message {
+            SocketMessage::LobbyState { users, .. } => Some(users),
+            _ => None,
+        }).await;
+        assert_eq!(users.len(), 1);
+        tokio::time::sleep(RESUME_WINDOW).await;
+        let mut guest = TestClient::connect(&restored, lobby_id, 2);
+        assert_ne!(guest.user_id().await, guest_id);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn restored_organized_lobbies_turn_players_away() {
+        let lobbies = LobbyCollection::for_tests();
+        let (results, _reports) = tokio::sync::mpsc::unbounded_channel();
+        let organizer = LobbyOrganizer { players: vec![1, 2], results, no_show_window: RESUME_WINDOW };
+        let lobby_id = lobbies.create_organized(LobbySettings { max_players: 2, ..LobbySettings::default() }, organizer);
+        let mut first = TestClient::connect(&lobbies, lobby_id, 1);
+        first.user_id().await;
+
+        let restored = restart(&lobbies).await;
+        let mut first = TestClient::connect(&restored, lobby_id, 1);
+        let reason = first.receive_until(|socket_message| match socket_message {
+            SocketMessage::LobbyClosed { reason } => Some(reason),
+            _ => None,
+        }).await;
+        assert_eq!(reason, INTERRUPTED_REASON);
+        //Once every player was told, the lobby is gone.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(restored.list().await.is_empty());
+    }
+}
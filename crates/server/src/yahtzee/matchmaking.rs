@@ -0,0 +1,363 @@
+use std::time::{Duration, Instant};
+use axum::extract::ws::{Message, WebSocket};
+use tokio::sync::mpsc::UnboundedSender;
+use yahtzee_rules::GameOptions;
+
+use super::{identity::PlayerID, lobby::{self, LobbyCollection, LobbyID, LobbySettings, SocketMessage, MAX_PLAYERS}};
+
+const MIN_PLAYERS: u8 = 2;
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+//After this long a player accepts a game with fewer players than they asked for.
+const FALLBACK_WAIT: Duration = Duration::from_secs(30);
+//After this long without any match the player is sent back.
+const QUEUE_TIMEOUT: Duration = Duration::from_secs(120);
+
+type TicketID = u64;
+
+//What a quick-play player is looking for. Only players with equal options are ever matched together.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Preferences {
+    pub players: u8,
+    pub options: GameOptions,
+}
+
+enum TicketEvent {
+    Status {
+        waiting: u8,
+    },
+    Matched {
+        lobby_id: LobbyID,
+    },
+    TimedOut,
+    //The same player queued again from another connection.
+    Replaced,
+}
+
+enum MatchmakerMessage {
+    Enqueue {
+        ticket: Ticket,
+    },
+    Leave {
+        ticket_id: TicketID,
+    },
+}
+
+struct Ticket {
+    ticket_id: TicketID,
+    player_id: PlayerID,
+    preferences: Preferences,
+    queued_at: Instant,
+    sender: UnboundedSender<TicketEvent>,
+}
+
+impl Ticket {
+    //Whether the player takes a game of this size. Anyone who waited long enough takes a smaller one.
+    fn accepts(&self, players: u8, now: Instant) -> bool {
+        players == self.preferences.players || (players < self.preferences.players && now.duration_since(self.queued_at) >= FALLBACK_WAIT)
+    }
+}
+
+//State owned by the matchmaker task. Tickets are kept in queue order.
+struct MatchmakerTask {
+    lobbies: LobbyCollection,
+    tickets: Vec<Ticket>,
+}
+impl MatchmakerTask {
+    fn handle(&mut self, matchmaker_message: MatchmakerMessage) {
+        match matchmaker_message {
+            MatchmakerMessage::Enqueue { ticket } => {
+                //A player is only ever queued once, so they can not be matched against themselves.
+                if let Some(index) = self.tickets.iter().position(|queued| queued.player_id == ticket.player_id) {
+                    let replaced = self.tickets.remove(index);
+                    let _ = replaced.sender.send(TicketEvent::Replaced);
+                    self.send_status(replaced.preferences);
+                }
+                let preferences = ticket.preferences;
+                self.tickets.push(ticket);
+                self.match_full_groups(preferences);
+                self.send_status(preferences);
+            }
+            MatchmakerMessage::Leave { ticket_id } => {
+                if let Some(index) = self.tickets.iter().position(|ticket| ticket.ticket_id == ticket_id) {
+                    let ticket = self.tickets.remove(index);
+                    self.send_status(ticket.preferences);
+                }
+            }
+        }
+    }
+
+    //Start a game as soon as enough players with the same preferences are waiting.
+    fn match_full_groups(&mut self, preferences: Preferences) {
+        loop {
+            let group = self.tickets.iter()
+                .filter(|ticket| ticket.preferences == preferences)
+                .take(preferences.players as usize)
+                .map(|ticket| ticket.ticket_id)
+                .collect::<Vec<_>>();
+            if group.len() < preferences.players as usize {
+                break
+            }
+            self.start_match(&group, preferences);
+        }
+    }
+
+    //Periodically drop expired tickets and let players who waited long enough start with fewer players.
+    fn tick(&mut self, now: Instant) {
+        //Tickets whose connection is gone are dropped as well, in case their task ended without leaving.
+        self.tickets.retain(|ticket| {
+            let expired = now.duration_since(ticket.queued_at) >= QUEUE_TIMEOUT;
+            if expired {
+                let _ = ticket.sender.send(TicketEvent::TimedOut);
+            }
+            !expired && !ticket.sender.is_closed()
+        });
+
+        //The longest waiting player gets the biggest game, up to the size they asked for, that enough players with the
+        //same options accept.
+        let mut unmatched = Vec::new();
+        while let Some(oldest) = self.tickets.iter().find(|ticket| now.duration_since(ticket.queued_at) >= FALLBACK_WAIT && !unmatched.contains(&ticket.ticket_id)) {
+            let (ticket_id, preferences) = (oldest.ticket_id, oldest.preferences);
+            let group = (MIN_PLAYERS..=preferences.players).rev().find_map(|players| {
+                let others = self.tickets.iter()
+                    .filter(|ticket| ticket.ticket_id != ticket_id && ticket.preferences.options == preferences.options && ticket.accepts(players, now))
+                    .map(|ticket| ticket.ticket_id);
+                let group = std::iter::once(ticket_id).chain(others).take(players as usize).collect::<Vec<_>>();
+                (group.len() == players as usize).then_some(group)
+            });
+            match group {
+                Some(group) => self.start_match(&group, Preferences { players: group.len() as u8, ..preferences }),
+                None => unmatched.push(ticket_id),
+            }
+        }
+    }
+
+    fn start_match(&mut self, group: &[TicketID], preferences: Preferences) {
+        let settings = LobbySettings {
+            max_players: preferences.players,
+            game: preferences.options,
+        };
+        let lobby_id = self.lobbies.create(settings);
+        println!("->> Matched {} players into lobby {lobby_id}", group.len());
+        self.tickets.retain(|ticket| {
+            let matched = group.contains(&ticket.ticket_id);
+            if matched {
+                let _ = ticket.sender.send(TicketEvent::Matched { lobby_id });
+            }
+            !matched
+        });
+    }
+
+    fn send_status(&self, preferences: Preferences) {
+        let tickets = self.tickets.iter().filter(|ticket| ticket.preferences == preferences);
+        let waiting = tickets.clone().count() as u8;
+        for ticket in tickets {
+            let _ = ticket.sender.send(TicketEvent::Status { waiting });
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Matchmaker {
+    channel: UnboundedSender<MatchmakerMessage>,
+    lobbies: LobbyCollection,
+}
+impl Matchmaker {
+    pub fn new(lobbies: LobbyCollection) -> Self {
+        let (matchmaker_sender, mut matchmaker_receiver) = tokio::sync::mpsc::unbounded_channel::<MatchmakerMessage>();
+
+        //Spawn a task that owns the queue.
+        let mut matchmaker_task = MatchmakerTask {
+            lobbies: lobbies.clone(),
+            tickets: Vec::new(),
+        };
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(TICK_INTERVAL);
+            loop {
+                tokio::select! {
+                    matchmaker_message = matchmaker_receiver.recv() => match matchmaker_message {
+                        Some(matchmaker_message) => matchmaker_task.handle(matchmaker_message),
+                        None => break,
+                    },
+                    _ = interval.tick() => matchmaker_task.tick(Instant::now()),
+                }
+            }
+        }); //End of matchmaker task.
+
+        Self {
+            channel: matchmaker_sender,
+            lobbies,
+        }
+    }
+    //Hold on to the websocket until the player is matched, times out or disconnects.
+    pub fn enqueue(&self, mut websocket: WebSocket, preferences: Preferences, player_id: PlayerID, display_name: String, token: String) {
+        let preferences = Preferences {
+            players: preferences.players.clamp(MIN_PLAYERS, MAX_PLAYERS),
+            ..preferences
+        };
+        let ticket_id = rand::random::<TicketID>();
+        let (ticket_sender, mut ticket_receiver) = tokio::sync::mpsc::unbounded_channel::<TicketEvent>();
+        let ticket = Ticket { ticket_id, player_id, preferences, queued_at: Instant::now(), sender: ticket_sender };
+        if self.channel.send(MatchmakerMessage::Enqueue { ticket }).is_err() {
+            return
+        }
+
+        //Spawn a task that waits on the queue and watches the connection.
+        let matchmaker_sender = self.channel.clone();
+        let lobbies = self.lobbies.clone();
+        tokio::spawn(async move {
+            println!("->> Player {player_id} queued for a {} player game", preferences.players);
+            loop {
+                tokio::select! {
+                    ticket_event = ticket_receiver.recv() => match ticket_event {
+                        Some(TicketEvent::Status { waiting }) => {
+                            lobby::send(&SocketMessage::QueueStatus { waiting, players: preferences.players }, &mut websocket).await;
+                        }
+                        Some(TicketEvent::Matched { lobby_id }) => {
+                            lobbies.join(lobby_id, websocket, player_id, display_name, token, false).await;
+                            return
+                        }
+                        Some(TicketEvent::TimedOut) | None => {
+                            lobby::send(&SocketMessage::JoinRejected { reason: "No match found".to_string() }, &mut websocket).await;
+                            let _ = websocket.send(Message::Close(None)).await;
+                            return
+                        }
+                        Some(TicketEvent::Replaced) => {
+                            lobby::send(&SocketMessage::JoinRejected { reason: "Queued from another connection".to_string() }, &mut websocket).await;
+                            let _ = websocket.send(Message::Close(None)).await;
+                            return
+                        }
+                    },
+                    socket_message = websocket.recv() => {
+                        //Anything but a clean message while queued means the client is gone.
+                        if !matches!(socket_message, Some(Ok(Message::Binary(_) | Message::Ping(_) | Message::Pong(_)))) {
+                            break
+                        }
+                    }
+                }
+            }
+            println!("->> Player {player_id} left the matchmaking queue");
+            let _ = matchmaker_sender.send(MatchmakerMessage::Leave { ticket_id });
+        }); //End of ticket task.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    fn matchmaker() -> MatchmakerTask {
+        MatchmakerTask { lobbies: LobbyCollection::for_tests(), tickets: Vec::new() }
+    }
+
+    fn enqueue(matchmaker: &mut MatchmakerTask, player_id: PlayerID, players: u8, queued_at: Instant) -> UnboundedReceiver<TicketEvent> {
+        enqueue_with(matchmaker, player_id, Preferences { players, options: GameOptions::default() }, queued_at)
+    }
+    fn enqueue_with(matchmaker: &mut MatchmakerTask, player_id: PlayerID, preferences: Preferences, queued_at: Instant) -> UnboundedReceiver<TicketEvent> {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let ticket = Ticket { ticket_id: player_id * 100 + matchmaker.tickets.len() as TicketID, player_id, preferences, queued_at, sender };
+        matchmaker.handle(MatchmakerMessage::Enqueue { ticket });
+        receiver
+    }
+
+    //The last thing the ticket task was told, if anything.
+    fn last_event(receiver: &mut UnboundedReceiver<TicketEvent>) -> Option<TicketEvent> {
+        std::iter::from_fn(|| receiver.try_recv().ok()).last()
+    }
+    fn matched(receiver: &mut UnboundedReceiver<TicketEvent>) -> Option<LobbyID> {
+        match last_event(receiver) {
+            Some(TicketEvent::Matched { lobby_id }) => Some(lobby_id),
+            _ => None,
+        }
+    }
+    fn queued(matchmaker: &MatchmakerTask) -> Vec<PlayerID> {
+        matchmaker.tickets.iter().map(|ticket| ticket.player_id).collect()
+    }
+
+    #[tokio::test]
+    async fn full_groups_start_right_away() {
+        let mut matchmaker = matchmaker();
+        let start = Instant::now();
+        let mut first = enqueue(&mut matchmaker, 1, 2, start);
+        assert!(matches!(last_event(&mut first), Some(TicketEvent::Status { waiting: 1 })));
+        let mut second = enqueue(&mut matchmaker, 2, 2, start);
+        let mut third = enqueue(&mut matchmaker, 3, 2, start);
+
+        let lobby_id = matched(&mut first).unwrap();
+        assert_eq!(matched(&mut second), Some(lobby_id));
+        assert!(matches!(last_event(&mut third), Some(TicketEvent::Status { waiting: 1 })));
+        assert_eq!(queued(&matchmaker), vec![3]);
+        assert_eq!(matchmaker.lobbies.list().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn queuing_again_replaces_the_earlier_ticket() {
+        let mut matchmaker = matchmaker();
+        let start = Instant::now();
+        let mut first = enqueue(&mut matchmaker, 1, 2, start);
+        let mut again = enqueue(&mut matchmaker, 1, 2, start);
+        assert!(matches!(last_event(&mut first), Some(TicketEvent::Replaced)));
+        assert_eq!(queued(&matchmaker), vec![1]);
+
+        //Even after the fallback wait, a player is never matched with themselves.
+        matchmaker.tick(start + FALLBACK_WAIT);
+        assert_eq!(matched(&mut again), None);
+        let mut other = enqueue(&mut matchmaker, 2, 2, start);
+        let lobby_id = matched(&mut again);
+        assert!(lobby_id.is_some());
+        assert_eq!(matched(&mut other), lobby_id);
+        assert!(matchmaker.tickets.is_empty());
+    }
+
+    #[tokio::test]
+    async fn long_waits_fall_back_to_smaller_games_the_others_accept() {
+        let mut matchmaker = matchmaker();
+        let start = Instant::now();
+        let mut four = enqueue(&mut matchmaker, 1, 4, start);
+        let mut three = enqueue(&mut matchmaker, 2, 3, start + Duration::from_secs(20));
+        let other_options = Preferences { players: 2, options: GameOptions { yahtzee_bonus: false, ..GameOptions::default() } };
+        let mut other = enqueue_with(&mut matchmaker, 3, other_options, start);
+
+        //The oldest player would take two players, but the second still waits for the three they asked for.
+        matchmaker.tick(start + FALLBACK_WAIT);
+        assert_eq!(queued(&matchmaker), vec![1, 2, 3]);
+
+        matchmaker.tick(start + Duration::from_secs(20) + FALLBACK_WAIT);
+        let lobby_id = matched(&mut four).unwrap();
+        assert_eq!(matched(&mut three), Some(lobby_id));
+        assert_eq!(matched(&mut other), None);
+        assert_eq!(queued(&matchmaker), vec![3]);
+        assert_eq!(matchmaker.lobbies.list().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn tickets_time_out() {
+        let mut matchmaker = matchmaker();
+        let start = Instant::now();
+        let mut alone = enqueue(&mut matchmaker, 1, 3, start);
+        matchmaker.tick(start + QUEUE_TIMEOUT - Duration::from_secs(1));
+        assert_eq!(queued(&matchmaker), vec![1]);
+        matchmaker.tick(start + QUEUE_TIMEOUT);
+        assert!(matches!(last_event(&mut alone), Some(TicketEvent::TimedOut)));
+        assert!(matchmaker.tickets.is_empty());
+    }
+
+    #[tokio::test]
+    async fn players_who_left_are_not_matched() {
+        let mut matchmaker = matchmaker();
+        let start = Instant::now();
+        let mut staying = enqueue(&mut matchmaker, 1, 2, start);
+        let _ = enqueue(&mut matchmaker, 2, 3, start);
+        let closed = enqueue(&mut matchmaker, 3, 3, start);
+        let leaving_ticket = matchmaker.tickets[1].ticket_id;
+        matchmaker.handle(MatchmakerMessage::Leave { ticket_id: leaving_ticket });
+        assert_eq!(queued(&matchmaker), vec![1, 3]);
+
+        //A ticket whose connection is gone is dropped on the next tick, even without leaving.
+        drop(closed);
+        matchmaker.tick(start + FALLBACK_WAIT);
+        assert!(matches!(last_event(&mut staying), Some(TicketEvent::Status { waiting: 1 })));
+        assert_eq!(queued(&matchmaker), vec![1]);
+        assert!(matchmaker.lobbies.list().await.is_empty());
+    }
+}
@@ -1,4 +1,4 @@
-use std::net::SocketAddr;
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
 
 use axum::{
     extract::{ConnectInfo, Query, State, WebSocketUpgrade},
@@ -7,33 +7,216 @@ use axum::{
     Router
 };
 use serde::Deserialize;
+use yahtzee_rules::{GameOptions, Variant};
+
+use crate::Result;
 
 pub mod lobby;
-use lobby::LobbyCollection;
+use lobby::{LobbyCollection, LobbySettings, LobbySnapshot};
+
+pub mod matchmaking;
+use matchmaking::{Matchmaker, Preferences};
+
+pub mod identity;
+use identity::TokenSigner;
+
+pub mod profile;
+use profile::{Profile, ProfileStore};
+
+pub mod history;
+use history::GameHistory;
+
+pub mod registry;
+use registry::{InMemoryRegistry, InstanceID, LobbyRegistry, RedisRegistry};
+
+pub mod tournament;
+use tournament::TournamentCollection;
+
+pub mod ai;
 
-pub fn routes() -> Router {
-    let lobby_collection = LobbyCollection::default();
+pub mod solvers;
+use solvers::Solvers;
+
+mod admin;
+mod api;
+mod storage;
+use storage::Writer;
+
+const TOKEN_SECRET_FILE: &str = "token_secret";
+const PROFILES_FILE: &str = "profiles.json";
+const HISTORY_FILE: &str = "history.jsonl";
+const LOBBIES_FILE: &str = "lobbies.json";
+const SHUTDOWN_NOTICE: &str = "The server is restarting. Reconnect in a moment to resume your game.";
+
+#[derive(Clone)]
+pub struct YahtzeeState {
+    pub lobbies: LobbyCollection,
+    pub matchmaker: Matchmaker,
+    pub tournaments: TournamentCollection,
+    pub profiles: ProfileStore,
+    pub history: GameHistory,
+    pub token_signer: TokenSigner,
+    pub admin_token: Option<Arc<str>>,
+    snapshot_path: Arc<PathBuf>,
+    writer: Writer,
+}
+impl YahtzeeState {
+    //Save every lobby so the next start can restore it, and finish writing profiles and history.
+    pub async fn shutdown(&self) -> Result<()> {
+        let snapshots = self.lobbies.snapshot(SHUTDOWN_NOTICE).await;
+        println!("->> Saving {} lobbies", snapshots.len());
+        self.writer.flush().await;
+        storage::save(&self.snapshot_path, &snapshots)
+    }
+}
+
+//Where the server keeps its files and which optional services it uses.
+pub struct Config {
+    pub data_dir: PathBuf,
+    pub admin_token: Option<String>,
+    pub redis_url: Option<String>,
+    //Set when running several instances, so an instance keeps its lobbies across restarts. Random otherwise.
+    pub instance_id: Option<InstanceID>,
+    //Solve the optimal strategy for hints and expert AI players. Takes a while on first start.
+    pub solver: bool,
+}
+impl Config {
+    pub fn from_env(data_dir: impl Into<PathBuf>) -> Self {
+        let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+        Self {
+            data_dir: data_dir.into(),
+            admin_token: var(admin::ADMIN_TOKEN_VAR),
+            redis_url: var(registry::REDIS_URL_VAR),
+            instance_id: var(registry::INSTANCE_ID_VAR).and_then(|instance_id| instance_id.parse().ok()),
+            solver: true,
+        }
+    }
+}
+
+pub fn state(config: &Config) -> Result<YahtzeeState> {
+    //Lobbies are shared through redis when running several instances, otherwise they stay in this process.
+    let registry: Arc<dyn LobbyRegistry> = match config.redis_url.as_deref() {
+        Some(url) => Arc::new(RedisRegistry::open(url)?),
+        None => Arc::new(InMemoryRegistry::new()),
+    };
+    state_with_registry(config, registry)
+}
+
+//Like `state`, with the registry given instead of chosen from the config.
+pub fn state_with_registry(config: &Config, registry: Arc<dyn LobbyRegistry>) -> Result<YahtzeeState> {
+    let data_dir = config.data_dir.as_path();
+    std::fs::create_dir_all(data_dir).map_err(|error| {
+        println!("->> Failed to create data directory {}: {error}", data_dir.display());
+        crate::error::Error::YahtzeeStorageError
+    })?;
+    let writer = Writer::spawn();
+    let profiles = ProfileStore::open(data_dir.join(PROFILES_FILE), writer.clone())?;
+    let history = GameHistory::open(data_dir.join(HISTORY_FILE), profiles.clone(), writer.clone())?;
+    let solvers = if config.solver { Solvers::load_or_solve(data_dir) } else { Solvers::default() };
+    let lobbies = LobbyCollection::new(history.clone(), solvers, registry, config.instance_id.unwrap_or_else(rand::random));
+
+    //Restore lobbies saved on the last shutdown. The snapshot is cleared so a crash later does not bring them back twice.
+    let snapshot_path = data_dir.join(LOBBIES_FILE);
+    lobbies.restore(storage::load::<Vec<LobbySnapshot>>(&snapshot_path).unwrap_or_default());
+    storage::save(&snapshot_path, &Vec::<LobbySnapshot>::new())?;
+
+    let state = YahtzeeState {
+        matchmaker: Matchmaker::new(lobbies.clone()),
+        tournaments: TournamentCollection::new(lobbies.clone()),
+        lobbies,
+        history,
+        profiles,
+        token_signer: TokenSigner::load_or_generate(&data_dir.join(TOKEN_SECRET_FILE))?,
+        admin_token: config.admin_token.as_deref().map(Arc::from),
+        snapshot_path: Arc::new(snapshot_path),
+        writer,
+    };
+    if state.admin_token.is_none() {
+        println!("->> {} not set, admin endpoints disabled", admin::ADMIN_TOKEN_VAR);
+    }
+    Ok(state)
+}
+
+pub fn routes(state: YahtzeeState) -> Router {
     Router::new()
         .route("/ws", get(lobby_connection_handler))
-        .with_state(lobby_collection)
+        .route("/quickplay", get(quick_play_handler))
+        .nest("/api", api::routes())
+        .nest("/admin", admin::routes())
+        .with_state(state)
 }
 
 #[derive(Deserialize)]
 struct LobbyQuery {
     lobby_id: Option<u64>,
+    token: Option<String>,
+    name: Option<String>,
+    #[serde(default)]
+    spectate: bool,
 }
-async fn lobby_connection_handler( 
+async fn lobby_connection_handler(
     websocket_upgrade: WebSocketUpgrade,
-    State(lobby_collection): State<LobbyCollection>,
+    State(state): State<YahtzeeState>,
     Query(lobby_query): Query<LobbyQuery>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
-) -> impl IntoResponse {
+) -> Result<impl IntoResponse> {
     println!("->> New connection at {addr}");
-    websocket_upgrade.on_upgrade(move |websocket| async move {
+    let profile = resolve_profile(&state, lobby_query.token.as_deref(), lobby_query.name.as_deref());
+    let token = state.token_signer.issue(profile.player_id);
+    let lobby_collection = state.lobbies;
+    Ok(websocket_upgrade.on_upgrade(move |websocket| async move {
         let lobby_id = match lobby_query.lobby_id {
             Some(lobby_id) => lobby_id,
-            None => lobby_collection.create(),
+            None => lobby_collection.create(LobbySettings::default()),
         };
-        lobby_collection.join(lobby_id, websocket);
-    })
+        lobby_collection.join(lobby_id, websocket, profile.player_id, profile.display_name, token, lobby_query.spectate).await;
+    }))
+}
+
+#[derive(Deserialize)]
+struct QuickPlayQuery {
+    token: Option<String>,
+    name: Option<String>,
+    players: Option<u8>,
+    yahtzee_bonus: Option<bool>,
+    variant: Option<Variant>,
+}
+async fn quick_play_handler(
+    websocket_upgrade: WebSocketUpgrade,
+    State(state): State<YahtzeeState>,
+    Query(quick_play_query): Query<QuickPlayQuery>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> Result<impl IntoResponse> {
+    println!("->> New quick-play connection at {addr}");
+    let profile = resolve_profile(&state, quick_play_query.token.as_deref(), quick_play_query.name.as_deref());
+    let token = state.token_signer.issue(profile.player_id);
+    let default_options = GameOptions::default();
+    let preferences = Preferences {
+        players: quick_play_query.players.unwrap_or(LobbySettings::default().max_players),
+        options: GameOptions {
+            yahtzee_bonus: quick_play_query.yahtzee_bonus.unwrap_or(default_options.yahtzee_bonus),
+            variant: quick_play_query.variant.unwrap_or(default_options.variant),
+            ..default_options
+        },
+    };
+    let matchmaker = state.matchmaker;
+    Ok(websocket_upgrade.on_upgrade(move |websocket| async move {
+        matchmaker.enqueue(websocket, preferences, profile.player_id, profile.display_name, token);
+    }))
+}
+
+//Find the profile a guest token belongs to, or make up a guest profile if the token is missing or invalid.
+//Guest profiles are only stored once they have something worth keeping, so connecting never writes to disk.
+fn resolve_profile(state: &YahtzeeState, token: Option<&str>, name: Option<&str>) -> Profile {
+    let name = name.map(profile::sanitize_display_name).filter(|name| !name.is_empty());
+    let Some(player_id) = token.and_then(|token| state.token_signer.verify(token)) else {
+        return state.profiles.guest(name.as_deref().unwrap_or("Guest"))
+    };
+    match (state.profiles.get(player_id), name) {
+        (Some(profile), Some(name)) if profile.display_name != name => {
+            state.profiles.update(player_id, |profile| profile.display_name = name).unwrap_or(profile)
+        }
+        (Some(profile), _) => profile,
+        (None, name) => Profile::new(player_id, name.as_deref().unwrap_or("Guest")),
+    }
 }
@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::PathBuf, sync::{Arc, Mutex}};
+
+use crate::error::Result;
+use super::{identity::{AI_PLAYER_ID, PlayerID}, storage::{self, Writer}};
+
+pub const MAX_DISPLAY_NAME_LENGTH: usize = 16;
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct ProfileStats {
+    pub games_played: u32,
+    pub games_won: u32,
+    pub total_score: u64,
+    pub high_score: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Profile {
+    pub player_id: PlayerID,
+    pub display_name: String,
+    pub avatar_color: u32, //0xRRGGBB
+    pub stats: ProfileStats,
+}
+
+//Trim whitespace and limit display names to what the client's name input allows.
+pub fn sanitize_display_name(name: &str) -> String {
+    name.trim().chars().filter(|c| !c.is_control()).take(MAX_DISPLAY_NAME_LENGTH).collect()
+}
+
+impl Profile {
+    //A profile that nothing has been saved for yet. The avatar color comes from the ID so it stays the same once saved.
+    pub fn new(player_id: PlayerID, display_name: &str) -> Self {
+        Self {
+            player_id,
+            display_name: sanitize_display_name(display_name),
+            avatar_color: (player_id >> 8) as u32 & 0xFFFFFF,
+            stats: ProfileStats::default(),
+        }
+    }
+}
+
+//Player profiles persisted as a single JSON document, rewritten on every change.
+//Guests only get a stored profile once there is something to keep, like a finished game or a chosen avatar.
+#[derive(Clone)]
+pub struct ProfileStore {
+    path: Arc<PathBuf>,
+    profiles: Arc<Mutex<BTreeMap<PlayerID, Profile>>>,
+    writer: Writer,
+}
+impl ProfileStore {
+    pub fn open(path: PathBuf, writer: Writer) -> Result<Self> {
+        let profiles = storage::load(&path)?;
+        Ok(Self {
+            path: Arc::new(path),
+            profiles: Arc::new(Mutex::new(profiles)),
+            writer,
+        })
+    }
+    //A new guest profile, not stored until `upsert` is called for it.
+    pub fn guest(&self, display_name: &str) -> Profile {
+        let profiles = self.profiles.lock().unwrap();
+
+        //Loop until randomly generated player ID does not collide with existing profiles.
+        let player_id = loop {
+            let player_id = rand::random::<PlayerID>();
+            if player_id != AI_PLAYER_ID && !profiles.contains_key(&player_id) {
+                break player_id
+            }
+        };
+        Profile::new(player_id, display_name)
+    }
+    pub fn get(&self, player_id: PlayerID) -> Option<Profile> {
+        self.profiles.lock().unwrap().get(&player_id).cloned()
+    }
+    //Apply a modification to a profile and persist it. Returns None if the profile does not exist.
+    pub fn update<F: FnOnce(&mut Profile)>(&self, player_id: PlayerID, f: F) -> Option<Profile> {
+        let profile = {
+            let mut profiles = self.profiles.lock().unwrap();
+            let profile = profiles.get_mut(&player_id)?;
+            f(profile);
+            profile.clone()
+        };
+        self.save();
+        Some(profile)
+    }
+    //Like `update`, storing the profile first if this is the first change for it.
+    pub fn upsert<F: FnOnce(&mut Profile)>(&self, player_id: PlayerID, display_name: &str, f: F) -> Profile {
+        let profile = {
+            let mut profiles = self.profiles.lock().unwrap();
+            let profile = profiles.entry(player_id).or_insert_with(|| Profile::new(player_id, display_name));
+            f(profile);
+            profile.clone()
+        };
+        self.save();
+        profile
+    }
+    fn save(&self) {
+        self.writer.save(&self.path, self.profiles.clone());
+    }
+}
@@ -0,0 +1,225 @@
+use dashmap::{DashMap, mapref::entry::Entry};
+use futures::{future::BoxFuture, stream::{BoxStream, StreamExt}};
+use std::{sync::Arc, time::{Duration, Instant}};
+use tokio::sync::{Mutex, mpsc::UnboundedSender};
+
+use super::lobby::LobbyID;
+
+pub type InstanceID = u64;
+
+pub const REDIS_URL_VAR: &str = "YAHTZEE_REDIS_URL";
+pub const INSTANCE_ID_VAR: &str = "YAHTZEE_INSTANCE_ID";
+
+//Claims lapse unless renewed, so lobbies of an instance that crashed stop being routed to it.
+pub const CLAIM_TTL: Duration = Duration::from_secs(30);
+pub const CLAIM_REFRESH: Duration = Duration::from_secs(10);
+//How long to wait before subscribing again after the redis subscription failed or ended. Doubles on every failure.
+const RESUBSCRIBE_DELAY: Duration = Duration::from_millis(500);
+const MAX_RESUBSCRIBE_DELAY: Duration = Duration::from_secs(30);
+
+//Lua scripts so checking the owner and changing the key happen in one step.
+const CLAIM_SCRIPT: &str = r#"
+local owner = redis.call('GET', KEYS[1])
+if owner == false or owner == ARGV[1] then
+    redis.call('SET', KEYS[1], ARGV[1], 'PX', ARGV[2])
+    return 1
+end
+return 0
+"#;
+const RELEASE_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    redis.call('DEL', KEYS[1])
+end
+return 0
+"#;
+
+//Shared record of which server instance owns each lobby, plus a message bus between instances.
+//Envelopes are opaque to the registry; lobbies encode and decode them.
+pub trait LobbyRegistry: Send + Sync + 'static {
+    //Record this instance as the owner of a lobby for `ttl`, or renew a claim it already holds.
+    //Returns false if another instance owns the lobby or the registry could not be reached.
+    fn claim(&self, lobby_id: LobbyID, instance_id: InstanceID, ttl: Duration) -> BoxFuture<'_, bool>;
+    fn owner(&self, lobby_id: LobbyID) -> BoxFuture<'_, Option<InstanceID>>;
+    //Give up a claim, unless another instance has taken the lobby over since.
+    fn release(&self, lobby_id: LobbyID, instance_id: InstanceID) -> BoxFuture<'_, ()>;
+    //Deliver an envelope to another instance. Envelopes to unknown instances are dropped.
+    fn publish(&self, instance_id: InstanceID, envelope: Vec<u8>) -> BoxFuture<'_, ()>;
+    //Envelopes published to this instance from now on. The stream keeps going for as long as the registry does.
+    fn subscribe(&self, instance_id: InstanceID) -> BoxFuture<'_, BoxStream<'static, Vec<u8>>>;
+}
+
+//Registry for a single process. Several lobby collections may share one to stand in for separate instances.
+#[derive(Clone, Default)]
+pub struct InMemoryRegistry {
+    owners: Arc<DashMap<LobbyID, (InstanceID, Instant)>>,
+    instances: Arc<DashMap<InstanceID, UnboundedSender<Vec<u8>>>>,
+}
+impl InMemoryRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+impl LobbyRegistry for InMemoryRegistry {
+    fn claim(&self, lobby_id: LobbyID, instance_id: InstanceID, ttl: Duration) -> BoxFuture<'_, bool> {
+        let now = Instant::now();
+        let claimed = match self.owners.entry(lobby_id) {
+            Entry::Vacant(v) => {
+                v.insert((instance_id, now + ttl));
+                true
+            }
+            Entry::Occupied(mut o) if o.get().0 == instance_id || o.get().1 <= now => {
+                o.insert((instance_id, now + ttl));
+                true
+            }
+            Entry::Occupied(_) => false,
+        };
+        Box::pin(async move { claimed })
+    }
+    fn owner(&self, lobby_id: LobbyID) -> BoxFuture<'_, Option<InstanceID>> {
+        let owner = self.owners.get(&lobby_id).filter(|owner| owner.1 > Instant::now()).map(|owner| owner.0);
+        Box::pin(async move { owner })
+    }
+    fn release(&self, lobby_id: LobbyID, instance_id: InstanceID) -> BoxFuture<'_, ()> {
+        self.owners.remove_if(&lobby_id, |_, owner| owner.0 == instance_id);
+        Box::pin(async {})
+    }
+    fn publish(&self, instance_id: InstanceID, envelope: Vec<u8>) -> BoxFuture<'_, ()> {
+        if let Some(instance) = self.instances.get(&instance_id) {
+            let _ = instance.send(envelope);
+        }
+        Box::pin(async {})
+    }
+    fn subscribe(&self, instance_id: InstanceID) -> BoxFuture<'_, BoxStream<'static, Vec<u8>>> {
+        let (instance_sender, instance_receiver) = tokio::sync::mpsc::unbounded_channel();
+        self.instances.insert(instance_id, instance_sender);
+        let stream = futures::stream::unfold(instance_receiver, |mut instance_receiver| async move {
+            instance_receiver.recv().await.map(|envelope| (envelope, instance_receiver))
+        });
+        Box::pin(async move { stream.boxed() })
+    }
+}
+
+//Registry backed by Redis. Ownership is a key per lobby and every instance listens on its own pub/sub channel.
+//A connection that fails is dropped and the next command opens a new one, so the registry recovers from redis restarts.
+#[derive(Clone)]
+pub struct RedisRegistry {
+    client: redis::Client,
+    connection: Arc<Mutex<Option<redis::aio::MultiplexedConnection>>>,
+}
+impl RedisRegistry {
+    pub fn open(url: &str) -> crate::Result<Self> {
+        let client = redis::Client::open(url).map_err(|error| {
+            println!("->> Invalid redis URL {url}: {error}");
+            crate::error::Error::YahtzeeRegistryError
+        })?;
+        Ok(Self {
+            client,
+            connection: Arc::new(Mutex::new(None)),
+        })
+    }
+    async fn connection(&self) -> Option<redis::aio::MultiplexedConnection> {
+        let mut connection = self.connection.lock().await;
+        if connection.is_none() {
+            match self.client.get_multiplexed_async_connection().await {
+                Ok(new_connection) => *connection = Some(new_connection),
+                Err(error) => println!("->> Failed to connect to redis: {error}"),
+            }
+        }
+        connection.clone()
+    }
+    async fn query<T: redis::FromRedisValue>(&self, command: &redis::Cmd) -> Option<T> {
+        let mut connection = self.connection().await?;
+        match command.query_async(&mut connection).await {
+            Ok(value) => Some(value),
+            Err(error) => {
+                println!("->> Redis command failed: {error}");
+                if error.is_io_error() || error.is_connection_dropped() || error.is_unrecoverable_error() {
+                    *self.connection.lock().await = None;
+                }
+                None
+            }
+        }
+    }
+}
+async fn subscribe_once(client: &redis::Client, instance_id: InstanceID) -> redis::RedisResult<BoxStream<'static, Vec<u8>>> {
+    let mut pubsub = client.get_async_pubsub().await?;
+    pubsub.subscribe(instance_channel(instance_id)).await?;
+    Ok(pubsub.into_on_message().map(|message| message.get_payload_bytes().to_vec()).boxed())
+}
+fn lobby_key(lobby_id: LobbyID) -> String {
+    format!("yahtzee:lobby:{lobby_id}")
+}
+fn instance_channel(instance_id: InstanceID) -> String {
+    format!("yahtzee:instance:{instance_id}")
+}
+impl LobbyRegistry for RedisRegistry {
+    fn claim(&self, lobby_id: LobbyID, instance_id: InstanceID, ttl: Duration) -> BoxFuture<'_, bool> {
+        Box::pin(async move {
+            let command = redis::cmd("EVAL").arg(CLAIM_SCRIPT).arg(1).arg(lobby_key(lobby_id)).arg(instance_id).arg(ttl.as_millis() as u64).clone();
+            self.query::<i64>(&command).await == Some(1)
+        })
+    }
+    fn owner(&self, lobby_id: LobbyID) -> BoxFuture<'_, Option<InstanceID>> {
+        Box::pin(async move {
+            self.query::<Option<InstanceID>>(redis::cmd("GET").arg(lobby_key(lobby_id))).await.flatten()
+        })
+    }
+    fn release(&self, lobby_id: LobbyID, instance_id: InstanceID) -> BoxFuture<'_, ()> {
+        Box::pin(async move {
+            let _ = self.query::<()>(redis::cmd("EVAL").arg(RELEASE_SCRIPT).arg(1).arg(lobby_key(lobby_id)).arg(instance_id)).await;
+        })
+    }
+    fn publish(&self, instance_id: InstanceID, envelope: Vec<u8>) -> BoxFuture<'_, ()> {
+        Box::pin(async move {
+            let _ = self.query::<()>(redis::cmd("PUBLISH").arg(instance_channel(instance_id)).arg(envelope)).await;
+        })
+    }
+    fn subscribe(&self, instance_id: InstanceID) -> BoxFuture<'_, BoxStream<'static, Vec<u8>>> {
+        let (envelope_sender, envelope_receiver) = tokio::sync::mpsc::unbounded_channel();
+
+        //Spawn a task that subscribes again whenever the subscription fails or ends, until nobody listens anymore.
+        let client = self.client.clone();
+        tokio::spawn(async move {
+            let mut delay = RESUBSCRIBE_DELAY;
+            while !envelope_sender.is_closed() {
+                match subscribe_once(&client, instance_id).await {
+                    Ok(mut envelopes) => {
+                        delay = RESUBSCRIBE_DELAY;
+                        while let Some(envelope) = envelopes.next().await {
+                            if envelope_sender.send(envelope).is_err() {
+                                return
+                            }
+                        }
+                        println!("->> Redis subscription ended, subscribing again in {delay:?}");
+                    }
+                    Err(error) => println!("->> Failed to subscribe to redis: {error}, retrying in {delay:?}"),
+                }
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_RESUBSCRIBE_DELAY);
+            }
+        }); //End of subscription task.
+
+        let stream = futures::stream::unfold(envelope_receiver, |mut envelope_receiver| async move {
+            envelope_receiver.recv().await.map(|envelope| (envelope, envelope_receiver))
+        });
+        Box::pin(async move { stream.boxed() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Nothing listens on this port, so every connection attempt fails right away.
+    const UNREACHABLE_URL: &str = "redis://127.0.0.1:1";
+
+    #[tokio::test]
+    async fn unreachable_redis_does_not_end_the_subscription() {
+        let registry = RedisRegistry::open(UNREACHABLE_URL).unwrap();
+        let mut envelopes = registry.subscribe(1).await;
+        //An empty stream would end at once. This one keeps retrying in the background instead.
+        assert!(tokio::time::timeout(RESUBSCRIBE_DELAY * 4, envelopes.next()).await.is_err());
+        assert!(!registry.claim(1, 1, CLAIM_TTL).await);
+        assert_eq!(registry.owner(1).await, None);
+    }
+}
@@ -0,0 +1,54 @@
+use std::{path::Path, sync::{Arc, OnceLock}};
+use yahtzee_rules::{GameOptions, solver::Solver};
+
+const SOLVER_BONUS_FILE: &str = "solver_bonus.bin";
+const SOLVER_NO_BONUS_FILE: &str = "solver_no_bonus.bin";
+
+//Optimal-strategy tables for hints and expert AI players, one per set of game options.
+//Loaded from the data directory, or solved in the background and saved there on first start.
+//Until a table is ready, or for variants the solver does not know, lookups come back empty.
+#[derive(Clone, Default)]
+pub struct Solvers {
+    with_bonus: Arc<OnceLock<Solver>>,
+    without_bonus: Arc<OnceLock<Solver>>,
+}
+impl Solvers {
+    pub fn load_or_solve(data_dir: &Path) -> Self {
+        let solvers = Self::default();
+        let tables = [
+            (solvers.with_bonus.clone(), GameOptions { yahtzee_bonus: true, ..GameOptions::default() }, data_dir.join(SOLVER_BONUS_FILE)),
+            (solvers.without_bonus.clone(), GameOptions { yahtzee_bonus: false, ..GameOptions::default() }, data_dir.join(SOLVER_NO_BONUS_FILE)),
+        ];
+        //Spawn a thread that loads or solves each table in turn.
+        std::thread::spawn(move || {
+            for (solver, options, path) in tables {
+                let _ = solver.set(load_or_solve(options, &path));
+            }
+        }); //End of solver thread.
+        solvers
+    }
+    pub fn get(&self, options: GameOptions) -> Option<&Solver> {
+        if !Solver::supports(&options) {
+            return None
+        }
+        match options.yahtzee_bonus {
+            true => self.with_bonus.get(),
+            false => self.without_bonus.get(),
+        }
+    }
+}
+
+fn load_or_solve(options: GameOptions, path: &Path) -> Solver {
+    if let Some(solver) = std::fs::read(path).ok().and_then(|bytes| Solver::from_bytes(options, &bytes)) {
+        println!("->> Loaded solver table {}", path.display());
+        return solver
+    }
+    println!("->> Solving optimal strategy for {options:?}");
+    let solver = Solver::solve(options);
+    let temp_path = path.with_extension("tmp");
+    match std::fs::write(&temp_path, solver.to_bytes()).and_then(|_| std::fs::rename(&temp_path, path)) {
+        Ok(_) => println!("->> Saved solver table {}", path.display()),
+        Err(error) => println!("->> Failed to write {}: {error}", path.display()),
+    }
+    solver
+}
@@ -0,0 +1,120 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::{collections::HashMap, fs, io::{ErrorKind, Write}, path::{Path, PathBuf}, sync::{mpsc, Arc, Mutex}};
+use tokio::sync::oneshot;
+
+use crate::error::{Error, Result};
+
+//Load a JSON document from disk, falling back to its default value if the file does not exist yet.
+pub fn load<T: DeserializeOwned + Default>(path: &Path) -> Result<T> {
+    match fs::read(path) {
+        Ok(bytes) => serde_json::from_slice(&bytes).map_err(|error| {
+            println!("->> Failed to parse {}: {error}", path.display());
+            Error::YahtzeeStorageError
+        }),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(T::default()),
+        Err(error) => {
+            println!("->> Failed to read {}: {error}", path.display());
+            Err(Error::YahtzeeStorageError)
+        }
+    }
+}
+
+//Write a JSON document to disk. Writes to a temporary file first so a crash never leaves a truncated document behind.
+pub fn save<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let serialized = serde_json::to_vec(value).map_err(|_| Error::YahtzeeStorageError)?;
+    write_document(path, &serialized)
+}
+fn write_document(path: &Path, serialized: &[u8]) -> Result<()> {
+    let temp_path = path.with_extension("tmp");
+    fs::write(&temp_path, serialized)
+        .and_then(|_| fs::rename(&temp_path, path))
+        .map_err(|error| {
+            println!("->> Failed to write {}: {error}", path.display());
+            Error::YahtzeeStorageError
+        })
+}
+
+//Load every record of an append-only JSON lines file. A missing file is an empty log.
+pub fn load_lines<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => {
+            println!("->> Failed to read {}: {error}", path.display());
+            return Err(Error::YahtzeeStorageError)
+        }
+    };
+    contents.lines().filter(|line| !line.trim().is_empty()).map(|line| serde_json::from_str(line).map_err(|error| {
+        println!("->> Failed to parse record in {}: {error}", path.display());
+        Error::YahtzeeStorageError
+    })).collect()
+}
+
+fn append_line(path: &Path, serialized: &[u8]) -> Result<()> {
+    fs::OpenOptions::new().create(true).append(true).open(path)
+        .and_then(|mut file| file.write_all(serialized))
+        .map_err(|error| {
+            println!("->> Failed to append to {}: {error}", path.display());
+            Error::YahtzeeStorageError
+        })
+}
+
+type Serializer = Box<dyn FnOnce() -> serde_json::Result<Vec<u8>> + Send>;
+
+enum Job {
+    Save(PathBuf, Serializer),
+    Append(PathBuf, Vec<u8>),
+    Flush(oneshot::Sender<()>),
+}
+
+//Writes files on a dedicated thread, so the tasks that change them never wait on the disk.
+#[derive(Clone)]
+pub struct Writer {
+    sender: mpsc::Sender<Job>,
+}
+impl Writer {
+    pub fn spawn() -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        std::thread::spawn(move || {
+            while let Ok(job) = receiver.recv() {
+                let jobs = std::iter::once(job).chain(receiver.try_iter()).collect::<Vec<_>>();
+                //A document saved several times while the disk was busy is only written once, with its latest contents.
+                let last_saves = jobs.iter().enumerate().filter_map(|(index, job)| match job {
+                    Job::Save(path, _) => Some((path.clone(), index)),
+                    _ => None,
+                }).collect::<HashMap<_, _>>();
+                for (index, job) in jobs.into_iter().enumerate() {
+                    match job {
+                        Job::Save(path, serialize) if last_saves.get(&path) == Some(&index) => match serialize() {
+                            Ok(serialized) => { let _ = write_document(&path, &serialized); }
+                            Err(error) => println!("->> Failed to serialize {}: {error}", path.display()),
+                        },
+                        Job::Save(..) => {}
+                        Job::Append(path, serialized) => { let _ = append_line(&path, &serialized); }
+                        Job::Flush(done) => { let _ = done.send(()); }
+                    }
+                }
+            }
+        });
+        Self { sender }
+    }
+    //Save a shared document. It is serialized when written rather than now, so it is only locked on the writer thread.
+    pub fn save<T: Serialize + Send + 'static>(&self, path: &Path, value: Arc<Mutex<T>>) {
+        let serialize = Box::new(move || serde_json::to_vec(&*value.lock().unwrap()));
+        let _ = self.sender.send(Job::Save(path.to_path_buf(), serialize));
+    }
+    //Append a single record to a JSON lines file.
+    pub fn append_line<T: Serialize>(&self, path: &Path, value: &T) -> Result<()> {
+        let mut serialized = serde_json::to_vec(value).map_err(|_| Error::YahtzeeStorageError)?;
+        serialized.push(b'\n');
+        let _ = self.sender.send(Job::Append(path.to_path_buf(), serialized));
+        Ok(())
+    }
+    //Wait until everything requested so far is on disk.
+    pub async fn flush(&self) {
+        let (done, wait) = oneshot::channel();
+        if self.sender.send(Job::Flush(done)).is_ok() {
+            let _ = wait.await;
+        }
+    }
+}
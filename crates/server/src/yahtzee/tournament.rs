@@ -0,0 +1,487 @@
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::{cmp::Reverse, collections::BTreeMap, sync::Arc, time::Duration};
+use tokio::sync::mpsc::UnboundedSender;
+
+use super::{
+    identity::PlayerID,
+    history::{unix_time, GameRecord},
+    lobby::{LobbyCollection, LobbyID, LobbyOrganizer, LobbySettings, OrganizerReport},
+};
+
+pub type TournamentID = u64;
+
+pub const MAX_BEST_OF: u8 = 9;
+pub const MAX_ENTRANTS: usize = 64;
+const MAX_NAME_LENGTH: usize = 40;
+//Entrants not ready in their series lobby this long after it opens, or after a game ends, forfeit the series.
+const NO_SHOW_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TournamentFormat {
+    //Every entrant plays a series against every other entrant.
+    RoundRobin,
+    //Single elimination. Series winners advance until one entrant is left.
+    Bracket,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Entrant {
+    pub player_id: PlayerID,
+    pub display_name: String,
+}
+
+//A best-of-N series between two entrants, played in its own lobby.
+#[derive(Serialize, Clone)]
+pub struct Series {
+    pub lobby_id: LobbyID,
+    pub players: [PlayerID; 2],
+    pub wins: [u8; 2],
+    pub totals: [u32; 2],
+    pub games_played: u8,
+    pub winner: Option<PlayerID>,
+    //Entrants who did not show up. The series went to the other one, or to the higher seed if both were missing.
+    pub forfeited: Vec<PlayerID>,
+}
+impl Series {
+    fn new(lobby_id: LobbyID, players: [PlayerID; 2]) -> Self {
+        Self {
+            lobby_id,
+            players,
+            wins: [0; 2],
+            totals: [0; 2],
+            games_played: 0,
+            winner: None,
+            forfeited: Vec::new(),
+        }
+    }
+    //Count a finished game towards the series. A player missing from the record left and scores nothing.
+    fn record(&mut self, record: &GameRecord, best_of: u8) {
+        let totals = self.players.map(|player_id| record.result_of(player_id).map_or(0, |result| result.total as u32));
+        for (total, game_total) in self.totals.iter_mut().zip(totals) {
+            *total += game_total;
+        }
+        if totals[0] != totals[1] {
+            self.wins[if totals[0] > totals[1] { 0 } else { 1 }] += 1;
+        }
+        self.games_played += 1;
+
+        //Drawn games may leave the series undecided after N games. Aggregate score breaks the tie, then seeding.
+        let wins_needed = best_of / 2 + 1;
+        if let Some(index) = self.wins.iter().position(|&wins| wins >= wins_needed) {
+            self.winner = Some(self.players[index]);
+        }
+        else if self.games_played >= best_of {
+            let index = if (self.wins[1], self.totals[1]) > (self.wins[0], self.totals[0]) { 1 } else { 0 };
+            self.winner = Some(self.players[index]);
+        }
+    }
+    fn forfeit(&mut self, absent: &[PlayerID]) {
+        self.forfeited = self.players.iter().copied().filter(|player_id| absent.contains(player_id)).collect();
+        let index = if self.forfeited == [self.players[0]] { 1 } else { 0 };
+        self.winner = Some(self.players[index]);
+    }
+}
+
+#[derive(Serialize, Clone, Default)]
+pub struct Round {
+    pub series: Vec<Series>,
+    pub byes: Vec<PlayerID>,
+}
+impl Round {
+    fn is_finished(&self) -> bool {
+        self.series.iter().all(|series| series.winner.is_some())
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct Standing {
+    pub player_id: PlayerID,
+    pub display_name: String,
+    pub series_played: u32,
+    pub series_won: u32,
+    pub games_won: u32,
+    pub total_score: u32,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Tournament {
+    pub tournament_id: TournamentID,
+    pub name: String,
+    pub format: TournamentFormat,
+    pub best_of: u8,
+    pub created_at: u64,
+    pub entrants: Vec<Entrant>,
+    pub rounds: Vec<Round>,
+    pub winner: Option<PlayerID>,
+    #[serde(skip)]
+    schedule: Vec<Vec<(PlayerID, Option<PlayerID>)>>,
+}
+impl Tournament {
+    fn new(tournament_id: TournamentID, name: String, format: TournamentFormat, best_of: u8, entrants: Vec<Entrant>) -> Self {
+        let players = entrants.iter().map(|entrant| entrant.player_id).collect::<Vec<_>>();
+        let schedule = match format {
+            TournamentFormat::RoundRobin => round_robin_schedule(&players),
+            TournamentFormat::Bracket => Vec::new(),
+        };
+        Self {
+            tournament_id,
+            name,
+            format,
+            best_of,
+            created_at: unix_time(),
+            entrants,
+            rounds: Vec::new(),
+            winner: None,
+            schedule,
+        }
+    }
+
+    //Pairings for the next round, or None once the tournament is decided. A missing opponent is a bye.
+    fn next_pairings(&self) -> Option<Vec<(PlayerID, Option<PlayerID>)>> {
+        match self.format {
+            TournamentFormat::RoundRobin => self.schedule.get(self.rounds.len()).cloned(),
+            TournamentFormat::Bracket => {
+                let advancing = match self.rounds.last() {
+                    Some(round) => round.series.iter().filter_map(|series| series.winner).chain(round.byes.iter().copied()).collect(),
+                    None => self.entrants.iter().map(|entrant| entrant.player_id).collect::<Vec<_>>(),
+                };
+                (advancing.len() > 1).then(|| advancing.chunks(2).map(|pair| (pair[0], pair.get(1).copied())).collect())
+            }
+        }
+    }
+
+    pub fn standings(&self) -> Vec<Standing> {
+        let mut standings = self.entrants.iter().map(|entrant| (entrant.player_id, Standing {
+            player_id: entrant.player_id,
+            display_name: entrant.display_name.clone(),
+            series_played: 0,
+            series_won: 0,
+            games_won: 0,
+            total_score: 0,
+        })).collect::<BTreeMap<_, _>>();
+        for series in self.rounds.iter().flat_map(|round| round.series.iter()) {
+            for (index, player_id) in series.players.iter().enumerate() {
+                if let Some(standing) = standings.get_mut(player_id) {
+                    standing.games_won += series.wins[index] as u32;
+                    standing.total_score += series.totals[index];
+                    if let Some(winner) = series.winner {
+                        standing.series_played += 1;
+                        standing.series_won += (winner == *player_id) as u32;
+                    }
+                }
+            }
+        }
+        let mut standings = standings.into_values().collect::<Vec<_>>();
+        standings.sort_by_key(|standing| Reverse((standing.series_won, standing.games_won, standing.total_score)));
+        standings
+    }
+}
+
+//Circle method: one entrant stays in place while the others rotate, so everyone meets once.
+fn round_robin_schedule(players: &[PlayerID]) -> Vec<Vec<(PlayerID, Option<PlayerID>)>> {
+    let mut slots = players.iter().copied().map(Some).collect::<Vec<_>>();
+    if slots.len() % 2 == 1 {
+        slots.push(None);
+    }
+    let count = slots.len();
+    let mut schedule = Vec::new();
+    for _ in 1..count {
+        let round = (0..count / 2).filter_map(|index| match (slots[index], slots[count - 1 - index]) {
+            (Some(first), second) => Some((first, second)),
+            (None, Some(second)) => Some((second, None)),
+            (None, None) => None,
+        }).collect();
+        schedule.push(round);
+        slots[1..].rotate_right(1);
+    }
+    schedule
+}
+
+#[derive(Serialize, Clone)]
+pub struct TournamentSummary {
+    pub tournament_id: TournamentID,
+    pub name: String,
+    pub format: TournamentFormat,
+    pub created_at: u64,
+    pub entrants: usize,
+    pub finished: bool,
+}
+
+#[derive(Clone)]
+pub struct TournamentCollection {
+    tournaments: Arc<DashMap<TournamentID, Tournament>>,
+    lobbies: LobbyCollection,
+    results: UnboundedSender<OrganizerReport>,
+}
+impl TournamentCollection {
+    pub fn new(lobbies: LobbyCollection) -> Self {
+        let (results_sender, mut results_receiver) = tokio::sync::mpsc::unbounded_channel::<OrganizerReport>();
+        let tournament_collection = Self {
+            tournaments: Arc::new(DashMap::new()),
+            lobbies,
+            results: results_sender,
+        };
+
+        //Spawn a task that applies game results and no-shows reported by tournament lobbies.
+        let tournament_collection_clone = tournament_collection.clone();
+        tokio::spawn(async move {
+            while let Some(report) = results_receiver.recv().await {
+                tournament_collection_clone.record(report);
+            }
+        }); //End of results task.
+
+        tournament_collection
+    }
+    pub fn create(&self, name: String, format: TournamentFormat, best_of: u8, entrants: Vec<Entrant>) -> Tournament {
+        let tournament_id = rand::random::<TournamentID>();
+        let name = name.trim().chars().filter(|c| !c.is_control()).take(MAX_NAME_LENGTH).collect();
+        let mut tournament = Tournament::new(tournament_id, name, format, best_of, entrants);
+        self.start_round(&mut tournament);
+        println!("->> Tournament {tournament_id} created with {} entrants", tournament.entrants.len());
+        self.tournaments.insert(tournament_id, tournament.clone());
+        tournament
+    }
+    pub fn get(&self, tournament_id: TournamentID) -> Option<Tournament> {
+        self.tournaments.get(&tournament_id).map(|tournament| tournament.clone())
+    }
+    pub fn list(&self) -> Vec<TournamentSummary> {
+        let mut summaries = self.tournaments.iter().map(|tournament| TournamentSummary {
+            tournament_id: tournament.tournament_id,
+            name: tournament.name.clone(),
+            format: tournament.format,
+            created_at: tournament.created_at,
+            entrants: tournament.entrants.len(),
+            finished: tournament.winner.is_some(),
+        }).collect::<Vec<_>>();
+        summaries.sort_by_key(|summary| Reverse(summary.created_at));
+        summaries
+    }
+
+    fn record(&self, report: OrganizerReport) {
+        let lobby_id = match &report {
+            OrganizerReport::Finished(record) => record.lobby_id,
+            OrganizerReport::NoShow { lobby_id, .. } => *lobby_id,
+        };
+        let Some(mut tournament) = self.tournaments.iter_mut().find(|tournament| {
+            tournament.rounds.last().is_some_and(|round| round.series.iter().any(|series| series.lobby_id == lobby_id))
+        }) else {
+            return
+        };
+        let best_of = tournament.best_of;
+        let Some(series) = tournament.rounds.last_mut()
+            .and_then(|round| round.series.iter_mut().find(|series| series.lobby_id == lobby_id && series.winner.is_none())) else {
+            return
+        };
+        match report {
+            OrganizerReport::Finished(record) => series.record(&record, best_of),
+            OrganizerReport::NoShow { absent, .. } => series.forfeit(&absent),
+        }
+        if series.winner.is_some() {
+            self.lobbies.release(series.lobby_id);
+        }
+        if tournament.rounds.last().is_some_and(Round::is_finished) {
+            self.start_round(&mut tournament);
+        }
+    }
+
+    //Create a lobby for every series in the next round, or crown the winner when there is none.
+    fn start_round(&self, tournament: &mut Tournament) {
+        let Some(pairings) = tournament.next_pairings() else {
+            tournament.winner = match tournament.format {
+                TournamentFormat::RoundRobin => tournament.standings().first().map(|standing| standing.player_id),
+                TournamentFormat::Bracket => tournament.rounds.last()
+                    .and_then(|round| round.series.iter().filter_map(|series| series.winner).chain(round.byes.iter().copied()).next()),
+            };
+            println!("->> Tournament {} finished", tournament.tournament_id);
+            return
+        };
+        let mut round = Round::default();
+        for (first, second) in pairings {
+            let Some(second) = second else {
+                round.byes.push(first);
+                continue
+            };
+            let settings = LobbySettings {
+                max_players: 2,
+                ..LobbySettings::default()
+            };
+            let organizer = LobbyOrganizer {
+                players: vec![first, second],
+                results: self.results.clone(),
+                no_show_window: NO_SHOW_WINDOW,
+            };
+            round.series.push(Series::new(self.lobbies.create_organized(settings, organizer), [first, second]));
+        }
+        tournament.rounds.push(round);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::yahtzee::{history::PlayerResult, lobby::SocketMessage};
+    use axum::extract::ws::Message;
+    use yahtzee_rules::Scorecard;
+
+    fn entrants(count: PlayerID) -> Vec<Entrant> {
+        (1..=count).map(|player_id| Entrant { player_id, display_name: format!("Player {player_id}") }).collect()
+    }
+
+    fn game(totals: &[(PlayerID, u16)]) -> GameRecord {
+        GameRecord {
+            game_id: 0,
+            lobby_id: 0,
+            started_at: 0,
+            finished_at: 0,
+            players: totals.iter().map(|&(player_id, total)| PlayerResult {
+                player_id,
+                display_name: String::new(),
+                scorecard: Scorecard::default(),
+                total,
+            }).collect(),
+        }
+    }
+
+    //A finished series between the first two players, won by `winner`.
+    fn decided(players: [PlayerID; 2], winner: PlayerID) -> Series {
+        Series { winner: Some(winner), ..Series::new(0, players) }
+    }
+
+    #[test]
+    fn round_robin_pairs_everyone_once() {
+        for count in [4, 5] {
+            let players = (1..=count).collect::<Vec<PlayerID>>();
+            let schedule = round_robin_schedule(&players);
+            assert_eq!(schedule.len(), if count % 2 == 0 { count as usize - 1 } else { count as usize });
+
+            let mut meetings = BTreeMap::new();
+            for round in &schedule {
+                //Everyone appears once per round, at most one of them with a bye.
+                let mut seen = round.iter().flat_map(|&(first, second)| [Some(first), second]).flatten().collect::<Vec<_>>();
+                seen.sort();
+                assert_eq!(seen, players);
+                assert!(round.iter().filter(|(_, second)| second.is_none()).count() <= 1);
+                for &(first, second) in round {
+                    if let Some(second) = second {
+                        *meetings.entry((first.min(second), first.max(second))).or_insert(0) += 1;
+                    }
+                }
+            }
+            assert_eq!(meetings.len(), players.len() * (players.len() - 1) / 2);
+            assert!(meetings.values().all(|&count| count == 1));
+        }
+    }
+
+    #[test]
+    fn bracket_advances_winners_and_byes() {
+        let mut tournament = Tournament::new(0, String::new(), TournamentFormat::Bracket, 1, entrants(5));
+        assert_eq!(tournament.next_pairings(), Some(vec![(1, Some(2)), (3, Some(4)), (5, None)]));
+
+        tournament.rounds.push(Round { series: vec![decided([1, 2], 2), decided([3, 4], 3)], byes: vec![5] });
+        assert_eq!(tournament.next_pairings(), Some(vec![(2, Some(3)), (5, None)]));
+
+        tournament.rounds.push(Round { series: vec![decided([2, 3], 3)], byes: vec![5] });
+        assert_eq!(tournament.next_pairings(), Some(vec![(3, Some(5))]));
+
+        tournament.rounds.push(Round { series: vec![decided([3, 5], 5)], byes: Vec::new() });
+        assert_eq!(tournament.next_pairings(), None);
+    }
+
+    #[test]
+    fn series_is_decided_by_a_majority_of_games() {
+        let mut series = Series::new(0, [1, 2]);
+        series.record(&game(&[(1, 200), (2, 150)]), 3);
+        assert_eq!(series.winner, None);
+        series.record(&game(&[(1, 180), (2, 190)]), 3);
+        assert_eq!(series.winner, None);
+        series.record(&game(&[(1, 210), (2, 100)]), 3);
+        assert_eq!((series.winner, series.wins, series.totals, series.games_played), (Some(1), [2, 1], [590, 440], 3));
+
+        //Decided early once a majority is out of reach.
+        let mut series = Series::new(0, [1, 2]);
+        series.record(&game(&[(1, 100), (2, 150)]), 5);
+        series.record(&game(&[(1, 100), (2, 150)]), 5);
+        series.record(&game(&[(1, 100), (2, 150)]), 5);
+        assert_eq!((series.winner, series.games_played), (Some(2), 3));
+    }
+
+    #[test]
+    fn undecided_series_goes_to_aggregate_score_then_seeding() {
+        //A drawn single game goes to the higher seed.
+        let mut series = Series::new(0, [1, 2]);
+        series.record(&game(&[(1, 150), (2, 150)]), 1);
+        assert_eq!((series.winner, series.wins), (Some(1), [0, 0]));
+
+        let mut series = Series::new(0, [1, 2]);
+        series.record(&game(&[(1, 150), (2, 150)]), 3);
+        series.record(&game(&[(1, 100), (2, 100)]), 3);
+        series.record(&game(&[(1, 90), (2, 120)]), 3);
+        assert_eq!(series.winner, Some(2));
+
+        //A player who left scores nothing for that game.
+        let mut series = Series::new(0, [1, 2]);
+        series.record(&game(&[(1, 150), (2, 150)]), 3);
+        series.record(&game(&[(1, 50)]), 3);
+        assert_eq!((series.winner, series.wins, series.totals), (None, [1, 0], [200, 150]));
+        series.record(&game(&[(1, 100), (2, 300)]), 3);
+        assert_eq!((series.winner, series.wins, series.totals), (Some(2), [1, 1], [300, 450]));
+    }
+
+    #[test]
+    fn no_shows_forfeit_the_series() {
+        let mut series = Series::new(0, [1, 2]);
+        series.forfeit(&[1]);
+        assert_eq!((series.winner, series.forfeited.as_slice()), (Some(2), [1].as_slice()));
+
+        let mut series = Series::new(0, [1, 2]);
+        series.forfeit(&[1, 2]);
+        assert_eq!((series.winner, series.forfeited.as_slice()), (Some(1), [1, 2].as_slice()));
+    }
+
+    #[test]
+    fn standings_add_up_series_games_and_scores() {
+        let mut tournament = Tournament::new(0, String::new(), TournamentFormat::RoundRobin, 3, entrants(3));
+        let mut first = Series::new(0, [1, 2]);
+        first.record(&game(&[(1, 200), (2, 150)]), 3);
+        first.record(&game(&[(1, 200), (2, 150)]), 3);
+        let mut second = Series::new(0, [3, 1]);
+        second.record(&game(&[(3, 250), (1, 100)]), 3);
+        second.record(&game(&[(3, 100), (1, 250)]), 3);
+        second.record(&game(&[(3, 200), (1, 190)]), 3);
+        //Still being played, so only its games count.
+        let mut third = Series::new(0, [2, 3]);
+        third.record(&game(&[(2, 300), (3, 100)]), 3);
+        tournament.rounds = vec![Round { series: vec![first, second, third], byes: Vec::new() }];
+
+        let standings = tournament.standings().into_iter()
+            .map(|standing| (standing.player_id, standing.series_played, standing.series_won, standing.games_won, standing.total_score))
+            .collect::<Vec<_>>();
+        assert_eq!(standings, vec![(1, 2, 1, 3, 940), (3, 1, 1, 2, 650), (2, 1, 0, 1, 600)]);
+    }
+
+    #[tokio::test]
+    async fn players_not_ready_in_time_forfeit() {
+        let lobbies = LobbyCollection::for_tests();
+        let (results, mut reports) = tokio::sync::mpsc::unbounded_channel();
+        let organizer = LobbyOrganizer { players: vec![1, 2], results, no_show_window: Duration::from_millis(200) };
+        let lobby_id = lobbies.create_organized(LobbySettings { max_players: 2, ..LobbySettings::default() }, organizer);
+
+        //Both players show up, but only the first one gets ready.
+        let (ready, _ready_receiver) = lobbies.connect_over_channels(lobby_id, 1, "Ready");
+        let (_idle, _idle_receiver) = lobbies.connect_over_channels(lobby_id, 2, "Idle");
+        let set_ready = bincode::serialize(&SocketMessage::SetReady { ready: true }).unwrap();
+        ready.send(Message::Binary(set_ready.into())).unwrap();
+
+        let report = tokio::time::timeout(Duration::from_secs(5), reports.recv()).await.unwrap().unwrap();
+        let OrganizerReport::NoShow { lobby_id: reported, absent } = report else {
+            panic!("Expected a no-show report")
+        };
+        assert_eq!((reported, absent.as_slice()), (lobby_id, [2].as_slice()));
+
+        let mut series = Series::new(lobby_id, [1, 2]);
+        series.forfeit(&absent);
+        assert_eq!(series.winner, Some(1));
+    }
+}
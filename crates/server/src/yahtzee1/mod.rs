@@ -15,12 +15,12 @@ pub fn routes() -> Router {
 
 async fn lobby_connection_handler(
     websocket_upgrade: WebSocketUpgrade,
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    ConnectInfo(_addr): ConnectInfo<SocketAddr>,
 ) -> Response {
-    websocket_upgrade.on_upgrade(move |mut websocket| async move {
+    websocket_upgrade.on_upgrade(move |websocket| async move {
         let (mut sender, mut receiver) = websocket.split();
         while let Some(Ok(Message::Text(message))) = receiver.next().await {
-            if let Ok(message) = serde_json::from_str::<ClientEvent>(&message) {
+            if let Ok(_message) = serde_json::from_str::<ClientEvent>(&message) {
                 let _ = sender.send(Message::text("received client event")).await;
             }
             else {
@@ -30,11 +30,15 @@ async fn lobby_connection_handler(
     })
 }
 
+#[allow(dead_code)]
 struct Player(SplitSink<WebSocket, Message>);
+#[allow(dead_code)]
 struct PlayerList([Option<Player>; 4]);
+#[allow(dead_code)]
 struct Room(Arc<Mutex<PlayerList>>);
 #[derive(Deserialize, Clone)]
 #[serde(tag = "type")]
+#[allow(dead_code)]
 enum ClientEvent {
     Join {
         room: Option<String>,
@@ -43,6 +47,7 @@ enum ClientEvent {
 }
 #[derive(Serialize, Clone)]
 #[serde(tag = "type")]
+#[allow(dead_code)]
 enum ServerEvent {
     JoinSuccess {
         id: usize,
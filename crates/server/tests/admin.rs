@@ -0,0 +1,84 @@
+mod common;
+
+use common::TestServer;
+use serde_json::json;
+use server::yahtzee::lobby::SocketMessage;
+
+const ADMIN_TOKEN: &str = "admin-secret";
+
+#[tokio::test]
+async fn admin_requests_need_the_admin_token() -> anyhow::Result<()> {
+    let server = TestServer::start_admin(ADMIN_TOKEN).await;
+    let (_host, host_joined) = server.join(None, "Host").await;
+    let lobby_path = format!("/lobbies/{}", host_joined.lobby_id);
+    let reason = json!({ "reason": "Closed" });
+
+    for token in [None, Some("wrong-token"), Some("admin-secre")] {
+        assert_eq!(server.admin_request("GET", "/lobbies", token, json!(null)).await.0, 401);
+        assert_eq!(server.admin_request("DELETE", &lobby_path, token, reason.clone()).await.0, 401);
+        assert_eq!(server.admin_request("POST", "/notice", token, json!({ "message": "Hello" })).await.0, 401);
+    }
+    assert_eq!(server.state.lobbies.list().await.len(), 1);
+
+    //Without a configured token, every admin request is refused.
+    let server = TestServer::start().await;
+    assert_eq!(server.admin_request("GET", "/lobbies", Some(ADMIN_TOKEN), json!(null)).await.0, 401);
+    Ok(())
+}
+
+#[tokio::test]
+async fn admin_lists_lobbies_and_sends_notices() -> anyhow::Result<()> {
+    let server = TestServer::start_admin(ADMIN_TOKEN).await;
+    let (mut host, host_joined) = server.join(None, "Host").await;
+    let (mut guest, _) = server.join(Some(host_joined.lobby_id), "Guest").await;
+
+    let (status, body) = server.admin_request("GET", "/lobbies", Some(ADMIN_TOKEN), json!(null)).await;
+    assert_eq!(status, 200);
+    let lobbies = serde_json::from_str::<serde_json::Value>(&body)?;
+    assert_eq!(lobbies.as_array().map(Vec::len), Some(1));
+    assert_eq!(lobbies[0]["lobby_id"], json!(host_joined.lobby_id));
+    assert_eq!(lobbies[0]["users"].as_array().map(Vec::len), Some(2));
+
+    let (status, _) = server.admin_request("POST", "/notice", Some(ADMIN_TOKEN), json!({ "message": "Maintenance soon" })).await;
+    assert_eq!(status, 204);
+    for client in [&mut host, &mut guest] {
+        let message = client.receive_until(|socket_message| match socket_message {
+            SocketMessage::ServerNotice { message } => Some(message),
+            _ => None,
+        }).await;
+        assert_eq!(message, "Maintenance soon");
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn admin_kicks_users_and_closes_lobbies() -> anyhow::Result<()> {
+    let server = TestServer::start_admin(ADMIN_TOKEN).await;
+    let (mut host, host_joined) = server.join(None, "Host").await;
+    let (mut guest, guest_joined) = server.join(Some(host_joined.lobby_id), "Guest").await;
+    let lobby_path = format!("/lobbies/{}", host_joined.lobby_id);
+
+    let kick_path = format!("{lobby_path}/users/{}", guest_joined.user_id);
+    let (status, _) = server.admin_request("DELETE", &kick_path, Some(ADMIN_TOKEN), json!({ "reason": "Be nice" })).await;
+    assert_eq!(status, 204);
+    let reason = guest.receive_until(|socket_message| match socket_message {
+        SocketMessage::Kicked { reason } => Some(reason),
+        _ => None,
+    }).await;
+    assert_eq!(reason, "Be nice");
+    guest.expect_closed().await;
+    //Kicking someone who is not there is an error.
+    let (status, _) = server.admin_request("DELETE", &kick_path, Some(ADMIN_TOKEN), json!({ "reason": "Again" })).await;
+    assert_eq!(status, 404);
+
+    let (status, _) = server.admin_request("DELETE", &lobby_path, Some(ADMIN_TOKEN), json!({ "reason": "Closing time" })).await;
+    assert_eq!(status, 204);
+    let reason = host.receive_until(|socket_message| match socket_message {
+        SocketMessage::LobbyClosed { reason } => Some(reason),
+        _ => None,
+    }).await;
+    assert_eq!(reason, "Closing time");
+    host.expect_closed().await;
+    assert!(server.wait_for_removal(host_joined.lobby_id).await);
+    Ok(())
+}
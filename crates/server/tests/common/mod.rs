@@ -0,0 +1,157 @@
+//Each test binary uses a different part of the harness.
+#![allow(dead_code)]
+
+use futures::{SinkExt, StreamExt};
+use server::yahtzee::{self, YahtzeeState, lobby::{LobbyID, SocketMessage, UserID}, registry::{InMemoryRegistry, LobbyRegistry}};
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(5);
+//Paused time jumps to the next timer whenever the runtime looks idle, even while a message is still on its way through
+//a socket. Waiting in short slices against the real clock lets tests with long server-side pauses, such as computer
+//players thinking, run with `start_paused` and step through those pauses without timing out.
+const RECEIVE_SLICE: Duration = Duration::from_millis(10);
+
+//The yahtzee routes served over plain http on an ephemeral port, with a throwaway data directory.
+pub struct TestServer {
+    pub addr: SocketAddr,
+    pub state: YahtzeeState,
+    data_dir: PathBuf,
+}
+impl TestServer {
+    pub async fn start() -> Self {
+        Self::start_sharing(Arc::new(InMemoryRegistry::new())).await
+    }
+    //Several servers sharing a registry stand in for instances behind a load balancer.
+    pub async fn start_sharing(registry: Arc<dyn LobbyRegistry>) -> Self {
+        Self::start_with(registry, None).await
+    }
+    pub async fn start_admin(admin_token: &str) -> Self {
+        Self::start_with(Arc::new(InMemoryRegistry::new()), Some(admin_token)).await
+    }
+    //Save the lobbies like a shutdown does and start another server on the same data directory, as a redeploy would.
+    pub async fn restart(&self) -> Self {
+        self.state.shutdown().await.expect("Failed to save lobbies");
+        Self::start_in(self.data_dir.clone(), Arc::new(InMemoryRegistry::new()), None).await
+    }
+    async fn start_with(registry: Arc<dyn LobbyRegistry>, admin_token: Option<&str>) -> Self {
+        let data_dir = std::env::temp_dir().join(format!("yahtzee-test-{:016x}", rand::random::<u64>()));
+        Self::start_in(data_dir, registry, admin_token).await
+    }
+    async fn start_in(data_dir: PathBuf, registry: Arc<dyn LobbyRegistry>, admin_token: Option<&str>) -> Self {
+        let config = yahtzee::Config {
+            data_dir: data_dir.clone(),
+            admin_token: admin_token.map(str::to_string),
+            redis_url: None,
+            instance_id: None,
+            solver: false,
+        };
+        let state = yahtzee::state_with_registry(&config, registry).expect("Failed to create server state");
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("Failed to bind listener");
+        let addr = listener.local_addr().unwrap();
+        let app = server::app(state.clone());
+        tokio::spawn(async move {
+            let _ = axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await;
+        });
+        Self { addr, state, data_dir }
+    }
+    pub fn http_client(&self) -> httpc_test::Client {
+        httpc_test::new_client(format!("http://{}", self.addr)).expect("Failed to create http client")
+    }
+    //Send a request to the admin api, with a bearer token if given. Returns the status and the body.
+    pub async fn admin_request(&self, method: &str, path: &str, token: Option<&str>, body: serde_json::Value) -> (u16, String) {
+        let client = self.http_client();
+        let mut request = client.reqwest_client()
+            .request(method.parse().unwrap(), format!("http://{}/yahtzee/admin{path}", self.addr))
+            .header("content-type", "application/json")
+            .body(body.to_string());
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {token}"));
+        }
+        let response = request.send().await.expect("Failed to send admin request");
+        (response.status().as_u16(), response.text().await.unwrap_or_default())
+    }
+    pub async fn connect(&self, query: &str) -> TestClient {
+        let url = format!("ws://{}/yahtzee/ws?{query}", self.addr);
+        let (socket, _) = tokio_tungstenite::connect_async(url).await.expect("Failed to connect websocket");
+        TestClient { socket }
+    }
+    //Poll until the lobby is gone from this instance.
+    pub async fn wait_for_removal(&self, lobby_id: LobbyID) -> bool {
+        for _ in 0..50 {
+            if self.state.lobbies.list().await.iter().all(|lobby| lobby.lobby_id != lobby_id) {
+                return true
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        false
+    }
+    //Connect and wait for the lobby to accept the user.
+    pub async fn join(&self, lobby_id: Option<LobbyID>, name: &str) -> (TestClient, Joined) {
+        let query = match lobby_id {
+            Some(lobby_id) => format!("lobby_id={lobby_id}&name={name}"),
+            None => format!("name={name}"),
+        };
+        let mut client = self.connect(query.as_str()).await;
+        let joined = client.receive_until(|socket_message| match socket_message {
+            SocketMessage::ConnectSuccess { lobby_id, user_id, player_id, token, peers_id } => Some(Joined { lobby_id, user_id, player_id, token, peers_id }),
+            _ => None,
+        }).await;
+        (client, joined)
+    }
+}
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.data_dir);
+    }
+}
+
+pub struct Joined {
+    pub lobby_id: LobbyID,
+    pub user_id: UserID,
+    pub player_id: u64,
+    pub token: String,
+    pub peers_id: Vec<UserID>,
+}
+
+pub struct TestClient {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+impl TestClient {
+    pub async fn send(&mut self, socket_message: &SocketMessage) {
+        let socket_message_serialized = bincode::serialize(socket_message).unwrap();
+        self.socket.send(Message::Binary(socket_message_serialized.into())).await.expect("Failed to send message");
+    }
+    //Next message from the server, or None once the connection is closed.
+    pub async fn receive(&mut self) -> Option<SocketMessage> {
+        let started = std::time::Instant::now();
+        loop {
+            let Ok(message) = tokio::time::timeout(RECEIVE_SLICE, self.socket.next()).await else {
+                assert!(started.elapsed() < RECEIVE_TIMEOUT, "Timed out waiting for a message");
+                continue
+            };
+            match message {
+                Some(Ok(Message::Binary(socket_message_serialized))) => return Some(bincode::deserialize(&socket_message_serialized).expect("Failed to deserialize message")),
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return None,
+                Some(Ok(_)) => continue,
+            }
+        }
+    }
+    //Skip messages until one is picked out. Panics if the connection closes first.
+    pub async fn receive_until<T>(&mut self, mut pick: impl FnMut(SocketMessage) -> Option<T>) -> T {
+        loop {
+            let socket_message = self.receive().await.expect("Connection closed while waiting for a message");
+            if let Some(picked) = pick(socket_message) {
+                return picked
+            }
+        }
+    }
+    //Skip messages until the server closes the connection.
+    pub async fn expect_closed(&mut self) {
+        while self.receive().await.is_some() {}
+    }
+    pub async fn close(mut self) {
+        let _ = self.socket.close(None).await;
+    }
+}
@@ -0,0 +1,384 @@
+mod common;
+
+use common::{TestClient, TestServer};
+use server::yahtzee::{ai::AiDifficulty, history::HistoryFilter, lobby::{LobbyPhase, SdpType, SocketMessage, UserID}};
+use yahtzee_rules::{Action, GameState, MAX_DICE, RuleError, strategy::{Greedy, Strategy}};
+use server::yahtzee::registry::{InMemoryRegistry, LobbyRegistry};
+use std::{sync::Arc, time::Duration};
+
+#[tokio::test]
+async fn create_and_join_lobby() -> anyhow::Result<()> {
+    let server = TestServer::start().await;
+    let (_host, host_joined) = server.join(None, "Host").await;
+    assert!(host_joined.peers_id.is_empty());
+
+    let (mut guest, guest_joined) = server.join(Some(host_joined.lobby_id), "Guest").await;
+    assert_eq!(guest_joined.lobby_id, host_joined.lobby_id);
+    assert_eq!(guest_joined.peers_id, vec![host_joined.user_id]);
+
+    let (host_id, users) = guest.receive_until(|socket_message| match socket_message {
+        SocketMessage::LobbyState { host_id, users, .. } if users.len() == 2 => Some((host_id, users)),
+        _ => None,
+    }).await;
+    assert_eq!(host_id, host_joined.user_id);
+    assert!(users.iter().any(|user| user.user_id == guest_joined.user_id && user.display_name == "Guest"));
+
+    //Guests get a stored profile only once there is something to keep, so joining alone does not create one.
+    let response = server.http_client().do_get(format!("/yahtzee/api/profiles/{}", guest_joined.player_id).as_str()).await?;
+    assert_eq!(response.status(), 404);
+    Ok(())
+}
+
+#[tokio::test]
+async fn relay_handshake() -> anyhow::Result<()> {
+    let server = TestServer::start().await;
+    let (mut host, host_joined) = server.join(None, "Host").await;
+    let (mut guest, guest_joined) = server.join(Some(host_joined.lobby_id), "Guest").await;
+
+    guest.send(&SocketMessage::WebRtcHandshake {
+        source_id: guest_joined.user_id,
+        target_id: host_joined.user_id,
+        session_id: 1,
+        sdp_type: SdpType::Offer,
+        sdp_description: "offer".to_string(),
+        ice_candidates: vec![("candidate".to_string(), Some("0".to_string()), Some(0))],
+    }).await;
+    let (source_id, sdp_type, sdp_description) = host.receive_until(|socket_message| match socket_message {
+        SocketMessage::WebRtcHandshake { source_id, sdp_type, sdp_description, .. } => Some((source_id, sdp_type, sdp_description)),
+        _ => None,
+    }).await;
+    assert_eq!(source_id, guest_joined.user_id);
+    assert_eq!(sdp_type, SdpType::Offer);
+    assert_eq!(sdp_description, "offer");
+
+    host.send(&SocketMessage::WebRtcHandshake {
+        source_id: host_joined.user_id,
+        target_id: guest_joined.user_id,
+        session_id: 2,
+        sdp_type: SdpType::Answer,
+        sdp_description: "answer".to_string(),
+        ice_candidates: Vec::new(),
+    }).await;
+    let sdp_description = guest.receive_until(|socket_message| match socket_message {
+        SocketMessage::WebRtcHandshake { sdp_description, .. } => Some(sdp_description),
+        _ => None,
+    }).await;
+    assert_eq!(sdp_description, "answer");
+    Ok(())
+}
+
+#[tokio::test]
+async fn host_disconnect_passes_host() -> anyhow::Result<()> {
+    let server = TestServer::start().await;
+    let (host, host_joined) = server.join(None, "Host").await;
+    let (mut guest, guest_joined) = server.join(Some(host_joined.lobby_id), "Guest").await;
+
+    host.close().await;
+    let host_id = guest.receive_until(|socket_message| match socket_message {
+        SocketMessage::LobbyState { host_id, users, .. } if users.len() == 1 => Some(host_id),
+        _ => None,
+    }).await;
+    assert_eq!(host_id, guest_joined.user_id);
+    Ok(())
+}
+
+#[tokio::test]
+async fn empty_lobby_is_removed() -> anyhow::Result<()> {
+    let server = TestServer::start().await;
+    let (host, host_joined) = server.join(None, "Host").await;
+    let (guest, _) = server.join(Some(host_joined.lobby_id), "Guest").await;
+
+    host.close().await;
+    guest.close().await;
+    assert!(server.wait_for_removal(host_joined.lobby_id).await);
+
+    //Joining a removed lobby closes the websocket without accepting the user.
+    let mut late = server.connect(format!("lobby_id={}", host_joined.lobby_id).as_str()).await;
+    late.expect_closed().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn full_lobby_rejects_join() -> anyhow::Result<()> {
+    let server = TestServer::start().await;
+    let (_host, host_joined) = server.join(None, "Host").await;
+    let mut guests = Vec::new();
+    for index in 1..4 {
+        guests.push(server.join(Some(host_joined.lobby_id), format!("Guest{index}").as_str()).await);
+    }
+
+    let mut late = server.connect(format!("lobby_id={}", host_joined.lobby_id).as_str()).await;
+    let reason = late.receive_until(|socket_message| match socket_message {
+        SocketMessage::JoinRejected { reason } => Some(reason),
+        SocketMessage::ConnectSuccess { .. } => panic!("Joined a full lobby"),
+        _ => None,
+    }).await;
+    assert_eq!(reason, "Lobby is full");
+    late.expect_closed().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn token_keeps_player_id() -> anyhow::Result<()> {
+    let server = TestServer::start().await;
+    let (first, first_joined) = server.join(None, "Returning").await;
+    first.close().await;
+
+    let mut again = server.connect(format!("token={}", first_joined.token).as_str()).await;
+    let player_id = again.receive_until(|socket_message| match socket_message {
+        SocketMessage::ConnectSuccess { player_id, .. } => Some(player_id),
+        _ => None,
+    }).await;
+    assert_eq!(player_id, first_joined.player_id);
+    Ok(())
+}
+
+#[tokio::test]
+async fn lobbies_survive_a_restart() -> anyhow::Result<()> {
+    let server = TestServer::start().await;
+    let (mut host, host_joined) = server.join(None, "Host").await;
+    let (_guest, _) = server.join(Some(host_joined.lobby_id), "Guest").await;
+
+    let restarted = server.restart().await;
+    host.receive_until(|socket_message| match socket_message {
+        SocketMessage::ServerNotice { .. } => Some(()),
+        _ => None,
+    }).await;
+    assert_eq!(restarted.state.lobbies.list().await.len(), 1);
+
+    //The host reconnects with their token and gets their old seat back. The guest's seat is kept until they are back.
+    let query = format!("lobby_id={}&token={}", host_joined.lobby_id, host_joined.token);
+    let mut host = restarted.connect(query.as_str()).await;
+    let user_id = host.receive_until(|socket_message| match socket_message {
+        SocketMessage::ConnectSuccess { user_id, .. } => Some(user_id),
+        _ => None,
+    }).await;
+    assert_eq!(user_id, host_joined.user_id);
+    let lobby = restarted.state.lobbies.list().await.remove(0);
+    assert_eq!(lobby.users.iter().map(|user| user.user_id).collect::<Vec<_>>(), vec![host_joined.user_id]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn join_lobby_owned_by_another_instance() -> anyhow::Result<()> {
+    let registry = Arc::new(InMemoryRegistry::new());
+    let first = TestServer::start_sharing(registry.clone()).await;
+    let second = TestServer::start_sharing(registry.clone()).await;
+
+    //The lobby lives on the second instance, the guest connects to the first.
+    let (mut host, host_joined) = second.join(None, "Host").await;
+    let lobby_id = host_joined.lobby_id;
+    assert!(registry.owner(lobby_id).await.is_some());
+    assert!(first.state.lobbies.list().await.is_empty());
+
+    let (mut guest, guest_joined) = first.join(Some(lobby_id), "Guest").await;
+    assert_eq!(guest_joined.lobby_id, lobby_id);
+    assert_eq!(guest_joined.peers_id, vec![host_joined.user_id]);
+    assert!(first.state.lobbies.list().await.is_empty());
+
+    guest.send(&SocketMessage::SendChat { text: "hello".to_string() }).await;
+    let (user_id, text) = host.receive_until(|socket_message| match socket_message {
+        SocketMessage::Chat { user_id, text } => Some((user_id, text)),
+        _ => None,
+    }).await;
+    assert_eq!((user_id, text.as_str()), (guest_joined.user_id, "hello"));
+
+    host.send(&SocketMessage::SendChat { text: "welcome".to_string() }).await;
+    //The guest gets its own message back first.
+    let user_id = guest.receive_until(|socket_message| match socket_message {
+        SocketMessage::Chat { user_id, text } if text == "welcome" => Some(user_id),
+        _ => None,
+    }).await;
+    assert_eq!(user_id, host_joined.user_id);
+
+    //Once everyone left, the lobby is gone and so is its claim.
+    host.close().await;
+    guest.close().await;
+    assert!(second.wait_for_removal(lobby_id).await);
+    assert_eq!(registry.owner(lobby_id).await, None);
+    Ok(())
+}
+
+#[tokio::test]
+async fn spectators_cannot_act() -> anyhow::Result<()> {
+    let server = TestServer::start().await;
+    let (mut host, host_joined) = server.join(None, "Host").await;
+    let mut watcher = server.connect(format!("lobby_id={}&name=Watcher&spectate=true", host_joined.lobby_id).as_str()).await;
+    let watcher_id = watcher.receive_until(|socket_message| match socket_message {
+        SocketMessage::ConnectSuccess { user_id, .. } => Some(user_id),
+        _ => None,
+    }).await;
+
+    //Spectators can neither get ready nor start the game.
+    watcher.send(&SocketMessage::SetReady { ready: true }).await;
+    watcher.send(&SocketMessage::StartGame).await;
+    let users = host.receive_until(|socket_message| match socket_message {
+        SocketMessage::LobbyState { users, .. } if users.len() == 2 => Some(users),
+        _ => None,
+    }).await;
+    let watcher_user = users.iter().find(|user| user.user_id == watcher_id).unwrap();
+    assert!(watcher_user.spectator && !watcher_user.ready);
+
+    host.send(&SocketMessage::SetReady { ready: true }).await;
+    host.send(&SocketMessage::StartGame).await;
+    let state = watcher.receive_until(|socket_message| match socket_message {
+        SocketMessage::GameState { state } => Some(state),
+        _ => None,
+    }).await;
+    assert_eq!(state.players.len(), 1);
+
+    //Spectators see the game but their actions are turned down.
+    let roll = Action::Roll { held: [false; MAX_DICE] };
+    watcher.send(&SocketMessage::GameAction { action: roll }).await;
+    let error = watcher.receive_until(|socket_message| match socket_message {
+        SocketMessage::GameActionRejected { error } => Some(error),
+        _ => None,
+    }).await;
+    assert!(matches!(error, RuleError::NotYourTurn));
+    host.send(&SocketMessage::GameAction { action: roll }).await;
+    let rolls_left = watcher.receive_until(|socket_message| match socket_message {
+        SocketMessage::GameState { state } => Some(state.rolls_left),
+        _ => None,
+    }).await;
+    assert_eq!(rolls_left, state.rolls_left - 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn chat_is_capped_and_rate_limited() -> anyhow::Result<()> {
+    let server = TestServer::start().await;
+    let (mut host, host_joined) = server.join(None, "Host").await;
+    let (mut guest, guest_joined) = server.join(Some(host_joined.lobby_id), "Guest").await;
+
+    guest.send(&SocketMessage::SendChat { text: "a".repeat(500) }).await;
+    for number in 1..=10 {
+        guest.send(&SocketMessage::SendChat { text: number.to_string() }).await;
+    }
+    //The guest's requests are handled in order, so its ready state shows up after every chat message it sent.
+    guest.send(&SocketMessage::SetReady { ready: true }).await;
+    let mut chats = Vec::new();
+    loop {
+        match host.receive().await.expect("Connection closed while waiting for chat") {
+            SocketMessage::Chat { user_id, text } => {
+                assert_eq!(user_id, guest_joined.user_id);
+                chats.push(text);
+            }
+            SocketMessage::LobbyState { users, .. } if users.iter().any(|user| user.user_id == guest_joined.user_id && user.ready) => break,
+            _ => {}
+        }
+    }
+    //The long message is cut short, and only the first few messages of the burst get through.
+    assert_eq!(chats.len(), 5);
+    assert_eq!(chats[0].len(), 200);
+    assert_eq!(chats[1..], ["1", "2", "3", "4"]);
+    Ok(())
+}
+
+//Start a game of the host against a computer player and return the computer player's user id.
+async fn start_against_ai(host: &mut TestClient, difficulty: AiDifficulty) -> UserID {
+    host.send(&SocketMessage::AddAi { difficulty }).await;
+    let ai_id = host.receive_until(|socket_message| match socket_message {
+        SocketMessage::LobbyState { users, .. } => users.iter().find(|user| user.ai).map(|user| user.user_id),
+        _ => None,
+    }).await;
+    host.send(&SocketMessage::SetReady { ready: true }).await;
+    host.send(&SocketMessage::StartGame).await;
+    ai_id
+}
+
+//Play the host's turns until the computer player is up, and return that game state.
+async fn play_until_ai_turn(host: &mut TestClient, host_id: UserID, ai_id: UserID) -> GameState {
+    loop {
+        let state = host.receive_until(|socket_message| match socket_message {
+            SocketMessage::GameState { state } => Some(state),
+            _ => None,
+        }).await;
+        match state.current_player() {
+            Some(user_id) if user_id == ai_id => return state,
+            _ => host.send(&SocketMessage::GameAction { action: Greedy.choose(&state, host_id) }).await,
+        }
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn human_plays_a_full_game_against_expert_ai() -> anyhow::Result<()> {
+    let server = TestServer::start().await;
+    let (mut host, host_joined) = server.join(None, "Host").await;
+    let ai_id = start_against_ai(&mut host, AiDifficulty::Expert).await;
+
+    let state = loop {
+        let state = host.receive_until(|socket_message| match socket_message {
+            SocketMessage::GameState { state } => Some(state),
+            _ => None,
+        }).await;
+        if state.is_finished() {
+            break state
+        }
+        if state.current_player() == Some(host_joined.user_id) {
+            let action = Greedy.choose(&state, host_joined.user_id);
+            host.send(&SocketMessage::GameAction { action }).await;
+        }
+    };
+    let phase = host.receive_until(|socket_message| match socket_message {
+        SocketMessage::LobbyState { phase, .. } => Some(phase),
+        _ => None,
+    }).await;
+    assert_eq!(phase, LobbyPhase::Finished);
+    assert!(state.player(ai_id).is_some_and(|player| player.scorecard.total() > 0));
+
+    //Only the human's game counts towards the leaderboard.
+    let leaderboard = server.state.history.leaderboard(HistoryFilter::AllTime, 10);
+    assert_eq!(leaderboard.len(), 1);
+    assert_eq!(leaderboard[0].player_id, host_joined.player_id);
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn ai_turn_is_dropped_when_the_game_moves_on() -> anyhow::Result<()> {
+    let server = TestServer::start().await;
+    let (mut host, host_joined) = server.join(None, "Host").await;
+    let ai_id = start_against_ai(&mut host, AiDifficulty::Normal).await;
+
+    //The host goes first, then the computer player is up.
+    play_until_ai_turn(&mut host, host_joined.user_id, ai_id).await;
+
+    //Removing the computer player while it thinks hands the turn back to the host.
+    assert!(server.state.lobbies.kick(host_joined.lobby_id, ai_id, "Removed".to_string()).await);
+    let state = host.receive_until(|socket_message| match socket_message {
+        SocketMessage::GameState { state } => Some(state),
+        _ => None,
+    }).await;
+    assert_eq!(state.players.len(), 1);
+    assert_eq!(state.current_player(), Some(host_joined.user_id));
+
+    //The move it was thinking about is never played for the host.
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    host.send(&SocketMessage::GameAction { action: Action::Roll { held: [false; MAX_DICE] } }).await;
+    let state = host.receive_until(|socket_message| match socket_message {
+        SocketMessage::GameState { state } => Some(state),
+        _ => None,
+    }).await;
+    assert_eq!(state.rolls_left + 1, state.turn_rolls);
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn ai_turn_is_dropped_when_the_lobby_closes() -> anyhow::Result<()> {
+    let server = TestServer::start().await;
+    let (mut host, host_joined) = server.join(None, "Host").await;
+    let ai_id = start_against_ai(&mut host, AiDifficulty::Normal).await;
+    play_until_ai_turn(&mut host, host_joined.user_id, ai_id).await;
+
+    //Closing the lobby while the computer player thinks ends the lobby task. Its move has nowhere to go.
+    assert!(server.state.lobbies.close(host_joined.lobby_id, "Closed".to_string()));
+    let reason = host.receive_until(|socket_message| match socket_message {
+        SocketMessage::LobbyClosed { reason } => Some(reason),
+        _ => None,
+    }).await;
+    assert_eq!(reason, "Closed");
+    host.expect_closed().await;
+    assert!(server.wait_for_removal(host_joined.lobby_id).await);
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    assert!(server.state.lobbies.list().await.is_empty());
+    Ok(())
+}
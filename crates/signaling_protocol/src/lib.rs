@@ -1,3 +1,5 @@
+pub mod lobby;
+
 use serde::{Serialize, Deserialize};
 
 pub type RoomID = u64;
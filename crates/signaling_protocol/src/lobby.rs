@@ -0,0 +1,214 @@
+use serde::{Serialize, Deserialize};
+use yahtzee_rules::{Action, GameOptions, GameState, RuleError};
+
+//Wire types of the lobby websocket, shared by the server, the browser client and the bot.
+//Messages are bincode encoded, so variants are only ever added at the end.
+
+pub type LobbyID = u64;
+pub type UserID = u32;
+pub type PlayerID = u64;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LobbyPhase {
+    Waiting,
+    Starting,
+    InGame,
+    Finished,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct LobbySettings {
+    pub max_players: u8,
+    pub game: GameOptions,
+}
+impl Default for LobbySettings {
+    fn default() -> Self {
+        Self {
+            max_players: 4,
+            game: GameOptions::default(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LobbyUser {
+    pub user_id: UserID,
+    pub display_name: String,
+    pub ready: bool,
+    pub spectator: bool,
+    pub ai: bool,
+}
+
+//Computer players the host can seat in a lobby. The server decides how each one plays.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AiDifficulty {
+    Easy,
+    Normal,
+    Hard,
+    Expert,
+}
+impl AiDifficulty {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Easy => "Easy",
+            Self::Normal => "Normal",
+            Self::Hard => "Hard",
+            Self::Expert => "Expert",
+        }
+    }
+}
+
+//Whether a relayed WebRTC handshake carries an offer or the answer to one.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SdpType {
+    #[default]
+    Offer,
+    Answer,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub enum SocketMessage {
+    ConnectSuccess {
+        lobby_id: LobbyID,
+        user_id: UserID,
+        player_id: PlayerID,
+        token: String,
+        peers_id: Vec<UserID>,
+    },
+    WebRtcHandshake {
+        source_id: UserID,
+        target_id: UserID,
+        session_id: u32,
+        sdp_type: SdpType,
+        sdp_description: String,
+        ice_candidates: Vec<(String, Option<String>, Option<u16>)>,
+    },
+    Kicked {
+        reason: String,
+    },
+    LobbyClosed {
+        reason: String,
+    },
+    ServerNotice {
+        message: String,
+    },
+    JoinRejected {
+        reason: String,
+    },
+    //Client requests, validated by the lobby task.
+    SetReady {
+        ready: bool,
+    },
+    KickUser {
+        user_id: UserID,
+    },
+    TransferHost {
+        user_id: UserID,
+    },
+    SetSettings {
+        settings: LobbySettings,
+    },
+    StartGame,
+    GameAction {
+        action: Action,
+    },
+    //Lobby and game state, broadcast by the lobby task whenever it changes.
+    LobbyState {
+        host_id: UserID,
+        phase: LobbyPhase,
+        settings: LobbySettings,
+        users: Vec<LobbyUser>,
+    },
+    GameState {
+        state: GameState,
+    },
+    GameActionRejected {
+        error: RuleError,
+    },
+    SetSpectator {
+        user_id: UserID,
+        spectator: bool,
+    },
+    SendChat {
+        text: String,
+    },
+    Chat {
+        user_id: UserID,
+        text: String,
+    },
+    //Sent to quick-play clients while they wait in the matchmaking queue.
+    QueueStatus {
+        waiting: u8,
+        players: u8,
+    },
+    //Host request to seat a computer player.
+    AddAi {
+        difficulty: AiDifficulty,
+    },
+    //Ask for the best choices on your turn. Answered with a Hint.
+    RequestHint,
+    Hint {
+        choices: Vec<HintChoice>,
+    },
+}
+
+//A choice open to the player and the final total it is expected to lead to with optimal play.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct HintChoice {
+    pub action: Action,
+    pub expected_total: f32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yahtzee_rules::{Category, MAX_DICE};
+
+    fn round_trip(message: &SocketMessage) -> Vec<u8> {
+        let bytes = bincode::serialize(message).unwrap();
+        let decoded: SocketMessage = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(bincode::serialize(&decoded).unwrap(), bytes);
+        bytes
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let messages = [
+            SocketMessage::ConnectSuccess { lobby_id: 7, user_id: 2, player_id: 99, token: "token".into(), peers_id: vec![1, 3] },
+            SocketMessage::WebRtcHandshake {
+                source_id: 1,
+                target_id: 2,
+                session_id: 5,
+                sdp_type: SdpType::Answer,
+                sdp_description: "v=0".into(),
+                ice_candidates: vec![("candidate".into(), Some("0".into()), Some(0))],
+            },
+            SocketMessage::SetSettings { settings: LobbySettings::default() },
+            SocketMessage::StartGame,
+            SocketMessage::GameAction { action: Action::Roll { held: [true; MAX_DICE] } },
+            SocketMessage::GameAction { action: Action::Score(Category::Ones, 0) },
+            SocketMessage::LobbyState {
+                host_id: 1,
+                phase: LobbyPhase::InGame,
+                settings: LobbySettings::default(),
+                users: vec![LobbyUser { user_id: 1, display_name: "Host".into(), ready: true, spectator: false, ai: false }],
+            },
+            SocketMessage::GameActionRejected { error: RuleError::NotYourTurn },
+            SocketMessage::AddAi { difficulty: AiDifficulty::Expert },
+            SocketMessage::Hint { choices: vec![HintChoice { action: Action::Score(Category::Ones, 0), expected_total: 254.5 }] },
+        ];
+        for message in &messages {
+            round_trip(message);
+        }
+    }
+
+    //Older clients decode by variant index, so existing variants must keep theirs.
+    #[test]
+    fn variant_indices_are_stable() {
+        let index = |message: &SocketMessage| u32::from_le_bytes(round_trip(message)[..4].try_into().unwrap());
+        assert_eq!(index(&SocketMessage::ConnectSuccess { lobby_id: 0, user_id: 0, player_id: 0, token: String::new(), peers_id: Vec::new() }), 0);
+        assert_eq!(index(&SocketMessage::StartGame), 10);
+        assert_eq!(index(&SocketMessage::GameState { state: GameState::new([1, 2], GameOptions::default()) }), 13);
+        assert_eq!(index(&SocketMessage::RequestHint), 20);
+    }
+}
@@ -0,0 +1,33 @@
+pub type Result<T> = core::result::Result<T, NetworkError>;
+
+#[derive(Clone, Debug)]
+pub enum NetworkError {
+    //A browser call threw or its promise was rejected.
+    Js(String),
+    Serialize(String),
+    Deserialize(String),
+    //A session description came back without its SDP.
+    MissingSdp,
+    //The channel or socket is not open yet or is already closing.
+    NotOpen,
+    //The socket closed before this many queued messages could be sent.
+    Unsent(usize),
+    //A peer sent a frame claiming to be from someone else.
+    WrongSender(u32),
+}
+
+impl core::fmt::Display for NetworkError {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::result::Result<(), core::fmt::Error> {
+        write!(fmt, "{self:?}")
+    }
+}
+
+impl std::error::Error for NetworkError {}
+
+#[cfg(feature = "js")]
+impl From<wasm_bindgen::JsValue> for NetworkError {
+    fn from(value: wasm_bindgen::JsValue) -> Self {
+        Self::Js(value.as_string().unwrap_or_else(|| format!("{value:?}")))
+    }
+}
+
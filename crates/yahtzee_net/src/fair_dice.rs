@@ -0,0 +1,232 @@
+use std::collections::{BTreeMap, BTreeSet};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use crate::error::NetworkError;
+use crate::link::Delivery;
+use crate::transport::Transport;
+
+//Rounds kept below and accepted above the latest one we began. Older rounds are forgotten and messages for rounds
+//outside the window are ignored, so a peer cannot make us keep rounds without limit.
+const ROUND_WINDOW: u32 = 16;
+
+pub type Seed = [u8; 32];
+pub type Commitment = [u8; 32];
+
+//Binds a seed to its round and owner, so a commitment cannot be replayed by another peer or in another round.
+pub fn commitment(round: u32, user_id: u32, seed: &Seed) -> Commitment {
+    let mut hasher = Sha256::new();
+    hasher.update(round.to_le_bytes());
+    hasher.update(user_id.to_le_bytes());
+    hasher.update(seed);
+    hasher.finalize().into()
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub enum DiceMessage {
+    Commit { round: u32, commitment: Commitment },
+    //Sent once the commitments of every participant are in.
+    Reveal { round: u32, seed: Seed },
+}
+
+pub enum DiceEvent {
+    //Every remaining participant revealed its seed, see `FairDice::rolls`.
+    Ready(u32),
+    //This peer revealed a seed that does not match its commitment, committed twice, or left without revealing once
+    //every commitment was in, which would let it see the other seeds and pick whether its own counts. Its seed is left out.
+    Flagged { round: u32, user_id: u32 },
+    Error(NetworkError),
+}
+
+//Dice faces derived from the seeds of a round. Every peer that knows the seeds gets the same faces.
+pub struct Rolls {
+    combined: [u8; 32],
+    block: [u8; 32],
+    counter: u32,
+    index: usize,
+}
+impl Rolls {
+    pub fn new(round: u32, seeds: &BTreeMap<u32, Seed>) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(round.to_le_bytes());
+        for (user_id, seed) in seeds {
+            hasher.update(user_id.to_le_bytes());
+            hasher.update(seed);
+        }
+        Self { combined: hasher.finalize().into(), block: [0; 32], counter: 0, index: 32 }
+    }
+}
+impl Iterator for Rolls {
+    type Item = u8;
+    fn next(&mut self) -> Option<u8> {
+        loop {
+            if self.index == self.block.len() {
+                let mut hasher = Sha256::new();
+                hasher.update(self.combined);
+                hasher.update(self.counter.to_le_bytes());
+                self.block = hasher.finalize().into();
+                self.counter += 1;
+                self.index = 0;
+            }
+            let byte = self.block[self.index];
+            self.index += 1;
+            //252 is the largest multiple of 6 that fits in a byte; anything above would favour low faces.
+            if byte < 252 {
+                return Some(byte % 6 + 1)
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Round {
+    //Unknown until we begin the round, though messages for it may arrive earlier.
+    participants: Option<BTreeSet<u32>>,
+    seed: Option<Seed>,
+    commitments: BTreeMap<u32, Commitment>,
+    seeds: BTreeMap<u32, Seed>,
+    flagged: BTreeSet<u32>,
+    revealed: bool,
+    ready: bool,
+}
+
+//Commit-reveal dice between peers. For every round each participant commits to a random seed,
+//reveals it once all commitments are in, and the dice come from all the seeds combined. Nobody can
+//pick the outcome, since no seed can be changed after the others are known.
+pub struct FairDice {
+    user_id: u32,
+    rounds: BTreeMap<u32, Round>,
+    latest: Option<u32>,
+}
+impl FairDice {
+    pub fn new(user_id: u32) -> Self {
+        Self { user_id, rounds: BTreeMap::new(), latest: None }
+    }
+    //Start a round with the peers that roll it. We commit to a seed from `seed` if we are one of
+    //them, so no randomness is drawn for rounds we have no part in. Does nothing if the round already began.
+    pub fn begin<T: From<DiceMessage>>(&mut self, transport: &dyn Transport<T>, round: u32, participants: impl IntoIterator<Item = u32>, seed: impl FnOnce() -> Seed) -> Vec<DiceEvent> {
+        let mut events = Vec::new();
+        if self.latest.is_some_and(|latest| round.saturating_add(ROUND_WINDOW) <= latest) {
+            return events
+        }
+        if self.latest.is_none_or(|latest| round > latest) {
+            self.latest = Some(round);
+            self.rounds.retain(|&number, _| number.saturating_add(ROUND_WINDOW) > round && number <= round.saturating_add(ROUND_WINDOW));
+        }
+        let entry = self.rounds.entry(round).or_default();
+        if entry.participants.is_some() {
+            return events
+        }
+        let participants = participants.into_iter().filter(|user_id| !entry.flagged.contains(user_id)).collect::<BTreeSet<_>>();
+        if participants.contains(&self.user_id) {
+            let seed = seed();
+            let commitment = commitment(round, self.user_id, &seed);
+            entry.seed = Some(seed);
+            entry.commitments.insert(self.user_id, commitment);
+            if let Err(error) = transport.broadcast(&DiceMessage::Commit { round, commitment }.into(), Delivery::Reliable) {
+                events.push(DiceEvent::Error(error));
+            }
+        }
+        entry.participants = Some(participants);
+        self.advance(transport, round, &mut events);
+        events
+    }
+    pub fn receive<T: From<DiceMessage>>(&mut self, transport: &dyn Transport<T>, peer_id: u32, message: DiceMessage) -> Vec<DiceEvent> {
+        let mut events = Vec::new();
+        let (DiceMessage::Commit { round, .. } | DiceMessage::Reveal { round, .. }) = message;
+        if !self.in_window(round) {
+            return events
+        }
+        match message {
+            DiceMessage::Commit { round, commitment } => {
+                let entry = self.rounds.entry(round).or_default();
+                match entry.commitments.get(&peer_id) {
+                    Some(&previous) if previous != commitment => {
+                        if let Some(participants) = entry.participants.as_mut() {
+                            participants.remove(&peer_id);
+                        }
+                        if entry.flagged.insert(peer_id) {
+                            events.push(DiceEvent::Flagged { round, user_id: peer_id });
+                        }
+                    }
+                    _ => {
+                        entry.commitments.insert(peer_id, commitment);
+                    }
+                }
+                self.advance(transport, round, &mut events);
+            }
+            DiceMessage::Reveal { round, seed } => {
+                self.rounds.entry(round).or_default().seeds.entry(peer_id).or_insert(seed);
+                self.advance(transport, round, &mut events);
+            }
+        }
+        events
+    }
+    //A participant that left cannot reveal any more, so its open rounds go on without it. Leaving after every
+    //commitment is in but before revealing is flagged like a wrong reveal.
+    pub fn disconnect<T: From<DiceMessage>>(&mut self, transport: &dyn Transport<T>, peer_id: u32) -> Vec<DiceEvent> {
+        let mut events = Vec::new();
+        let mut open = Vec::new();
+        for (&number, round) in self.rounds.iter_mut().filter(|(_, round)| !round.ready) {
+            let Some(participants) = round.participants.as_mut() else {
+                continue
+            };
+            let committed = participants.iter().all(|user_id| round.commitments.contains_key(user_id));
+            if participants.remove(&peer_id) {
+                if committed && !round.seeds.contains_key(&peer_id) && round.flagged.insert(peer_id) {
+                    events.push(DiceEvent::Flagged { round: number, user_id: peer_id });
+                }
+                open.push(number);
+            }
+        }
+        for round in open {
+            self.advance(transport, round, &mut events);
+        }
+        events
+    }
+    pub fn rolls(&self, round: u32) -> Option<Rolls> {
+        let entry = self.rounds.get(&round).filter(|entry| entry.ready)?;
+        let participants = entry.participants.as_ref()?;
+        let seeds = entry.seeds.iter().filter(|(user_id, _)| participants.contains(user_id)).map(|(&user_id, &seed)| (user_id, seed)).collect();
+        Some(Rolls::new(round, &seeds))
+    }
+
+    //Before our first round any round may be the current one, but only a few are kept until we catch up.
+    fn in_window(&self, round: u32) -> bool {
+        match self.latest {
+            Some(latest) => round.saturating_add(ROUND_WINDOW) > latest && round <= latest.saturating_add(ROUND_WINDOW),
+            None => self.rounds.contains_key(&round) || self.rounds.len() < ROUND_WINDOW as usize,
+        }
+    }
+
+    fn advance<T: From<DiceMessage>>(&mut self, transport: &dyn Transport<T>, round: u32, events: &mut Vec<DiceEvent>) {
+        let Some(entry) = self.rounds.get_mut(&round) else {
+            return
+        };
+        let Some(participants) = entry.participants.as_mut() else {
+            return
+        };
+        //Commitments and reveals from one peer arrive in order, so a reveal without a commitment is as bad as a wrong one.
+        let cheaters = entry.seeds.iter()
+            .filter(|(user_id, seed)| participants.contains(user_id) && entry.commitments.get(user_id) != Some(&commitment(round, **user_id, seed)))
+            .map(|(&user_id, _)| user_id)
+            .collect::<Vec<_>>();
+        for user_id in cheaters {
+            participants.remove(&user_id);
+            entry.flagged.insert(user_id);
+            events.push(DiceEvent::Flagged { round, user_id });
+        }
+        if !entry.revealed
+            && participants.iter().all(|user_id| entry.commitments.contains_key(user_id))
+            && let Some(seed) = entry.seed.filter(|_| participants.contains(&self.user_id)) {
+            entry.revealed = true;
+            entry.seeds.insert(self.user_id, seed);
+            if let Err(error) = transport.broadcast(&DiceMessage::Reveal { round, seed }.into(), Delivery::Reliable) {
+                events.push(DiceEvent::Error(error));
+            }
+        }
+        if !entry.ready && participants.iter().all(|user_id| entry.seeds.contains_key(user_id)) {
+            entry.ready = true;
+            events.push(DiceEvent::Ready(round));
+        }
+    }
+}
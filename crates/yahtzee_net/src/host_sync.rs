@@ -0,0 +1,349 @@
+use std::collections::{BTreeMap, BTreeSet};
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use crate::error::NetworkError;
+use crate::link::Delivery;
+use crate::transport::Transport;
+
+//Milliseconds a new host waits for the remaining peers to report their state before carrying on without them.
+const TAKEOVER_TIMEOUT: f64 = 3000.0;
+//Intents held per peer until we can handle them. A peer only has a few in flight at a time.
+const MAX_DEFERRED_INTENTS: usize = 16;
+
+//State that one peer, the host, decides on for everyone.
+pub trait Authoritative: Clone + Serialize + DeserializeOwned {
+    type Intent: Clone + Serialize + DeserializeOwned;
+    type Rejection: Serialize + DeserializeOwned;
+    //Check and apply what a peer wants to do. Only the host calls this.
+    fn apply(&mut self, user_id: u32, intent: Self::Intent) -> Result<(), Self::Rejection>;
+    //A peer left for good. Returns whether the state changed.
+    fn remove_peer(&mut self, _user_id: u32) -> bool {
+        false
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "")]
+pub enum SyncMessage<S: Authoritative> {
+    //To the host. Numbered so an intent resent to a new host is not applied twice.
+    Intent { sequence: u32, intent: S::Intent },
+    //From the host: the state after `version` changes, and the last intent handled from each peer.
+    State { version: u32, state: S, handled: BTreeMap<u32, u32> },
+    //From the host, to the peer whose intent was refused.
+    Rejected { sequence: u32, rejection: S::Rejection },
+    //To a new host: the last state this peer acknowledged, so play continues from the latest one anyone has.
+    Resume { version: u32, state: S, handled: BTreeMap<u32, u32> },
+}
+
+pub enum SyncEvent<S: Authoritative> {
+    //The state changed, see `HostSync::state`.
+    State,
+    //One of our intents was refused.
+    Rejected(S::Rejection),
+    HostChanged(u32),
+    Error(NetworkError),
+}
+
+//Host-authoritative state shared over a transport. The connected peer with the lowest user id
+//hosts: it applies intents and broadcasts the result, the others only send intents. When the host
+//disconnects, the next lowest takes over from the latest state the remaining peers report.
+pub struct HostSync<S: Authoritative> {
+    user_id: u32,
+    host_id: u32,
+    peers: BTreeSet<u32>,
+    version: u32,
+    state: S,
+    handled: BTreeMap<u32, u32>,
+    sequence: u32,
+    //Our intents the host has not handled yet, resent if the host changes.
+    pending: Vec<(u32, S::Intent)>,
+    //While taking over as host, the peers that have not reported their state yet.
+    takeover: Option<BTreeSet<u32>>,
+    //When the takeover gives up on them, set on the first update after it began.
+    takeover_deadline: Option<f64>,
+    //Peers that left during the takeover, removed from the state once it is settled.
+    departed: Vec<u32>,
+    //Messages for the host that arrived before we were ready to handle them. Only the latest resume of each peer is
+    //kept, along with up to `MAX_DEFERRED_INTENTS` of its intents.
+    deferred: Vec<(u32, SyncMessage<S>)>,
+    //Hosts we stopped trusting. They are treated as gone for good, whatever they send.
+    deposed: BTreeSet<u32>,
+}
+impl<S: Authoritative> HostSync<S> {
+    //`members` are the users already there when we join, connected or not yet.
+    pub fn new(user_id: u32, members: impl IntoIterator<Item = u32>, state: S) -> Self {
+        Self {
+            user_id,
+            host_id: members.into_iter().fold(user_id, u32::min),
+            peers: BTreeSet::new(),
+            version: 0,
+            state,
+            handled: BTreeMap::new(),
+            sequence: 0,
+            pending: Vec::new(),
+            takeover: None,
+            takeover_deadline: None,
+            departed: Vec::new(),
+            deferred: Vec::new(),
+            deposed: BTreeSet::new(),
+        }
+    }
+    pub fn host_id(&self) -> u32 {
+        self.host_id
+    }
+    pub fn is_host(&self) -> bool {
+        self.host_id == self.user_id
+    }
+    pub fn state(&self) -> &S {
+        &self.state
+    }
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+    pub fn send_intent<T: From<SyncMessage<S>>>(&mut self, transport: &dyn Transport<T>, intent: S::Intent) -> Vec<SyncEvent<S>> {
+        let mut events = Vec::new();
+        self.sequence += 1;
+        self.pending.push((self.sequence, intent.clone()));
+        if !self.is_host() {
+            self.send(transport, self.host_id, SyncMessage::Intent { sequence: self.sequence, intent }, &mut events);
+        }
+        //During a takeover our own intents wait in `pending` like everyone else's.
+        else if self.takeover.is_none() {
+            self.handle_intent(transport, self.user_id, self.sequence, intent, &mut events);
+        }
+        events
+    }
+    //Change the state as the host, for changes no peer asks for. `change` returns whether it changed anything.
+    pub fn modify<T: From<SyncMessage<S>>>(&mut self, transport: &dyn Transport<T>, change: impl FnOnce(&mut S) -> bool) -> Vec<SyncEvent<S>> {
+        let mut events = Vec::new();
+        if self.is_host() && self.takeover.is_none() && change(&mut self.state) {
+            self.version += 1;
+            self.broadcast_state(transport, &mut events);
+            events.push(SyncEvent::State);
+        }
+        events
+    }
+    pub fn connect<T: From<SyncMessage<S>>>(&mut self, transport: &dyn Transport<T>, peer_id: u32) -> Vec<SyncEvent<S>> {
+        let mut events = Vec::new();
+        if self.deposed.contains(&peer_id) {
+            return events
+        }
+        self.peers.insert(peer_id);
+        if self.is_host() && self.takeover.is_none() {
+            let state = self.state_message();
+            self.send(transport, peer_id, state, &mut events);
+        }
+        events
+    }
+    pub fn disconnect<T: From<SyncMessage<S>>>(&mut self, transport: &dyn Transport<T>, peer_id: u32) -> Vec<SyncEvent<S>> {
+        let mut events = Vec::new();
+        //The host counts as gone even if we never managed to connect to it.
+        if !self.peers.remove(&peer_id) && peer_id != self.host_id {
+            return events
+        }
+        if peer_id == self.host_id {
+            self.host_id = self.peers.first().copied().unwrap_or(self.user_id).min(self.user_id);
+            events.push(SyncEvent::HostChanged(self.host_id));
+            if self.is_host() {
+                self.takeover = Some(self.peers.clone());
+                self.departed.push(peer_id);
+                self.replay_deferred(transport, &mut events);
+                self.try_finish_takeover(transport, &mut events);
+            }
+            else {
+                let resume = SyncMessage::Resume { version: self.version, state: self.state.clone(), handled: self.handled.clone() };
+                self.send(transport, self.host_id, resume, &mut events);
+                for (sequence, intent) in self.pending.clone() {
+                    self.send(transport, self.host_id, SyncMessage::Intent { sequence, intent }, &mut events);
+                }
+            }
+        }
+        else if self.is_host() {
+            if let Some(waiting) = self.takeover.as_mut() {
+                waiting.remove(&peer_id);
+                self.departed.push(peer_id);
+                self.try_finish_takeover(transport, &mut events);
+            }
+            else if self.state.remove_peer(peer_id) {
+                self.version += 1;
+                self.broadcast_state(transport, &mut events);
+                events.push(SyncEvent::State);
+            }
+        }
+        events
+    }
+    //Give up on peers that did not report their state in time during a takeover. Call every frame.
+    pub fn update<T: From<SyncMessage<S>>>(&mut self, transport: &dyn Transport<T>, time: f64) -> Vec<SyncEvent<S>> {
+        let mut events = Vec::new();
+        let Some(waiting) = self.takeover.as_mut() else {
+            return events
+        };
+        if time >= *self.takeover_deadline.get_or_insert(time + TAKEOVER_TIMEOUT) {
+            waiting.clear();
+            self.try_finish_takeover(transport, &mut events);
+        }
+        events
+    }
+    //Drop a host that broke the rules, as if it left, and never follow it again. Peers that catch it
+    //the same way agree on the next host, which takes over from the state they report.
+    pub fn depose<T: From<SyncMessage<S>>>(&mut self, transport: &dyn Transport<T>) -> Vec<SyncEvent<S>> {
+        if self.is_host() {
+            return Vec::new()
+        }
+        self.deposed.insert(self.host_id);
+        self.disconnect(transport, self.host_id)
+    }
+    pub fn receive<T: From<SyncMessage<S>>>(&mut self, transport: &dyn Transport<T>, peer_id: u32, message: SyncMessage<S>) -> Vec<SyncEvent<S>> {
+        let mut events = Vec::new();
+        if !self.deposed.contains(&peer_id) {
+            self.handle_message(transport, peer_id, message, &mut events);
+        }
+        events
+    }
+
+    fn handle_message<T: From<SyncMessage<S>>>(&mut self, transport: &dyn Transport<T>, peer_id: u32, message: SyncMessage<S>, events: &mut Vec<SyncEvent<S>>) {
+        match message {
+            SyncMessage::Intent { sequence, intent } => {
+                //A peer may see the old host leave before we do, or send intents while we settle the state.
+                if !self.is_host() || self.takeover.is_some() {
+                    self.defer(peer_id, SyncMessage::Intent { sequence, intent });
+                }
+                else {
+                    self.handle_intent(transport, peer_id, sequence, intent, events);
+                }
+            }
+            SyncMessage::State { version, state, handled } => {
+                if peer_id == self.host_id && version >= self.version {
+                    let last = handled.get(&self.user_id).copied().unwrap_or(0);
+                    self.pending.retain(|&(sequence, _)| sequence > last);
+                    self.version = version;
+                    self.state = state;
+                    self.handled = handled;
+                    events.push(SyncEvent::State);
+                }
+            }
+            SyncMessage::Rejected { sequence, rejection } => {
+                if peer_id == self.host_id {
+                    self.pending.retain(|&(pending, _)| pending != sequence);
+                    events.push(SyncEvent::Rejected(rejection));
+                }
+            }
+            SyncMessage::Resume { version, state, handled } => {
+                if !self.is_host() {
+                    self.defer(peer_id, SyncMessage::Resume { version, state, handled });
+                    return
+                }
+                //Versions all count changes made by the old host, so the highest one is the latest state.
+                if version > self.version {
+                    self.version = version;
+                    self.state = state;
+                    self.handled = handled;
+                    events.push(SyncEvent::State);
+                    if self.takeover.is_none() {
+                        self.broadcast_state(transport, events);
+                    }
+                }
+                if let Some(waiting) = self.takeover.as_mut() {
+                    waiting.remove(&peer_id);
+                    self.try_finish_takeover(transport, events);
+                }
+            }
+        }
+    }
+    fn defer(&mut self, peer_id: u32, message: SyncMessage<S>) {
+        let intents = self.deferred.iter().filter(|(from, message)| *from == peer_id && matches!(message, SyncMessage::Intent { .. })).count();
+        match message {
+            SyncMessage::Resume { .. } => self.deferred.retain(|(from, message)| *from != peer_id || !matches!(message, SyncMessage::Resume { .. })),
+            SyncMessage::Intent { .. } if intents >= MAX_DEFERRED_INTENTS => return,
+            _ => {}
+        }
+        self.deferred.push((peer_id, message));
+    }
+    fn handle_intent<T: From<SyncMessage<S>>>(&mut self, transport: &dyn Transport<T>, peer_id: u32, sequence: u32, intent: S::Intent, events: &mut Vec<SyncEvent<S>>) {
+        if self.handled.get(&peer_id).is_some_and(|&last| sequence <= last) {
+            return
+        }
+        self.handled.insert(peer_id, sequence);
+        if peer_id == self.user_id {
+            self.pending.retain(|&(pending, _)| pending != sequence);
+        }
+        match self.state.apply(peer_id, intent) {
+            Ok(()) => {
+                self.version += 1;
+                self.broadcast_state(transport, events);
+                events.push(SyncEvent::State);
+            }
+            Err(rejection) if peer_id == self.user_id => events.push(SyncEvent::Rejected(rejection)),
+            Err(rejection) => self.send(transport, peer_id, SyncMessage::Rejected { sequence, rejection }, events),
+        }
+    }
+    //Once every remaining peer reported its state or the wait timed out, remove the peers that left and carry on.
+    fn try_finish_takeover<T: From<SyncMessage<S>>>(&mut self, transport: &dyn Transport<T>, events: &mut Vec<SyncEvent<S>>) {
+        if self.takeover.as_ref().is_none_or(|waiting| !waiting.is_empty()) {
+            return
+        }
+        self.takeover = None;
+        self.takeover_deadline = None;
+        for peer_id in std::mem::take(&mut self.departed) {
+            self.state.remove_peer(peer_id);
+        }
+        self.version += 1;
+        self.broadcast_state(transport, events);
+        events.push(SyncEvent::State);
+        let last = self.handled.get(&self.user_id).copied().unwrap_or(0);
+        for (sequence, intent) in self.pending.clone().into_iter().filter(|&(sequence, _)| sequence > last) {
+            self.handle_intent(transport, self.user_id, sequence, intent, events);
+        }
+        self.replay_deferred(transport, events);
+    }
+    fn replay_deferred<T: From<SyncMessage<S>>>(&mut self, transport: &dyn Transport<T>, events: &mut Vec<SyncEvent<S>>) {
+        for (peer_id, message) in std::mem::take(&mut self.deferred) {
+            self.handle_message(transport, peer_id, message, events);
+        }
+    }
+    fn state_message(&self) -> SyncMessage<S> {
+        SyncMessage::State { version: self.version, state: self.state.clone(), handled: self.handled.clone() }
+    }
+    fn broadcast_state<T: From<SyncMessage<S>>>(&self, transport: &dyn Transport<T>, events: &mut Vec<SyncEvent<S>>) {
+        if let Err(error) = transport.broadcast(&self.state_message().into(), Delivery::Reliable) {
+            events.push(SyncEvent::Error(error));
+        }
+    }
+    fn send<T: From<SyncMessage<S>>>(&self, transport: &dyn Transport<T>, peer_id: u32, message: SyncMessage<S>, events: &mut Vec<SyncEvent<S>>) {
+        if let Err(error) = transport.send(peer_id, &message.into(), Delivery::Reliable) {
+            events.push(SyncEvent::Error(error));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loopback::{Conditions, LoopbackNetwork};
+
+    #[derive(Serialize, Deserialize, Clone, Default)]
+    struct Count(u32);
+    impl Authoritative for Count {
+        type Intent = ();
+        type Rejection = ();
+        fn apply(&mut self, _user_id: u32, _intent: ()) -> Result<(), ()> {
+            self.0 += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn deferred_messages_are_bounded() {
+        let network = LoopbackNetwork::<SyncMessage<Count>>::new(Conditions::default());
+        let transport = network.join(2, 0.0, |_| {});
+        let mut sync = HostSync::new(2, [1, 3], Count::default());
+        for sequence in 1..=100 {
+            sync.receive(&transport, 3, SyncMessage::Intent { sequence, intent: () });
+        }
+        for version in 1..=10 {
+            sync.receive(&transport, 3, SyncMessage::Resume { version, state: Count(version), handled: BTreeMap::new() });
+            sync.receive(&transport, 4, SyncMessage::Resume { version, state: Count(version), handled: BTreeMap::new() });
+        }
+        assert_eq!(sync.deferred.len(), MAX_DEFERRED_INTENTS + 2);
+        assert!(sync.deferred.iter().all(|(_, message)| !matches!(message, SyncMessage::Resume { version, .. } if *version != 10)));
+    }
+}
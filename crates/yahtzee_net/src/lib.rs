@@ -0,0 +1,19 @@
+//Peer to peer networking shared by the browser client and native tests: message framing, the
+//transport and signaling traits the game talks to, an in-memory loopback implementation,
+//host-authoritative state sync, commit-reveal dice and the Yahtzee game peers play over them.
+pub mod error;
+pub mod link;
+pub mod transport;
+pub mod signaling;
+pub mod loopback;
+pub mod host_sync;
+pub mod fair_dice;
+pub mod peer_game;
+
+pub use error::{NetworkError, Result};
+pub use link::{Delivery, Timing};
+pub use transport::{ConnectionStats, PeerHandshake, PeerNetworkEvent, PeerStats, SdpType, Transport};
+pub use signaling::{ReadyState, SignalingChannel, WebSocketEvent};
+pub use host_sync::{Authoritative, HostSync, SyncEvent, SyncMessage};
+pub use fair_dice::{DiceEvent, DiceMessage, FairDice, Rolls, Seed};
+pub use peer_game::{PeerGame, PeerIntent, PeerRejection};
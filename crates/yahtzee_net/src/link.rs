@@ -0,0 +1,305 @@
+use std::collections::{BTreeMap, VecDeque};
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use crate::error::{NetworkError, Result};
+
+//Milliseconds to wait for an acknowledgement before sending a reliable frame again.
+const RETRANSMIT_INTERVAL: f64 = 500.0;
+//Milliseconds between pings measuring round trip time and clock offset.
+const PING_INTERVAL: f64 = 1000.0;
+//Recent pings kept for the loss and clock offset estimates.
+const PING_WINDOW: usize = 16;
+//Reliable frames further than this ahead of the next one to deliver are dropped rather than held, so a misbehaving
+//peer cannot grow the buffer without limit. The sender retransmits them until they fit.
+const RECEIVE_WINDOW: u32 = 256;
+//Weight of a new round trip sample in the smoothed round trip time.
+const RTT_SMOOTHING: f64 = 0.125;
+
+//Whether sequence `a` comes after `b`. Sequences wrap around, so anything less than half the range ahead counts as after.
+fn is_after(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+//Every message between peers travels in a frame, whether sent to one peer or broadcast.
+#[derive(Serialize, Deserialize)]
+pub struct Frame<T> {
+    pub sender_id: u32,
+    //Position in the sender's sequence for this peer. Reliable and unreliable frames count separately.
+    pub sequence: u32,
+    pub kind: FrameKind<T>,
+}
+
+impl<T: DeserializeOwned> Frame<T> {
+    //Read a frame that arrived on a channel to `peer_id`. Frames claiming another sender are rejected.
+    pub fn decode(peer_id: u32, data: &[u8]) -> Result<Self> {
+        let frame = bincode::deserialize::<Self>(data).map_err(|error| NetworkError::Deserialize(error.to_string()))?;
+        if frame.sender_id != peer_id {
+            return Err(NetworkError::WrongSender(frame.sender_id))
+        }
+        Ok(frame)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum FrameKind<T> {
+    //Retransmitted until acknowledged and delivered exactly once, in order.
+    Reliable(T),
+    //Sent once. Frames arriving after a newer one are dropped.
+    Unreliable(T),
+    //Every reliable frame before `sequence` has arrived.
+    Ack,
+    //Timing probe in the sender's clock, answered right away with a pong of the same sequence.
+    Ping { sent_at: f64 },
+    //Answer to a ping with when it arrived in the answering peer's clock. The pong leaves at once,
+    //so the same time stands for when it was sent.
+    Pong { ping_sent_at: f64, answered_at: f64 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Delivery {
+    Reliable,
+    Unreliable,
+}
+
+//An encoded frame to send back and the lane it goes on.
+pub type Reply = (Delivery, Vec<u8>);
+
+//Round trip time and clock offset to a peer in milliseconds, measured with pings.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Timing {
+    pub rtt: Option<f64>,
+    //Add to a local time to get the peer's time.
+    pub clock_offset: Option<f64>,
+    //Share of recent pings that went unanswered.
+    pub packet_loss: Option<f64>,
+}
+
+struct PingSample {
+    sequence: u32,
+    sent_at: f64,
+    //Round trip time and clock offset, once answered.
+    answer: Option<(f64, f64)>,
+}
+
+//Sequencing and acknowledgement state for one peer, independent of how frames are carried.
+pub struct Link<T> {
+    user_id: u32,
+    next_reliable: u32,
+    next_unreliable: u32,
+    //Encoded reliable frames waiting for an ack, with when they were last sent.
+    unacked: BTreeMap<u32, (Vec<u8>, f64)>,
+    //Next reliable sequence to deliver and frames that arrived ahead of it, at most `RECEIVE_WINDOW` of them.
+    next_expected: u32,
+    pending: BTreeMap<u32, T>,
+    newest_unreliable: Option<u32>,
+    next_ping: u32,
+    pings: VecDeque<PingSample>,
+    rtt: Option<f64>,
+}
+impl<T: Serialize + DeserializeOwned> Link<T> {
+    pub fn new(user_id: u32) -> Self {
+        Self {
+            user_id,
+            next_reliable: 0,
+            next_unreliable: 0,
+            unacked: BTreeMap::new(),
+            next_expected: 0,
+            pending: BTreeMap::new(),
+            newest_unreliable: None,
+            next_ping: 0,
+            pings: VecDeque::new(),
+            rtt: None,
+        }
+    }
+    //Frame a message for this peer. Reliable frames are kept until acknowledged.
+    pub fn encode(&mut self, message: &T, delivery: Delivery, time: f64) -> Result<Vec<u8>> {
+        let (sequence, kind) = match delivery {
+            Delivery::Reliable => (&mut self.next_reliable, FrameKind::Reliable(message)),
+            Delivery::Unreliable => (&mut self.next_unreliable, FrameKind::Unreliable(message)),
+        };
+        let frame = Frame { sender_id: self.user_id, sequence: *sequence, kind };
+        *sequence = sequence.wrapping_add(1);
+        let encoded = bincode::serialize(&frame).map_err(|error| NetworkError::Serialize(error.to_string()))?;
+        if delivery == Delivery::Reliable {
+            self.unacked.insert(frame.sequence, (encoded.clone(), time));
+        }
+        Ok(encoded)
+    }
+    //Handle a frame from this peer that arrived at `time`. Returns the messages now ready for the game,
+    //in order, and an ack or pong to send back if the frame needs one.
+    pub fn receive(&mut self, frame: Frame<T>, time: f64) -> Result<(Vec<T>, Option<Reply>)> {
+        let mut messages = Vec::new();
+        match frame.kind {
+            FrameKind::Reliable(message) => {
+                //Anything before the next expected frame is a retransmit of something already delivered.
+                //Sequences wrap around, so those come out far ahead along with frames beyond the window.
+                if frame.sequence.wrapping_sub(self.next_expected) < RECEIVE_WINDOW {
+                    self.pending.entry(frame.sequence).or_insert(message);
+                }
+                while let Some(message) = self.pending.remove(&self.next_expected) {
+                    messages.push(message);
+                    self.next_expected = self.next_expected.wrapping_add(1);
+                }
+                let ack = Frame::<T> { sender_id: self.user_id, sequence: self.next_expected, kind: FrameKind::Ack };
+                let ack = bincode::serialize(&ack).map_err(|error| NetworkError::Serialize(error.to_string()))?;
+                return Ok((messages, Some((Delivery::Reliable, ack))))
+            }
+            FrameKind::Unreliable(message) => {
+                if self.newest_unreliable.is_none_or(|newest| is_after(frame.sequence, newest)) {
+                    self.newest_unreliable = Some(frame.sequence);
+                    messages.push(message);
+                }
+            }
+            FrameKind::Ack => {
+                self.unacked.retain(|&sequence, _| !is_after(frame.sequence, sequence));
+            }
+            FrameKind::Ping { sent_at } => {
+                let pong = Frame::<T> { sender_id: self.user_id, sequence: frame.sequence, kind: FrameKind::Pong { ping_sent_at: sent_at, answered_at: time } };
+                let pong = bincode::serialize(&pong).map_err(|error| NetworkError::Serialize(error.to_string()))?;
+                return Ok((messages, Some((Delivery::Unreliable, pong))))
+            }
+            FrameKind::Pong { ping_sent_at, answered_at } => {
+                if let Some(ping) = self.pings.iter_mut().find(|ping| ping.sequence == frame.sequence && ping.answer.is_none()) {
+                    let rtt = time - ping_sent_at;
+                    //NTP style: the answer was taken halfway through the round trip.
+                    let clock_offset = answered_at - (ping_sent_at + time) / 2.0;
+                    ping.answer = Some((rtt, clock_offset));
+                    self.rtt = Some(self.rtt.map_or(rtt, |smoothed| smoothed + (rtt - smoothed) * RTT_SMOOTHING));
+                }
+            }
+        }
+        Ok((messages, None))
+    }
+    //A ping frame if one is due, to be sent on the unreliable lane.
+    pub fn ping(&mut self, time: f64) -> Result<Option<Vec<u8>>> {
+        if self.pings.back().is_some_and(|ping| time - ping.sent_at < PING_INTERVAL) {
+            return Ok(None)
+        }
+        let frame = Frame::<T> { sender_id: self.user_id, sequence: self.next_ping, kind: FrameKind::Ping { sent_at: time } };
+        let encoded = bincode::serialize(&frame).map_err(|error| NetworkError::Serialize(error.to_string()))?;
+        if self.pings.len() == PING_WINDOW {
+            self.pings.pop_front();
+        }
+        self.pings.push_back(PingSample { sequence: self.next_ping, sent_at: time, answer: None });
+        self.next_ping = self.next_ping.wrapping_add(1);
+        Ok(Some(encoded))
+    }
+    pub fn timing(&self) -> Timing {
+        //The newest ping may still be on its way.
+        let settled = self.pings.len().saturating_sub(1);
+        let lost = self.pings.iter().take(settled).filter(|ping| ping.answer.is_none()).count();
+        //The fastest round trip was delayed least on either way, giving the best offset estimate.
+        let clock_offset = self.pings.iter()
+            .filter_map(|ping| ping.answer)
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, clock_offset)| clock_offset);
+        Timing {
+            rtt: self.rtt,
+            clock_offset,
+            packet_loss: (settled > 0).then(|| lost as f64 / settled as f64),
+        }
+    }
+    //Reliable frames whose ack is overdue, to be sent again.
+    pub fn due(&mut self, time: f64) -> Vec<Vec<u8>> {
+        self.unacked.values_mut().filter(|(_, sent_at)| time - *sent_at >= RETRANSMIT_INTERVAL).map(|(encoded, sent_at)| {
+            *sent_at = time;
+            encoded.clone()
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reliable(sequence: u32, message: u32) -> Frame<u32> {
+        Frame { sender_id: 2, sequence, kind: FrameKind::Reliable(message) }
+    }
+    fn acked(reply: Option<Reply>) -> u32 {
+        let (delivery, ack) = reply.unwrap();
+        assert_eq!(delivery, Delivery::Reliable);
+        let ack = Frame::<u32>::decode(1, &ack).unwrap();
+        assert!(matches!(ack.kind, FrameKind::Ack));
+        ack.sequence
+    }
+    //A link that has already delivered reliable frames up to just before `next_expected`.
+    fn link_expecting(next_expected: u32) -> Link<u32> {
+        Link { next_expected, ..Link::new(1) }
+    }
+
+    #[test]
+    fn reordered_frames_are_delivered_in_order() {
+        let mut link = link_expecting(0);
+        let (messages, reply) = link.receive(reliable(1, 11), 0.0).unwrap();
+        assert!(messages.is_empty());
+        assert_eq!(acked(reply), 0);
+        let (messages, reply) = link.receive(reliable(0, 10), 0.0).unwrap();
+        assert_eq!(messages, [10, 11]);
+        assert_eq!(acked(reply), 2);
+    }
+
+    #[test]
+    fn duplicates_are_delivered_once() {
+        let mut link = link_expecting(0);
+        assert_eq!(link.receive(reliable(0, 10), 0.0).unwrap().0, [10]);
+        let (messages, reply) = link.receive(reliable(0, 10), 0.0).unwrap();
+        assert!(messages.is_empty());
+        assert_eq!(acked(reply), 1);
+
+        link.receive(reliable(2, 12), 0.0).unwrap();
+        assert!(link.receive(reliable(2, 12), 0.0).unwrap().0.is_empty());
+        assert_eq!(link.receive(reliable(1, 11), 0.0).unwrap().0, [11, 12]);
+    }
+
+    #[test]
+    fn sequences_wrap_around() {
+        let mut link = link_expecting(u32::MAX - 1);
+        assert!(link.receive(reliable(0, 2), 0.0).unwrap().0.is_empty());
+        assert!(link.receive(reliable(u32::MAX, 1), 0.0).unwrap().0.is_empty());
+        let (messages, reply) = link.receive(reliable(u32::MAX - 1, 0), 0.0).unwrap();
+        assert_eq!(messages, [0, 1, 2]);
+        assert_eq!(acked(reply), 1);
+        //Frames from before the wrap are retransmits of what was already delivered.
+        assert!(link.receive(reliable(u32::MAX, 1), 0.0).unwrap().0.is_empty());
+
+        let unreliable = |sequence, message| Frame { sender_id: 2, sequence, kind: FrameKind::Unreliable(message) };
+        assert_eq!(link.receive(unreliable(u32::MAX, 3), 0.0).unwrap().0, [3]);
+        assert_eq!(link.receive(unreliable(0, 4), 0.0).unwrap().0, [4]);
+        assert!(link.receive(unreliable(u32::MAX, 3), 0.0).unwrap().0.is_empty());
+    }
+
+    #[test]
+    fn frames_beyond_the_window_are_dropped() {
+        let mut link = link_expecting(u32::MAX);
+        let last = RECEIVE_WINDOW - 2;
+        assert!(link.receive(reliable(last, 1), 0.0).unwrap().0.is_empty());
+        assert!(link.receive(reliable(last + 1, 2), 0.0).unwrap().0.is_empty());
+        assert_eq!(link.pending.len(), 1);
+        assert_eq!(acked(link.receive(reliable(last + 1000, 3), 0.0).unwrap().1), u32::MAX);
+
+        //Once the gap is filled, a retransmit of a dropped frame is taken.
+        for sequence in (u32::MAX..=u32::MAX).chain(0..last) {
+            link.receive(reliable(sequence, 0), 0.0).unwrap();
+        }
+        assert_eq!(link.receive(reliable(last + 1, 2), 0.0).unwrap().0, [2]);
+        assert!(link.pending.is_empty());
+    }
+
+    #[test]
+    fn unacked_frames_are_sent_again_until_acked() {
+        let mut sender = Link::<u32> { next_reliable: u32::MAX, ..Link::new(2) };
+        let mut receiver = link_expecting(u32::MAX);
+        let first = sender.encode(&1, Delivery::Reliable, 0.0).unwrap();
+        sender.encode(&2, Delivery::Reliable, 0.0).unwrap();
+        assert!(sender.due(RETRANSMIT_INTERVAL - 1.0).is_empty());
+        assert_eq!(sender.due(RETRANSMIT_INTERVAL).len(), 2);
+
+        //Only the first frame gets through, so only the second is still due after the ack.
+        let (messages, reply) = receiver.receive(Frame::decode(2, &first).unwrap(), 0.0).unwrap();
+        assert_eq!(messages, [1]);
+        let ack = Frame::decode(1, &reply.unwrap().1).unwrap();
+        sender.receive(ack, 0.0).unwrap();
+        let due = sender.due(RETRANSMIT_INTERVAL * 2.0);
+        assert_eq!(due.len(), 1);
+        assert_eq!(receiver.receive(Frame::decode(2, &due[0]).unwrap(), 0.0).unwrap().0, [2]);
+    }
+}
@@ -0,0 +1,307 @@
+use std::{rc::Rc, cell::RefCell, collections::BTreeMap};
+use serde::{Serialize, de::DeserializeOwned};
+use crate::error::{NetworkError, Result};
+use crate::link::{Delivery, Frame, Link, Timing};
+use crate::signaling::{ReadyState, SignalingChannel, WebSocketEvent};
+use crate::transport::{PeerHandshake, PeerNetworkEvent, Transport};
+
+type EventCallback<E> = Rc<RefCell<dyn FnMut(E)>>;
+
+//What happens to frames between loopback peers. Applies to both lanes, the link resends lost reliable frames.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Conditions {
+    //Milliseconds every frame takes, plus up to `jitter` more.
+    pub latency: f64,
+    pub jitter: f64,
+    //Chance that a frame is dropped.
+    pub loss: f64,
+}
+
+struct InFlight {
+    //Network time, before the receiver's clock skew is applied.
+    arrives_at: f64,
+    from: u32,
+    to: u32,
+    data: Vec<u8>,
+}
+
+struct Endpoint<T> {
+    //Added to network time to get this peer's clock.
+    clock_skew: f64,
+    //Local time of the last update.
+    time: f64,
+    links: BTreeMap<u32, Link<T>>,
+    event_callback: EventCallback<PeerNetworkEvent<T>>,
+}
+
+struct Medium<T> {
+    conditions: Conditions,
+    random_state: u64,
+    in_flight: Vec<InFlight>,
+    endpoints: BTreeMap<u32, Endpoint<T>>,
+}
+impl<T> Medium<T> {
+    //Xorshift with a fixed seed. Tests need repeatable runs more than good randomness.
+    fn random(&mut self) -> f64 {
+        self.random_state ^= self.random_state << 13;
+        self.random_state ^= self.random_state >> 7;
+        self.random_state ^= self.random_state << 17;
+        (self.random_state >> 11) as f64 / (1u64 << 53) as f64
+    }
+    //Put a frame on its way, unless it is lost.
+    fn transmit(&mut self, from: u32, to: u32, data: Vec<u8>) {
+        let Some(sender) = self.endpoints.get(&from) else {
+            return
+        };
+        let sent_at = sender.time - sender.clock_skew;
+        if self.random() < self.conditions.loss {
+            return
+        }
+        let arrives_at = sent_at + self.conditions.latency + self.random() * self.conditions.jitter;
+        self.in_flight.push(InFlight { arrives_at, from, to, data });
+    }
+}
+
+fn dispatch<E>(events: Vec<(EventCallback<E>, E)>) {
+    for (event_callback, event) in events {
+        event_callback.borrow_mut()(event);
+    }
+}
+
+//Simulated network between loopback transports, so lobby and game logic can run natively.
+pub struct LoopbackNetwork<T> {
+    medium: Rc<RefCell<Medium<T>>>,
+}
+impl<T> Clone for LoopbackNetwork<T> {
+    fn clone(&self) -> Self {
+        Self { medium: self.medium.clone() }
+    }
+}
+impl<T: Serialize + DeserializeOwned + 'static> LoopbackNetwork<T> {
+    pub fn new(conditions: Conditions) -> Self {
+        Self {
+            medium: Rc::new(RefCell::new(Medium {
+                conditions,
+                random_state: 0x2545_f491_4f6c_dd1d,
+                in_flight: Vec::new(),
+                endpoints: BTreeMap::new(),
+            })),
+        }
+    }
+    pub fn set_conditions(&self, conditions: Conditions) {
+        self.medium.borrow_mut().conditions = conditions;
+    }
+    //Add a peer whose clock runs `clock_skew` milliseconds ahead of network time.
+    pub fn join<F: FnMut(PeerNetworkEvent<T>) + 'static>(&self, user_id: u32, clock_skew: f64, event_handler: F) -> LoopbackTransport<T> {
+        self.medium.borrow_mut().endpoints.insert(user_id, Endpoint {
+            clock_skew,
+            time: clock_skew,
+            links: BTreeMap::new(),
+            event_callback: Rc::new(RefCell::new(event_handler)),
+        });
+        LoopbackTransport { user_id, medium: self.medium.clone() }
+    }
+    //Cut the connection between two peers, losing the frames still on their way. Both are told.
+    pub fn disconnect(&self, user_id: u32, peer_id: u32) {
+        let mut events = Vec::new();
+        {
+            let mut medium = self.medium.borrow_mut();
+            medium.in_flight.retain(|frame| (frame.from, frame.to) != (user_id, peer_id) && (frame.from, frame.to) != (peer_id, user_id));
+            for (id, other_id) in [(user_id, peer_id), (peer_id, user_id)] {
+                if let Some(endpoint) = medium.endpoints.get_mut(&id) && endpoint.links.remove(&other_id).is_some() {
+                    events.push((endpoint.event_callback.clone(), PeerNetworkEvent::Disconnect(other_id)));
+                }
+            }
+        }
+        dispatch(events);
+    }
+    //Take a peer off the network, as if its page was closed.
+    pub fn leave(&self, user_id: u32) {
+        let peer_ids = self.medium.borrow().endpoints.get(&user_id).map(|endpoint| endpoint.links.keys().copied().collect::<Vec<_>>()).unwrap_or_default();
+        for peer_id in peer_ids {
+            self.disconnect(user_id, peer_id);
+        }
+        self.medium.borrow_mut().endpoints.remove(&user_id);
+    }
+    //Update every peer at this network time, each with its own clock.
+    pub fn advance(&self, time: f64) {
+        let clocks = self.medium.borrow().endpoints.iter().map(|(&user_id, endpoint)| (user_id, time + endpoint.clock_skew)).collect::<Vec<_>>();
+        for (user_id, local_time) in clocks {
+            update_endpoint(&self.medium, user_id, local_time);
+        }
+    }
+    pub fn in_flight(&self) -> usize {
+        self.medium.borrow().in_flight.len()
+    }
+}
+
+//Deliver the frames that reached this peer by its local `time`, then run its link timers.
+fn update_endpoint<T: Serialize + DeserializeOwned>(medium: &RefCell<Medium<T>>, user_id: u32, time: f64) {
+    let mut events = Vec::new();
+    {
+        let mut medium = medium.borrow_mut();
+        let Some(clock_skew) = medium.endpoints.get(&user_id).map(|endpoint| endpoint.clock_skew) else {
+            return
+        };
+        let (mut arrived, in_flight): (Vec<_>, Vec<_>) = std::mem::take(&mut medium.in_flight).into_iter()
+            .partition(|frame| frame.to == user_id && frame.arrives_at + clock_skew <= time);
+        medium.in_flight = in_flight;
+        arrived.sort_by(|a, b| a.arrives_at.total_cmp(&b.arrives_at));
+
+        let Some(endpoint) = medium.endpoints.get_mut(&user_id) else {
+            return
+        };
+        endpoint.time = time;
+        let mut outgoing = Vec::new();
+        for frame in arrived {
+            let Some(link) = endpoint.links.get_mut(&frame.from) else {
+                continue
+            };
+            match Frame::decode(frame.from, frame.data.as_slice()).and_then(|received| link.receive(received, frame.arrives_at + clock_skew)) {
+                Ok((messages, reply)) => {
+                    events.extend(messages.into_iter().map(|message| (endpoint.event_callback.clone(), PeerNetworkEvent::Message(frame.from, message))));
+                    outgoing.extend(reply.map(|(_, reply)| (frame.from, reply)));
+                }
+                Err(error) => events.push((endpoint.event_callback.clone(), PeerNetworkEvent::Error(frame.from, error))),
+            }
+        }
+        for (&peer_id, link) in endpoint.links.iter_mut() {
+            outgoing.extend(link.due(time).into_iter().map(|encoded| (peer_id, encoded)));
+            match link.ping(time) {
+                Ok(ping) => outgoing.extend(ping.map(|ping| (peer_id, ping))),
+                Err(error) => events.push((endpoint.event_callback.clone(), PeerNetworkEvent::Error(peer_id, error))),
+            }
+        }
+        for (peer_id, data) in outgoing {
+            medium.transmit(user_id, peer_id, data);
+        }
+    }
+    dispatch(events);
+}
+
+//One peer on a loopback network.
+pub struct LoopbackTransport<T> {
+    user_id: u32,
+    medium: Rc<RefCell<Medium<T>>>,
+}
+impl<T: Serialize + DeserializeOwned + 'static> Transport<T> for LoopbackTransport<T> {
+    fn user_id(&self) -> u32 {
+        self.user_id
+    }
+    //Loopback peers connect at once, if the other peer is on the network.
+    fn connect(&self, peer_id: u32) {
+        let mut events = Vec::new();
+        {
+            let mut medium = self.medium.borrow_mut();
+            let connected = medium.endpoints.get(&self.user_id).is_none_or(|endpoint| endpoint.links.contains_key(&peer_id));
+            if peer_id == self.user_id || connected || !medium.endpoints.contains_key(&peer_id) {
+                return
+            }
+            for (id, other_id) in [(self.user_id, peer_id), (peer_id, self.user_id)] {
+                if let Some(endpoint) = medium.endpoints.get_mut(&id) {
+                    endpoint.links.insert(other_id, Link::new(id));
+                    events.push((endpoint.event_callback.clone(), PeerNetworkEvent::Connect(other_id)));
+                }
+            }
+        }
+        dispatch(events);
+    }
+    //Loopback peers need no handshakes.
+    fn receive_handshake(&self, _handshake: PeerHandshake) {}
+    fn send(&self, peer_id: u32, message: &T, delivery: Delivery) -> Result<()> {
+        let mut medium = self.medium.borrow_mut();
+        let Some(endpoint) = medium.endpoints.get_mut(&self.user_id) else {
+            return Err(NetworkError::NotOpen)
+        };
+        let time = endpoint.time;
+        if let Some(link) = endpoint.links.get_mut(&peer_id) {
+            let encoded = link.encode(message, delivery, time)?;
+            medium.transmit(self.user_id, peer_id, encoded);
+        }
+        Ok(())
+    }
+    fn broadcast(&self, message: &T, delivery: Delivery) -> Result<()> {
+        let peer_ids = self.medium.borrow().endpoints.get(&self.user_id).map(|endpoint| endpoint.links.keys().copied().collect::<Vec<_>>()).unwrap_or_default();
+        let mut result = Ok(());
+        for peer_id in peer_ids {
+            let sent = self.send(peer_id, message, delivery);
+            if result.is_ok() {
+                result = sent;
+            }
+        }
+        result
+    }
+    fn timing(&self, peer_id: u32) -> Option<Timing> {
+        self.medium.borrow().endpoints.get(&self.user_id).and_then(|endpoint| endpoint.links.get(&peer_id)).map(|link| link.timing())
+    }
+    fn update(&self, time: f64) {
+        update_endpoint(&self.medium, self.user_id, time);
+    }
+}
+
+struct SignalingState<T> {
+    ready_state: ReadyState,
+    queued: Vec<T>,
+    sent: Vec<T>,
+}
+
+//In-memory stand-in for the lobby websocket. Tests play the server: they open and close the
+//connection, deliver messages and look at what the client sent.
+pub struct LoopbackSignaling<T> {
+    state: Rc<RefCell<SignalingState<T>>>,
+    event_callback: EventCallback<WebSocketEvent<T>>,
+}
+impl<T> Clone for LoopbackSignaling<T> {
+    fn clone(&self) -> Self {
+        Self { state: self.state.clone(), event_callback: self.event_callback.clone() }
+    }
+}
+impl<T: 'static> LoopbackSignaling<T> {
+    pub fn new<F: FnMut(WebSocketEvent<T>) + 'static>(event_handler: F) -> Self {
+        Self {
+            state: Rc::new(RefCell::new(SignalingState { ready_state: ReadyState::Connecting, queued: Vec::new(), sent: Vec::new() })),
+            event_callback: Rc::new(RefCell::new(event_handler)),
+        }
+    }
+    pub fn open(&self) {
+        {
+            let mut state = self.state.borrow_mut();
+            state.ready_state = ReadyState::Open;
+            let queued = std::mem::take(&mut state.queued);
+            state.sent.extend(queued);
+        }
+        self.event_callback.borrow_mut()(WebSocketEvent::Connect);
+    }
+    pub fn deliver(&self, message: T) {
+        self.event_callback.borrow_mut()(WebSocketEvent::Message(message));
+    }
+    pub fn close(&self, code: u16, reason: &str) {
+        let unsent = {
+            let mut state = self.state.borrow_mut();
+            state.ready_state = ReadyState::Closed;
+            std::mem::take(&mut state.queued).len()
+        };
+        if unsent > 0 {
+            self.event_callback.borrow_mut()(WebSocketEvent::Error(NetworkError::Unsent(unsent)));
+        }
+        self.event_callback.borrow_mut()(WebSocketEvent::Disconnect { code, reason: reason.to_string() });
+    }
+    //Messages that reached the server since the last call, in order.
+    pub fn take_sent(&self) -> Vec<T> {
+        std::mem::take(&mut self.state.borrow_mut().sent)
+    }
+}
+impl<T> SignalingChannel<T> for LoopbackSignaling<T> {
+    fn ready_state(&self) -> ReadyState {
+        self.state.borrow().ready_state
+    }
+    fn send(&self, message: T) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        match state.ready_state {
+            ReadyState::Connecting => state.queued.push(message),
+            ReadyState::Open => state.sent.push(message),
+            ReadyState::Closing | ReadyState::Closed => return Err(NetworkError::NotOpen),
+        }
+        Ok(())
+    }
+}
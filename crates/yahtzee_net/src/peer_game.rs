@@ -0,0 +1,107 @@
+use serde::{Serialize, Deserialize};
+use yahtzee_rules::{Action, GameOptions, GameState, Held, RuleError};
+use crate::host_sync::Authoritative;
+
+//A game played between the peers of a lobby without the server. The peer hosting the sync checks
+//every action. Dice come from a commit-reveal round between the players, so the host cannot pick them.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct PeerGame {
+    pub state: Option<GameState>,
+    //Roll waiting for the dice of round `rolls + 1`.
+    pub pending_roll: Option<Held>,
+    pub rolls: u32,
+    //Faces drawn for the last roll, so every peer can check them against the revealed seeds.
+    pub last_faces: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub enum PeerIntent {
+    Start { players: Vec<u32>, options: GameOptions },
+    Action(Action),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum PeerRejection {
+    GameRunning,
+    NoGame,
+    RollPending,
+    Rule(RuleError),
+}
+
+impl PeerGame {
+    pub fn is_running(&self) -> bool {
+        self.state.as_ref().is_some_and(|state| !state.is_finished())
+    }
+    pub fn pending_round(&self) -> Option<u32> {
+        self.pending_roll.map(|_| self.rolls + 1)
+    }
+    pub fn players(&self) -> Vec<u32> {
+        self.state.as_ref().map(|state| state.players.iter().map(|player| player.id).collect()).unwrap_or_default()
+    }
+    //Finish the pending roll of `round` with dice from the fair dice round. Returns whether there was one.
+    pub fn resolve_roll(&mut self, round: u32, mut faces: impl Iterator<Item = u8>) -> bool {
+        if self.pending_round() != Some(round) {
+            return false
+        }
+        let (Some(state), Some(held)) = (self.state.as_mut(), self.pending_roll.take()) else {
+            return false
+        };
+        let Some(player_id) = state.current_player() else {
+            return false
+        };
+        let mut drawn = Vec::new();
+        let rolled = state.apply(player_id, Action::Roll { held }, || {
+            let face = faces.next().unwrap_or(1);
+            drawn.push(face);
+            face
+        });
+        self.rolls = round;
+        self.last_faces = drawn;
+        rolled.is_ok()
+    }
+}
+impl Authoritative for PeerGame {
+    type Intent = PeerIntent;
+    type Rejection = PeerRejection;
+    fn apply(&mut self, user_id: u32, intent: PeerIntent) -> Result<(), PeerRejection> {
+        match intent {
+            PeerIntent::Start { players, options } => {
+                if self.is_running() {
+                    return Err(PeerRejection::GameRunning)
+                }
+                self.state = Some(GameState::new(players, options));
+                self.pending_roll = None;
+                Ok(())
+            }
+            PeerIntent::Action(action) => {
+                if self.pending_roll.is_some() {
+                    return Err(PeerRejection::RollPending)
+                }
+                let state = self.state.as_mut().ok_or(PeerRejection::NoGame)?;
+                match action {
+                    //Check the roll on a copy; the real dice are only known once the round is revealed.
+                    Action::Roll { held } => {
+                        state.clone().apply(user_id, action, || 1).map_err(PeerRejection::Rule)?;
+                        self.pending_roll = Some(held);
+                        Ok(())
+                    }
+                    Action::Score(..) => state.apply(user_id, action, || 1).map_err(PeerRejection::Rule),
+                }
+            }
+        }
+    }
+    fn remove_peer(&mut self, user_id: u32) -> bool {
+        match self.state.as_mut() {
+            Some(state) if state.player(user_id).is_some() => {
+                //A roll waiting for dice belongs to the current player. Its round may be revealed already, so it is skipped.
+                if state.current_player() == Some(user_id) && self.pending_roll.take().is_some() {
+                    self.rolls += 1;
+                    self.last_faces.clear();
+                }
+                state.remove_player(user_id);
+                true
+            }
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,25 @@
+use crate::error::{NetworkError, Result};
+
+//Client side of the connection to the lobby server, which also relays handshakes between peers.
+//Events are reported through the callback the channel was created with.
+pub trait SignalingChannel<T> {
+    fn ready_state(&self) -> ReadyState;
+    //Messages sent while still connecting are queued and go out in order once the connection opens.
+    fn send(&self, message: T) -> Result<()>;
+}
+
+pub enum WebSocketEvent<T> {
+    Connect,
+    //Close code and reason as sent by the server, or 1006 with no reason if the connection dropped.
+    Disconnect { code: u16, reason: String },
+    Message(T),
+    Error(NetworkError),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReadyState {
+    Connecting,
+    Open,
+    Closing,
+    Closed,
+}
@@ -0,0 +1,92 @@
+use signaling_protocol::lobby::SocketMessage;
+use crate::error::{NetworkError, Result};
+use crate::link::{Delivery, Timing};
+
+pub use signaling_protocol::lobby::SdpType;
+
+//Peer to peer messaging between the users of a lobby. The browser runs it over WebRTC, tests run it
+//in memory. Events are reported through the callback the transport was created with.
+pub trait Transport<T> {
+    fn user_id(&self) -> u32;
+    //Start connecting to a peer. `Connect` is reported once messages can be sent.
+    fn connect(&self, peer_id: u32);
+    //Handle a handshake from a peer, relayed by the signaling channel.
+    fn receive_handshake(&self, handshake: PeerHandshake);
+    fn send(&self, peer_id: u32, message: &T, delivery: Delivery) -> Result<()>;
+    //Sends to every connected peer, even if sending to some of them fails. Returns the first failure.
+    fn broadcast(&self, message: &T, delivery: Delivery) -> Result<()>;
+    fn timing(&self, peer_id: u32) -> Option<Timing>;
+    //Resend unacknowledged messages, ping peers and run other timers. Call every frame.
+    fn update(&self, time: f64);
+}
+
+#[derive(Default)]
+pub struct PeerHandshake {
+    pub source_id: u32,
+    pub target_id: u32,
+    //Identifies the sender's peer network, so an offer from a peer that reloaded is not taken for renegotiation.
+    pub session_id: u32,
+    pub sdp_type: SdpType,
+    pub sdp_description: String,
+    pub ice_candidates: Vec<(String, Option<String>, Option<u16>)>,
+}
+impl From<PeerHandshake> for SocketMessage {
+    fn from(value: PeerHandshake) -> Self {
+        Self::WebRtcHandshake {
+            source_id: value.source_id,
+            target_id: value.target_id,
+            session_id: value.session_id,
+            sdp_type: value.sdp_type,
+            sdp_description: value.sdp_description,
+            ice_candidates: value.ice_candidates,
+        }
+    }
+}
+impl From<SocketMessage> for PeerHandshake {
+    fn from(value: SocketMessage) -> Self {
+        if let SocketMessage::WebRtcHandshake { source_id, target_id, session_id, sdp_type, sdp_description, ice_candidates } = value {
+            PeerHandshake {
+                source_id,
+                target_id,
+                session_id,
+                sdp_type,
+                sdp_description,
+                ice_candidates,
+            }
+        }
+        else {
+            PeerHandshake::default()
+        }
+    }
+}
+
+pub enum PeerNetworkEvent<T> {
+    Handshake(PeerHandshake),
+    //Sent when the data channels first open, and again when an interrupted connection recovers.
+    Connect(u32),
+    //The connection was interrupted and is being recovered. Reliable messages still arrive if it comes back.
+    Reconnecting(u32),
+    Disconnect(u32),
+    Message(u32, T),
+    Stats(u32, PeerStats),
+    //Something went wrong with the connection to this peer. The connection itself may still be usable.
+    Error(u32, NetworkError),
+}
+
+//Connection quality of a peer, reported every few seconds while it is connected.
+#[derive(Clone, Debug)]
+pub struct PeerStats {
+    pub timing: Timing,
+    pub connection: ConnectionStats,
+}
+
+//Parts of RTCPeerConnection.getStats() describing the candidate pair in use.
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionStats {
+    //Type of our candidate in the pair: host, srflx, prflx or relay.
+    pub candidate_type: Option<String>,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    //Round trip time of the latest ICE connectivity check, in milliseconds.
+    pub ice_rtt: Option<f64>,
+}
@@ -0,0 +1,47 @@
+//Each test binary uses a different part of the harness.
+#![allow(dead_code)]
+
+use std::{rc::Rc, cell::RefCell};
+use serde::{Serialize, de::DeserializeOwned};
+use yahtzee_net::{PeerNetworkEvent, Transport};
+use yahtzee_net::loopback::{LoopbackNetwork, LoopbackTransport};
+
+//A peer on a loopback network. What its transport reports is kept until the test takes it.
+pub struct Node<M> {
+    pub transport: LoopbackTransport<M>,
+    events: Rc<RefCell<Vec<PeerNetworkEvent<M>>>>,
+}
+impl<M: Serialize + DeserializeOwned + 'static> Node<M> {
+    //Join the network and start connecting to the `members` already there.
+    pub fn join(network: &LoopbackNetwork<M>, user_id: u32, members: &[u32]) -> Self {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let transport = network.join(user_id, 0.0, {
+            let events = events.clone();
+            move |event| events.borrow_mut().push(event)
+        });
+        for &member in members {
+            transport.connect(member);
+        }
+        Self { transport, events }
+    }
+    pub fn take_events(&self) -> Vec<PeerNetworkEvent<M>> {
+        std::mem::take(&mut *self.events.borrow_mut())
+    }
+}
+
+//A test peer that feeds what its transport reported into the parts under test.
+pub trait Pump {
+    fn pump(&mut self, time: f64);
+}
+
+//Advance the network in 5 ms steps up to and including `to`, pumping every peer after each step.
+pub fn run<M: Serialize + DeserializeOwned + 'static, P: Pump>(network: &LoopbackNetwork<M>, peers: &mut [&mut P], from: f64, to: f64) {
+    let mut time = from;
+    while time <= to {
+        network.advance(time);
+        for peer in peers.iter_mut() {
+            peer.pump(time);
+        }
+        time += 5.0;
+    }
+}
@@ -0,0 +1,185 @@
+mod common;
+
+use std::collections::BTreeMap;
+use common::{Node, Pump, run};
+use yahtzee_net::{Delivery, DiceEvent, DiceMessage, FairDice, PeerNetworkEvent, Rolls, Transport};
+use yahtzee_net::fair_dice::commitment;
+use yahtzee_net::loopback::{Conditions, LoopbackNetwork};
+
+struct Peer {
+    node: Node<DiceMessage>,
+    dice: FairDice,
+    received: Vec<DiceMessage>,
+    ready: Vec<u32>,
+    flagged: Vec<(u32, u32)>,
+}
+impl Peer {
+    fn join(network: &LoopbackNetwork<DiceMessage>, user_id: u32, members: &[u32]) -> Self {
+        let node = Node::join(network, user_id, members);
+        Self { node, dice: FairDice::new(user_id), received: Vec::new(), ready: Vec::new(), flagged: Vec::new() }
+    }
+    fn begin(&mut self, round: u32, participants: &[u32], seed: u8) {
+        let events = self.dice.begin(&self.node.transport, round, participants.iter().copied(), || [seed; 32]);
+        self.handle(events);
+    }
+    fn handle(&mut self, events: Vec<DiceEvent>) {
+        for event in events {
+            match event {
+                DiceEvent::Ready(round) => self.ready.push(round),
+                DiceEvent::Flagged { round, user_id } => self.flagged.push((round, user_id)),
+                DiceEvent::Error(error) => panic!("{error}"),
+            }
+        }
+    }
+    fn faces(&self, round: u32) -> Vec<u8> {
+        self.dice.rolls(round).expect("round is ready").take(20).collect()
+    }
+}
+impl Pump for Peer {
+    fn pump(&mut self, _time: f64) {
+        for event in self.node.take_events() {
+            let dice_events = match event {
+                PeerNetworkEvent::Disconnect(peer_id) => self.dice.disconnect(&self.node.transport, peer_id),
+                PeerNetworkEvent::Message(peer_id, message) => {
+                    self.received.push(message.clone());
+                    self.dice.receive(&self.node.transport, peer_id, message)
+                }
+                _ => Vec::new(),
+            };
+            self.handle(dice_events);
+        }
+    }
+}
+
+#[test]
+fn peers_derive_the_same_dice() {
+    let network = LoopbackNetwork::new(Conditions { latency: 30.0, jitter: 20.0, loss: 0.2 });
+    let mut first = Peer::join(&network, 1, &[]);
+    let mut second = Peer::join(&network, 2, &[1]);
+    let mut third = Peer::join(&network, 3, &[1, 2]);
+    first.begin(1, &[1, 2, 3], 1);
+    second.begin(1, &[1, 2, 3], 2);
+    third.begin(1, &[1, 2, 3], 3);
+    run(&network, &mut [&mut first, &mut second, &mut third], 0.0, 3000.0);
+
+    for peer in [&first, &second, &third] {
+        assert_eq!(peer.ready, vec![1]);
+        assert!(peer.flagged.is_empty());
+        assert_eq!(peer.faces(1), first.faces(1));
+    }
+    assert!(first.faces(1).iter().all(|face| (1..=6).contains(face)));
+}
+
+#[test]
+fn seeds_stay_hidden_until_everyone_committed() {
+    let network = LoopbackNetwork::new(Conditions { latency: 30.0, ..Default::default() });
+    let mut first = Peer::join(&network, 1, &[]);
+    let mut second = Peer::join(&network, 2, &[1]);
+    first.begin(1, &[1, 2], 1);
+    run(&network, &mut [&mut first, &mut second], 0.0, 1000.0);
+    assert!(second.received.iter().all(|message| matches!(message, DiceMessage::Commit { .. })));
+    assert!(first.ready.is_empty());
+
+    second.begin(1, &[1, 2], 2);
+    run(&network, &mut [&mut first, &mut second], 1005.0, 2000.0);
+    assert_eq!(first.ready, vec![1]);
+    assert_eq!(second.ready, vec![1]);
+    assert_eq!(first.faces(1), second.faces(1));
+}
+
+#[test]
+fn mismatching_reveal_is_flagged() {
+    let network = LoopbackNetwork::new(Conditions { latency: 30.0, ..Default::default() });
+    let mut first = Peer::join(&network, 1, &[]);
+    let mut second = Peer::join(&network, 2, &[1]);
+    let cheater = Peer::join(&network, 3, &[1, 2]);
+    first.begin(1, &[1, 2, 3], 1);
+    second.begin(1, &[1, 2, 3], 2);
+    //Commits to one seed, then reveals another once it has seen the others.
+    cheater.node.transport.broadcast(&DiceMessage::Commit { round: 1, commitment: commitment(1, 3, &[3; 32]) }, Delivery::Reliable).unwrap();
+    cheater.node.transport.broadcast(&DiceMessage::Reveal { round: 1, seed: [4; 32] }, Delivery::Reliable).unwrap();
+    run(&network, &mut [&mut first, &mut second], 0.0, 2000.0);
+
+    for peer in [&first, &second] {
+        assert_eq!(peer.flagged, vec![(1, 3)]);
+        assert_eq!(peer.ready, vec![1]);
+    }
+    let honest = BTreeMap::from([(1, [1; 32]), (2, [2; 32])]);
+    let expected = Rolls::new(1, &honest).take(20).collect::<Vec<_>>();
+    assert_eq!(first.faces(1), expected);
+    assert_eq!(second.faces(1), expected);
+}
+
+#[test]
+fn departed_participant_is_left_out() {
+    let network = LoopbackNetwork::new(Conditions { latency: 30.0, ..Default::default() });
+    let mut first = Peer::join(&network, 1, &[]);
+    let mut second = Peer::join(&network, 2, &[1]);
+    let _third = Peer::join(&network, 3, &[1, 2]);
+    first.begin(1, &[1, 2, 3], 1);
+    second.begin(1, &[1, 2, 3], 2);
+    run(&network, &mut [&mut first, &mut second], 0.0, 500.0);
+    assert!(first.ready.is_empty());
+
+    //It never committed, so it saw no seeds and is not held to anything.
+    network.leave(3);
+    run(&network, &mut [&mut first, &mut second], 505.0, 1000.0);
+    assert_eq!(first.ready, vec![1]);
+    assert!(first.flagged.is_empty());
+    assert_eq!(first.faces(1), second.faces(1));
+}
+
+#[test]
+fn leaving_before_revealing_is_flagged() {
+    let network = LoopbackNetwork::new(Conditions { latency: 30.0, ..Default::default() });
+    let mut first = Peer::join(&network, 1, &[]);
+    let mut second = Peer::join(&network, 2, &[1]);
+    let quitter = Peer::join(&network, 3, &[1, 2]);
+    first.begin(1, &[1, 2, 3], 1);
+    second.begin(1, &[1, 2, 3], 2);
+    //Commits, waits for the other seeds and leaves instead of revealing its own.
+    quitter.node.transport.broadcast(&DiceMessage::Commit { round: 1, commitment: commitment(1, 3, &[3; 32]) }, Delivery::Reliable).unwrap();
+    run(&network, &mut [&mut first, &mut second], 0.0, 500.0);
+    network.leave(3);
+    run(&network, &mut [&mut first, &mut second], 505.0, 1000.0);
+
+    for peer in [&first, &second] {
+        assert_eq!(peer.flagged, vec![(1, 3)]);
+        assert_eq!(peer.ready, vec![1]);
+    }
+    assert_eq!(first.faces(1), second.faces(1));
+}
+
+#[test]
+fn rounds_outside_the_window_are_ignored() {
+    let network = LoopbackNetwork::new(Conditions { latency: 30.0, ..Default::default() });
+    let mut first = Peer::join(&network, 1, &[]);
+    let mut second = Peer::join(&network, 2, &[1]);
+    first.begin(1, &[1, 2], 1);
+    second.begin(1, &[1, 2], 2);
+    run(&network, &mut [&mut first, &mut second], 0.0, 500.0);
+    assert_eq!(first.ready, vec![1]);
+
+    //A round far ahead of the current one is dropped, so its seeds never count.
+    second.node.transport.broadcast(&DiceMessage::Commit { round: 100, commitment: commitment(100, 2, &[2; 32]) }, Delivery::Reliable).unwrap();
+    second.node.transport.broadcast(&DiceMessage::Reveal { round: 100, seed: [2; 32] }, Delivery::Reliable).unwrap();
+    run(&network, &mut [&mut first], 505.0, 1000.0);
+    first.begin(100, &[1, 2], 1);
+    run(&network, &mut [&mut first], 1005.0, 1500.0);
+    assert_eq!(first.ready, vec![1]);
+    //Beginning it moved the window on, past the finished first round.
+    assert!(first.dice.rolls(1).is_none());
+}
+
+#[test]
+fn rolls_are_uniform_and_differ_between_rounds() {
+    let seeds = BTreeMap::from([(1, [7; 32]), (2, [9; 32])]);
+    let mut counts = [0u32; 6];
+    for face in Rolls::new(1, &seeds).take(60000) {
+        counts[face as usize - 1] += 1;
+    }
+    assert!(counts.iter().all(|&count| (9500..10500).contains(&count)), "{counts:?}");
+    let first = Rolls::new(1, &seeds).take(20).collect::<Vec<_>>();
+    assert_eq!(first, Rolls::new(1, &seeds).take(20).collect::<Vec<_>>());
+    assert_ne!(first, Rolls::new(2, &seeds).take(20).collect::<Vec<_>>());
+}
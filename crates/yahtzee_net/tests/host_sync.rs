@@ -0,0 +1,185 @@
+mod common;
+
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use common::{Node, Pump, run};
+use yahtzee_net::{Authoritative, HostSync, PeerNetworkEvent, SyncEvent, SyncMessage};
+use yahtzee_net::loopback::{Conditions, LoopbackNetwork};
+
+//Each peer adds to its own total, up to 10 at a time.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+struct Totals(BTreeMap<u32, u32>);
+impl Authoritative for Totals {
+    type Intent = u32;
+    type Rejection = u32;
+    fn apply(&mut self, user_id: u32, amount: u32) -> Result<(), u32> {
+        if amount > 10 {
+            return Err(amount)
+        }
+        *self.0.entry(user_id).or_default() += amount;
+        Ok(())
+    }
+    fn remove_peer(&mut self, user_id: u32) -> bool {
+        self.0.remove(&user_id).is_some()
+    }
+}
+
+struct Peer {
+    node: Node<SyncMessage<Totals>>,
+    sync: HostSync<Totals>,
+    rejected: Vec<u32>,
+    hosts: Vec<u32>,
+}
+impl Peer {
+    fn join(network: &LoopbackNetwork<SyncMessage<Totals>>, user_id: u32, members: &[u32]) -> Self {
+        let node = Node::join(network, user_id, members);
+        let sync = HostSync::new(user_id, members.iter().copied(), Totals::default());
+        Self { node, sync, rejected: Vec::new(), hosts: Vec::new() }
+    }
+    fn send(&mut self, amount: u32) {
+        let events = self.sync.send_intent(&self.node.transport, amount);
+        self.handle(events);
+    }
+    fn handle(&mut self, events: Vec<SyncEvent<Totals>>) {
+        for event in events {
+            match event {
+                SyncEvent::Rejected(amount) => self.rejected.push(amount),
+                SyncEvent::HostChanged(host_id) => self.hosts.push(host_id),
+                SyncEvent::State => {}
+                SyncEvent::Error(error) => panic!("{error}"),
+            }
+        }
+    }
+}
+impl Pump for Peer {
+    //Feed what the transport reported into the sync.
+    fn pump(&mut self, time: f64) {
+        for event in self.node.take_events() {
+            let sync_events = match event {
+                PeerNetworkEvent::Connect(peer_id) => self.sync.connect(&self.node.transport, peer_id),
+                PeerNetworkEvent::Disconnect(peer_id) => self.sync.disconnect(&self.node.transport, peer_id),
+                PeerNetworkEvent::Message(peer_id, message) => self.sync.receive(&self.node.transport, peer_id, message),
+                _ => Vec::new(),
+            };
+            self.handle(sync_events);
+        }
+        let events = self.sync.update(&self.node.transport, time);
+        self.handle(events);
+    }
+}
+
+#[test]
+fn host_applies_intents_for_everyone() {
+    let network = LoopbackNetwork::new(Conditions { latency: 30.0, jitter: 20.0, loss: 0.2 });
+    let mut host = Peer::join(&network, 1, &[]);
+    let mut guest = Peer::join(&network, 2, &[1]);
+    let mut other = Peer::join(&network, 3, &[1, 2]);
+    assert!(host.sync.is_host());
+    assert_eq!(other.sync.host_id(), 1);
+
+    host.send(4);
+    guest.send(5);
+    guest.send(11);
+    other.send(6);
+    run(&network, &mut [&mut host, &mut guest, &mut other], 0.0, 3000.0);
+
+    let expected = Totals(BTreeMap::from([(1, 4), (2, 5), (3, 6)]));
+    for peer in [&host, &guest, &other] {
+        assert_eq!(peer.sync.state(), &expected);
+        assert_eq!(peer.sync.version(), host.sync.version());
+    }
+    assert_eq!(guest.rejected, vec![11]);
+    assert!(host.rejected.is_empty() && other.rejected.is_empty());
+}
+
+#[test]
+fn next_lowest_peer_takes_over_when_host_leaves() {
+    let network = LoopbackNetwork::new(Conditions { latency: 30.0, ..Default::default() });
+    let mut host = Peer::join(&network, 1, &[]);
+    let mut guest = Peer::join(&network, 2, &[1]);
+    let mut other = Peer::join(&network, 3, &[1, 2]);
+    host.send(1);
+    other.send(2);
+    run(&network, &mut [&mut host, &mut guest, &mut other], 0.0, 1000.0);
+
+    //The host applies this one, but leaves before its state reaches anyone.
+    other.send(3);
+    run(&network, &mut [&mut host, &mut guest, &mut other], 1005.0, 1040.0);
+    //This one never reaches the host at all.
+    other.send(4);
+    network.leave(1);
+    run(&network, &mut [&mut guest, &mut other], 1045.0, 3000.0);
+
+    assert_eq!(guest.hosts, vec![2]);
+    assert_eq!(other.hosts, vec![2]);
+    assert!(guest.sync.is_host());
+    let expected = Totals(BTreeMap::from([(3, 9)]));
+    assert_eq!(guest.sync.state(), &expected);
+    assert_eq!(other.sync.state(), &expected);
+
+    //Play goes on with the new host.
+    other.send(1);
+    guest.send(2);
+    run(&network, &mut [&mut guest, &mut other], 3005.0, 4000.0);
+    let expected = Totals(BTreeMap::from([(2, 2), (3, 10)]));
+    assert_eq!(guest.sync.state(), &expected);
+    assert_eq!(other.sync.state(), &expected);
+}
+
+#[test]
+fn takeover_gives_up_on_silent_peers() {
+    let network = LoopbackNetwork::new(Conditions { latency: 30.0, ..Default::default() });
+    let mut host = Peer::join(&network, 1, &[]);
+    let mut guest = Peer::join(&network, 2, &[1]);
+    let mut other = Peer::join(&network, 3, &[1, 2]);
+    other.send(1);
+    run(&network, &mut [&mut host, &mut guest, &mut other], 0.0, 1000.0);
+
+    //The other peer stays connected but stops handling messages, so its state never comes.
+    network.leave(1);
+    guest.send(2);
+    run(&network, &mut [&mut guest], 1005.0, 3000.0);
+    assert!(guest.sync.is_host());
+    assert_eq!(guest.sync.state(), &Totals(BTreeMap::from([(3, 1)])));
+
+    //The new host carries on without it, and it catches up once it is back.
+    run(&network, &mut [&mut guest], 3005.0, 5000.0);
+    let expected = Totals(BTreeMap::from([(2, 2), (3, 1)]));
+    assert_eq!(guest.sync.state(), &expected);
+    run(&network, &mut [&mut guest, &mut other], 5005.0, 6000.0);
+    assert_eq!(other.sync.state(), &expected);
+    assert_eq!(other.hosts, vec![2]);
+}
+
+#[test]
+fn deposed_host_is_replaced_and_ignored() {
+    let network = LoopbackNetwork::new(Conditions { latency: 30.0, ..Default::default() });
+    let mut host = Peer::join(&network, 1, &[]);
+    let mut guest = Peer::join(&network, 2, &[1]);
+    let mut other = Peer::join(&network, 3, &[1, 2]);
+    host.send(1);
+    guest.send(2);
+    other.send(3);
+    run(&network, &mut [&mut host, &mut guest, &mut other], 0.0, 1000.0);
+
+    //Both guests catch the host out, while it stays connected.
+    for peer in [&mut guest, &mut other] {
+        let events = peer.sync.depose(&peer.node.transport);
+        peer.handle(events);
+    }
+    run(&network, &mut [&mut host, &mut guest, &mut other], 1005.0, 2000.0);
+    assert_eq!(guest.hosts, vec![2]);
+    assert_eq!(other.hosts, vec![2]);
+    assert!(guest.sync.is_host());
+    let expected = Totals(BTreeMap::from([(2, 2), (3, 3)]));
+    assert_eq!(guest.sync.state(), &expected);
+    assert_eq!(other.sync.state(), &expected);
+
+    //Whatever the old host sends from now on is dropped.
+    host.send(5);
+    other.send(4);
+    run(&network, &mut [&mut host, &mut guest, &mut other], 2005.0, 3000.0);
+    let expected = Totals(BTreeMap::from([(2, 2), (3, 7)]));
+    assert_eq!(guest.sync.state(), &expected);
+    assert_eq!(other.sync.state(), &expected);
+}
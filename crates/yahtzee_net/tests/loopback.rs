@@ -0,0 +1,177 @@
+use std::{rc::Rc, cell::RefCell};
+use yahtzee_net::{Delivery, NetworkError, PeerNetworkEvent, ReadyState, SignalingChannel, Transport, WebSocketEvent};
+use yahtzee_net::loopback::{Conditions, LoopbackNetwork, LoopbackSignaling, LoopbackTransport};
+
+type Events = Rc<RefCell<Vec<PeerNetworkEvent<u32>>>>;
+
+fn join(network: &LoopbackNetwork<u32>, user_id: u32, clock_skew: f64) -> (LoopbackTransport<u32>, Events) {
+    let events = Events::default();
+    let transport = network.join(user_id, clock_skew, {
+        let events = events.clone();
+        move |event| events.borrow_mut().push(event)
+    });
+    (transport, events)
+}
+
+fn messages(events: &Events) -> Vec<(u32, u32)> {
+    events.borrow().iter().filter_map(|event| match event {
+        PeerNetworkEvent::Message(peer_id, message) => Some((*peer_id, *message)),
+        _ => None,
+    }).collect()
+}
+
+fn disconnects(events: &Events) -> Vec<u32> {
+    events.borrow().iter().filter_map(|event| match event {
+        PeerNetworkEvent::Disconnect(peer_id) => Some(*peer_id),
+        _ => None,
+    }).collect()
+}
+
+//Advance the network in 5 ms steps up to and including `to`.
+fn run(network: &LoopbackNetwork<u32>, from: f64, to: f64) {
+    let mut time = from;
+    while time <= to {
+        network.advance(time);
+        time += 5.0;
+    }
+}
+
+#[test]
+fn connect_reports_both_sides() {
+    let network = LoopbackNetwork::new(Conditions::default());
+    let (host, host_events) = join(&network, 1, 0.0);
+    let (_guest, guest_events) = join(&network, 2, 0.0);
+
+    host.connect(2);
+    host.connect(2);
+    assert!(matches!(host_events.borrow().as_slice(), [PeerNetworkEvent::Connect(2)]));
+    assert!(matches!(guest_events.borrow().as_slice(), [PeerNetworkEvent::Connect(1)]));
+}
+
+#[test]
+fn messages_arrive_after_latency() {
+    let network = LoopbackNetwork::new(Conditions { latency: 50.0, ..Default::default() });
+    let (host, _) = join(&network, 1, 0.0);
+    let (_guest, guest_events) = join(&network, 2, 0.0);
+    host.connect(2);
+
+    host.send(2, &7, Delivery::Unreliable).unwrap();
+    network.advance(49.0);
+    assert!(messages(&guest_events).is_empty());
+    network.advance(50.0);
+    assert_eq!(messages(&guest_events), vec![(1, 7)]);
+}
+
+#[test]
+fn reliable_messages_survive_loss() {
+    let network = LoopbackNetwork::new(Conditions { latency: 20.0, jitter: 30.0, loss: 0.5 });
+    let (host, _) = join(&network, 1, 0.0);
+    let (_guest, guest_events) = join(&network, 2, 0.0);
+    host.connect(2);
+
+    for message in 0..20 {
+        host.send(2, &message, Delivery::Reliable).unwrap();
+    }
+    run(&network, 0.0, 20000.0);
+    assert_eq!(messages(&guest_events), (0..20).map(|message| (1, message)).collect::<Vec<_>>());
+}
+
+#[test]
+fn unreliable_messages_are_not_resent() {
+    let network = LoopbackNetwork::new(Conditions { latency: 20.0, loss: 1.0, ..Default::default() });
+    let (host, _) = join(&network, 1, 0.0);
+    let (_guest, guest_events) = join(&network, 2, 0.0);
+    host.connect(2);
+
+    host.send(2, &1, Delivery::Unreliable).unwrap();
+    network.set_conditions(Conditions { latency: 20.0, ..Default::default() });
+    host.send(2, &2, Delivery::Unreliable).unwrap();
+    run(&network, 0.0, 5000.0);
+    assert_eq!(messages(&guest_events), vec![(1, 2)]);
+}
+
+#[test]
+fn disconnect_loses_frames_in_flight() {
+    let network = LoopbackNetwork::new(Conditions { latency: 100.0, ..Default::default() });
+    let (host, host_events) = join(&network, 1, 0.0);
+    let (guest, guest_events) = join(&network, 2, 0.0);
+    let (_other, other_events) = join(&network, 3, 0.0);
+    host.connect(2);
+    host.connect(3);
+
+    host.send(2, &1, Delivery::Reliable).unwrap();
+    network.disconnect(1, 2);
+    assert_eq!(network.in_flight(), 0);
+    assert_eq!(disconnects(&host_events), vec![2]);
+    assert_eq!(disconnects(&guest_events), vec![1]);
+    assert!(host.timing(2).is_none());
+
+    //Sending to a peer that is gone is not an error, the message just goes nowhere.
+    host.send(2, &2, Delivery::Reliable).unwrap();
+    guest.broadcast(&3, Delivery::Reliable).unwrap();
+    run(&network, 0.0, 1000.0);
+    assert!(messages(&guest_events).is_empty());
+
+    network.leave(1);
+    assert_eq!(disconnects(&other_events), vec![1]);
+}
+
+#[test]
+fn pings_measure_round_trip_and_clock_offset() {
+    let network = LoopbackNetwork::new(Conditions { latency: 40.0, ..Default::default() });
+    let (host, _) = join(&network, 1, 0.0);
+    let (guest, _) = join(&network, 2, 1000.0);
+    host.connect(2);
+    run(&network, 0.0, 5000.0);
+
+    let host_timing = host.timing(2).unwrap();
+    assert!((host_timing.rtt.unwrap() - 80.0).abs() < 1.0);
+    assert!((host_timing.clock_offset.unwrap() - 1000.0).abs() < 1.0);
+    assert_eq!(host_timing.packet_loss, Some(0.0));
+    let guest_timing = guest.timing(1).unwrap();
+    assert!((guest_timing.clock_offset.unwrap() + 1000.0).abs() < 1.0);
+}
+
+#[test]
+fn signaling_queues_until_open() {
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let signaling = LoopbackSignaling::new({
+        let events = events.clone();
+        move |event| events.borrow_mut().push(event)
+    });
+
+    signaling.send(1).unwrap();
+    assert_eq!(signaling.ready_state(), ReadyState::Connecting);
+    assert!(signaling.take_sent().is_empty());
+    signaling.open();
+    signaling.send(2).unwrap();
+    assert_eq!(signaling.take_sent(), vec![1, 2]);
+    assert!(matches!(events.borrow().as_slice(), [WebSocketEvent::Connect]));
+
+    signaling.deliver(3);
+    signaling.close(4000, "Lobby closed");
+    assert!(matches!(signaling.send(4), Err(NetworkError::NotOpen)));
+    assert!(matches!(events.borrow().as_slice(), [
+        WebSocketEvent::Connect,
+        WebSocketEvent::Message(3),
+        WebSocketEvent::Disconnect { code: 4000, reason },
+    ] if reason == "Lobby closed"));
+}
+
+#[test]
+fn signaling_reports_unsent_messages() {
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let signaling = LoopbackSignaling::new({
+        let events = events.clone();
+        move |event| events.borrow_mut().push(event)
+    });
+
+    signaling.send(1).unwrap();
+    signaling.send(2).unwrap();
+    signaling.close(1006, "");
+    assert!(signaling.take_sent().is_empty());
+    assert!(matches!(events.borrow().as_slice(), [
+        WebSocketEvent::Error(NetworkError::Unsent(2)),
+        WebSocketEvent::Disconnect { code: 1006, .. },
+    ]));
+}
@@ -0,0 +1,236 @@
+mod common;
+
+use serde::{Serialize, Deserialize};
+use common::{Node, Pump, run};
+use yahtzee_net::{DiceEvent, DiceMessage, FairDice, HostSync, PeerGame, PeerIntent, PeerNetworkEvent, PeerRejection, SyncEvent, SyncMessage};
+use yahtzee_net::loopback::{Conditions, LoopbackNetwork};
+use yahtzee_rules::{Action, GameOptions, MAX_DICE, RuleError};
+
+//What peers of a lobby send each other for a peer game, like the browser client does.
+#[derive(Serialize, Deserialize)]
+enum Message {
+    Sync(SyncMessage<PeerGame>),
+    Dice(DiceMessage),
+}
+impl From<SyncMessage<PeerGame>> for Message {
+    fn from(value: SyncMessage<PeerGame>) -> Self {
+        Self::Sync(value)
+    }
+}
+impl From<DiceMessage> for Message {
+    fn from(value: DiceMessage) -> Self {
+        Self::Dice(value)
+    }
+}
+
+struct Peer {
+    user_id: u32,
+    node: Node<Message>,
+    sync: HostSync<PeerGame>,
+    dice: FairDice,
+    rejected: Vec<PeerRejection>,
+    hosts: Vec<u32>,
+}
+impl Peer {
+    fn join(network: &LoopbackNetwork<Message>, user_id: u32, members: &[u32]) -> Self {
+        let node = Node::join(network, user_id, members);
+        let sync = HostSync::new(user_id, members.iter().copied(), PeerGame::default());
+        Self { user_id, node, sync, dice: FairDice::new(user_id), rejected: Vec::new(), hosts: Vec::new() }
+    }
+    fn send(&mut self, intent: PeerIntent) {
+        let events = self.sync.send_intent(&self.node.transport, intent);
+        self.handle_sync(events);
+    }
+    fn handle_sync(&mut self, events: Vec<SyncEvent<PeerGame>>) {
+        for event in events {
+            match event {
+                SyncEvent::State => self.update_dice(),
+                SyncEvent::Rejected(rejection) => self.rejected.push(rejection),
+                SyncEvent::HostChanged(host_id) => {
+                    self.hosts.push(host_id);
+                    self.update_dice();
+                }
+                SyncEvent::Error(error) => panic!("{error}"),
+            }
+        }
+    }
+    fn handle_dice(&mut self, events: Vec<DiceEvent>) {
+        for event in events {
+            match event {
+                DiceEvent::Ready(_) => self.update_dice(),
+                DiceEvent::Flagged { round, user_id } => panic!("{user_id} flagged in round {round}"),
+                DiceEvent::Error(error) => panic!("{error}"),
+            }
+        }
+    }
+    //Roll a pending roll with the other players, and resolve it when hosting.
+    fn update_dice(&mut self) {
+        let Some(round) = self.sync.state().pending_round() else { return };
+        let players = self.sync.state().players();
+        let seed = [(round * 31 + self.user_id) as u8; 32];
+        let events = self.dice.begin(&self.node.transport, round, players, || seed);
+        self.handle_dice(events);
+        if self.sync.is_host() && let Some(rolls) = self.dice.rolls(round) {
+            let events = self.sync.modify(&self.node.transport, |game| game.resolve_roll(round, rolls));
+            self.handle_sync(events);
+        }
+    }
+    //The faces everyone derives for the last resolved roll.
+    fn expected_faces(&self) -> Vec<u8> {
+        let game = self.sync.state();
+        self.dice.rolls(game.rolls).expect("round is ready").take(game.last_faces.len()).collect()
+    }
+}
+impl Pump for Peer {
+    //Feed what the transport reported into the sync and the dice.
+    fn pump(&mut self, time: f64) {
+        for event in self.node.take_events() {
+            match event {
+                PeerNetworkEvent::Connect(peer_id) => {
+                    let events = self.sync.connect(&self.node.transport, peer_id);
+                    self.handle_sync(events);
+                }
+                PeerNetworkEvent::Disconnect(peer_id) => {
+                    let events = self.dice.disconnect(&self.node.transport, peer_id);
+                    self.handle_dice(events);
+                    let events = self.sync.disconnect(&self.node.transport, peer_id);
+                    self.handle_sync(events);
+                }
+                PeerNetworkEvent::Message(peer_id, Message::Sync(message)) => {
+                    let events = self.sync.receive(&self.node.transport, peer_id, message);
+                    self.handle_sync(events);
+                }
+                PeerNetworkEvent::Message(peer_id, Message::Dice(message)) => {
+                    let events = self.dice.receive(&self.node.transport, peer_id, message);
+                    self.handle_dice(events);
+                }
+                _ => {}
+            }
+        }
+        let events = self.sync.update(&self.node.transport, time);
+        self.handle_sync(events);
+    }
+}
+
+fn roll() -> PeerIntent {
+    PeerIntent::Action(Action::Roll { held: [false; MAX_DICE] })
+}
+
+#[test]
+fn peers_play_a_full_game() {
+    let network = LoopbackNetwork::new(Conditions { latency: 20.0, jitter: 10.0, loss: 0.1 });
+    let mut host = Peer::join(&network, 1, &[]);
+    let mut guest = Peer::join(&network, 2, &[1]);
+    let mut time = 0.0;
+    //Run until both peers have seen the game reach `version`.
+    let mut settle = |host: &mut Peer, guest: &mut Peer, version: u32| {
+        let limit = time + 10000.0;
+        while host.sync.version() < version || guest.sync.version() < version {
+            assert!(time < limit, "peers did not reach version {version}");
+            time += 5.0;
+            run(&network, &mut [&mut *host, &mut *guest], time, time);
+        }
+    };
+    host.send(PeerIntent::Start { players: vec![1, 2], options: GameOptions::default() });
+    let mut version = 1;
+    settle(&mut host, &mut guest, version);
+
+    while let Some(player_id) = host.sync.state().state.as_ref().and_then(|state| state.current_player()) {
+        //A roll changes the game twice: once when asked for and once when its dice are in.
+        let player = if player_id == 1 { &mut host } else { &mut guest };
+        player.send(roll());
+        version += 2;
+        settle(&mut host, &mut guest, version);
+
+        let player = if player_id == 1 { &mut host } else { &mut guest };
+        assert_eq!(player.sync.state().last_faces, player.expected_faces());
+        let state = player.sync.state().state.clone().unwrap();
+        let (category, column, _) = state.player(player_id).unwrap().scorecard.choices(&state.dice).next().unwrap();
+        player.send(PeerIntent::Action(Action::Score(category, column)));
+        version += 1;
+        settle(&mut host, &mut guest, version);
+    }
+
+    let state = host.sync.state().state.as_ref().unwrap();
+    assert!(state.is_finished());
+    assert_eq!(host.sync.state().rolls, 26);
+    assert_eq!(guest.sync.state().state.as_ref(), Some(state));
+    assert!(host.rejected.is_empty() && guest.rejected.is_empty());
+}
+
+#[test]
+fn new_host_resolves_a_pending_roll() {
+    let network = LoopbackNetwork::new(Conditions { latency: 30.0, ..Default::default() });
+    let mut host = Peer::join(&network, 1, &[]);
+    let mut guest = Peer::join(&network, 2, &[1]);
+    let mut other = Peer::join(&network, 3, &[1, 2]);
+    host.send(PeerIntent::Start { players: vec![2, 3, 1], options: GameOptions::default() });
+    run(&network, &mut [&mut host, &mut guest, &mut other], 0.0, 500.0);
+
+    //The host takes the roll, then leaves before revealing its seed for it.
+    guest.send(roll());
+    run(&network, &mut [&mut host, &mut guest, &mut other], 505.0, 570.0);
+    assert_eq!(guest.sync.state().pending_round(), Some(1));
+    network.leave(1);
+    run(&network, &mut [&mut guest, &mut other], 575.0, 2000.0);
+
+    assert_eq!(guest.hosts, vec![2]);
+    assert_eq!(other.hosts, vec![2]);
+    for peer in [&guest, &other] {
+        let game = peer.sync.state();
+        assert_eq!((game.pending_roll, game.rolls), (None, 1));
+        assert_eq!(game.players(), vec![2, 3]);
+        assert_eq!(game.last_faces, peer.expected_faces());
+        assert_eq!(game.state.as_ref().unwrap().rolls_left, 2);
+    }
+    assert_eq!(guest.sync.state().state, other.sync.state().state);
+}
+
+#[test]
+fn pending_roll_of_a_leaving_host_is_skipped() {
+    let network = LoopbackNetwork::new(Conditions { latency: 30.0, ..Default::default() });
+    let mut host = Peer::join(&network, 1, &[]);
+    let mut guest = Peer::join(&network, 2, &[1]);
+    let mut other = Peer::join(&network, 3, &[1, 2]);
+    host.send(PeerIntent::Start { players: vec![1, 2, 3], options: GameOptions::default() });
+    run(&network, &mut [&mut host, &mut guest, &mut other], 0.0, 500.0);
+
+    host.send(roll());
+    run(&network, &mut [&mut host, &mut guest, &mut other], 505.0, 540.0);
+    network.leave(1);
+    run(&network, &mut [&mut guest, &mut other], 545.0, 2000.0);
+
+    //The round of the skipped roll is not reused, and play goes on with the next player.
+    for peer in [&guest, &other] {
+        let game = peer.sync.state();
+        assert_eq!((game.pending_roll, game.rolls), (None, 1));
+        assert_eq!(game.state.as_ref().unwrap().current_player(), Some(2));
+    }
+    guest.send(roll());
+    run(&network, &mut [&mut guest, &mut other], 2005.0, 3000.0);
+    assert_eq!(other.sync.state().rolls, 2);
+    assert_eq!(other.sync.state().last_faces, other.expected_faces());
+    assert_eq!(guest.sync.state().state, other.sync.state().state);
+}
+
+#[test]
+fn out_of_turn_actions_are_rejected() {
+    let network = LoopbackNetwork::new(Conditions { latency: 30.0, ..Default::default() });
+    let mut host = Peer::join(&network, 1, &[]);
+    let mut guest = Peer::join(&network, 2, &[1]);
+    host.send(PeerIntent::Start { players: vec![1, 2], options: GameOptions::default() });
+    run(&network, &mut [&mut host, &mut guest], 0.0, 500.0);
+
+    guest.send(roll());
+    run(&network, &mut [&mut host, &mut guest], 505.0, 1000.0);
+    //A roll still waiting for its dice holds up every other action.
+    host.send(roll());
+    guest.send(PeerIntent::Start { players: vec![2], options: GameOptions::default() });
+    host.send(PeerIntent::Action(Action::Score(yahtzee_rules::Category::Chance, 0)));
+    run(&network, &mut [&mut host, &mut guest], 1005.0, 2000.0);
+
+    assert!(matches!(guest.rejected.as_slice(), [PeerRejection::Rule(RuleError::NotYourTurn), PeerRejection::GameRunning]));
+    assert!(matches!(host.rejected.as_slice(), [PeerRejection::RollPending]));
+    assert_eq!(host.sync.state().rolls, 1);
+    assert_eq!(guest.sync.state().state, host.sync.state().state);
+}
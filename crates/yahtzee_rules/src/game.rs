@@ -0,0 +1,168 @@
+use serde::{Serialize, Deserialize};
+
+use crate::{Category, Held, Scorecard, Variant, MAX_DICE, ROLLS_PER_TURN};
+
+//The variant and house rules a game is played with. Missing fields in older saves take their defaults.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct GameOptions {
+    pub yahtzee_bonus: bool,
+    pub variant: Variant,
+    //Yahtzees rolled after the Yahtzee box is filled must be played as jokers.
+    pub joker_rules: bool,
+    //Upper subtotal needed for the bonus. None uses the variant's own threshold.
+    pub upper_bonus_threshold: Option<u16>,
+}
+impl Default for GameOptions {
+    fn default() -> Self {
+        Self {
+            yahtzee_bonus: true,
+            variant: Variant::Yahtzee,
+            joker_rules: true,
+            upper_bonus_threshold: None,
+        }
+    }
+}
+impl GameOptions {
+    pub fn upper_bonus_threshold(&self) -> u16 {
+        self.upper_bonus_threshold.unwrap_or(self.variant.upper_bonus_threshold())
+    }
+    //The Yahtzee bonus and joker rules only exist in variants with a Yahtzee box worth 50.
+    pub fn has_yahtzee_bonus(&self) -> bool {
+        self.yahtzee_bonus && self.variant.has_jokers()
+    }
+    pub fn has_jokers(&self) -> bool {
+        self.joker_rules && self.variant.has_jokers()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    //Reroll every die that is not held. Held dice are ignored on the first roll of a turn.
+    Roll { held: Held },
+    //Score the dice in a category of one of the player's scorecard columns. The column is 0 unless the variant has several.
+    Score(Category, usize),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RuleError {
+    GameOver,
+    NotYourTurn,
+    NoRollsLeft,
+    MustRollFirst,
+    CategoryUnavailable,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PlayerState {
+    pub id: u32,
+    pub scorecard: Scorecard,
+    //Rolls left unused on earlier turns, for variants that save them.
+    #[serde(default)]
+    pub saved_rolls: u8,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GameState {
+    pub options: GameOptions,
+    pub players: Vec<PlayerState>, //In turn order.
+    pub turn: usize,
+    pub dice: Vec<u8>,
+    pub held: Held,
+    pub rolls_left: u8,
+    //Rolls the current player started the turn with, including saved ones.
+    #[serde(default = "default_turn_rolls")]
+    pub turn_rolls: u8,
+}
+fn default_turn_rolls() -> u8 {
+    ROLLS_PER_TURN
+}
+impl GameState {
+    pub fn new(player_ids: impl IntoIterator<Item = u32>, options: GameOptions) -> Self {
+        Self {
+            options,
+            players: player_ids.into_iter().map(|id| PlayerState { id, scorecard: Scorecard::new(options), saved_rolls: 0 }).collect(),
+            turn: 0,
+            dice: vec![1; options.variant.dice_count()],
+            held: [false; MAX_DICE],
+            rolls_left: ROLLS_PER_TURN,
+            turn_rolls: ROLLS_PER_TURN,
+        }
+    }
+    pub fn is_finished(&self) -> bool {
+        self.players.iter().all(|player| player.scorecard.is_complete())
+    }
+    pub fn current_player(&self) -> Option<u32> {
+        (!self.is_finished()).then(|| self.players.get(self.turn).map(|player| player.id)).flatten()
+    }
+    pub fn player(&self, id: u32) -> Option<&PlayerState> {
+        self.players.iter().find(|player| player.id == id)
+    }
+    pub fn has_rolled(&self) -> bool {
+        self.rolls_left < self.turn_rolls
+    }
+    //Apply a player's action. `roll_die` must return a face between 1 and 6.
+    pub fn apply<R: FnMut() -> u8>(&mut self, player_id: u32, action: Action, mut roll_die: R) -> Result<(), RuleError> {
+        let current_player = self.current_player().ok_or(RuleError::GameOver)?;
+        if current_player != player_id {
+            return Err(RuleError::NotYourTurn)
+        }
+        match action {
+            Action::Roll { held } => {
+                if self.rolls_left == 0 {
+                    return Err(RuleError::NoRollsLeft)
+                }
+                let held = if self.has_rolled() { held } else { [false; MAX_DICE] };
+                for (die, held) in self.dice.iter_mut().zip(held) {
+                    if !held {
+                        *die = roll_die();
+                    }
+                }
+                self.held = held;
+                self.rolls_left -= 1;
+            }
+            Action::Score(category, column) => {
+                if !self.has_rolled() {
+                    return Err(RuleError::MustRollFirst)
+                }
+                let player = &mut self.players[self.turn];
+                player.scorecard.record(column, category, &self.dice).ok_or(RuleError::CategoryUnavailable)?;
+                if self.options.variant.saves_rolls() {
+                    player.saved_rolls += self.rolls_left;
+                }
+                self.advance_turn();
+            }
+        }
+        Ok(())
+    }
+    //Drop a player who left mid-game. Their turn passes to the next player.
+    pub fn remove_player(&mut self, player_id: u32) {
+        if let Some(index) = self.players.iter().position(|player| player.id == player_id) {
+            self.players.remove(index);
+            if index < self.turn {
+                self.turn -= 1;
+            }
+            else if index == self.turn {
+                self.turn = self.turn.checked_sub(1).unwrap_or(self.players.len().saturating_sub(1));
+                self.advance_turn();
+            }
+        }
+    }
+    fn advance_turn(&mut self) {
+        self.held = [false; MAX_DICE];
+        self.rolls_left = ROLLS_PER_TURN;
+        self.turn_rolls = ROLLS_PER_TURN;
+        if self.players.is_empty() {
+            return
+        }
+        //Skip players whose scorecards are already complete.
+        for _ in 0..self.players.len() {
+            self.turn = (self.turn + 1) % self.players.len();
+            if !self.players[self.turn].scorecard.is_complete() {
+                break
+            }
+        }
+        self.rolls_left += std::mem::take(&mut self.players[self.turn].saved_rolls);
+        self.turn_rolls = self.rolls_left;
+    }
+}
@@ -0,0 +1,208 @@
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+
+mod game;
+pub use game::{Action, GameOptions, GameState, PlayerState, RuleError};
+
+mod variant;
+pub use variant::Variant;
+
+pub mod strategy;
+pub mod solver;
+
+pub const UPPER_BONUS_THRESHOLD: u16 = 63;
+pub const UPPER_BONUS: u16 = 35;
+pub const YAHTZEE_BONUS: u16 = 100;
+pub const MAX_DICE: usize = 6;
+pub const ROLLS_PER_TURN: u8 = 3;
+
+//Dice to keep when rolling. Positions past the variant's dice count are ignored.
+pub type Held = [bool; MAX_DICE];
+
+//Count how many dice show each face. Index 0 is unused.
+fn face_counts(dice: &[u8]) -> [u8; 7] {
+    let mut counts = [0; 7];
+    for &die in dice {
+        counts[die as usize] += 1;
+    }
+    counts
+}
+
+pub fn is_yahtzee(dice: &[u8]) -> bool {
+    dice.iter().all(|&die| die == dice[0])
+}
+
+//Every category of every variant. Which ones a scorecard has is decided by `Variant::categories`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Category {
+    Ones,
+    Twos,
+    Threes,
+    Fours,
+    Fives,
+    Sixes,
+    ThreeOfAKind,
+    FourOfAKind,
+    FullHouse,
+    SmallStraight,
+    LargeStraight,
+    Yahtzee,
+    Chance,
+    OnePair,
+    TwoPairs,
+    ThreePairs,
+    FiveOfAKind,
+    FullStraight,
+    Villa,
+    Tower,
+}
+impl Category {
+    const UPPER: [Category; 6] = [Self::Ones, Self::Twos, Self::Threes, Self::Fours, Self::Fives, Self::Sixes];
+
+    pub fn is_upper(self) -> bool {
+        self.face().is_some()
+    }
+    //The face an upper section category counts.
+    pub fn face(self) -> Option<u8> {
+        Self::UPPER.iter().position(|&category| category == self).map(|index| index as u8 + 1)
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Ones => "Ones",
+            Self::Twos => "Twos",
+            Self::Threes => "Threes",
+            Self::Fours => "Fours",
+            Self::Fives => "Fives",
+            Self::Sixes => "Sixes",
+            Self::ThreeOfAKind => "Three of a Kind",
+            Self::FourOfAKind => "Four of a Kind",
+            Self::FullHouse => "Full House",
+            Self::SmallStraight => "Small Straight",
+            Self::LargeStraight => "Large Straight",
+            Self::Yahtzee => "Yahtzee",
+            Self::Chance => "Chance",
+            Self::OnePair => "One Pair",
+            Self::TwoPairs => "Two Pairs",
+            Self::ThreePairs => "Three Pairs",
+            Self::FiveOfAKind => "Five of a Kind",
+            Self::FullStraight => "Full Straight",
+            Self::Villa => "Villa",
+            Self::Tower => "Tower",
+        }
+    }
+    //The upper section category counting the given face.
+    pub fn upper_for_face(face: u8) -> Category {
+        Self::UPPER[face as usize - 1]
+    }
+    //Score of a lower section category when a Yahtzee is played as a joker.
+    pub(crate) fn joker_score(self, dice: &[u8]) -> u16 {
+        match self {
+            Self::FullHouse => 25,
+            Self::SmallStraight => 30,
+            Self::LargeStraight => 40,
+            _ => Variant::Yahtzee.score(self, dice),
+        }
+    }
+}
+
+//A single player's scorecard. Categories are absent until scored.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Scorecard {
+    //The first column. Variants with several columns keep the rest in `extra_columns`.
+    pub scores: BTreeMap<Category, u16>,
+    pub yahtzee_bonus_count: u16,
+    #[serde(default)]
+    pub options: GameOptions,
+    #[serde(default)]
+    pub extra_columns: Vec<BTreeMap<Category, u16>>,
+}
+impl Scorecard {
+    pub fn new(options: GameOptions) -> Self {
+        Self {
+            options,
+            extra_columns: vec![BTreeMap::new(); options.variant.columns() - 1],
+            ..Self::default()
+        }
+    }
+    pub fn columns(&self) -> usize {
+        1 + self.extra_columns.len()
+    }
+    pub fn column(&self, column: usize) -> Option<&BTreeMap<Category, u16>> {
+        match column {
+            0 => Some(&self.scores),
+            _ => self.extra_columns.get(column - 1),
+        }
+    }
+    pub fn get(&self, column: usize, category: Category) -> Option<u16> {
+        self.column(column)?.get(&category).copied()
+    }
+    pub fn is_scored(&self, column: usize, category: Category) -> bool {
+        self.get(column, category).is_some()
+    }
+    //Whether the category is on this scorecard and still empty in the column.
+    pub fn is_open(&self, column: usize, category: Category) -> bool {
+        column < self.columns() && self.options.variant.has_category(category) && !self.is_scored(column, category)
+    }
+    pub fn is_complete(&self) -> bool {
+        (0..self.columns()).all(|column| self.options.variant.categories().iter().all(|&category| self.is_scored(column, category)))
+    }
+    pub fn upper_subtotal(&self, column: usize) -> u16 {
+        self.column(column).map_or(0, |scores| scores.iter().filter(|(category, _)| category.is_upper()).map(|(_, &score)| score).sum())
+    }
+    pub fn upper_bonus(&self, column: usize) -> u16 {
+        if self.upper_subtotal(column) >= self.options.upper_bonus_threshold() { self.options.variant.upper_bonus() } else { 0 }
+    }
+    //Every bonus earned so far, with column multipliers applied.
+    pub fn bonus(&self) -> u16 {
+        let upper_bonuses = (0..self.columns()).map(|column| self.upper_bonus(column) * self.options.variant.column_multiplier(column)).sum::<u16>();
+        upper_bonuses + self.yahtzee_bonus_count * YAHTZEE_BONUS
+    }
+    pub fn column_total(&self, column: usize) -> u16 {
+        self.column(column).map_or(0, |scores| scores.values().sum::<u16>()) + self.upper_bonus(column)
+    }
+    pub fn total(&self) -> u16 {
+        let columns = (0..self.columns()).map(|column| self.column_total(column) * self.options.variant.column_multiplier(column)).sum::<u16>();
+        columns + self.yahtzee_bonus_count * YAHTZEE_BONUS
+    }
+    //Score these dice would earn in an open category, following the forced joker rule when a Yahtzee
+    //is rolled after the column's Yahtzee box has been filled. Returns None if the category may not be used.
+    pub fn score_for(&self, column: usize, category: Category, dice: &[u8]) -> Option<u16> {
+        if !self.is_open(column, category) {
+            return None
+        }
+        let score = self.options.variant.score(category, dice);
+        if !self.options.has_jokers() || !is_yahtzee(dice) || !self.is_scored(column, Category::Yahtzee) {
+            return Some(score)
+        }
+        let upper = Category::upper_for_face(dice[0]);
+        if !self.is_scored(column, upper) {
+            //The matching upper box must be used while it is open.
+            return (category == upper).then_some(score)
+        }
+        let lower_open = self.options.variant.categories().iter().any(|&category| !category.is_upper() && !self.is_scored(column, category));
+        match (category.is_upper(), lower_open) {
+            (false, _) => Some(category.joker_score(dice)),
+            (true, true) => None,
+            (true, false) => Some(0),
+        }
+    }
+    //Every box these dice may be scored in, with the points each would earn.
+    pub fn choices<'a>(&'a self, dice: &'a [u8]) -> impl Iterator<Item = (Category, usize, u16)> + 'a {
+        self.options.variant.categories().iter().flat_map(move |&category| {
+            (0..self.columns()).filter_map(move |column| self.score_for(column, category, dice).map(|score| (category, column, score)))
+        })
+    }
+    //Score the dice in a box, counting a Yahtzee bonus if one is earned. None if the box may not be used.
+    pub(crate) fn record(&mut self, column: usize, category: Category, dice: &[u8]) -> Option<u16> {
+        let score = self.score_for(column, category, dice)?;
+        let yahtzee_scored = (0..self.columns()).any(|column| self.get(column, Category::Yahtzee) == Some(50));
+        if self.options.has_yahtzee_bonus() && is_yahtzee(dice) && yahtzee_scored {
+            self.yahtzee_bonus_count += 1;
+        }
+        match column {
+            0 => self.scores.insert(category, score),
+            _ => self.extra_columns[column - 1].insert(category, score),
+        };
+        Some(score)
+    }
+}
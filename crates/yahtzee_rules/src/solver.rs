@@ -0,0 +1,456 @@
+use crate::{
+    Action, Category, GameOptions, GameState, Scorecard, Variant, MAX_DICE, ROLLS_PER_TURN,
+    UPPER_BONUS, UPPER_BONUS_THRESHOLD, YAHTZEE_BONUS, strategy::Strategy,
+};
+
+//Expected-value solver for a single player's game, built by dynamic programming over every
+//state at the start of a turn: which categories are scored, the upper subtotal (capped at the
+//bonus threshold) and whether the Yahtzee box holds 50. Only those turn values are stored, one
+//f32 each. The dice and rolls left part of the state is worked out from them on demand, which
+//takes well under a millisecond per turn. Only standard Yahtzee rules are solved, see `supports`.
+pub struct Solver {
+    options: GameOptions,
+    table: Vec<f32>,
+    tables: Tables,
+}
+
+const FACES: usize = 6;
+const DICE_COUNT: usize = 5;
+type Dice = [u8; DICE_COUNT];
+//Standard categories come first in `Category`, so each one's bit is its discriminant.
+const CATEGORIES: &[Category] = Variant::Yahtzee.categories();
+const CATEGORY_COUNT: usize = CATEGORIES.len();
+const MASKS: usize = 1 << CATEGORY_COUNT;
+const UPPER_VALUES: usize = UPPER_BONUS_THRESHOLD as usize + 1;
+const STATES: usize = MASKS * UPPER_VALUES * 2;
+const YAHTZEE_BIT: usize = 1 << Category::Yahtzee as usize;
+const UPPER_BITS: usize = (1 << FACES) - 1;
+
+fn state_index(mask: usize, upper: usize, yahtzee_scored: bool) -> usize {
+    (mask * UPPER_VALUES + upper) * 2 + yahtzee_scored as usize
+}
+
+//Everything that depends only on the dice: each multiset of up to five dice is a "keep" and the
+//full ones are also rolls. Rolls are indexed by their position among the keeps of size five.
+struct Tables {
+    keep_counts: Vec<[u8; FACES]>,
+    keep_size: Vec<usize>,
+    //The keep with one more die of each face, for keeps of fewer than five dice.
+    keep_add: Vec<[usize; FACES]>,
+    rolls: Vec<usize>,
+    //Every distinct keep that can be taken from each roll, with the dice positions to hold for it.
+    roll_keeps: Vec<Vec<(usize, [bool; DICE_COUNT])>>,
+    roll_scores: Vec<RollScores>,
+    roll_index: Vec<usize>, //Keep index to roll index.
+    keep_lookup: Vec<usize>, //Face counts in base 6 to keep index.
+}
+
+//What a roll scores in each category, with and without joker rules.
+struct RollScores {
+    scores: [u16; CATEGORY_COUNT],
+    joker_scores: [u16; CATEGORY_COUNT],
+    yahtzee: bool,
+    face: u8,
+}
+impl Tables {
+    fn new() -> Self {
+        let mut keep_counts = Vec::new();
+        let mut counts = [0u8; FACES];
+        loop {
+            if counts.iter().map(|&count| count as usize).sum::<usize>() <= DICE_COUNT {
+                keep_counts.push(counts);
+            }
+            //Step to the next set of face counts like an odometer.
+            let Some(face) = (0..FACES).find(|&face| counts[face] < DICE_COUNT as u8) else {
+                break
+            };
+            counts[face] += 1;
+            counts[..face].fill(0);
+        }
+        let key = |counts: &[u8; FACES]| counts.iter().rev().fold(0, |key, &count| key * FACES + count as usize);
+        let mut keep_lookup = vec![usize::MAX; FACES.pow(FACES as u32)];
+        for (index, counts) in keep_counts.iter().enumerate() {
+            keep_lookup[key(counts)] = index;
+        }
+        let keep_size = keep_counts.iter().map(|counts| counts.iter().map(|&count| count as usize).sum()).collect::<Vec<usize>>();
+        let keep_add = keep_counts.iter().map(|counts| std::array::from_fn(|face| {
+            let mut added = *counts;
+            added[face] += 1;
+            keep_lookup.get(key(&added)).copied().unwrap_or(usize::MAX)
+        })).collect();
+
+        let rolls = (0..keep_counts.len()).filter(|&keep| keep_size[keep] == DICE_COUNT).collect::<Vec<_>>();
+        let mut roll_index = vec![usize::MAX; keep_counts.len()];
+        for (index, &keep) in rolls.iter().enumerate() {
+            roll_index[keep] = index;
+        }
+        let roll_dice = rolls.iter().map(|&keep| {
+            let mut dice = [0; DICE_COUNT];
+            let faces = (0..FACES).flat_map(|face| std::iter::repeat_n(face as u8 + 1, keep_counts[keep][face] as usize));
+            for (die, face) in dice.iter_mut().zip(faces) {
+                *die = face;
+            }
+            dice
+        }).collect::<Vec<Dice>>();
+        let roll_keeps = roll_dice.iter().map(|dice| {
+            let mut keeps: Vec<(usize, [bool; DICE_COUNT])> = Vec::new();
+            for mask in 0..1 << DICE_COUNT {
+                let held = std::array::from_fn(|index| mask & (1 << index) != 0);
+                let keep = keep_lookup[key(&held_counts(dice, &held))];
+                if !keeps.iter().any(|&(other, _)| other == keep) {
+                    keeps.push((keep, held));
+                }
+            }
+            keeps
+        }).collect();
+
+        let roll_scores = roll_dice.iter().map(|dice| RollScores {
+            scores: std::array::from_fn(|index| Variant::Yahtzee.score(CATEGORIES[index], dice)),
+            joker_scores: std::array::from_fn(|index| match CATEGORIES[index] {
+                category if category.is_upper() => Variant::Yahtzee.score(category, dice),
+                category => category.joker_score(dice),
+            }),
+            yahtzee: crate::is_yahtzee(dice),
+            face: dice[0],
+        }).collect();
+
+        Self { keep_counts, keep_size, keep_add, rolls, roll_keeps, roll_scores, roll_index, keep_lookup }
+    }
+    fn roll_of(&self, dice: &[u8]) -> usize {
+        let key = held_counts(dice, &[true; DICE_COUNT]).iter().rev().fold(0, |key, &count| key * FACES + count as usize);
+        self.roll_index[self.keep_lookup[key]]
+    }
+    //Expected value of every keep when the missing dice are rolled, given the value of each roll.
+    fn expect_keeps(&self, roll_values: &[f64], keep_values: &mut [f64]) {
+        //Keeps are ordered so that adding a die always leads to a later keep.
+        for keep in (0..self.keep_counts.len()).rev() {
+            keep_values[keep] = match self.keep_size[keep] {
+                DICE_COUNT => roll_values[self.roll_index[keep]],
+                _ => self.keep_add[keep].iter().map(|&added| keep_values[added]).sum::<f64>() / FACES as f64,
+            };
+        }
+    }
+}
+fn held_counts(dice: &[u8], held: &[bool; DICE_COUNT]) -> [u8; FACES] {
+    let mut counts = [0; FACES];
+    for (&die, &held) in dice.iter().zip(held) {
+        if held {
+            counts[die as usize - 1] += 1;
+        }
+    }
+    counts
+}
+
+//The points a scoring choice earns right away and the turn state it leads to.
+struct Outcome {
+    points: f64,
+    mask: usize,
+    upper: usize,
+    yahtzee_scored: bool,
+}
+
+//Scoring options for a roll, following the same rules as `Scorecard::score_for`.
+fn outcomes(options: &GameOptions, mask: usize, upper: usize, yahtzee_scored: bool, roll: &RollScores, mut f: impl FnMut(Category, Outcome)) {
+    let is_scored = |category: Category| mask & (1 << category as usize) != 0;
+    let yahtzee = roll.yahtzee;
+    let joker = yahtzee && is_scored(Category::Yahtzee);
+    let forced_upper = Category::upper_for_face(roll.face);
+    let lower_open = mask | UPPER_BITS != MASKS - 1;
+    for &category in CATEGORIES {
+        if is_scored(category) {
+            continue
+        }
+        let score = match (joker, category.is_upper()) {
+            (false, _) => roll.scores[category as usize],
+            _ if !is_scored(forced_upper) => if category == forced_upper { roll.scores[category as usize] } else { continue },
+            (true, false) => roll.joker_scores[category as usize],
+            (true, true) if lower_open => continue,
+            (true, true) => 0,
+        };
+        let mut points = score as f64;
+        if options.yahtzee_bonus && yahtzee && yahtzee_scored {
+            points += YAHTZEE_BONUS as f64;
+        }
+        let mut new_upper = upper;
+        if category.is_upper() {
+            new_upper = (upper + score as usize).min(UPPER_BONUS_THRESHOLD as usize);
+            if upper < UPPER_BONUS_THRESHOLD as usize && new_upper == UPPER_BONUS_THRESHOLD as usize {
+                points += UPPER_BONUS as f64;
+            }
+        }
+        let yahtzee_scored = yahtzee_scored || (options.yahtzee_bonus && category == Category::Yahtzee && score > 0);
+        f(category, Outcome { points, mask: mask | 1 << category as usize, upper: new_upper, yahtzee_scored });
+    }
+}
+
+//Working buffers for evaluating one turn.
+struct Turn {
+    //Value of each roll with 0, 1 and 2 rolls left.
+    roll_values: [Vec<f64>; ROLLS_PER_TURN as usize],
+    //Value of each keep when rolled with 0 and 1 rolls left afterwards, then of rolling all five dice.
+    keep_values: [Vec<f64>; ROLLS_PER_TURN as usize],
+}
+impl Turn {
+    fn new(tables: &Tables) -> Self {
+        Self {
+            roll_values: std::array::from_fn(|_| vec![0.0; tables.rolls.len()]),
+            keep_values: std::array::from_fn(|_| vec![0.0; tables.keep_counts.len()]),
+        }
+    }
+    //Fill in the value of every roll and keep for a turn starting in this state, given the values of later turns.
+    fn evaluate(&mut self, tables: &Tables, options: &GameOptions, table: &[f32], mask: usize, upper: usize, yahtzee_scored: bool) -> f64 {
+        for (roll, scores) in tables.roll_scores.iter().enumerate() {
+            let mut best = f64::NEG_INFINITY;
+            outcomes(options, mask, upper, yahtzee_scored, scores, |_, outcome| {
+                let value = outcome.points + table[state_index(outcome.mask, outcome.upper, outcome.yahtzee_scored)] as f64;
+                best = best.max(value);
+            });
+            self.roll_values[0][roll] = best;
+        }
+        for rolls_left in 1..ROLLS_PER_TURN as usize {
+            let (earlier, later) = self.roll_values.split_at_mut(rolls_left);
+            tables.expect_keeps(&earlier[rolls_left - 1], &mut self.keep_values[rolls_left - 1]);
+            for (roll, value) in later[0].iter_mut().enumerate() {
+                let keep_values = &self.keep_values[rolls_left - 1];
+                *value = tables.roll_keeps[roll].iter().map(|&(keep, _)| keep_values[keep]).fold(earlier[0][roll], f64::max);
+            }
+        }
+        let last = ROLLS_PER_TURN as usize - 1;
+        tables.expect_keeps(&self.roll_values[last], &mut self.keep_values[last]);
+        self.keep_values[last][tables.keep_lookup[0]]
+    }
+}
+
+//Upper subtotals that can be reached with exactly these upper categories scored, capped at the threshold.
+fn reachable_uppers() -> Vec<[bool; UPPER_VALUES]> {
+    (0..1 << FACES).map(|upper_mask: usize| {
+        let mut reachable = [false; UPPER_VALUES];
+        reachable[0] = true;
+        for face in (0..FACES).filter(|face| upper_mask & (1 << face) != 0) {
+            let mut next = [false; UPPER_VALUES];
+            for (subtotal, _) in reachable.iter().enumerate().filter(|(_, reachable)| **reachable) {
+                for count in 0..=DICE_COUNT {
+                    next[(subtotal + count * (face + 1)).min(UPPER_VALUES - 1)] = true;
+                }
+            }
+            reachable = next;
+        }
+        reachable
+    }).collect()
+}
+
+impl Solver {
+    //Solve every turn state. Takes around ten seconds per core in an optimized build, so callers
+    //should do this once in the background and keep the table with `to_bytes`.
+    pub fn solve(options: GameOptions) -> Self {
+        Self::solve_from(options, 0)
+    }
+    //Solve only the turn states with at least `min_scored` categories scored. The rest of the table stays zero.
+    fn solve_from(options: GameOptions, min_scored: usize) -> Self {
+        let tables = Tables::new();
+        let reachable = reachable_uppers();
+        let mut table = vec![0.0f32; STATES];
+        let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+
+        //A turn only leads to states with one more category scored, so solve by categories left, fewest first.
+        for scored in (min_scored..CATEGORY_COUNT).rev() {
+            let masks = (0..MASKS).filter(|mask| mask.count_ones() as usize == scored).collect::<Vec<_>>();
+            let solve_masks = |masks: &[usize]| {
+                let mut turn = Turn::new(&tables);
+                let mut values = Vec::new();
+                for &mask in masks {
+                    for upper in (0..UPPER_VALUES).filter(|&upper| reachable[mask & UPPER_BITS][upper]) {
+                        for yahtzee_scored in [false, true] {
+                            //The 50 can only be in the box if the box is scored, and only matters with the bonus rule.
+                            if yahtzee_scored && (mask & YAHTZEE_BIT == 0 || !options.yahtzee_bonus) {
+                                continue
+                            }
+                            let value = turn.evaluate(&tables, &options, &table, mask, upper, yahtzee_scored);
+                            values.push((state_index(mask, upper, yahtzee_scored), value as f32));
+                        }
+                    }
+                }
+                values
+            };
+            let values = if threads > 1 {
+                std::thread::scope(|scope| {
+                    let chunk = masks.len().div_ceil(threads).max(1);
+                    let handles = masks.chunks(chunk).map(|masks| scope.spawn(|| solve_masks(masks))).collect::<Vec<_>>();
+                    handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect::<Vec<_>>()
+                })
+            } else {
+                solve_masks(&masks)
+            };
+            for (index, value) in values {
+                table[index] = value;
+            }
+        }
+        Self { options, table, tables }
+    }
+    pub fn options(&self) -> GameOptions {
+        self.options
+    }
+    //Whether games with these options play by the rules the solver knows. The Yahtzee bonus may be on or off.
+    pub fn supports(options: &GameOptions) -> bool {
+        options.variant == Variant::Yahtzee && options.joker_rules && options.upper_bonus_threshold() == UPPER_BONUS_THRESHOLD
+    }
+
+    //The table as little-endian f32s, for saving and loading with `from_bytes`.
+    //Starts with a byte recording whether the Yahtzee bonus was on, so a table is never loaded for the wrong rules.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        std::iter::once(self.options.yahtzee_bonus as u8).chain(self.table.iter().flat_map(|value| value.to_le_bytes())).collect()
+    }
+    pub fn from_bytes(options: GameOptions, bytes: &[u8]) -> Option<Self> {
+        let (&yahtzee_bonus, bytes) = bytes.split_first()?;
+        if !Self::supports(&options) || yahtzee_bonus != options.yahtzee_bonus as u8 || bytes.len() != STATES * 4 {
+            return None
+        }
+        let table = bytes.chunks_exact(4).map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]])).collect();
+        Some(Self { options, table, tables: Tables::new() })
+    }
+
+    fn solves(&self, options: &GameOptions) -> bool {
+        Self::supports(options) && options.yahtzee_bonus == self.options.yahtzee_bonus
+    }
+    fn turn_state(&self, scorecard: &Scorecard) -> (usize, usize, bool) {
+        let mask = CATEGORIES.iter().filter(|&&category| scorecard.is_scored(0, category)).fold(0, |mask, &category| mask | 1 << category as usize);
+        let upper = scorecard.upper_subtotal(0).min(UPPER_BONUS_THRESHOLD) as usize;
+        (mask, upper, self.options.yahtzee_bonus && scorecard.get(0, Category::Yahtzee) == Some(50))
+    }
+    //Expected final total for this scorecard at the start of a turn, playing optimally from here.
+    pub fn expected_total(&self, scorecard: &Scorecard) -> f32 {
+        let (mask, upper, yahtzee_scored) = self.turn_state(scorecard);
+        scorecard.total() as f32 + self.table[state_index(mask, upper, yahtzee_scored)]
+    }
+
+    //Every choice open to the player on their turn, with the final total each is expected to lead to.
+    //Best first. Rerolls are listed once per distinct set of kept dice.
+    pub fn evaluate(&self, state: &GameState, player_id: u32) -> Vec<(Action, f32)> {
+        let Some(player) = state.player(player_id).filter(|_| state.current_player() == Some(player_id) && self.solves(&state.options)) else {
+            return Vec::new()
+        };
+        let scorecard = &player.scorecard;
+        if !state.has_rolled() {
+            return vec![(Action::Roll { held: [false; MAX_DICE] }, self.expected_total(scorecard))]
+        }
+
+        let tables = &self.tables;
+        let (mask, upper, yahtzee_scored) = self.turn_state(scorecard);
+        let roll = tables.roll_of(&state.dice);
+        let mut turn = Turn::new(tables);
+        turn.evaluate(tables, &self.options, &self.table, mask, upper, yahtzee_scored);
+
+        let base = scorecard.total() as f64;
+        let mut choices = Vec::new();
+        outcomes(&self.options, mask, upper, yahtzee_scored, &tables.roll_scores[roll], |category, outcome| {
+            let value = base + outcome.points + self.table[state_index(outcome.mask, outcome.upper, outcome.yahtzee_scored)] as f64;
+            choices.push((Action::Score(category, 0), value as f32));
+        });
+        if state.rolls_left > 0 {
+            let keep_values = &turn.keep_values[state.rolls_left as usize - 1];
+            //Map the sorted dice positions of each keep back onto the player's dice.
+            let sorted = {
+                let mut positions: [usize; DICE_COUNT] = std::array::from_fn(|index| index);
+                positions.sort_by_key(|&index| state.dice[index]);
+                positions
+            };
+            for &(keep, sorted_held) in tables.roll_keeps[roll].iter() {
+                let mut held = [false; MAX_DICE];
+                for (position, &index) in sorted.iter().enumerate() {
+                    held[index] = sorted_held[position];
+                }
+                choices.push((Action::Roll { held }, (base + keep_values[keep]) as f32));
+            }
+        }
+        choices.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        choices
+    }
+}
+//Plays the choice with the highest expected final total.
+impl Strategy for &Solver {
+    fn choose(&mut self, state: &GameState, player_id: u32) -> Action {
+        self.evaluate(state, player_id).first().map_or(Action::Roll { held: [false; MAX_DICE] }, |&(action, _)| action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::OnceLock;
+
+    //Categories scored in the endgame table, which leaves the last three turns to play.
+    const ENDGAME_SCORED: usize = CATEGORY_COUNT - 3;
+
+    //Solving the last few turns is quick, so most tests share a table that only covers those.
+    fn solver() -> &'static Solver {
+        static SOLVER: OnceLock<Solver> = OnceLock::new();
+        SOLVER.get_or_init(|| Solver::solve_from(GameOptions::default(), ENDGAME_SCORED))
+    }
+
+    //A game on one of its last turns, with every category but `open` scratched.
+    fn rolled(open: &[Category], dice: [u8; DICE_COUNT], rolls_left: u8) -> GameState {
+        let mut state = GameState::new([1], GameOptions::default());
+        for &category in CATEGORIES.iter().filter(|category| !open.contains(category)) {
+            state.players[0].scorecard.scores.insert(category, 0);
+        }
+        state.apply(1, Action::Roll { held: [false; MAX_DICE] }, || 1).unwrap();
+        state.dice[..DICE_COUNT].copy_from_slice(&dice);
+        state.rolls_left = rolls_left;
+        state
+    }
+
+    //Solving the whole table takes a while even in an optimized build. Run with `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn expected_total_of_an_empty_card() {
+        let expected = Solver::solve(GameOptions::default()).expected_total(&Scorecard::new(GameOptions::default()));
+        assert!((expected - 254.59).abs() < 0.01, "expected total {expected}");
+    }
+
+    #[test]
+    fn expected_total_of_a_last_chance() {
+        let state = rolled(&[Category::Chance], [1; DICE_COUNT], 0);
+        let expected = solver().expected_total(&state.players[0].scorecard);
+        assert!((expected - 23.33).abs() < 0.01, "expected total {expected}");
+    }
+
+    #[test]
+    fn table_round_trips_through_bytes() {
+        let bytes = solver().to_bytes();
+        let loaded = Solver::from_bytes(GameOptions::default(), &bytes).unwrap();
+        assert_eq!(loaded.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn from_bytes_rejects_truncated_tables() {
+        let bytes = solver().to_bytes();
+        assert!(Solver::from_bytes(GameOptions::default(), &bytes[..bytes.len() - 1]).is_none());
+        assert!(Solver::from_bytes(GameOptions::default(), &[]).is_none());
+    }
+
+    #[test]
+    fn from_bytes_rejects_tables_for_other_options() {
+        let bytes = solver().to_bytes();
+        assert!(Solver::from_bytes(GameOptions { yahtzee_bonus: false, ..GameOptions::default() }, &bytes).is_none());
+        assert!(Solver::from_bytes(GameOptions { variant: Variant::Yatzy, ..GameOptions::default() }, &bytes).is_none());
+        assert!(Solver::from_bytes(GameOptions { joker_rules: false, ..GameOptions::default() }, &bytes).is_none());
+    }
+
+    #[test]
+    fn keeps_a_rolled_yahtzee() {
+        let state = rolled(&[Category::Yahtzee, Category::Fours, Category::Chance], [4; DICE_COUNT], 2);
+        assert_eq!((&mut solver()).choose(&state, 1), Action::Score(Category::Yahtzee, 0));
+    }
+
+    #[test]
+    fn scores_a_large_straight_on_the_last_roll() {
+        let state = rolled(&[Category::LargeStraight, Category::Sixes, Category::Chance], [2, 3, 4, 5, 6], 0);
+        assert_eq!((&mut solver()).choose(&state, 1), Action::Score(Category::LargeStraight, 0));
+    }
+
+    #[test]
+    fn only_answers_on_the_players_turn() {
+        let state = rolled(&[Category::Yahtzee], [4; DICE_COUNT], 2);
+        assert!(solver().evaluate(&state, 2).is_empty());
+    }
+}
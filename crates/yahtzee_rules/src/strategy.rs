@@ -0,0 +1,124 @@
+use crate::{Action, Category, GameState, Held, Scorecard, MAX_DICE, face_counts};
+
+//Decides a player's next action. Only called on that player's turn.
+pub trait Strategy {
+    fn choose(&mut self, state: &GameState, player_id: u32) -> Action;
+}
+
+//Category and column scoring the most points for these dice once column multipliers are applied,
+//preferring the upper section and the first column on ties so zeroes land in cheap boxes.
+//None once the scorecard is complete.
+pub fn best_category(scorecard: &Scorecard, dice: &[u8]) -> Option<(Category, usize, u16)> {
+    let mut best: Option<(Category, usize, u16)> = None;
+    for (category, column, score) in scorecard.choices(dice) {
+        let points = score * scorecard.options.variant.column_multiplier(column);
+        if best.is_none_or(|(_, _, best_points)| points > best_points) {
+            best = Some((category, column, points));
+        }
+    }
+    best
+}
+
+//Plays the way a casual player does: chase straights when a run is close, otherwise keep the
+//most common face, and score whatever is worth the most right now.
+#[derive(Default)]
+pub struct Greedy;
+impl Strategy for Greedy {
+    fn choose(&mut self, state: &GameState, player_id: u32) -> Action {
+        let Some(player) = state.player(player_id) else {
+            return Action::Roll { held: [false; MAX_DICE] }
+        };
+        let scorecard = &player.scorecard;
+        if !state.has_rolled() {
+            return Action::Roll { held: [false; MAX_DICE] }
+        }
+        let best = best_category(scorecard, &state.dice);
+        let settled = best.is_some_and(|(category, column, score)| score > 0 && match category {
+            Category::Yahtzee | Category::LargeStraight | Category::FullStraight | Category::FullHouse => true,
+            Category::SmallStraight => !scorecard.is_open(column, Category::LargeStraight),
+            _ => false,
+        });
+        match best {
+            Some((category, column, _)) if settled || state.rolls_left == 0 => Action::Score(category, column),
+            _ => Action::Roll { held: keep(scorecard, &state.dice) },
+        }
+    }
+}
+
+//Dice worth keeping for the next roll.
+fn keep(scorecard: &Scorecard, dice: &[u8]) -> Held {
+    let counts = face_counts(dice);
+    let straights_open = (0..scorecard.columns()).any(|column| {
+        [Category::SmallStraight, Category::LargeStraight, Category::FullStraight].iter().any(|&category| scorecard.is_open(column, category))
+    });
+    let most_common = (1..=6).max_by_key(|&face| (counts[face], face)).unwrap_or(6);
+
+    //Keep one of each face in the longest run when it beats the most common face.
+    let (run_start, run_length) = (1..=6).map(|start| (start, (start..=6).take_while(|&face| counts[face] > 0).count())).max_by_key(|&(_, length)| length).unwrap_or((1, 0));
+    if straights_open && run_length >= 3 && run_length > counts[most_common] as usize {
+        let mut kept = [false; 7];
+        return held_where(dice, |face| {
+            let keep = (run_start..run_start + run_length).contains(&face) && !kept[face];
+            kept[face] |= keep;
+            keep
+        });
+    }
+    held_where(dice, |face| face == most_common)
+}
+
+fn held_where(dice: &[u8], mut keep: impl FnMut(usize) -> bool) -> Held {
+    let mut held = [false; MAX_DICE];
+    for (held, &die) in held.iter_mut().zip(dice) {
+        *held = keep(die as usize);
+    }
+    held
+}
+
+//Looks one roll ahead: keeps whichever dice give the best expected score after the next roll,
+//and scores now if no reroll is expected to beat the current dice.
+#[derive(Default)]
+pub struct Lookahead;
+impl Strategy for Lookahead {
+    fn choose(&mut self, state: &GameState, player_id: u32) -> Action {
+        let Some(player) = state.player(player_id) else {
+            return Action::Roll { held: [false; MAX_DICE] }
+        };
+        let scorecard = &player.scorecard;
+        let best = best_category(scorecard, &state.dice);
+        match best {
+            Some((category, column, _)) if state.rolls_left == 0 => return Action::Score(category, column),
+            None => return Action::Roll { held: [false; MAX_DICE] },
+            _ if !state.has_rolled() => return Action::Roll { held: [false; MAX_DICE] },
+            _ => {}
+        }
+        let dice_count = state.dice.len();
+        let expected = |held: Held| expected_best_score(scorecard, &state.dice, held);
+        let (held, reroll_score) = (0..1 << dice_count)
+            .map(|mask: usize| std::array::from_fn(|index| mask & (1 << index) != 0))
+            .filter(|held: &Held| held[..dice_count].contains(&false))
+            .map(|held| (held, expected(held)))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .unwrap_or(([false; MAX_DICE], 0.0));
+        match best {
+            Some((category, column, score)) if score as f64 >= reroll_score => Action::Score(category, column),
+            _ => Action::Roll { held },
+        }
+    }
+}
+
+//Average best category score over every outcome of rerolling the dice that are not held.
+fn expected_best_score(scorecard: &Scorecard, dice: &[u8], held: Held) -> f64 {
+    let rerolled = (0..dice.len()).filter(|&index| !held[index]).collect::<Vec<_>>();
+    let outcomes = 6usize.pow(rerolled.len() as u32);
+    let mut total = 0u64;
+    for outcome in 0..outcomes {
+        let mut dice = dice.to_vec();
+        let mut faces = outcome;
+        for &index in rerolled.iter() {
+            dice[index] = (faces % 6) as u8 + 1;
+            faces /= 6;
+        }
+        total += best_category(scorecard, &dice).map_or(0, |(_, _, score)| score as u64);
+    }
+    total as f64 / outcomes as f64
+}
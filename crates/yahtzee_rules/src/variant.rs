@@ -0,0 +1,314 @@
+use serde::{Serialize, Deserialize};
+
+use crate::{Category, UPPER_BONUS, UPPER_BONUS_THRESHOLD, face_counts};
+
+//Rule sets a game can be played with. Each decides the dice, the scorecard and how categories score.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Variant {
+    #[default]
+    Yahtzee,
+    //Scandinavian rules: pairs instead of the joker rules, fixed straights and only the dice in a combination count.
+    Yatzy,
+    //Yatzy with six dice and more categories. Rolls a player does not use are saved for their later turns.
+    MaxiYatzy,
+    //Yahtzee scored in three columns that count once, twice and three times.
+    TripleYahtzee,
+}
+
+const YAHTZEE_CATEGORIES: [Category; 13] = [
+    Category::Ones, Category::Twos, Category::Threes, Category::Fours, Category::Fives, Category::Sixes,
+    Category::ThreeOfAKind, Category::FourOfAKind, Category::FullHouse, Category::SmallStraight, Category::LargeStraight, Category::Yahtzee, Category::Chance,
+];
+const YATZY_CATEGORIES: [Category; 15] = [
+    Category::Ones, Category::Twos, Category::Threes, Category::Fours, Category::Fives, Category::Sixes,
+    Category::OnePair, Category::TwoPairs, Category::ThreeOfAKind, Category::FourOfAKind,
+    Category::SmallStraight, Category::LargeStraight, Category::FullHouse, Category::Chance, Category::Yahtzee,
+];
+const MAXI_YATZY_CATEGORIES: [Category; 20] = [
+    Category::Ones, Category::Twos, Category::Threes, Category::Fours, Category::Fives, Category::Sixes,
+    Category::OnePair, Category::TwoPairs, Category::ThreePairs, Category::ThreeOfAKind, Category::FourOfAKind, Category::FiveOfAKind,
+    Category::SmallStraight, Category::LargeStraight, Category::FullStraight, Category::FullHouse, Category::Villa, Category::Tower,
+    Category::Chance, Category::Yahtzee,
+];
+
+impl Variant {
+    pub const ALL: [Variant; 4] = [Self::Yahtzee, Self::Yatzy, Self::MaxiYatzy, Self::TripleYahtzee];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Yahtzee => "Yahtzee",
+            Self::Yatzy => "Yatzy",
+            Self::MaxiYatzy => "Maxi Yatzy",
+            Self::TripleYahtzee => "Triple Yahtzee",
+        }
+    }
+    pub fn dice_count(self) -> usize {
+        match self {
+            Self::MaxiYatzy => 6,
+            _ => 5,
+        }
+    }
+    //Categories on the scorecard, in the order they are shown.
+    pub const fn categories(self) -> &'static [Category] {
+        match self {
+            Self::Yahtzee | Self::TripleYahtzee => &YAHTZEE_CATEGORIES,
+            Self::Yatzy => &YATZY_CATEGORIES,
+            Self::MaxiYatzy => &MAXI_YATZY_CATEGORIES,
+        }
+    }
+    pub fn has_category(self, category: Category) -> bool {
+        self.categories().contains(&category)
+    }
+    pub fn columns(self) -> usize {
+        match self {
+            Self::TripleYahtzee => 3,
+            _ => 1,
+        }
+    }
+    //How many times a column's total counts towards the final score.
+    pub fn column_multiplier(self, column: usize) -> u16 {
+        match self {
+            Self::TripleYahtzee => column as u16 + 1,
+            _ => 1,
+        }
+    }
+    pub fn saves_rolls(self) -> bool {
+        self == Self::MaxiYatzy
+    }
+    //Whether the Yahtzee bonus and joker rules exist. The Yatzy variants have neither.
+    pub fn has_jokers(self) -> bool {
+        matches!(self, Self::Yahtzee | Self::TripleYahtzee)
+    }
+    pub fn upper_bonus_threshold(self) -> u16 {
+        match self {
+            Self::MaxiYatzy => 84,
+            _ => UPPER_BONUS_THRESHOLD,
+        }
+    }
+    pub fn upper_bonus(self) -> u16 {
+        match self {
+            Self::Yahtzee | Self::TripleYahtzee => UPPER_BONUS,
+            Self::Yatzy | Self::MaxiYatzy => 50,
+        }
+    }
+    pub fn category_name(self, category: Category) -> &'static str {
+        match (self, category) {
+            (Self::Yatzy, Category::Yahtzee) => "Yatzy",
+            (Self::MaxiYatzy, Category::Yahtzee) => "Maxi Yatzy",
+            _ => category.name(),
+        }
+    }
+    //Score these dice would earn in this category, without joker rules applied.
+    pub fn score(self, category: Category, dice: &[u8]) -> u16 {
+        let counts = face_counts(dice);
+        let sum = dice.iter().map(|&die| die as u16).sum::<u16>();
+        if let Some(face) = category.face() {
+            return counts[face as usize] as u16 * face as u16
+        }
+        match self {
+            Self::Yahtzee | Self::TripleYahtzee => yahtzee_score(category, &counts, sum),
+            Self::Yatzy | Self::MaxiYatzy => yatzy_score(category, &counts, sum, dice.len()),
+        }
+    }
+}
+
+fn contains_run(counts: &[u8; 7], length: usize) -> bool {
+    (1..=7 - length).any(|start| (start..start + length).all(|face| counts[face] > 0))
+}
+
+fn yahtzee_score(category: Category, counts: &[u8; 7], sum: u16) -> u16 {
+    match category {
+        Category::ThreeOfAKind if counts.iter().any(|&count| count >= 3) => sum,
+        Category::FourOfAKind if counts.iter().any(|&count| count >= 4) => sum,
+        Category::FullHouse if counts.contains(&3) && counts.contains(&2) => 25,
+        Category::SmallStraight if contains_run(counts, 4) => 30,
+        Category::LargeStraight if contains_run(counts, 5) => 40,
+        Category::Yahtzee if counts.contains(&5) => 50,
+        Category::Chance => sum,
+        _ => 0,
+    }
+}
+
+//In Yatzy only the dice making up a combination count, and straights are fixed runs.
+fn yatzy_score(category: Category, counts: &[u8; 7], sum: u16, dice_count: usize) -> u16 {
+    //Faces shown by at least this many dice, highest first.
+    let faces_with = |count: u8| (1..=6u16).rev().filter(move |&face| counts[face as usize] >= count);
+    let of_a_kind = |count: u8| faces_with(count).next().map_or(0, |face| face * count as u16);
+    let pairs = |pairs: usize| {
+        let faces = faces_with(2).take(pairs).collect::<Vec<_>>();
+        if faces.len() == pairs { faces.iter().map(|face| face * 2).sum() } else { 0 }
+    };
+    let straight = |first: usize, last: usize| if (first..=last).all(|face| counts[face] > 0) { (first..=last).sum::<usize>() as u16 } else { 0 };
+    //Two groups of different faces, like the three and two of a full house.
+    let groups = |big: u8, small: u8| faces_with(big)
+        .flat_map(|big_face| faces_with(small).filter(move |&small_face| small_face != big_face).map(move |small_face| big_face * big as u16 + small_face * small as u16))
+        .max()
+        .unwrap_or(0);
+    match category {
+        Category::OnePair => of_a_kind(2),
+        Category::TwoPairs => pairs(2),
+        Category::ThreePairs => pairs(3),
+        Category::ThreeOfAKind => of_a_kind(3),
+        Category::FourOfAKind => of_a_kind(4),
+        Category::FiveOfAKind => of_a_kind(5),
+        Category::SmallStraight => straight(1, 5),
+        Category::LargeStraight => straight(2, 6),
+        Category::FullStraight => straight(1, 6),
+        Category::FullHouse => groups(3, 2),
+        Category::Villa => groups(3, 3),
+        Category::Tower => groups(4, 2),
+        Category::Chance => sum,
+        Category::Yahtzee if counts.contains(&(dice_count as u8)) => if dice_count > 5 { 100 } else { 50 },
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Action, GameOptions, GameState, MAX_DICE, PlayerState, ROLLS_PER_TURN, RuleError, Scorecard};
+
+    fn options(variant: Variant) -> GameOptions {
+        GameOptions { variant, ..GameOptions::default() }
+    }
+    //Roll the given faces for the current player, in order.
+    fn roll(state: &mut GameState, player_id: u32, faces: &[u8]) {
+        let mut faces = faces.iter().copied();
+        state.apply(player_id, Action::Roll { held: [false; MAX_DICE] }, || faces.next().unwrap()).unwrap();
+    }
+    fn scored(options: GameOptions, scores: &[(usize, Category, u16)]) -> Scorecard {
+        let mut scorecard = Scorecard::new(options);
+        for &(column, category, score) in scores {
+            match column {
+                0 => scorecard.scores.insert(category, score),
+                _ => scorecard.extra_columns[column - 1].insert(category, score),
+            };
+        }
+        scorecard
+    }
+
+    #[test]
+    fn yatzy_pairs_count_only_the_paired_dice() {
+        assert_eq!(Variant::Yatzy.score(Category::OnePair, &[3, 3, 5, 5, 1]), 10);
+        assert_eq!(Variant::Yatzy.score(Category::TwoPairs, &[3, 3, 5, 5, 1]), 16);
+        assert_eq!(Variant::Yatzy.score(Category::TwoPairs, &[3, 3, 3, 3, 1]), 0);
+        assert_eq!(Variant::Yatzy.score(Category::ThreeOfAKind, &[4, 4, 4, 4, 2]), 12);
+        assert_eq!(Variant::Yatzy.score(Category::FullHouse, &[2, 2, 6, 6, 6]), 22);
+        assert_eq!(Variant::Yatzy.score(Category::Yahtzee, &[2; 5]), 50);
+    }
+
+    #[test]
+    fn yatzy_straights_are_fixed() {
+        assert_eq!(Variant::Yatzy.score(Category::SmallStraight, &[1, 2, 3, 4, 5]), 15);
+        assert_eq!(Variant::Yatzy.score(Category::LargeStraight, &[2, 3, 4, 5, 6]), 20);
+        assert_eq!(Variant::Yatzy.score(Category::SmallStraight, &[2, 3, 4, 5, 6]), 0);
+        assert_eq!(Variant::Yatzy.score(Category::LargeStraight, &[1, 2, 3, 4, 5]), 0);
+        assert_eq!(Variant::Yahtzee.score(Category::SmallStraight, &[2, 3, 4, 5, 2]), 30);
+    }
+
+    #[test]
+    fn yatzy_has_no_jokers_or_yahtzee_bonus() {
+        let options = options(Variant::Yatzy);
+        let scorecard = scored(options, &[(0, Category::Yahtzee, 50)]);
+        assert_eq!(scorecard.score_for(0, Category::FullHouse, &[4; 5]), Some(0));
+        let mut state = GameState { players: vec![PlayerState { id: 1, scorecard, saved_rolls: 0 }], ..GameState::new([1], options) };
+        roll(&mut state, 1, &[4; 5]);
+        state.apply(1, Action::Score(Category::Fours, 0), || 1).unwrap();
+        assert_eq!(state.players[0].scorecard.yahtzee_bonus_count, 0);
+    }
+
+    #[test]
+    fn maxi_yatzy_rolls_six_dice() {
+        let mut state = GameState::new([1], options(Variant::MaxiYatzy));
+        assert_eq!(state.dice.len(), 6);
+        roll(&mut state, 1, &[1, 2, 3, 4, 5, 6]);
+        assert_eq!(state.dice, [1, 2, 3, 4, 5, 6]);
+        assert_eq!(Variant::MaxiYatzy.score(Category::FullStraight, &state.dice), 21);
+        assert_eq!(Variant::MaxiYatzy.score(Category::Villa, &[2, 2, 2, 5, 5, 5]), 21);
+        assert_eq!(Variant::MaxiYatzy.score(Category::Tower, &[3, 3, 3, 3, 6, 6]), 24);
+        assert_eq!(Variant::MaxiYatzy.score(Category::Yahtzee, &[6; 6]), 100);
+    }
+
+    #[test]
+    fn maxi_yatzy_saves_unused_rolls() {
+        let mut state = GameState::new([1, 2], options(Variant::MaxiYatzy));
+        roll(&mut state, 1, &[6; 6]);
+        state.apply(1, Action::Score(Category::Yahtzee, 0), || 1).unwrap();
+        assert_eq!(state.players[0].saved_rolls, 2);
+
+        roll(&mut state, 2, &[1; 6]);
+        state.apply(2, Action::Score(Category::Ones, 0), || 1).unwrap();
+        //Player 1 starts their next turn with the two rolls they saved.
+        assert_eq!((state.rolls_left, state.turn_rolls, state.players[0].saved_rolls), (5, 5, 0));
+        for _ in 0..5 {
+            roll(&mut state, 1, &[2; 6]);
+        }
+        assert_eq!(state.apply(1, Action::Roll { held: [false; MAX_DICE] }, || 2), Err(RuleError::NoRollsLeft));
+    }
+
+    #[test]
+    fn other_variants_do_not_save_rolls() {
+        let mut state = GameState::new([1, 2], GameOptions::default());
+        roll(&mut state, 1, &[6; 5]);
+        state.apply(1, Action::Score(Category::Yahtzee, 0), || 1).unwrap();
+        assert_eq!((state.players[0].saved_rolls, state.rolls_left), (0, ROLLS_PER_TURN));
+    }
+
+    #[test]
+    fn triple_yahtzee_columns_are_multiplied() {
+        let options = options(Variant::TripleYahtzee);
+        let scorecard = scored(options, &[(0, Category::Chance, 20), (1, Category::Chance, 20), (2, Category::Chance, 20)]);
+        assert_eq!(scorecard.total(), 20 + 40 + 60);
+
+        let upper = [(0, Category::Sixes, 30), (0, Category::Fives, 25), (0, Category::Fours, 20), (2, Category::Sixes, 30), (2, Category::Fives, 25), (2, Category::Fours, 20)];
+        let scorecard = scored(options, &upper);
+        assert_eq!(scorecard.bonus(), UPPER_BONUS + UPPER_BONUS * 3);
+        assert_eq!(scorecard.total(), 75 + UPPER_BONUS + (75 + UPPER_BONUS) * 3);
+    }
+
+    #[test]
+    fn triple_yahtzee_scores_in_any_open_column() {
+        let mut state = GameState::new([1], options(Variant::TripleYahtzee));
+        roll(&mut state, 1, &[2, 3, 4, 5, 6]);
+        assert_eq!(state.players[0].scorecard.choices(&state.dice).filter(|&(category, ..)| category == Category::LargeStraight).count(), 3);
+        state.apply(1, Action::Score(Category::LargeStraight, 2), || 1).unwrap();
+        assert_eq!(state.players[0].scorecard.total(), 40 * 3);
+        assert!(state.apply(1, Action::Score(Category::Chance, 3), || 1).is_err());
+    }
+
+    #[test]
+    fn jokers_follow_the_forced_upper_box() {
+        let options = GameOptions::default();
+        let yahtzee = [5; 5];
+        let scorecard = scored(options, &[(0, Category::Yahtzee, 50)]);
+        //The matching upper box has to be used while it is open.
+        assert_eq!(scorecard.score_for(0, Category::Fives, &yahtzee), Some(25));
+        assert_eq!(scorecard.score_for(0, Category::FullHouse, &yahtzee), None);
+
+        //Then any lower box scores as a joker.
+        let scorecard = scored(options, &[(0, Category::Yahtzee, 50), (0, Category::Fives, 25)]);
+        assert_eq!(scorecard.score_for(0, Category::FullHouse, &yahtzee), Some(25));
+        assert_eq!(scorecard.score_for(0, Category::LargeStraight, &yahtzee), Some(40));
+        assert_eq!(scorecard.score_for(0, Category::Ones, &yahtzee), None);
+    }
+
+    #[test]
+    fn jokers_can_be_turned_off() {
+        let options = GameOptions { joker_rules: false, ..GameOptions::default() };
+        let scorecard = scored(options, &[(0, Category::Yahtzee, 50)]);
+        assert_eq!(scorecard.score_for(0, Category::FullHouse, &[5; 5]), Some(0));
+        assert_eq!(scorecard.score_for(0, Category::Ones, &[5; 5]), Some(0));
+    }
+
+    #[test]
+    fn upper_bonus_threshold_can_be_changed() {
+        let scores = [(0, Category::Sixes, 18), (0, Category::Fives, 15), (0, Category::Fours, 12)];
+        let default = scored(GameOptions::default(), &scores);
+        assert_eq!(default.upper_bonus(0), 0);
+        let lowered = scored(GameOptions { upper_bonus_threshold: Some(45), ..GameOptions::default() }, &scores);
+        assert_eq!(lowered.upper_bonus(0), UPPER_BONUS);
+        assert_eq!(lowered.total(), 45 + UPPER_BONUS);
+        assert_eq!(options(Variant::MaxiYatzy).upper_bonus_threshold(), 84);
+        assert_eq!(GameOptions { upper_bonus_threshold: Some(70), ..options(Variant::MaxiYatzy) }.upper_bonus_threshold(), 70);
+    }
+}